@@ -4,6 +4,13 @@ pub enum ActionType {
     Reactions,
     Forwards,
     Views,
+    Velocity,
+    ReactionRate,
+    ForwardRate,
+    ReplyRate,
+    EngagementRate,
+    Reaction,
+    TopComment,
 }
 
 impl ActionType {
@@ -13,7 +20,42 @@ impl ActionType {
             1 => ActionType::Reactions,
             2 => ActionType::Forwards,
             3 => ActionType::Views,
+            4 => ActionType::Velocity,
+            5 => ActionType::ReactionRate,
+            6 => ActionType::ForwardRate,
+            7 => ActionType::ReplyRate,
+            8 => ActionType::EngagementRate,
+            9 => ActionType::Reaction,
+            10 => ActionType::TopComment,
             _ => panic!("No ActionType for {value}"),
         }
     }
 }
+
+/// How posts are ordered inside the per-action blocks.
+#[derive(Copy, Clone, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Rank {
+    /// Absolute counters as reported by Telegram
+    #[default]
+    Total,
+    /// Counters gained per hour, see `cache::PostCache::get_velocities`
+    Velocity,
+}
+
+impl Rank {
+    pub fn parse(value: &str) -> Option<Rank> {
+        match value {
+            "total" => Some(Rank::Total),
+            "velocity" => Some(Rank::Velocity),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Rank::Total => "total",
+            Rank::Velocity => "velocity",
+        }
+    }
+}
@@ -25,11 +25,29 @@
 //! older than 24 hours are re-fetched (TTL = 1 day). Posts older than
 //! 7 days are considered permanently fresh and are never re-fetched
 //! unless `force=true`.
+//!
+//! Every time a post is re-fetched, its counters are also appended to
+//! `post_metrics_history` (skipped when nothing changed since the last
+//! snapshot), so the growth of a post can be charted over time and
+//! posts can be ranked by velocity (counters gained per hour).
+//!
+//! Replies of a post's discussion thread are cached in `post_comments` with
+//! their reactions, replaced as a whole on every fetch of the thread.
+//!
+//! Rows are keyed by the `-100<id>` name of their channel once its id is
+//! known, see `PostCache::identify_channel`. Any other name of the channel
+//! (username, invite link) is translated through `channel_names`, so the
+//! cache survives username changes and serves private channels. `channels`
+//! holds the current username, title and photo of each identified channel,
+//! a username it no longer has is an alias of it, see `PostCache::renamed_channel`.
 
-use rusqlite::{params, Connection};
+use rusqlite::{params, Connection, OptionalExtension as _};
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 
-use crate::post::Post;
+use crate::post::{Post, Velocity};
+use crate::post_data::{CommentData, ReactionData};
+use crate::source::{self, ChannelInfo, ChannelRef};
 use crate::util::Result;
 
 const DAY: i64 = 86400;
@@ -46,6 +64,56 @@ pub const DEFAULT_FETCH_CAP: usize = 1_000;
 
 const MAX_CACHED_MEDIA_SIZE: i64 = 10 * 1024 * 1024; // 10 MB
 
+/// Files of the output directory that are tracked for the output quota
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ArtifactKind {
+    /// Rendered `<hash>.<ext>` video
+    Video,
+    /// Channel avatar `<channel>.png`
+    Avatar,
+    /// Post photo `<photo_id>.jpg`
+    Photo,
+}
+
+impl ArtifactKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ArtifactKind::Video => "video",
+            ArtifactKind::Avatar => "avatar",
+            ArtifactKind::Photo => "photo",
+        }
+    }
+}
+
+/// Count and total size of tracked output files
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize)]
+pub struct ArtifactUsage {
+    pub files: i64,
+    pub bytes: i64,
+}
+
+/// A point-in-time sample of a post's engagement counters.
+#[derive(Clone, serde::Serialize)]
+pub struct MetricsSnapshot {
+    pub fetched_at: i64,
+    pub views: Option<i32>,
+    pub forwards: Option<i32>,
+    pub replies: Option<i32>,
+    pub reactions: Option<i32>,
+}
+
+/// What is known of an identified channel, as of its last resolve
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+pub struct ChannelRecord {
+    pub id: i64,
+    /// `None` for private channels
+    pub username: Option<String>,
+    pub title: String,
+    /// Id of the channel userpic, it changes with the picture
+    pub photo_id: Option<i64>,
+    pub updated_at: i64,
+}
+
 /// Describes what needs to be fetched from Telegram.
 /// Each entry is `(from_date, to_date, limit)`.
 pub struct FetchPlan {
@@ -66,6 +134,73 @@ impl FetchPlan {
     }
 }
 
+/// `channel_names` rows of the name `?1`. Usernames match in any case, invite hashes exactly.
+fn name_matches(name: &str) -> &'static str {
+    match ChannelRef::parse(name) {
+        ChannelRef::Username(_) => "name = ?1 COLLATE NOCASE",
+        _ => "name = ?1",
+    }
+}
+
+/// Rows of other tables cached under the name `?1`, in any case for usernames like `name_matches`
+fn channel_matches(name: &str) -> &'static str {
+    match ChannelRef::parse(name) {
+        ChannelRef::Username(_) => "channel = ?1 COLLATE NOCASE",
+        _ => "channel = ?1",
+    }
+}
+
+/// Point `name` at the channel `key`, replacing the rows of the name in another case
+fn set_channel_name(conn: &Connection, name: &str, key: &str) -> Result<()> {
+    conn.execute(&format!("DELETE FROM channel_names WHERE {}", name_matches(name)), params![name])?;
+    conn.execute("INSERT INTO channel_names (name, channel) VALUES (?1, ?2)", params![name, key])?;
+    Ok(())
+}
+
+/// `-100<id>` names are keys already, other names are keys until their channel is identified
+fn channel_key(conn: &Connection, channel: &str) -> Result<String> {
+    if let ChannelRef::Id(id) = ChannelRef::parse(channel) {
+        return Ok(source::channel_key(id));
+    }
+    let key: Option<String> = conn
+        .query_row(
+            &format!("SELECT channel FROM channel_names WHERE {}", name_matches(channel)),
+            params![channel],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(key.unwrap_or_else(|| channel.to_string()))
+}
+
+/// A comment from `id, date, author, text, reactions` columns starting at `first`
+fn comment_from_row_at(row: &rusqlite::Row, first: usize) -> rusqlite::Result<CommentData> {
+    Ok(CommentData {
+        id: row.get(first)?,
+        date: row.get(first + 1)?,
+        author: row.get(first + 2)?,
+        text: row.get(first + 3)?,
+        reactions: row.get(first + 4)?,
+        reaction_counts: Vec::new(),
+    })
+}
+
+fn comment_from_row(row: &rusqlite::Row) -> rusqlite::Result<CommentData> {
+    comment_from_row_at(row, 0)
+}
+
+fn comment_reactions(conn: &Connection, channel: &str, post_id: i32, id: i32) -> Result<Vec<ReactionData>> {
+    let mut stmt = conn.prepare(
+        "SELECT reaction, count FROM comment_reactions
+         WHERE channel = ?1 AND post_id = ?2 AND id = ?3 ORDER BY count DESC",
+    )?;
+    let reactions = stmt
+        .query_map(params![channel, post_id, id], |row| {
+            Ok(ReactionData::from_key(&row.get::<_, String>(0)?, row.get(1)?))
+        })?
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    Ok(reactions)
+}
+
 pub struct PostCache {
     conn: std::sync::Mutex<Connection>,
     media_dir: PathBuf,
@@ -104,6 +239,65 @@ impl PostCache {
                 min_fetched_date INTEGER NOT NULL,
                 max_fetched_date INTEGER NOT NULL
             );
+            CREATE TABLE IF NOT EXISTS post_metrics_history (
+                channel TEXT NOT NULL,
+                id INTEGER NOT NULL,
+                fetched_at INTEGER NOT NULL,
+                views INTEGER,
+                forwards INTEGER,
+                replies INTEGER,
+                reactions INTEGER,
+                PRIMARY KEY (channel, id, fetched_at)
+            );
+            CREATE TABLE IF NOT EXISTS output_artifacts (
+                path TEXT NOT NULL PRIMARY KEY,
+                kind TEXT NOT NULL,
+                size INTEGER NOT NULL,
+                created_at INTEGER NOT NULL,
+                last_accessed INTEGER NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS post_reactions (
+                channel TEXT NOT NULL,
+                id INTEGER NOT NULL,
+                reaction TEXT NOT NULL,
+                count INTEGER NOT NULL,
+                PRIMARY KEY (channel, id, reaction)
+            );
+            CREATE TABLE IF NOT EXISTS channel_names (
+                name TEXT NOT NULL PRIMARY KEY,
+                channel TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS post_comments (
+                channel TEXT NOT NULL,
+                post_id INTEGER NOT NULL,
+                id INTEGER NOT NULL,
+                date INTEGER NOT NULL,
+                author TEXT,
+                text TEXT NOT NULL,
+                reactions INTEGER,
+                PRIMARY KEY (channel, post_id, id)
+            );
+            CREATE TABLE IF NOT EXISTS comment_reactions (
+                channel TEXT NOT NULL,
+                post_id INTEGER NOT NULL,
+                id INTEGER NOT NULL,
+                reaction TEXT NOT NULL,
+                count INTEGER NOT NULL,
+                PRIMARY KEY (channel, post_id, id, reaction)
+            );
+            CREATE TABLE IF NOT EXISTS comment_fetches (
+                channel TEXT NOT NULL,
+                post_id INTEGER NOT NULL,
+                fetched_at INTEGER NOT NULL,
+                PRIMARY KEY (channel, post_id)
+            );
+            CREATE TABLE IF NOT EXISTS channels (
+                id INTEGER NOT NULL PRIMARY KEY,
+                username TEXT,
+                title TEXT NOT NULL,
+                photo_id INTEGER,
+                updated_at INTEGER NOT NULL
+            );
             CREATE INDEX IF NOT EXISTS idx_posts_channel_date_id
                 ON posts(channel, date, id);",
         )?;
@@ -135,6 +329,24 @@ impl PostCache {
             )?;
         }
 
+        // Migrate: channels identified before `channels` existed keep the username they were asked
+        // for until they are resolved again
+        let named: Vec<(String, String)> = conn
+            .prepare("SELECT name, channel FROM channel_names")?
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<std::result::Result<_, _>>()?;
+        for (name, key) in named {
+            if let (ChannelRef::Username(username), ChannelRef::Id(id)) =
+                (ChannelRef::parse(&name), ChannelRef::parse(&key))
+            {
+                conn.execute(
+                    "INSERT OR IGNORE INTO channels (id, username, title, photo_id, updated_at)
+                     VALUES (?1, ?2, ?2, NULL, 0)",
+                    params![id, username],
+                )?;
+            }
+        }
+
         std::fs::create_dir_all(media_dir)?;
 
         Ok(Self {
@@ -148,6 +360,158 @@ impl PostCache {
         &self.media_dir
     }
 
+    // ── Channel names ──────────────────────────────────────────────────
+
+    /// The name rows of `channel` are stored under
+    pub fn channel_key(&self, channel: &str) -> Result<String> {
+        let conn = self.conn.lock().map_err(|e| format!("Lock error: {}", e))?;
+        channel_key(&conn, channel)
+    }
+
+    /// Whether the id behind `channel` is known
+    pub fn is_identified(&self, channel: &str) -> Result<bool> {
+        let conn = self.conn.lock().map_err(|e| format!("Lock error: {}", e))?;
+        Ok(matches!(ChannelRef::parse(channel), ChannelRef::Id(_)) || channel_key(&conn, channel)? != channel)
+    }
+
+    /// Record that `name` is the channel with `id`. Rows cached under `name` before move to the
+    /// channel's key; where both have a row the key's one stays.
+    pub fn identify_channel(&self, name: &str, id: i64) -> Result<()> {
+        let key = source::channel_key(id);
+        if name == key {
+            return Ok(());
+        }
+        let conn = self.conn.lock().map_err(|e| format!("Lock error: {}", e))?;
+        let tx = conn.unchecked_transaction()?;
+        set_channel_name(&tx, name, &key)?;
+        let mut moved = 0;
+        let tables = [
+            "posts",
+            "media_cache",
+            "channel_fetch_bounds",
+            "post_metrics_history",
+            "post_reactions",
+            "post_comments",
+            "comment_reactions",
+            "comment_fetches",
+        ];
+        let matches = channel_matches(name);
+        for table in tables {
+            moved += tx.execute(
+                &format!("UPDATE OR IGNORE {} SET channel = ?2 WHERE {}", table, matches),
+                params![name, key],
+            )?;
+            tx.execute(&format!("DELETE FROM {} WHERE {}", table, matches), params![name])?;
+        }
+        tx.commit()?;
+        if moved > 0 {
+            log::info!("Moved {} cached rows of {} to {}", moved, name, key);
+        }
+        self.move_thumbs(name, &key);
+        Ok(())
+    }
+
+    /// Rename `thumb_{name}_{id}.jpg` files cached before the channel was identified, with the
+    /// username in any case
+    fn move_thumbs(&self, name: &str, key: &str) {
+        let username = matches!(ChannelRef::parse(name), ChannelRef::Username(_));
+        let fold = |text: &str| if username { text.to_lowercase() } else { text.to_string() };
+        let prefix = fold(&format!("thumb_{}_", name));
+        let Ok(entries) = std::fs::read_dir(&self.media_dir) else {
+            return;
+        };
+        for entry in entries.filter_map(|entry| entry.ok()) {
+            let file_name = entry.file_name();
+            let Some(file_name) = file_name.to_str().map(&fold) else {
+                continue;
+            };
+            let Some(rest) = file_name.strip_prefix(&prefix) else {
+                continue;
+            };
+            // `thumb_a_b_1.jpg` is not a thumb of `a`
+            if !rest.strip_suffix(".jpg").is_some_and(|id| id.parse::<i32>().is_ok()) {
+                continue;
+            }
+            let to = self.media_dir.join(format!("thumb_{}_{}", key, rest));
+            if let Err(e) = std::fs::rename(entry.path(), &to) {
+                log::warn!("Can't move {}: {}", entry.path().display(), e);
+            }
+        }
+    }
+
+    /// Record what resolving `name` told about its channel. A channel that changed its username
+    /// keeps the old one as an alias, until another channel takes that username.
+    pub fn record_channel(&self, name: &str, channel: &ChannelInfo) -> Result<()> {
+        let Some(id) = channel.id else {
+            return Ok(());
+        };
+        self.identify_channel(name, id)?;
+        let key = source::channel_key(id);
+        let now = chrono::Utc::now().timestamp();
+
+        let conn = self.conn.lock().map_err(|e| format!("Lock error: {}", e))?;
+        let tx = conn.unchecked_transaction()?;
+        let previous: Option<Option<String>> = tx
+            .query_row("SELECT username FROM channels WHERE id = ?1", params![id], |row| row.get(0))
+            .optional()?;
+        let usernames = previous.iter().flatten().chain(channel.username.as_ref());
+        for username in usernames {
+            set_channel_name(&tx, username, &key)?;
+        }
+        // A channel that had the username gave it up, it is not known under it anymore
+        tx.execute(
+            "UPDATE channels SET username = NULL WHERE username = ?1 COLLATE NOCASE AND id != ?2",
+            params![channel.username, id],
+        )?;
+        tx.execute(
+            "INSERT OR REPLACE INTO channels (id, username, title, photo_id, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![id, channel.username, channel.title, channel.photo_id, now],
+        )?;
+        tx.commit()?;
+
+        if let Some(Some(old)) = previous
+            && let Some(new) = &channel.username
+            && !old.eq_ignore_ascii_case(new)
+        {
+            log::info!("Channel {} renamed from {} to {}", key, old, new);
+        }
+        Ok(())
+    }
+
+    /// What is known of the channel behind `name`, if it was identified
+    pub fn channel(&self, name: &str) -> Result<Option<ChannelRecord>> {
+        let conn = self.conn.lock().map_err(|e| format!("Lock error: {}", e))?;
+        let ChannelRef::Id(id) = ChannelRef::parse(&channel_key(&conn, name)?) else {
+            return Ok(None);
+        };
+        let record = conn
+            .query_row(
+                "SELECT id, username, title, photo_id, updated_at FROM channels WHERE id = ?1",
+                params![id],
+                |row| {
+                    Ok(ChannelRecord {
+                        id: row.get(0)?,
+                        username: row.get(1)?,
+                        title: row.get(2)?,
+                        photo_id: row.get(3)?,
+                        updated_at: row.get(4)?,
+                    })
+                },
+            )
+            .optional()?;
+        Ok(record)
+    }
+
+    /// The current username of a channel `name` is an old username of
+    pub fn renamed_channel(&self, name: &str) -> Result<Option<String>> {
+        let ChannelRef::Username(username) = ChannelRef::parse(name) else {
+            return Ok(None);
+        };
+        let current = self.channel(name)?.and_then(|channel| channel.username);
+        Ok(current.filter(|current| !current.eq_ignore_ascii_case(username)))
+    }
+
     // ── Post cache ─────────────────────────────────────────────────────
 
     /// Returns all cached posts in the range (always returned, even if a
@@ -168,6 +532,7 @@ impl PostCache {
         }
 
         let conn = self.conn.lock().map_err(|e| format!("Lock error: {}", e))?;
+        let channel = &channel_key(&conn, channel)?;
         let now = chrono::Utc::now().timestamp();
 
         // Load all cached posts in the range (date ASC, id ASC)
@@ -197,6 +562,11 @@ impl PostCache {
                     message: row.get(6)?,
                     image: row.get(7)?,
                     grouped_id: row.get(9)?,
+                    velocity: None,
+                    rates: None,
+                    reaction_counts: Vec::new(),
+                    selected_reaction: None,
+                    top_comment: None,
                 },
                 row.get::<_, i64>(8)?, // fetched_at
             ))
@@ -308,9 +678,20 @@ impl PostCache {
         Ok((all_posts, FetchPlan::new(ranges)))
     }
 
+    /// Number of cached posts of every channel
+    pub fn channel_post_counts(&self) -> Result<HashMap<String, i64>> {
+        let conn = self.conn.lock().map_err(|e| format!("Lock error: {}", e))?;
+        let mut stmt = conn.prepare("SELECT channel, COUNT(*) FROM posts GROUP BY channel")?;
+        let counts = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<std::result::Result<HashMap<_, _>, _>>()?;
+        Ok(counts)
+    }
+
     /// Return the number of cached posts for a channel in a date range.
     pub fn count_cached_posts(&self, channel: &str, from_date: i64, to_date: i64) -> Result<usize> {
         let conn = self.conn.lock().map_err(|e| format!("Lock error: {}", e))?;
+        let channel = &channel_key(&conn, channel)?;
         let count: i64 = conn.query_row(
             "SELECT COUNT(*) FROM posts WHERE channel = ?1 AND date >= ?2 AND date <= ?3",
             params![channel, from_date, to_date],
@@ -321,27 +702,294 @@ impl PostCache {
 
     pub fn store_posts(&self, channel: &str, posts: &[Post]) -> Result<()> {
         let conn = self.conn.lock().map_err(|e| format!("Lock error: {}", e))?;
+        let channel = &channel_key(&conn, channel)?;
         let now = chrono::Utc::now().timestamp();
 
         let tx = conn.unchecked_transaction()?;
 
+        let mut snapshots = 0;
         for post in posts {
             tx.execute(
                 "INSERT OR REPLACE INTO posts (channel, id, date, views, forwards, replies, reactions, message, image, fetched_at, grouped_id)
                  VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
                 params![channel, post.id, post.date, post.views, post.forwards, post.replies, post.reactions, post.message, post.image, now, post.grouped_id],
             )?;
+
+            // Append a history snapshot unless the counters are unchanged since the last one
+            snapshots += tx.execute(
+                "INSERT OR IGNORE INTO post_metrics_history (channel, id, fetched_at, views, forwards, replies, reactions)
+                 SELECT ?1, ?2, ?3, ?4, ?5, ?6, ?7
+                 WHERE NOT EXISTS (
+                     SELECT 1 FROM (
+                         SELECT views, forwards, replies, reactions FROM post_metrics_history
+                         WHERE channel = ?1 AND id = ?2 ORDER BY fetched_at DESC LIMIT 1
+                     ) AS last
+                     WHERE last.views IS ?4 AND last.forwards IS ?5
+                       AND last.replies IS ?6 AND last.reactions IS ?7
+                 )",
+                params![channel, post.id, now, post.views, post.forwards, post.replies, post.reactions],
+            )?;
+
+            // Posts loaded back from the cache carry no breakdown, keep the stored one. An empty
+            // breakdown is only fresh when the post has no reactions at all.
+            if post.reaction_counts.is_empty() && post.reactions.unwrap_or(0) > 0 {
+                continue;
+            }
+            tx.execute(
+                "DELETE FROM post_reactions WHERE channel = ?1 AND id = ?2",
+                params![channel, post.id],
+            )?;
+            for reaction in &post.reaction_counts {
+                tx.execute(
+                    "INSERT OR REPLACE INTO post_reactions (channel, id, reaction, count) VALUES (?1, ?2, ?3, ?4)",
+                    params![channel, post.id, reaction.key(), reaction.count],
+                )?;
+            }
         }
 
         tx.commit()?;
-        log::debug!("Cached {} posts for {}", posts.len(), channel);
+        log::debug!("Cached {} posts for {} ({} history snapshots)", posts.len(), channel, snapshots);
         Ok(())
     }
 
+    // ── Comments ───────────────────────────────────────────────────────
+
+    /// Replace the cached discussion thread of a post
+    pub fn store_comments(&self, channel: &str, post_id: i32, comments: &[CommentData]) -> Result<()> {
+        let conn = self.conn.lock().map_err(|e| format!("Lock error: {}", e))?;
+        let channel = &channel_key(&conn, channel)?;
+        let now = chrono::Utc::now().timestamp();
+
+        let tx = conn.unchecked_transaction()?;
+        for table in ["post_comments", "comment_reactions"] {
+            tx.execute(
+                &format!("DELETE FROM {} WHERE channel = ?1 AND post_id = ?2", table),
+                params![channel, post_id],
+            )?;
+        }
+        for comment in comments {
+            tx.execute(
+                "INSERT OR REPLACE INTO post_comments (channel, post_id, id, date, author, text, reactions)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![channel, post_id, comment.id, comment.date, comment.author, comment.text, comment.reactions],
+            )?;
+            for reaction in &comment.reaction_counts {
+                tx.execute(
+                    "INSERT OR REPLACE INTO comment_reactions (channel, post_id, id, reaction, count)
+                     VALUES (?1, ?2, ?3, ?4, ?5)",
+                    params![channel, post_id, comment.id, reaction.key(), reaction.count],
+                )?;
+            }
+        }
+        tx.execute(
+            "INSERT OR REPLACE INTO comment_fetches (channel, post_id, fetched_at) VALUES (?1, ?2, ?3)",
+            params![channel, post_id, now],
+        )?;
+        tx.commit()?;
+        log::debug!("Cached {} comments of {}/{}", comments.len(), channel, post_id);
+        Ok(())
+    }
+
+    /// Cached replies to a post, newest first. `None` if the thread was never fetched or, with
+    /// `max_age`, was fetched longer ago.
+    pub fn get_comments(&self, channel: &str, post_id: i32, max_age: Option<i64>) -> Result<Option<Vec<CommentData>>> {
+        let conn = self.conn.lock().map_err(|e| format!("Lock error: {}", e))?;
+        let channel = &channel_key(&conn, channel)?;
+
+        let fetched_at: Option<i64> = conn
+            .query_row(
+                "SELECT fetched_at FROM comment_fetches WHERE channel = ?1 AND post_id = ?2",
+                params![channel, post_id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let now = chrono::Utc::now().timestamp();
+        match (fetched_at, max_age) {
+            (None, _) => return Ok(None),
+            (Some(fetched_at), Some(max_age)) if fetched_at < now - max_age => return Ok(None),
+            _ => {}
+        }
+
+        let mut stmt = conn.prepare(
+            "SELECT id, date, author, text, reactions FROM post_comments
+             WHERE channel = ?1 AND post_id = ?2 ORDER BY id DESC",
+        )?;
+        let mut comments = stmt
+            .query_map(params![channel, post_id], comment_from_row)?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        for comment in comments.iter_mut() {
+            comment.reaction_counts = comment_reactions(&conn, channel, post_id, comment.id)?;
+        }
+        Ok(Some(comments))
+    }
+
+    /// Returns `post id -> comment` of the most reacted cached comment of every post in the
+    /// range that has a comment with reactions.
+    pub fn get_top_comments(&self, channel: &str, from_date: i64, to_date: i64) -> Result<HashMap<i32, CommentData>> {
+        let conn = self.conn.lock().map_err(|e| format!("Lock error: {}", e))?;
+        let channel = &channel_key(&conn, channel)?;
+        let mut stmt = conn.prepare(
+            "SELECT c.post_id, c.id, c.date, c.author, c.text, c.reactions
+             FROM posts p JOIN post_comments c ON c.channel = p.channel AND c.post_id = p.id
+             WHERE p.channel = ?1 AND p.date >= ?2 AND p.date <= ?3 AND c.reactions > 0
+             ORDER BY c.reactions DESC, c.id ASC",
+        )?;
+        let rows = stmt
+            .query_map(params![channel, from_date, to_date], |row| {
+                Ok((row.get::<_, i32>(0)?, comment_from_row_at(row, 1)?))
+            })?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+
+        let mut top = HashMap::new();
+        for (post_id, comment) in rows {
+            top.entry(post_id).or_insert(comment);
+        }
+        for (post_id, comment) in top.iter_mut() {
+            comment.reaction_counts = comment_reactions(&conn, channel, *post_id, comment.id)?;
+        }
+        Ok(top)
+    }
+
+    /// Returns `id -> count` of a single reaction (see `ReactionData::key`)
+    /// for every cached post in the range that received it.
+    pub fn get_reaction_counts(
+        &self,
+        channel: &str,
+        from_date: i64,
+        to_date: i64,
+        reaction: &str,
+    ) -> Result<HashMap<i32, i32>> {
+        let conn = self.conn.lock().map_err(|e| format!("Lock error: {}", e))?;
+        let channel = &channel_key(&conn, channel)?;
+        let mut stmt = conn.prepare(
+            "SELECT p.id, r.count
+             FROM posts p JOIN post_reactions r ON r.channel = p.channel AND r.id = p.id
+             WHERE p.channel = ?1 AND p.date >= ?2 AND p.date <= ?3 AND r.reaction = ?4",
+        )?;
+        let counts = stmt
+            .query_map(params![channel, from_date, to_date, reaction], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })?
+            .collect::<std::result::Result<HashMap<_, _>, _>>()?;
+        Ok(counts)
+    }
+
+    /// Computes the per-hour growth of every cached post in the range from
+    /// its `post_metrics_history` snapshots.
+    ///
+    /// The rate is measured from the publish date (when all counters are
+    /// zero) to the latest snapshot taken within `window` seconds after
+    /// publishing. Posts first seen after the window has passed fall back to
+    /// their earliest snapshot. Posts without any snapshot are omitted.
+    pub fn get_velocities(
+        &self,
+        channel: &str,
+        from_date: i64,
+        to_date: i64,
+        window: i64,
+    ) -> Result<HashMap<i32, Velocity>> {
+        let conn = self.conn.lock().map_err(|e| format!("Lock error: {}", e))?;
+        let channel = &channel_key(&conn, channel)?;
+
+        let mut stmt = conn.prepare(
+            "SELECT p.id, p.date, h.fetched_at, h.views, h.forwards, h.replies, h.reactions
+             FROM posts p JOIN post_metrics_history h ON h.channel = p.channel AND h.id = p.id
+             WHERE p.channel = ?1 AND p.date >= ?2 AND p.date <= ?3
+             ORDER BY p.id ASC, h.fetched_at ASC",
+        )?;
+        let rows = stmt.query_map(params![channel, from_date, to_date], |row| {
+            Ok((
+                row.get::<_, i32>(0)?,
+                row.get::<_, i64>(1)?,
+                MetricsSnapshot {
+                    fetched_at: row.get(2)?,
+                    views: row.get(3)?,
+                    forwards: row.get(4)?,
+                    replies: row.get(5)?,
+                    reactions: row.get(6)?,
+                },
+            ))
+        })?;
+
+        // id -> (date, chosen snapshot)
+        let mut samples: HashMap<i32, (i64, MetricsSnapshot)> = HashMap::new();
+        for row in rows {
+            let (id, date, snapshot) = row?;
+            // Later snapshots within the window replace earlier ones; past the
+            // window, the first snapshot seen is kept
+            let in_window = snapshot.fetched_at <= date.saturating_add(window);
+            if samples.contains_key(&id) && !in_window {
+                continue;
+            }
+            samples.insert(id, (date, snapshot));
+        }
+
+        Ok(samples
+            .into_iter()
+            .map(|(id, (date, s))| {
+                // At least an hour, so a post fetched minutes after publishing doesn't explode
+                let hours = ((s.fetched_at - date) as f64 / 3600.0).max(1.0);
+                let per_hour = |count: Option<i32>| (count.unwrap_or(0) as f64 / hours).round() as i32;
+                let velocity = Velocity {
+                    views: per_hour(s.views),
+                    forwards: per_hour(s.forwards),
+                    replies: per_hour(s.replies),
+                    reactions: per_hour(s.reactions),
+                };
+                (id, velocity)
+            })
+            .collect())
+    }
+
+    /// Returns the recorded engagement snapshots of a post (oldest first) and
+    /// the post's publish date, if the post is cached. With `max_age` set,
+    /// only snapshots taken within that many seconds after publishing are kept.
+    pub fn get_post_history(
+        &self,
+        channel: &str,
+        id: i32,
+        max_age: Option<i64>,
+    ) -> Result<Option<(i64, Vec<MetricsSnapshot>)>> {
+        let conn = self.conn.lock().map_err(|e| format!("Lock error: {}", e))?;
+        let channel = &channel_key(&conn, channel)?;
+
+        let date: Option<i64> = conn
+            .query_row(
+                "SELECT date FROM posts WHERE channel = ?1 AND id = ?2",
+                params![channel, id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let Some(date) = date else {
+            return Ok(None);
+        };
+
+        let until = max_age.map_or(i64::MAX, |age| date.saturating_add(age));
+        let mut stmt = conn.prepare(
+            "SELECT fetched_at, views, forwards, replies, reactions FROM post_metrics_history
+             WHERE channel = ?1 AND id = ?2 AND fetched_at <= ?3
+             ORDER BY fetched_at ASC",
+        )?;
+        let snapshots = stmt
+            .query_map(params![channel, id, until], |row| {
+                Ok(MetricsSnapshot {
+                    fetched_at: row.get(0)?,
+                    views: row.get(1)?,
+                    forwards: row.get(2)?,
+                    replies: row.get(3)?,
+                    reactions: row.get(4)?,
+                })
+            })?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+
+        Ok(Some((date, snapshots)))
+    }
+
     /// Touch fetched_at for all cached posts in a range so they're no longer stale.
     /// Covers posts that exist in cache but were not returned by the API (e.g. deleted).
     pub fn touch_posts_in_range(&self, channel: &str, from_date: i64, to_date: i64) -> Result<()> {
         let conn = self.conn.lock().map_err(|e| format!("Lock error: {}", e))?;
+        let channel = &channel_key(&conn, channel)?;
         let now = chrono::Utc::now().timestamp();
         let updated = conn.execute(
             "UPDATE posts SET fetched_at = ?1 WHERE channel = ?2 AND date >= ?3 AND date <= ?4",
@@ -355,6 +1003,7 @@ impl PostCache {
 
     pub fn update_fetch_bounds(&self, channel: &str, from_date: i64, to_date: i64) -> Result<()> {
         let conn = self.conn.lock().map_err(|e| format!("Lock error: {}", e))?;
+        let channel = &channel_key(&conn, channel)?;
         conn.execute(
             "INSERT INTO channel_fetch_bounds (channel, min_fetched_date, max_fetched_date)
              VALUES (?1, ?2, ?3)
@@ -376,6 +1025,7 @@ impl PostCache {
     /// Returns (file_path, mime_type, file_size) if cached.
     pub fn get_cached_media(&self, channel: &str, msg_id: i32) -> Result<Option<(PathBuf, String, i64)>> {
         let conn = self.conn.lock().map_err(|e| format!("Lock error: {}", e))?;
+        let channel = &channel_key(&conn, channel)?;
         let now = chrono::Utc::now().timestamp();
 
         let result: std::result::Result<(String, i64, i64), _> = conn.query_row(
@@ -420,6 +1070,7 @@ impl PostCache {
         std::fs::write(&path, data)?;
 
         let conn = self.conn.lock().map_err(|e| format!("Lock error: {}", e))?;
+        let channel = &channel_key(&conn, channel)?;
         let now = chrono::Utc::now().timestamp();
 
         conn.execute(
@@ -435,19 +1086,34 @@ impl PostCache {
         Ok(())
     }
 
-    fn evict_media_locked(&self, conn: &Connection) -> Result<()> {
+    /// Count and total size of the media cache
+    pub fn media_usage(&self) -> Result<ArtifactUsage> {
+        let conn = self.conn.lock().map_err(|e| format!("Lock error: {}", e))?;
+        let usage = conn.query_row("SELECT COUNT(*), COALESCE(SUM(size), 0) FROM media_cache", [], |row| {
+            Ok(ArtifactUsage { files: row.get(0)?, bytes: row.get(1)? })
+        })?;
+        Ok(usage)
+    }
+
+    /// Bring the media cache within its limit now, e.g. after `cache_limit_mb` was lowered
+    pub fn evict_media(&self) -> Result<ArtifactUsage> {
+        let conn = self.conn.lock().map_err(|e| format!("Lock error: {}", e))?;
+        self.evict_media_locked(&conn)
+    }
+
+    fn evict_media_locked(&self, conn: &Connection) -> Result<ArtifactUsage> {
         let total_size: i64 = conn.query_row(
             "SELECT COALESCE(SUM(size), 0) FROM media_cache",
             [],
             |row| row.get(0),
         )?;
 
+        let mut freed = ArtifactUsage::default();
         if total_size <= self.cache_limit_bytes {
-            return Ok(());
+            return Ok(freed);
         }
 
         let to_free = total_size - self.cache_limit_bytes;
-        let mut freed: i64 = 0;
 
         let mut stmt = conn.prepare(
             "SELECT channel, msg_id, size, media_id, mime FROM media_cache ORDER BY last_accessed ASC",
@@ -457,7 +1123,7 @@ impl PostCache {
             .collect::<std::result::Result<Vec<_>, _>>()?;
 
         for (ch, mid, sz, media_id, mime) in entries {
-            if freed >= to_free {
+            if freed.bytes >= to_free {
                 break;
             }
             let path = self.media_path(media_id, &mime);
@@ -466,11 +1132,591 @@ impl PostCache {
                 "DELETE FROM media_cache WHERE channel = ?1 AND msg_id = ?2",
                 params![ch, mid],
             )?;
-            freed += sz;
+            freed.files += 1;
+            freed.bytes += sz;
             log::debug!("Media evicted: {}/{} ({} bytes)", ch, mid, sz);
         }
 
-        log::info!("Media cache eviction: freed {} bytes", freed);
+        log::info!("Media cache eviction: freed {} bytes", freed.bytes);
+        Ok(freed)
+    }
+
+    // ── Output artifacts ───────────────────────────────────────────────
+
+    /// Track a file written to the output directory, replacing an earlier entry for the same path.
+    pub fn register_artifact(&self, path: &Path, kind: ArtifactKind) -> Result<()> {
+        let size = std::fs::metadata(path)?.len() as i64;
+        let conn = self.conn.lock().map_err(|e| format!("Lock error: {}", e))?;
+        let now = chrono::Utc::now().timestamp();
+        conn.execute(
+            "INSERT OR REPLACE INTO output_artifacts (path, kind, size, created_at, last_accessed)
+             VALUES (?1, ?2, ?3, ?4, ?4)",
+            params![path.to_string_lossy(), kind.as_str(), size, now],
+        )?;
+        Ok(())
+    }
+
+    /// LRU touch of a tracked file; untracked paths are ignored.
+    pub fn touch_artifact(&self, path: &Path) -> Result<()> {
+        let conn = self.conn.lock().map_err(|e| format!("Lock error: {}", e))?;
+        let now = chrono::Utc::now().timestamp();
+        conn.execute(
+            "UPDATE output_artifacts SET last_accessed = ?1 WHERE path = ?2",
+            params![now, path.to_string_lossy()],
+        )?;
         Ok(())
     }
+
+    pub fn artifact_usage(&self) -> Result<HashMap<String, ArtifactUsage>> {
+        let conn = self.conn.lock().map_err(|e| format!("Lock error: {}", e))?;
+        let mut stmt = conn.prepare("SELECT kind, COUNT(*), COALESCE(SUM(size), 0) FROM output_artifacts GROUP BY kind")?;
+        let usage = stmt
+            .query_map([], |row| {
+                Ok((row.get::<_, String>(0)?, ArtifactUsage { files: row.get(1)?, bytes: row.get(2)? }))
+            })?
+            .collect::<std::result::Result<HashMap<_, _>, _>>()?;
+        Ok(usage)
+    }
+
+    /// Delete tracked files not used for `max_age` seconds, then the least recently used ones until
+    /// they fit in `limit_bytes`. `keep` is never deleted. Entries whose file is gone are dropped.
+    pub fn evict_artifacts(&self, limit_bytes: i64, max_age: Option<i64>, keep: Option<&Path>) -> Result<ArtifactUsage> {
+        let conn = self.conn.lock().map_err(|e| format!("Lock error: {}", e))?;
+        let now = chrono::Utc::now().timestamp();
+        let keep = keep.map(|path| path.to_string_lossy().into_owned());
+
+        let mut stmt = conn.prepare("SELECT path, size, last_accessed FROM output_artifacts ORDER BY last_accessed ASC")?;
+        let entries: Vec<(String, i64, i64)> = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+
+        let mut total: i64 = entries.iter().map(|entry| entry.1).sum();
+        let mut freed = ArtifactUsage::default();
+        for (path, size, last_accessed) in entries {
+            let missing = !Path::new(&path).exists();
+            let expired = max_age.is_some_and(|max_age| last_accessed < now - max_age);
+            if !missing && (keep.as_ref() == Some(&path) || (!expired && total <= limit_bytes)) {
+                continue;
+            }
+            if !missing {
+                match std::fs::remove_file(&path) {
+                    Ok(()) => {
+                        freed.files += 1;
+                        freed.bytes += size;
+                        log::debug!("Output evicted: {} ({} bytes)", path, size);
+                    }
+                    // Gone since the check, there is nothing left to free
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                    // Still tracked, the next sweep tries again
+                    Err(e) => {
+                        log::warn!("Can't evict output {}: {}", path, e);
+                        continue;
+                    }
+                }
+            }
+            conn.execute("DELETE FROM output_artifacts WHERE path = ?1", params![path])?;
+            total -= size;
+        }
+
+        if freed.files > 0 {
+            log::info!("Output eviction: freed {} files, {} bytes", freed.files, freed.bytes);
+        }
+        Ok(freed)
+    }
+
+    /// Track files of `dir` written before the registry existed: `<hash>.<ext>` videos, `<channel>.png`
+    /// avatars and `<photo_id>.jpg` photos. Files of the media cache are left to it.
+    pub fn adopt_artifacts(&self, dir: &Path) -> Result<usize> {
+        let media: std::collections::HashSet<PathBuf> = {
+            let conn = self.conn.lock().map_err(|e| format!("Lock error: {}", e))?;
+            let mut stmt = conn.prepare("SELECT media_id, mime FROM media_cache")?;
+            let rows = stmt
+                .query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)))?
+                .collect::<std::result::Result<Vec<_>, _>>()?;
+            rows.into_iter().map(|(media_id, mime)| self.media_path(media_id, &mime)).collect()
+        };
+        let tracked: std::collections::HashSet<String> = {
+            let conn = self.conn.lock().map_err(|e| format!("Lock error: {}", e))?;
+            let mut stmt = conn.prepare("SELECT path FROM output_artifacts")?;
+            stmt.query_map([], |row| row.get(0))?
+                .collect::<std::result::Result<_, _>>()?
+        };
+
+        let mut adopted = 0;
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if !path.is_file() || media.contains(&path) || tracked.contains(path.to_string_lossy().as_ref()) {
+                continue;
+            }
+            let stem = path.file_stem().and_then(|stem| stem.to_str()).unwrap_or_default();
+            let numeric = !stem.is_empty() && stem.bytes().all(|b| b.is_ascii_digit());
+            let kind = match path.extension().and_then(|ext| ext.to_str()) {
+                Some("mp4" | "webm" | "gif" | "webp") if numeric => ArtifactKind::Video,
+                Some("jpg") if numeric => ArtifactKind::Photo,
+                Some("png") if !numeric => ArtifactKind::Avatar,
+                _ => continue,
+            };
+            self.register_artifact(&path, kind)?;
+            adopted += 1;
+        }
+        Ok(adopted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHANNEL: &str = "test_channel";
+    const HOUR: i64 = 3600;
+
+    struct TempCache {
+        cache: PostCache,
+        dir: PathBuf,
+    }
+
+    impl Drop for TempCache {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    fn temp_cache() -> TempCache {
+        let dir = std::env::temp_dir().join(format!("tgdigest-cache-{}", uuid::Uuid::new_v4().as_simple()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let cache = PostCache::new(&dir.join("cache.db"), &dir.join("media"), 16).unwrap();
+        TempCache { cache, dir }
+    }
+
+    fn post(id: i32, date: i64) -> Post {
+        Post {
+            date,
+            id,
+            views: Some(100 * id),
+            forwards: Some(id),
+            replies: Some(id),
+            reactions: Some(id),
+            message: Some(format!("Post {}", id)),
+            image: None,
+            grouped_id: None,
+            velocity: None,
+            rates: None,
+            reaction_counts: Vec::new(),
+            selected_reaction: None,
+            top_comment: None,
+        }
+    }
+
+    /// `count` posts `step` seconds apart, the newest published at `newest`
+    fn store(cache: &PostCache, count: i32, newest: i64, step: i64) -> Vec<Post> {
+        let posts: Vec<Post> = (1..=count)
+            .map(|id| post(id, newest - (count - id) as i64 * step))
+            .collect();
+        cache.store_posts(CHANNEL, &posts).unwrap();
+        posts
+    }
+
+    fn set_fetched_at(cache: &PostCache, fetched_at: i64, max_date: i64) {
+        let conn = cache.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE posts SET fetched_at = ?1 WHERE channel = ?2 AND date <= ?3",
+            params![fetched_at, CHANNEL, max_date],
+        )
+        .unwrap();
+    }
+
+    /// Move every history snapshot `secs` into the past, so the next store takes another one
+    fn age_snapshots(cache: &PostCache, secs: i64) {
+        let conn = cache.conn.lock().unwrap();
+        conn.execute("UPDATE post_metrics_history SET fetched_at = fetched_at - ?1", params![secs])
+            .unwrap();
+    }
+
+    fn now() -> i64 {
+        chrono::Utc::now().timestamp()
+    }
+
+    #[test]
+    fn force_ignores_cache() {
+        let t = temp_cache();
+        let to = now() - 10 * DAY;
+        let from = to - 30 * DAY;
+        store(&t.cache, 10, to, 3600);
+
+        let (posts, plan) = t.cache.get_posts_and_fetch_plan(CHANNEL, from, to, None, true).unwrap();
+        assert!(posts.is_empty());
+        assert_eq!(plan.ranges, vec![(from, to, MAX_FETCH_PER_REQUEST)]);
+
+        let (_, plan) = t.cache.get_posts_and_fetch_plan(CHANNEL, from, to, Some(5000), true).unwrap();
+        assert_eq!(plan.ranges, vec![(from, to, 5000)]);
+    }
+
+    #[test]
+    fn empty_cache_fetches_head() {
+        let t = temp_cache();
+        let to = now();
+        let from = to - 30 * DAY;
+
+        let (posts, plan) = t.cache.get_posts_and_fetch_plan(CHANNEL, from, to, None, false).unwrap();
+        assert!(posts.is_empty());
+        assert_eq!(plan.ranges, vec![(from, to, ALWAYS_REFRESH_HEAD)]);
+
+        let (_, plan) = t.cache.get_posts_and_fetch_plan(CHANNEL, from, to, Some(3000), false).unwrap();
+        assert_eq!(plan.ranges, vec![(from, to, 3000)]);
+    }
+
+    #[test]
+    fn fresh_cache_needs_no_fetch() {
+        let t = temp_cache();
+        let to = now() - 10 * DAY;
+        let from = to - 30 * DAY;
+        store(&t.cache, 10, to, 3600);
+        t.cache.update_fetch_bounds(CHANNEL, from, to).unwrap();
+
+        let (posts, plan) = t.cache.get_posts_and_fetch_plan(CHANNEL, from, to, None, false).unwrap();
+        assert_eq!(posts.len(), 10);
+        assert!(plan.is_empty());
+    }
+
+    #[test]
+    fn head_is_refreshed_after_a_minute() {
+        let t = temp_cache();
+        let to = now() - 10 * DAY;
+        let from = to - 30 * DAY;
+        store(&t.cache, 10, to, 3600);
+        t.cache.update_fetch_bounds(CHANNEL, from, to).unwrap();
+
+        set_fetched_at(&t.cache, now() - 30, to);
+        let (_, plan) = t.cache.get_posts_and_fetch_plan(CHANNEL, from, to, None, false).unwrap();
+        assert!(plan.is_empty());
+
+        set_fetched_at(&t.cache, now() - 120, to);
+        let (_, plan) = t.cache.get_posts_and_fetch_plan(CHANNEL, from, to, None, false).unwrap();
+        assert_eq!(plan.ranges, vec![(from, to, ALWAYS_REFRESH_HEAD)]);
+    }
+
+    #[test]
+    fn head_refresh_only_looks_at_newest_posts() {
+        let t = temp_cache();
+        let to = now() - 10 * DAY;
+        let from = to - 30 * DAY;
+        let posts = store(&t.cache, ALWAYS_REFRESH_HEAD as i32 + 50, to, 3600);
+        t.cache.update_fetch_bounds(CHANNEL, from, to).unwrap();
+
+        // Posts older than the newest 200 and older than a week are never stale
+        set_fetched_at(&t.cache, now() - 30 * DAY, posts[49].date);
+        let (_, plan) = t.cache.get_posts_and_fetch_plan(CHANNEL, from, to, None, false).unwrap();
+        assert!(plan.is_empty());
+    }
+
+    #[test]
+    fn weekly_ttl_refetches_recent_posts() {
+        let t = temp_cache();
+        let to = now();
+        let from = to - 30 * DAY;
+        // 250 posts half an hour apart span ~5 days; the newest 200 stay fresh
+        let posts = store(&t.cache, ALWAYS_REFRESH_HEAD as i32 + 50, to - 60, 1800);
+        t.cache.update_fetch_bounds(CHANNEL, from, to).unwrap();
+
+        let stale_date = posts[49].date;
+        assert!(to - stale_date < WEEK);
+        set_fetched_at(&t.cache, now() - 2 * DAY, stale_date);
+
+        let (_, plan) = t.cache.get_posts_and_fetch_plan(CHANNEL, from, to, None, false).unwrap();
+        assert_eq!(plan.ranges.len(), 1);
+        let (weekly_from, weekly_to, limit) = plan.ranges[0];
+        assert!((weekly_from - (now() - WEEK)).abs() <= 5);
+        assert_eq!(weekly_to, to);
+        assert_eq!(limit, MAX_FETCH_PER_REQUEST);
+
+        // Less than a day since the last fetch
+        set_fetched_at(&t.cache, now() - DAY + 60, stale_date);
+        let (_, plan) = t.cache.get_posts_and_fetch_plan(CHANNEL, from, to, None, false).unwrap();
+        assert!(plan.is_empty());
+    }
+
+    #[test]
+    fn force_limit_walks_back_from_oldest_cached_post() {
+        let t = temp_cache();
+        let to = now() - 10 * DAY;
+        let from = to - 30 * DAY;
+        let posts = store(&t.cache, 10, to, 3600);
+        t.cache.update_fetch_bounds(CHANNEL, from, to).unwrap();
+
+        let (_, plan) = t.cache.get_posts_and_fetch_plan(CHANNEL, from, to, Some(500), false).unwrap();
+        assert_eq!(plan.ranges, vec![(from, posts[0].date, 500)]);
+        assert_eq!(plan.total_limit(), 500);
+    }
+
+    #[test]
+    fn identified_channel_keeps_its_posts() {
+        let t = temp_cache();
+        let to = now() - 10 * DAY;
+        let from = to - 30 * DAY;
+        store(&t.cache, 10, to, 3600);
+        t.cache.update_fetch_bounds(CHANNEL, from, to).unwrap();
+        assert!(!t.cache.is_identified(CHANNEL).unwrap());
+
+        t.cache.identify_channel(CHANNEL, 42).unwrap();
+        assert!(t.cache.is_identified(CHANNEL).unwrap());
+        assert_eq!(t.cache.channel_key(CHANNEL).unwrap(), "-10042");
+        for channel in [CHANNEL, "-10042"] {
+            let (posts, plan) = t.cache.get_posts_and_fetch_plan(channel, from, to, None, false).unwrap();
+            assert_eq!(posts.len(), 10);
+            assert!(plan.is_empty());
+        }
+    }
+
+    #[test]
+    fn identified_channel_keeps_posts_cached_in_another_case() {
+        let t = temp_cache();
+        let date = now() - HOUR;
+        t.cache.store_posts("Test_Channel", &[post(1, date)]).unwrap();
+        t.cache.store_posts(CHANNEL, &[post(2, date)]).unwrap();
+        std::fs::write(t.cache.media_dir().join("thumb_TEST_CHANNEL_1.jpg"), b"thumb").unwrap();
+
+        t.cache.identify_channel(CHANNEL, 42).unwrap();
+        let (posts, _) = t.cache.get_posts_and_fetch_plan("-10042", date, date, None, false).unwrap();
+        let ids: Vec<i32> = posts.iter().map(|p| p.id).collect();
+        assert_eq!(ids, vec![1, 2]);
+        assert!(t.cache.media_dir().join("thumb_-10042_1.jpg").exists());
+    }
+
+    #[test]
+    fn renamed_channel_keeps_its_posts_and_thumbs() {
+        let t = temp_cache();
+        let to = now() - 10 * DAY;
+        let from = to - 30 * DAY;
+        store(&t.cache, 10, to, 3600);
+        let thumb = |name: &str| t.cache.media_dir().join(format!("thumb_{}_3.jpg", name));
+        std::fs::write(thumb(CHANNEL), b"thumb").unwrap();
+
+        let info = |username: &str| ChannelInfo {
+            id: Some(42),
+            username: Some(username.to_string()),
+            title: "Test".to_string(),
+            photo_id: None,
+        };
+        t.cache.record_channel(CHANNEL, &info(CHANNEL)).unwrap();
+        assert!(thumb("-10042").exists());
+        assert!(!thumb(CHANNEL).exists());
+        assert_eq!(t.cache.renamed_channel(CHANNEL).unwrap(), None);
+
+        t.cache.record_channel("renamed", &info("renamed")).unwrap();
+        assert_eq!(t.cache.renamed_channel(CHANNEL).unwrap().as_deref(), Some("renamed"));
+        assert_eq!(t.cache.renamed_channel("renamed").unwrap(), None);
+        assert_eq!(t.cache.channel(CHANNEL).unwrap().unwrap().username.as_deref(), Some("renamed"));
+        assert_eq!(t.cache.count_cached_posts("renamed", from, to).unwrap(), 10);
+    }
+
+    #[test]
+    fn history_keeps_a_snapshot_per_change() {
+        let t = temp_cache();
+        let date = now() - 10 * HOUR;
+        let mut first = post(1, date);
+        t.cache.store_posts(CHANNEL, &[first.clone()]).unwrap();
+        age_snapshots(&t.cache, 8 * HOUR);
+        first.views = Some(1000);
+        t.cache.store_posts(CHANNEL, &[first.clone()]).unwrap();
+        // Unchanged counters take no snapshot
+        age_snapshots(&t.cache, HOUR);
+        t.cache.store_posts(CHANNEL, &[first]).unwrap();
+
+        let (published, snapshots) = t.cache.get_post_history(CHANNEL, 1, None).unwrap().unwrap();
+        assert_eq!(published, date);
+        let views: Vec<Option<i32>> = snapshots.iter().map(|s| s.views).collect();
+        assert_eq!(views, vec![Some(100), Some(1000)]);
+        assert!(snapshots[0].fetched_at < snapshots[1].fetched_at);
+
+        let (_, snapshots) = t.cache.get_post_history(CHANNEL, 1, Some(4 * HOUR)).unwrap().unwrap();
+        assert_eq!(snapshots.len(), 1);
+        assert!(t.cache.get_post_history(CHANNEL, 2, None).unwrap().is_none());
+    }
+
+    #[test]
+    fn velocity_uses_the_last_snapshot_in_the_window() {
+        let t = temp_cache();
+        let date = now() - 10 * HOUR;
+        let mut first = post(1, date);
+        t.cache.store_posts(CHANNEL, &[first.clone()]).unwrap();
+        // Taken 2 hours after publishing, the second one 10 hours after
+        age_snapshots(&t.cache, 8 * HOUR);
+        first.views = Some(1000);
+        t.cache.store_posts(CHANNEL, &[first]).unwrap();
+
+        let velocities = t.cache.get_velocities(CHANNEL, date, date, 4 * HOUR).unwrap();
+        assert_eq!(velocities[&1].views, 50);
+        let velocities = t.cache.get_velocities(CHANNEL, date, date, 24 * HOUR).unwrap();
+        assert_eq!(velocities[&1].views, 100);
+        // Past the window only the first snapshot is there to measure
+        let velocities = t.cache.get_velocities(CHANNEL, date, date, HOUR).unwrap();
+        assert_eq!(velocities[&1].views, 50);
+        assert!(t.cache.get_velocities(CHANNEL, date + 1, now(), HOUR).unwrap().is_empty());
+    }
+
+    #[test]
+    fn reaction_counts_are_kept_per_kind() {
+        let t = temp_cache();
+        let date = now() - HOUR;
+        let reactions = |counts: &[(&str, i32)]| {
+            counts.iter().map(|(key, count)| ReactionData::from_key(key, *count)).collect::<Vec<_>>()
+        };
+        let mut posts = vec![post(1, date), post(2, date), post(3, date)];
+        posts[0].reaction_counts = reactions(&[("🔥", 5), ("custom:42", 2), ("paid", 7)]);
+        posts[1].reaction_counts = reactions(&[("🔥", 9), ("👍", 1)]);
+        posts[2].reaction_counts = reactions(&[("custom:42", 3)]);
+        t.cache.store_posts(CHANNEL, &posts).unwrap();
+
+        let counts = |reaction: &str| t.cache.get_reaction_counts(CHANNEL, date, date, reaction).unwrap();
+        assert_eq!(counts("🔥"), HashMap::from([(1, 5), (2, 9)]));
+        assert_eq!(counts("custom:42"), HashMap::from([(1, 2), (3, 3)]));
+        assert_eq!(counts("paid"), HashMap::from([(1, 7)]));
+        assert!(counts("custom:43").is_empty());
+
+        // A refetch replaces the breakdown of the post
+        posts[0].reaction_counts = reactions(&[("🔥", 6)]);
+        t.cache.store_posts(CHANNEL, &posts[..1]).unwrap();
+        assert_eq!(counts("🔥"), HashMap::from([(1, 6), (2, 9)]));
+        assert!(counts("paid").is_empty());
+        assert!(t.cache.get_reaction_counts(CHANNEL, date + 1, now(), "🔥").unwrap().is_empty());
+
+        // Storing posts loaded back from the cache keeps their breakdown
+        let (cached, _) = t.cache.get_posts_and_fetch_plan(CHANNEL, date, date, None, false).unwrap();
+        assert_eq!(cached.len(), 3);
+        t.cache.store_posts(CHANNEL, &cached).unwrap();
+        assert_eq!(counts("custom:42"), HashMap::from([(1, 2), (3, 3)]));
+        // Until a post has no reactions left
+        posts[1].reactions = Some(0);
+        posts[1].reaction_counts.clear();
+        t.cache.store_posts(CHANNEL, &posts[1..2]).unwrap();
+        assert_eq!(counts("🔥"), HashMap::from([(1, 6)]));
+    }
+
+    #[test]
+    fn top_comment_is_the_most_reacted_one() {
+        let t = temp_cache();
+        let to = now() - 10 * DAY;
+        let from = to - 30 * DAY;
+        store(&t.cache, 3, to, 3600);
+        let comment = |id: i32, reactions: i32| CommentData {
+            id,
+            date: to,
+            author: Some(format!("User {}", id)),
+            text: format!("Comment {}", id),
+            reactions: Some(reactions),
+            reaction_counts: vec![ReactionData::from_key("🔥", reactions)],
+        };
+        assert!(t.cache.get_comments(CHANNEL, 3, None).unwrap().is_none());
+
+        t.cache.store_comments(CHANNEL, 3, &[comment(10, 2), comment(11, 7), comment(12, 7)]).unwrap();
+        t.cache.store_comments(CHANNEL, 2, &[]).unwrap();
+        let comments = t.cache.get_comments(CHANNEL, 3, Some(DAY)).unwrap().unwrap();
+        assert_eq!(comments.iter().map(|c| c.id).collect::<Vec<_>>(), vec![12, 11, 10]);
+        assert_eq!(comments[0].reaction_counts[0].key(), "🔥");
+        assert!(t.cache.get_comments(CHANNEL, 2, None).unwrap().unwrap().is_empty());
+
+        let top = t.cache.get_top_comments(CHANNEL, from, to).unwrap();
+        assert_eq!(top.len(), 1);
+        assert_eq!(top[&3].id, 11);
+        assert_eq!(top[&3].reaction_counts[0].count, 7);
+    }
+
+    #[test]
+    fn artifacts_expire_then_leave_by_lru() {
+        let t = temp_cache();
+        let out = t.dir.join("out");
+        std::fs::create_dir_all(&out).unwrap();
+        let file = |name: &str, size: usize| {
+            let path = out.join(name);
+            std::fs::write(&path, vec![0u8; size]).unwrap();
+            path
+        };
+        let old = file("00000000000000000001.mp4", 100);
+        let used = file("00000000000000000002.mp4", 100);
+        let fresh = file("00000000000000000003.webm", 100);
+        let avatar = file("channel.png", 10);
+        for path in [&old, &used, &fresh] {
+            t.cache.register_artifact(path, ArtifactKind::Video).unwrap();
+        }
+        t.cache.register_artifact(&avatar, ArtifactKind::Avatar).unwrap();
+        {
+            let conn = t.cache.conn.lock().unwrap();
+            conn.execute("UPDATE output_artifacts SET last_accessed = ?1", params![now() - 10 * DAY]).unwrap();
+            conn.execute("UPDATE output_artifacts SET last_accessed = ?1 WHERE path = ?2", params![now() - DAY, fresh.to_string_lossy()]).unwrap();
+        }
+        t.cache.touch_artifact(&used).unwrap();
+        let usage = t.cache.artifact_usage().unwrap();
+        assert_eq!(usage["video"], ArtifactUsage { files: 3, bytes: 300 });
+
+        // Not used for a week: the old video and the avatar
+        let freed = t.cache.evict_artifacts(1000, Some(WEEK), None).unwrap();
+        assert_eq!(freed, ArtifactUsage { files: 2, bytes: 110 });
+        assert!(!old.exists() && !avatar.exists());
+
+        // Over quota: least recently used first, never the kept file
+        let freed = t.cache.evict_artifacts(150, Some(WEEK), Some(&fresh)).unwrap();
+        assert_eq!(freed.files, 1);
+        assert!(fresh.exists() && !used.exists());
+
+        // Files deleted behind the registry's back are forgotten
+        std::fs::remove_file(&fresh).unwrap();
+        assert_eq!(t.cache.evict_artifacts(1000, None, None).unwrap().files, 0);
+        assert!(t.cache.artifact_usage().unwrap().is_empty());
+    }
+
+    #[test]
+    fn media_evicts_to_a_lowered_limit() {
+        let mut t = temp_cache();
+        for id in 1..=3 {
+            t.cache.store_cached_media(CHANNEL, id, 40 + id as i64, "image/jpeg", &[0; 100]).unwrap();
+        }
+        assert_eq!(t.cache.media_usage().unwrap(), ArtifactUsage { files: 3, bytes: 300 });
+        assert_eq!(t.cache.evict_media().unwrap(), ArtifactUsage::default());
+
+        t.cache.cache_limit_bytes = 150;
+        assert_eq!(t.cache.evict_media().unwrap(), ArtifactUsage { files: 2, bytes: 200 });
+        assert_eq!(t.cache.media_usage().unwrap(), ArtifactUsage { files: 1, bytes: 100 });
+    }
+
+    #[test]
+    fn untracked_outputs_are_adopted() {
+        let t = temp_cache();
+        let out = t.cache.media_dir().to_path_buf();
+        t.cache.store_cached_media(CHANNEL, 1, 42, "video/mp4", b"cached").unwrap();
+        for name in ["00000000000000000001.mp4", "123.jpg", "ithueti.png", "notes.txt", "42.mp4"] {
+            std::fs::write(out.join(name), b"file").unwrap();
+        }
+        std::fs::create_dir_all(out.join("00000000000000000001")).unwrap();
+
+        assert_eq!(t.cache.adopt_artifacts(&out).unwrap(), 3, "the media cache keeps 42.mp4");
+        let usage = t.cache.artifact_usage().unwrap();
+        assert_eq!(usage["video"].files, 1);
+        assert_eq!(usage["photo"].files, 1);
+        assert_eq!(usage["avatar"].files, 1);
+        assert_eq!(t.cache.adopt_artifacts(&out).unwrap(), 0);
+    }
+
+    #[test]
+    fn uncovered_edges_are_fetched() {
+        let t = temp_cache();
+        let to = now() - 10 * DAY;
+        let from = to - 30 * DAY;
+        store(&t.cache, 10, to - 2 * DAY, 3600);
+        let (min_fetched, max_fetched) = (from + DAY, to - DAY);
+        t.cache.update_fetch_bounds(CHANNEL, min_fetched, max_fetched).unwrap();
+
+        let (_, plan) = t.cache.get_posts_and_fetch_plan(CHANNEL, from, to, None, false).unwrap();
+        assert_eq!(
+            plan.ranges,
+            vec![
+                (from, min_fetched, ALWAYS_REFRESH_HEAD),
+                (max_fetched, to, ALWAYS_REFRESH_HEAD),
+            ]
+        );
+
+        // Edges are not checked while other ranges are pending
+        set_fetched_at(&t.cache, now() - 120, to);
+        let (_, plan) = t.cache.get_posts_and_fetch_plan(CHANNEL, from, to, None, false).unwrap();
+        assert_eq!(plan.ranges, vec![(from, to, ALWAYS_REFRESH_HEAD)]);
+    }
 }
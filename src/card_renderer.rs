@@ -1,20 +1,137 @@
 use crate::util::*;
 
 use chromiumoxide::browser::{Browser, BrowserConfig};
+use chromiumoxide::cdp::browser_protocol::emulation::SetDeviceMetricsOverrideParams;
 use chromiumoxide::cdp::browser_protocol::page::CaptureScreenshotFormat;
+use chromiumoxide::element::Element;
+use chromiumoxide::error::CdpError;
 use chromiumoxide::handler::viewport::Viewport;
 use futures_util::StreamExt;
-use std::path::Path;
-use tokio::sync::{Mutex, Semaphore};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::{Mutex, RwLock, Semaphore};
+use tokio::task::JoinHandle;
 
-pub struct CardRenderer {
+/// Height of the page in device pixels, tall enough for every lazy iframe to load at once
+const PAGE_PIXEL_HEIGHT: u32 = 30000;
+
+/// Room for the default body margin next to a card
+const PAGE_MARGIN: u32 = 16;
+
+/// Time the browser gets to answer a health probe
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Size of the browser pool and the limits of a single render
+#[derive(Copy, Clone, Debug)]
+pub struct RendererSettings {
+    /// Pages rendering at once
+    pub pool_size: usize,
+    /// Renders a pooled page does before it is closed for a fresh one
+    pub page_uses: usize,
+    /// Whole render of a page, from opening a tab to the last screenshot
+    pub render_timeout: Duration,
+    /// Wait for the template to set `window.__READY`
+    pub ready_timeout: Duration,
+    /// Port of the local server the render pages load their iframes from
+    pub port: u16,
+}
+
+/// State of the browser reported by `/stats/renderer`
+#[derive(Debug, serde::Serialize)]
+pub struct RendererHealth {
+    /// `ok`, `unresponsive` when the browser does not answer the probe, `down` when it is not running.
+    /// The next render relaunches a browser that is not `ok`.
+    pub status: &'static str,
+    pub browser_version: Option<String>,
+    pub probe_ms: Option<u64>,
+    pub pool_size: usize,
+    pub renders_active: usize,
+    pub pages_idle: usize,
+    pub renders: usize,
+    pub failures: usize,
+    pub timeouts: usize,
+    pub restarts: usize,
+    pub last_error: Option<String>,
+}
+
+/// A running browser and the task polling its connection
+struct Session {
     browser: Browser,
-    render_pages: Mutex<Vec<chromiumoxide::Page>>,
+    handler: JoinHandle<()>,
+    /// Cleared once the connection to the browser is gone, the next render relaunches it
+    alive: Arc<AtomicBool>,
+    generation: u64,
+}
+
+/// A page of the pool and the renders it has done
+struct PooledPage {
+    page: chromiumoxide::Page,
+    uses: usize,
+    generation: u64,
+}
+
+#[derive(Default)]
+struct RenderCounters {
+    renders: AtomicUsize,
+    failures: AtomicUsize,
+    timeouts: AtomicUsize,
+    restarts: AtomicUsize,
+}
+
+/// Size of one card in CSS pixels and the device scale it is captured at
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct CardViewport {
+    pub width: u32,
+    pub height: u32,
+    pub scale: f64,
+}
+
+/// A card screenshot and the post it shows, if the template marks the card `data-post-id`
+#[derive(Clone, Debug)]
+pub struct RenderedCard {
+    pub image: PathBuf,
+    pub post_id: Option<i32>,
+    /// Area of the card's `data-media-slot` element, or the whole card, in image pixels as (x, y, width, height)
+    pub media_slot: (u32, u32, u32, u32),
+}
+
+/// Cards screenshotted so far out of the cards found on the page
+#[derive(Default)]
+pub struct CardProgress {
+    pub done: AtomicUsize,
+    pub total: AtomicUsize,
+}
+
+pub struct CardRenderer {
+    settings: RendererSettings,
+    session: RwLock<Option<Session>>,
+    generation: AtomicU64,
+    render_pages: Mutex<Vec<PooledPage>>,
     page_pool_permits: Semaphore,
+    counters: RenderCounters,
+    last_error: std::sync::Mutex<Option<String>>,
+    /// Set by `close`, the browser is not relaunched after it
+    closed: AtomicBool,
 }
 
 impl CardRenderer {
-    pub async fn new() -> Result<CardRenderer> {
+    pub async fn new(settings: RendererSettings) -> Result<CardRenderer> {
+        let session = Self::launch(0).await?;
+        Ok(CardRenderer {
+            settings,
+            session: RwLock::new(Some(session)),
+            generation: AtomicU64::new(0),
+            render_pages: Mutex::new(Vec::new()),
+            page_pool_permits: Semaphore::new(settings.pool_size.max(1)),
+            counters: RenderCounters::default(),
+            last_error: std::sync::Mutex::new(None),
+            closed: AtomicBool::new(false),
+        })
+    }
+
+    async fn launch(generation: u64) -> Result<Session> {
         let viewport = Viewport {
             width: 2000,
             height: 30000,
@@ -37,95 +154,388 @@ impl CardRenderer {
         .await?;
 
         // spawn a new task that continuously polls the handler
-        tokio::task::spawn(async move {
+        let alive = Arc::new(AtomicBool::new(true));
+        let handler_alive = alive.clone();
+        let handler = tokio::task::spawn(async move {
             while let Some(h) = handler.next().await {
-                if let Err(e) = h {
-                    log::warn!("Browser handler error: {e:?}");
-                    break;
+                match h {
+                    Err(e @ (CdpError::Ws(_) | CdpError::Io(_) | CdpError::ChannelSendError(_))) => {
+                        log::warn!("Browser connection lost: {e:?}");
+                        break;
+                    }
+                    Err(e) => log::warn!("Browser handler error: {e:?}"),
+                    Ok(()) => {}
                 }
             }
+            handler_alive.store(false, Ordering::Relaxed);
         });
 
-        Ok(CardRenderer {
+        Ok(Session {
             browser,
-            render_pages: Mutex::new(Vec::new()),
-            page_pool_permits: Semaphore::new(2),
+            handler,
+            alive,
+            generation,
         })
     }
 
-    async fn render_page(&self, output_dir: &Path, page: &chromiumoxide::Page) -> Result<()> {
+    fn is_alive(session: &Option<Session>) -> bool {
+        session.as_ref().is_some_and(|session| session.alive.load(Ordering::Relaxed))
+    }
+
+    /// Relaunch the browser if it crashed or was found unresponsive
+    async fn ensure_browser(&self) -> Result<()> {
+        if Self::is_alive(&*self.session.read().await) {
+            return Ok(());
+        }
+        let mut session = self.session.write().await;
+        if Self::is_alive(&session) {
+            return Ok(());
+        }
+        if self.closed.load(Ordering::Relaxed) {
+            return Err("The browser is closed".into());
+        }
+        if let Some(mut old) = session.take() {
+            log::warn!("Browser is down, relaunching");
+            old.handler.abort();
+            let _ = old.browser.kill().await;
+        }
+        // Pages of the old browser are gone with it
+        self.render_pages.lock().await.clear();
+
+        let generation = self.generation.fetch_add(1, Ordering::Relaxed) + 1;
+        match Self::launch(generation).await.map_err(|e| e.to_string()) {
+            Ok(new) => {
+                *session = Some(new);
+                self.counters.restarts.fetch_add(1, Ordering::Relaxed);
+                log::info!("Browser relaunched");
+                Ok(())
+            }
+            Err(e) => {
+                let e = format!("Can't relaunch the browser: {}", e);
+                self.set_last_error(&e);
+                Err(e.into())
+            }
+        }
+    }
+
+    /// Ask the browser for its version, a browser that does not answer is relaunched by the next render
+    async fn probe(&self) -> std::result::Result<String, String> {
+        let session = self.session.read().await;
+        let Some(current) = session.as_ref().filter(|session| session.alive.load(Ordering::Relaxed)) else {
+            return Err("Browser is not running".to_string());
+        };
+        match tokio::time::timeout(PROBE_TIMEOUT, current.browser.version()).await {
+            Ok(Ok(version)) => Ok(version.product),
+            Ok(Err(e)) => {
+                current.alive.store(false, Ordering::Relaxed);
+                Err(format!("Browser probe failed: {}", e))
+            }
+            Err(_) => {
+                current.alive.store(false, Ordering::Relaxed);
+                Err(format!("Browser did not answer the probe within {}s", PROBE_TIMEOUT.as_secs()))
+            }
+        }
+    }
+
+    pub fn port(&self) -> u16 {
+        self.settings.port
+    }
+
+    /// Report the browser as it is, a probe never relaunches it
+    pub async fn health(&self) -> RendererHealth {
+        let started_at = Instant::now();
+        let running = Self::is_alive(&*self.session.read().await);
+        let probe = if running { Some(self.probe().await) } else { None };
+        let (status, browser_version, probe_ms) = match probe {
+            Some(Ok(version)) => ("ok", Some(version), Some(started_at.elapsed().as_millis() as u64)),
+            Some(Err(e)) => {
+                self.set_last_error(&e);
+                ("unresponsive", None, None)
+            }
+            None => ("down", None, None),
+        };
+        let pool_size = self.settings.pool_size.max(1);
+        RendererHealth {
+            status,
+            browser_version,
+            probe_ms,
+            pool_size,
+            renders_active: pool_size - self.page_pool_permits.available_permits(),
+            pages_idle: self.render_pages.lock().await.len(),
+            renders: self.counters.renders.load(Ordering::Relaxed),
+            failures: self.counters.failures.load(Ordering::Relaxed),
+            timeouts: self.counters.timeouts.load(Ordering::Relaxed),
+            restarts: self.counters.restarts.load(Ordering::Relaxed),
+            last_error: self.last_error.lock().unwrap().clone(),
+        }
+    }
+
+    fn set_last_error(&self, e: &str) {
+        log::warn!("Card renderer: {}", e);
+        *self.last_error.lock().unwrap() = Some(e.to_string());
+    }
+
+    /// Lay the page out for cards of `viewport`
+    async fn set_viewport(page: &chromiumoxide::Page, viewport: CardViewport) -> Result<()> {
+        let height = (PAGE_PIXEL_HEIGHT as f64 / viewport.scale) as u32;
+        page.execute(SetDeviceMetricsOverrideParams::new(
+            viewport.width + PAGE_MARGIN,
+            height.max(viewport.height + PAGE_MARGIN),
+            viewport.scale,
+            false,
+        ))
+        .await?;
+        Ok(())
+    }
+
+    /// Area of the media slot of `card` relative to the card, scaled to image pixels
+    async fn media_slot(card: &Element, scale: f64) -> Result<(u32, u32, u32, u32)> {
+        let card_box = card.bounding_box().await?;
+        let slot_box = match card.find_element("[data-media-slot]").await {
+            Ok(slot) => slot.bounding_box().await?,
+            Err(_) => card_box.clone(),
+        };
+        let px = |value: f64| (value * scale).round().max(0.0) as u32;
+        // Even sizes, the slot is filled with yuv420p video
+        Ok((
+            px(slot_box.x - card_box.x),
+            px(slot_box.y - card_box.y),
+            px(slot_box.width) & !1,
+            px(slot_box.height) & !1,
+        ))
+    }
+
+    async fn render_page(
+        &self,
+        output_dir: &Path,
+        page: &chromiumoxide::Page,
+        scale: f64,
+        progress: Option<&CardProgress>,
+    ) -> Result<Vec<RenderedCard>> {
         let cards = page.find_elements("div").await?;
+        if let Some(progress) = progress {
+            progress.done.store(0, Ordering::Relaxed);
+            progress.total.store(cards.len(), Ordering::Relaxed);
+        }
 
+        let mut rendered = Vec::with_capacity(cards.len());
         for (i, card) in cards.iter().enumerate() {
             let card_path = output_dir.join(format!("card_{:02}.png", i));
             let _ = card
                 .save_screenshot(CaptureScreenshotFormat::Webp, &card_path)
                 .await?;
             log::debug!("Card rendered: {}", card_path.to_str().unwrap());
+            rendered.push(RenderedCard {
+                image: card_path,
+                post_id: card.attribute("data-post-id").await?.and_then(|id| id.parse().ok()),
+                media_slot: Self::media_slot(card, scale).await?,
+            });
+            if let Some(progress) = progress {
+                progress.done.store(i + 1, Ordering::Relaxed);
+            }
         }
 
-        Ok(())
+        Ok(rendered)
     }
 
-    pub async fn render_url(&self, output_dir: &Path, url: &str) -> Result<()> {
+    /// Render the cards of a page at `url` in a tab of its own, with the same pool permit and
+    /// timeout as `render_html`
+    pub async fn render_url(&self, output_dir: &Path, url: &str, viewport: CardViewport) -> Result<Vec<RenderedCard>> {
         log::trace!("Opening URL for rendering: {url}");
-        let page = self.browser.new_page(url).await?;
-        self.render_page(output_dir, &page).await?;
-        page.close().await?;
-        Ok(())
+        let _permit = self.page_pool_permits.acquire().await?;
+        self.ensure_browser().await?;
+
+        let timeout = self.settings.render_timeout;
+        let deadline = tokio::time::Instant::now() + timeout;
+        let page = tokio::time::timeout_at(deadline, async { self.new_page("about:blank").await.map_err(|e| e.to_string()) });
+        let page = match page.await {
+            Ok(page) => page?.page,
+            Err(_) => return Err(self.timed_out(timeout).await.into()),
+        };
+        let rendered = tokio::time::timeout_at(deadline, async {
+            self.render_url_on(&page, output_dir, url, viewport)
+                .await
+                .map_err(|e| e.to_string())
+        })
+        .await;
+        let closed = tokio::time::timeout(PROBE_TIMEOUT, page.close()).await;
+
+        match rendered {
+            Ok(Ok(cards)) => {
+                self.counters.renders.fetch_add(1, Ordering::Relaxed);
+                if let Ok(Err(e)) = closed {
+                    log::debug!("Can't close a rendered page: {e}");
+                }
+                Ok(cards)
+            }
+            Ok(Err(e)) => {
+                self.counters.failures.fetch_add(1, Ordering::Relaxed);
+                self.set_last_error(&e);
+                Err(e.into())
+            }
+            Err(_) => Err(self.timed_out(timeout).await.into()),
+        }
+    }
+
+    async fn render_url_on(
+        &self,
+        page: &chromiumoxide::Page,
+        output_dir: &Path,
+        url: &str,
+        viewport: CardViewport,
+    ) -> Result<Vec<RenderedCard>> {
+        Self::set_viewport(page, viewport).await?;
+        page.goto(url).await?;
+        self.render_page(output_dir, page, viewport.scale, None).await
     }
 
-    pub async fn render_file(&self, output_dir: &Path, file: &Path) -> Result<()> {
+    pub async fn render_file(&self, output_dir: &Path, file: &Path, viewport: CardViewport) -> Result<Vec<RenderedCard>> {
         log::trace!("Opening file for rendering: {}", file.to_str().unwrap());
         let url = String::from("file://") + file.to_str().unwrap();
-        self.render_url(output_dir, url.as_str()).await
+        self.render_url(output_dir, url.as_str(), viewport).await
     }
 
-    pub async fn render_html(&self, output_dir: &Path, html: &str) -> Result<()> {
-        let _permit = self.page_pool_permits.acquire().await?;
+    /// Open a tab in the current browser
+    async fn new_page(&self, url: impl Into<String>) -> Result<PooledPage> {
+        let session = self.session.read().await;
+        let session = session.as_ref().ok_or("Browser is not running")?;
+        Ok(PooledPage {
+            page: session.browser.new_page(url.into()).await?,
+            uses: 0,
+            generation: session.generation,
+        })
+    }
 
-        // Navigate to the server so the page has the right origin for iframe loading
-        let port = std::env::var("ROCKET_PORT").unwrap_or_else(|_| "8000".to_string());
-        let page = match self.render_pages.lock().await.pop() {
-            Some(page) => page,
-            None => self.browser.new_page(format!("http://127.0.0.1:{}", port)).await?,
-        };
+    /// A pooled page, or a new one navigated to the server so it has the right origin for iframe loading
+    async fn take_page(&self) -> Result<PooledPage> {
+        if let Some(page) = self.render_pages.lock().await.pop() {
+            return Ok(page);
+        }
+        self.new_page(format!("http://127.0.0.1:{}", self.settings.port)).await
+    }
 
-        if let Err(e) = async {
-            page.set_content(html).await?;
+    /// Put a page back in the pool, pages of an old browser or used `page_uses` times are closed instead
+    async fn return_page(&self, mut page: PooledPage) {
+        page.uses += 1;
+        if page.generation == self.generation.load(Ordering::Relaxed) && page.uses < self.settings.page_uses {
+            self.render_pages.lock().await.push(page);
+            return;
+        }
+        log::debug!("Recycling a page after {} renders", page.uses);
+        if let Err(e) = page.page.close().await {
+            log::debug!("Can't close a recycled page: {e}");
+        }
+    }
 
-            // Wait for window.__READY flag set by render templates (up to 30s)
-            let wait_js = r#"
-                new Promise((resolve) => {
-                    if (window.__READY) { resolve(); return; }
-                    const check = setInterval(() => {
-                        if (window.__READY) { clearInterval(check); resolve(); }
-                    }, 10);
-                    setTimeout(() => { clearInterval(check); resolve(); }, 30000);
-                })
-            "#;
-            let _ = page.evaluate(wait_js).await;
+    /// Load `html` into `page` and screenshot its cards once the template is ready
+    async fn render_on(
+        &self,
+        page: &chromiumoxide::Page,
+        output_dir: &Path,
+        html: &str,
+        viewport: CardViewport,
+        progress: Option<&CardProgress>,
+    ) -> Result<Vec<RenderedCard>> {
+        // Pages are pooled, every render sets the size of its own cards
+        Self::set_viewport(page, viewport).await?;
+        page.set_content(html).await?;
 
-            self.render_page(output_dir, &page).await
+        // Wait for window.__READY flag set by render templates
+        let wait_js = format!(
+            r#"
+            new Promise((resolve) => {{
+                if (window.__READY) {{ resolve(true); return; }}
+                const check = setInterval(() => {{
+                    if (window.__READY) {{ clearInterval(check); resolve(true); }}
+                }}, 10);
+                setTimeout(() => {{ clearInterval(check); resolve(false); }}, {});
+            }})
+            "#,
+            self.settings.ready_timeout.as_millis()
+        );
+        let ready: bool = page.evaluate(wait_js).await?.into_value()?;
+        if !ready {
+            return Err(format!(
+                "The page did not set window.__READY within {}s",
+                self.settings.ready_timeout.as_secs()
+            )
+            .into());
         }
-        .await
-        {
-            drop(page);
-            return Err(e);
+
+        self.render_page(output_dir, page, viewport.scale, progress).await
+    }
+
+    pub async fn render_html(
+        &self,
+        output_dir: &Path,
+        html: &str,
+        viewport: CardViewport,
+        progress: Option<&CardProgress>,
+    ) -> Result<Vec<RenderedCard>> {
+        let _permit = self.page_pool_permits.acquire().await?;
+        self.ensure_browser().await?;
+
+        let timeout = self.settings.render_timeout;
+        let deadline = tokio::time::Instant::now() + timeout;
+        let page = tokio::time::timeout_at(deadline, async { self.take_page().await.map_err(|e| e.to_string()) });
+        let page = match page.await {
+            Ok(page) => page?,
+            Err(_) => return Err(self.timed_out(timeout).await.into()),
+        };
+        let rendered = tokio::time::timeout_at(deadline, async {
+            self.render_on(&page.page, output_dir, html, viewport, progress)
+                .await
+                .map_err(|e| e.to_string())
+        })
+        .await;
+
+        match rendered {
+            Ok(Ok(cards)) => {
+                self.counters.renders.fetch_add(1, Ordering::Relaxed);
+                self.return_page(page).await;
+                Ok(cards)
+            }
+            Ok(Err(e)) => {
+                self.counters.failures.fetch_add(1, Ordering::Relaxed);
+                self.set_last_error(&e);
+                let _ = page.page.close().await;
+                Err(e.into())
+            }
+            Err(_) => {
+                let e = self.timed_out(timeout).await;
+                let _ = tokio::time::timeout(PROBE_TIMEOUT, page.page.close()).await;
+                Err(e.into())
+            }
         }
+    }
 
-        self.render_pages.lock().await.push(page);
-        Ok(())
+    /// Count a render that ran out of time and check the browser is still answering
+    async fn timed_out(&self, timeout: Duration) -> String {
+        self.counters.failures.fetch_add(1, Ordering::Relaxed);
+        self.counters.timeouts.fetch_add(1, Ordering::Relaxed);
+        let e = format!("Rendering cards timed out after {}s", timeout.as_secs());
+        self.set_last_error(&e);
+        if let Err(probe) = self.probe().await {
+            self.set_last_error(&probe);
+        }
+        e
     }
 
-    pub async fn close(&mut self) -> Result<()> {
+    /// Close the pages and the browser. Renders still running fail.
+    pub async fn close(&self) -> Result<()> {
         log::info!("Closing browser...");
+        self.closed.store(true, Ordering::Relaxed);
         let pages = std::mem::take(&mut *self.render_pages.lock().await);
         for page in pages {
-            page.close().await?;
+            page.page.close().await?;
         }
-        self.browser.close().await?;
-        match self.browser.wait().await {
+        let Some(mut session) = self.session.write().await.take() else {
+            return Ok(());
+        };
+        session.browser.close().await?;
+        let waited = session.browser.wait().await;
+        session.handler.abort();
+        match waited {
             Ok(_) => Ok(()),
             Err(e) => Err(e.into()),
         }
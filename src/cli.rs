@@ -1,5 +1,7 @@
 use clap::Parser;
 
+use crate::export::Period;
+
 #[derive(Parser)]
 #[command(name = "tgdigest")]
 #[command(author = "Anton Sosnin <antsosnin@yandex.ru>")]
@@ -9,12 +11,213 @@ pub struct Args {
     /// Path to configuration file
     #[arg(short, long)]
     pub config: std::path::PathBuf,
+
+    /// Runs the web server when no command is given
+    #[command(subcommand)]
+    pub command: Option<CliCommand>,
+}
+
+/// The digest a command works on: a calendar period, a range of days, or the last 7 days
+#[derive(clap::Args)]
+pub struct DigestArgs {
+    /// Mode with the templates to use
+    #[arg(long, default_value = "example")]
+    pub mode: String,
+
+    #[arg(long)]
+    pub channel: String,
+
+    #[arg(long)]
+    pub year: Option<i32>,
+
+    /// Month of `--year`
+    #[arg(long, requires = "year")]
+    pub month: Option<u32>,
+
+    /// Week of `--month`, from 1 to 5, weeks start on a Monday
+    #[arg(long, requires = "month")]
+    pub week: Option<u32>,
+
+    /// First day of the range, YYYY-MM-DD
+    #[arg(long, conflicts_with = "year")]
+    pub from: Option<chrono::NaiveDate>,
+
+    /// Last day of the range, today if unset
+    #[arg(long, requires = "from")]
+    pub to: Option<chrono::NaiveDate>,
+
+    #[arg(long)]
+    pub top_count: Option<usize>,
+
+    #[arg(long)]
+    pub editor_choice: Option<i32>,
+
+    /// `total` or `velocity`
+    #[arg(long)]
+    pub rank: Option<String>,
+
+    #[arg(long)]
+    pub velocity_window: Option<i64>,
+
+    /// Adds a block ranking posts by this reaction, e.g. 🔥
+    #[arg(long)]
+    pub reaction: Option<String>,
+
+    /// `default_lang` if unset
+    #[arg(long)]
+    pub lang: Option<String>,
+
+    /// Fetch the whole range again instead of what the cache lacks
+    #[arg(long)]
+    pub force: bool,
+}
+
+/// Cards of the posts at these places of their blocks, as `/video` takes them
+#[derive(clap::Args)]
+pub struct CardArgs {
+    #[arg(long)]
+    pub replies: Option<usize>,
+
+    #[arg(long)]
+    pub reactions: Option<usize>,
+
+    #[arg(long)]
+    pub forwards: Option<usize>,
+
+    #[arg(long)]
+    pub views: Option<usize>,
+
+    /// `story`, `square` or `landscape`
+    #[arg(long)]
+    pub format: Option<String>,
+}
+
+#[derive(clap::Subcommand)]
+pub enum CliCommand {
+    /// Run the web server
+    Serve,
+
+    /// Render the digest of a static digest template to a file
+    Digest {
+        #[command(flatten)]
+        digest: DigestArgs,
+
+        /// `<output_dir>/digest/<mode>/<channel>/<period>.html` if unset
+        #[arg(long)]
+        out: Option<std::path::PathBuf>,
+    },
+
+    /// Render the cards of a digest to images
+    Cards {
+        #[command(flatten)]
+        digest: DigestArgs,
+
+        #[command(flatten)]
+        cards: CardArgs,
+
+        /// Folder of the images, `<output_dir>/cards/<mode>/<channel>/<period>` if unset
+        #[arg(long)]
+        out: Option<std::path::PathBuf>,
+    },
+
+    /// Render the video of a digest, it stays in `output_dir` like the videos of `/video`
+    Video {
+        #[command(flatten)]
+        digest: DigestArgs,
+
+        #[command(flatten)]
+        cards: CardArgs,
+
+        /// `mp4`, `webm`, `gif` or `webp`
+        #[arg(long)]
+        output: Option<String>,
+
+        /// Seconds of a post's own video played on its card
+        #[arg(long)]
+        post_video: Option<u32>,
+
+        /// Also copy the video to this file
+        #[arg(long)]
+        out: Option<std::path::PathBuf>,
+    },
+
+    /// Save a post as `/post` returns it
+    Post {
+        #[arg(long)]
+        channel: String,
+
+        #[arg(long)]
+        id: i32,
+
+        /// `<output_dir>/post/<channel>/<id>.json` if unset
+        #[arg(long)]
+        out: Option<std::path::PathBuf>,
+    },
+
+    /// Sign in to Telegram and save the session, asks for the phone number and code
+    Login,
+
+    /// Inspect or clean up the post cache, media cache and output files
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommand,
+    },
+
+    /// Export the digests of a channel for every week, month and year of a range as a static site
+    Export {
+        /// Mode with a static digest template
+        #[arg(long, default_value = "example")]
+        mode: String,
+
+        #[arg(long)]
+        channel: String,
+
+        /// First day of the range, YYYY-MM-DD
+        #[arg(long)]
+        from: chrono::NaiveDate,
+
+        /// Last day of the range, today if unset
+        #[arg(long)]
+        to: Option<chrono::NaiveDate>,
+
+        #[arg(long, value_enum, value_delimiter = ',', default_value = "week,month,year")]
+        periods: Vec<Period>,
+
+        #[arg(long, default_value_t = 3)]
+        top_count: usize,
+
+        /// Language of the pages, `default_lang` if unset
+        #[arg(long)]
+        lang: Option<String>,
+
+        /// Site directory, `<output_dir>/site/<channel>` if unset
+        #[arg(long)]
+        out: Option<std::path::PathBuf>,
+
+        /// Larger post media stay links to the post on t.me
+        #[arg(long, default_value_t = 50)]
+        max_media_mb: u64,
+    },
+}
+
+#[derive(clap::Subcommand)]
+pub enum CacheCommand {
+    /// Print the size of the caches as JSON
+    Stats,
+
+    /// Remove leftover render folders and bring the caches within their limits now
+    Prune,
 }
 
 impl Args {
     pub fn parse_args() -> Self {
         Args::parse()
     }
+
+    /// Whether to run the web server rather than a command
+    pub fn serve(&self) -> bool {
+        matches!(self.command, None | Some(CliCommand::Serve))
+    }
 }
 
 #[derive(Clone, serde::Serialize, serde::Deserialize)]
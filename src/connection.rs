@@ -0,0 +1,224 @@
+//! Telegram connection supervision.
+//!
+//! The supervisor pings Telegram once a minute, or right away when a request
+//! reports an error. When the ping fails the service turns degraded and the
+//! client is rebuilt with exponential back-off until a ping succeeds again.
+//! Meanwhile routes answer from the cache or with `503` and `Retry-After`,
+//! and background fetches wait in `wait_online` to resume where they stopped.
+//!
+//! A session that is not signed in counts as offline too. The supervisor then
+//! leaves the connection alone until `login` signs in.
+//!
+//! With several accounts the connection is up while any account of the pool
+//! answers. Once every account left the pool the service is signed out.
+
+use crate::context::AppContext;
+use crate::source::pool::{PooledSource, account_lost};
+use crate::tg;
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::Notify;
+
+const PING_INTERVAL: Duration = Duration::from_secs(60);
+const PING_TIMEOUT: Duration = Duration::from_secs(30);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(60);
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+pub struct ConnectionState {
+    online: AtomicBool,
+    /// Cleared while the Telegram session is not signed in
+    authorized: AtomicBool,
+    /// Set once a supervisor runs; without one the connection is never questioned
+    supervised: AtomicBool,
+    /// Unix time of the next reconnect attempt while degraded
+    retry_at: AtomicU64,
+    /// Wakes the supervisor for an immediate ping
+    check: Notify,
+    /// Woken after every ping or reconnect attempt
+    checked: Notify,
+}
+
+impl ConnectionState {
+    pub fn new() -> ConnectionState {
+        ConnectionState {
+            online: AtomicBool::new(true),
+            authorized: AtomicBool::new(true),
+            supervised: AtomicBool::new(false),
+            retry_at: AtomicU64::new(0),
+            check: Notify::new(),
+            checked: Notify::new(),
+        }
+    }
+
+    pub fn is_online(&self) -> bool {
+        self.is_authorized() && self.online.load(Ordering::Relaxed)
+    }
+
+    pub fn is_authorized(&self) -> bool {
+        self.authorized.load(Ordering::Relaxed)
+    }
+
+    pub fn set_authorized(&self, authorized: bool) {
+        if self.authorized.swap(authorized, Ordering::Relaxed) != authorized {
+            if authorized {
+                log::info!("Signed in to Telegram");
+            } else {
+                log::warn!("Telegram session is signed out, serving from cache");
+            }
+        }
+        self.checked.notify_waiters();
+    }
+
+    /// Seconds until the next reconnect attempt, at least 1
+    pub fn retry_after(&self) -> u64 {
+        self.retry_at
+            .load(Ordering::Relaxed)
+            .saturating_sub(now_secs())
+            .max(1)
+    }
+
+    pub fn set_degraded(&self, retry_in: Duration) {
+        self.retry_at
+            .store(now_secs() + retry_in.as_secs(), Ordering::Relaxed);
+        if self.online.swap(false, Ordering::Relaxed) {
+            log::warn!("Telegram connection lost, serving from cache");
+        }
+        self.checked.notify_waiters();
+    }
+
+    pub fn set_online(&self) {
+        if !self.online.swap(true, Ordering::Relaxed) {
+            log::info!("Telegram connection restored");
+        }
+        self.checked.notify_waiters();
+    }
+
+    /// Ask the supervisor to ping now and wait for the verdict.
+    /// `true` if the connection is fine, so the error that prompted the check was not about it.
+    pub async fn confirm_online(&self) -> bool {
+        if !self.is_authorized() {
+            return false;
+        }
+        if !self.supervised.load(Ordering::Relaxed) {
+            return true;
+        }
+        if self.is_online() {
+            let checked = self.checked.notified();
+            self.check.notify_one();
+            let _ = tokio::time::timeout(PING_TIMEOUT * 2, checked).await;
+        }
+        self.is_online()
+    }
+
+    /// Resolves once the connection is back
+    pub async fn wait_online(&self) {
+        while !self.is_online() {
+            let checked = self.checked.notified();
+            if self.is_online() {
+                break;
+            }
+            checked.await;
+        }
+    }
+}
+
+/// Whether an account answers. One that was banned or signed out leaves the pool.
+async fn ping_account(pool: &PooledSource, account: usize) -> bool {
+    let client = match tg::TelegramAPI::account_client(account) {
+        Ok(client) => client,
+        Err(e) => {
+            log::error!("Telegram ping failed: {}", e);
+            return false;
+        }
+    };
+    match tokio::time::timeout(PING_TIMEOUT, client.get_me()).await {
+        Ok(Ok(_)) => {
+            log::debug!("Telegram ping successful");
+            true
+        }
+        Ok(Err(e)) => {
+            log::error!("Telegram ping of account {} failed: {}", account, e);
+            if account_lost(&e) {
+                pool.disable(account, &e.to_string());
+            }
+            false
+        }
+        Err(_) => {
+            log::error!("Telegram ping of account {} timed out", account);
+            false
+        }
+    }
+}
+
+/// Whether any account of the pool answers
+async fn ping(pool: &PooledSource) -> bool {
+    for account in pool.enabled_accounts() {
+        if ping_account(pool, account).await {
+            return true;
+        }
+    }
+    false
+}
+
+/// Rebuild the clients until a ping succeeds, doubling the delay between attempts.
+async fn reconnect(ctx: &AppContext, state: &ConnectionState, pool: &PooledSource) {
+    let mut backoff = MIN_BACKOFF;
+    loop {
+        state.set_degraded(backoff);
+        log::info!("Reconnecting to Telegram in {}s", backoff.as_secs());
+        tokio::time::sleep(backoff).await;
+        if pool.enabled_accounts().is_empty() {
+            state.set_authorized(false);
+            return;
+        }
+
+        let mut connected = false;
+        for account in pool.enabled_accounts() {
+            connected |= match tokio::time::timeout(CONNECT_TIMEOUT, tg::TelegramAPI::reconnect(ctx, account)).await {
+                Ok(Ok(())) => true,
+                Ok(Err(e)) => {
+                    log::error!("Telegram reconnect of account {} failed: {}", account, e);
+                    false
+                }
+                Err(_) => {
+                    log::error!("Telegram reconnect of account {} timed out", account);
+                    false
+                }
+            };
+        }
+        if connected && ping(pool).await {
+            state.set_online();
+            return;
+        }
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+/// Keep the Telegram connection alive. Runs until the task is aborted.
+pub async fn supervise(ctx: &AppContext, state: &ConnectionState, pool: &PooledSource) {
+    state.supervised.store(true, Ordering::Relaxed);
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep(PING_INTERVAL) => {}
+            _ = state.check.notified() => {}
+        }
+        if !state.is_authorized() {
+            continue;
+        }
+        if pool.enabled_accounts().is_empty() {
+            state.set_authorized(false);
+            continue;
+        }
+        if ping(pool).await {
+            state.set_online();
+        } else {
+            reconnect(ctx, state, pool).await;
+        }
+    }
+}
@@ -3,6 +3,15 @@ use crate::util::*;
 
 use std::fs;
 
+/// Credentials and session of a Telegram account
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct TelegramAccount {
+    pub tg_session: std::path::PathBuf,
+    pub tg_id: i32,
+    pub tg_hash: String,
+    pub proxy_url: Option<String>,
+}
+
 #[derive(Debug, serde::Deserialize)]
 pub struct AppContext {
     pub input_dir: std::path::PathBuf,
@@ -11,15 +20,100 @@ pub struct AppContext {
     pub tg_id: i32,
     pub tg_hash: String,
     pub proxy_url: Option<String>,
+    /// More accounts to spread Telegram requests over, after the `tg_*` one
+    #[serde(default)]
+    pub tg_accounts: Vec<TelegramAccount>,
     pub public_base_url: Option<String>,
     #[serde(default = "default_cache_limit_mb")]
     pub cache_limit_mb: u64,
+    /// Quota of rendered videos, avatars and photos in `output_dir`, the media cache has its own
+    #[serde(default = "default_output_limit_mb")]
+    pub output_limit_mb: u64,
+    /// Output files unused for longer are deleted, 0 keeps them until the quota needs the room
+    #[serde(default = "default_output_max_age_days")]
+    pub output_max_age_days: u64,
+    /// Pages of the card renderer rendering at once
+    #[serde(default = "default_renderer_pool_size")]
+    pub renderer_pool_size: usize,
+    /// Renders a browser page does before it is replaced by a fresh one
+    #[serde(default = "default_renderer_page_uses")]
+    pub renderer_page_uses: usize,
+    /// A render of cards taking longer fails, and the browser is checked for a hang
+    #[serde(default = "default_render_timeout_secs")]
+    pub render_timeout_secs: u64,
+    /// A render fails if its template does not set `window.__READY` in time
+    #[serde(default = "default_render_ready_timeout_secs")]
+    pub render_ready_timeout_secs: u64,
+    /// Posts with fewer views are left out of the engagement rate blocks
+    #[serde(default = "default_min_rate_views")]
+    pub min_rate_views: i32,
+    /// Background fetches also load the discussion threads of this many most commented posts,
+    /// for the top comment block. 0 loads them only on `/post/<channel>/<id>/comments`.
+    #[serde(default)]
+    pub comment_posts: usize,
+    /// Replies loaded per discussion thread, newest first, the top comment is picked among them
+    #[serde(default = "default_comment_limit")]
+    pub comment_limit: usize,
+    /// Language used when a request neither sets `lang` nor sends a known `Accept-Language`
+    #[serde(default = "default_lang")]
+    pub default_lang: String,
+    /// Shell running a mode's `make_video.sh`, a name in `PATH` or a path
+    #[serde(default = "default_shell")]
+    pub shell: String,
+    /// Bearer token of the admin routes signing in to Telegram, they are off without one
+    pub admin_token: Option<String>,
+    /// Serve Telegram data from this directory instead of connecting, see `source::fixture`
+    pub fixture_dir: Option<std::path::PathBuf>,
 }
 
 fn default_cache_limit_mb() -> u64 {
     1024
 }
 
+fn default_output_limit_mb() -> u64 {
+    4096
+}
+
+fn default_output_max_age_days() -> u64 {
+    30
+}
+
+fn default_renderer_pool_size() -> usize {
+    2
+}
+
+fn default_renderer_page_uses() -> usize {
+    50
+}
+
+fn default_render_timeout_secs() -> u64 {
+    120
+}
+
+fn default_render_ready_timeout_secs() -> u64 {
+    30
+}
+
+fn default_min_rate_views() -> i32 {
+    100
+}
+
+fn default_comment_limit() -> usize {
+    1000
+}
+
+fn default_shell() -> String {
+    if cfg!(windows) {
+        "C:/Program Files/Git/usr/bin/bash.exe".to_string()
+    } else {
+        "bash".to_string()
+    }
+}
+
+fn default_lang() -> String {
+    crate::locale::DEFAULT_LANG.to_string()
+}
+
 impl AppContext {
     pub fn new(config: &std::path::Path) -> Result<AppContext> {
         let working_dir = std::env::current_dir()?;
@@ -34,12 +128,41 @@ impl AppContext {
                 &working_dir,
                 Some(path_util::PathExists::DontCare),
             )?,
+            tg_accounts: ctx
+                .tg_accounts
+                .into_iter()
+                .map(|account| {
+                    Ok(TelegramAccount {
+                        tg_session: path_util::handle_path(
+                            Some(account.tg_session),
+                            &working_dir,
+                            Some(path_util::PathExists::DontCare),
+                        )?,
+                        ..account
+                    })
+                })
+                .collect::<Result<_>>()?,
+            fixture_dir: ctx
+                .fixture_dir
+                .map(|dir| path_util::handle_path(Some(dir), &working_dir, None))
+                .transpose()?,
             ..ctx
         };
         log::info!("Loaded context {:#?}", ctx);
         Ok(ctx)
     }
 
+    /// The `tg_*` account first, then `tg_accounts`
+    pub fn accounts(&self) -> Vec<TelegramAccount> {
+        let primary = TelegramAccount {
+            tg_session: self.tg_session.clone(),
+            tg_id: self.tg_id,
+            tg_hash: self.tg_hash.clone(),
+            proxy_url: self.proxy_url.clone(),
+        };
+        std::iter::once(primary).chain(self.tg_accounts.iter().cloned()).collect()
+    }
+
     pub fn public_base_url(&self) -> String {
         self.public_base_url
             .as_deref()
@@ -0,0 +1,966 @@
+//! Video encoding with ffmpeg.
+//!
+//! `Encoder` turns the `card_XX.png` screenshots of `CardRenderer` into a video:
+//! every card is shown for `card_seconds`, optionally between an intro and an
+//! outro card, joined by hard cuts or `xfade` transitions, over a looped music
+//! track that fades out at the end. A card can play its post's own video in
+//! place of the still screenshot. A `VideoFormat` sets the shape of the cards
+//! and an `OutputFormat` the container. A mode tunes it in `<mode>/video.json`; a
+//! `<mode>/make_video.sh` replaces the encoder entirely and runs in the directory
+//! with the cards.
+
+use crate::card_renderer::CardViewport;
+use crate::path_util;
+use crate::util::*;
+
+use std::io::Write as _;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+
+/// Lines of ffmpeg's stderr kept in the error of a failed encode
+const STDERR_TAIL_LINES: usize = 20;
+
+/// Frame size when cards of different sizes meet in one video and no resolution is set
+pub const COMPOSE_SIZE: (u32, u32) = (1080, 1920);
+
+/// Frame rate of videos with transitions or post videos when `video.json` sets none
+pub const COMPOSE_FPS: u32 = 30;
+
+/// Device scale the cards are captured at, a 540x960 story card becomes 1080x1920
+const CARD_SCALE: f64 = 2.0;
+
+/// Shape of the cards and of the video frame
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum VideoFormat {
+    /// 9:16 for Stories, Reels and Shorts
+    #[default]
+    Story,
+    /// 1:1 for feeds
+    Square,
+    /// 16:9 for YouTube
+    Landscape,
+}
+
+impl VideoFormat {
+    pub fn parse(value: &str) -> Option<VideoFormat> {
+        match value {
+            "story" => Some(VideoFormat::Story),
+            "square" => Some(VideoFormat::Square),
+            "landscape" => Some(VideoFormat::Landscape),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            VideoFormat::Story => "story",
+            VideoFormat::Square => "square",
+            VideoFormat::Landscape => "landscape",
+        }
+    }
+
+    /// Card size in CSS pixels as seen by the templates
+    pub fn card_size(&self) -> (u32, u32) {
+        match self {
+            VideoFormat::Story => (540, 960),
+            VideoFormat::Square => (540, 540),
+            VideoFormat::Landscape => (960, 540),
+        }
+    }
+
+    pub fn viewport(&self) -> CardViewport {
+        let (width, height) = self.card_size();
+        CardViewport {
+            width,
+            height,
+            scale: CARD_SCALE,
+        }
+    }
+
+    /// Video frame size in pixels
+    pub fn frame_size(&self) -> (u32, u32) {
+        let (width, height) = self.card_size();
+        let scale = |size: u32| (size as f64 * CARD_SCALE).round() as u32;
+        (scale(width), scale(height))
+    }
+}
+
+/// Container and codecs of the encoded video
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// H.264 (or the codec of `video.json`) with AAC
+    #[default]
+    Mp4,
+    /// VP9 with Opus
+    Webm,
+    /// Palette optimized, no sound
+    Gif,
+    /// Animated WebP, no sound
+    Webp,
+}
+
+impl OutputFormat {
+    pub fn parse(value: &str) -> Option<OutputFormat> {
+        match value {
+            "mp4" => Some(OutputFormat::Mp4),
+            "webm" => Some(OutputFormat::Webm),
+            "gif" => Some(OutputFormat::Gif),
+            "webp" => Some(OutputFormat::Webp),
+            _ => None,
+        }
+    }
+
+    /// Also the file extension
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OutputFormat::Mp4 => "mp4",
+            OutputFormat::Webm => "webm",
+            OutputFormat::Gif => "gif",
+            OutputFormat::Webp => "webp",
+        }
+    }
+
+    fn has_audio(&self) -> bool {
+        matches!(self, OutputFormat::Mp4 | OutputFormat::Webm)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransitionKind {
+    #[default]
+    None,
+    Crossfade,
+    Slide,
+}
+
+impl TransitionKind {
+    fn xfade_name(self) -> Option<&'static str> {
+        match self {
+            TransitionKind::None => None,
+            TransitionKind::Crossfade => Some("fade"),
+            TransitionKind::Slide => Some("slideleft"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(default)]
+pub struct TransitionSpec {
+    #[serde(rename = "type")]
+    pub kind: TransitionKind,
+    pub seconds: f64,
+}
+
+impl Default for TransitionSpec {
+    fn default() -> TransitionSpec {
+        TransitionSpec {
+            kind: TransitionKind::None,
+            seconds: 0.5,
+        }
+    }
+}
+
+/// Background music, looped for the whole video
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct AudioSpec {
+    /// Relative to the mode directory
+    pub file: PathBuf,
+    #[serde(default = "default_volume")]
+    pub volume: f64,
+    #[serde(default = "default_fade_out_seconds")]
+    pub fade_out_seconds: f64,
+}
+
+fn default_volume() -> f64 {
+    1.0
+}
+
+fn default_fade_out_seconds() -> f64 {
+    2.0
+}
+
+/// Intro or outro card rendered from a template of the mode
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct ExtraCardSpec {
+    /// Defaults to `intro_template.html` or `outro_template.html`
+    pub template: Option<String>,
+    #[serde(default = "default_extra_card_seconds")]
+    pub seconds: f64,
+    /// Text or locale key passed to the template as `call_to_action`, `{channel}` is the channel title
+    pub call_to_action: Option<String>,
+}
+
+fn default_extra_card_seconds() -> f64 {
+    3.0
+}
+
+/// Settings of `<mode>/video.json`, every field optional
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(default)]
+pub struct VideoSpec {
+    pub card_seconds: f64,
+    /// Constant output frame rate; without it every card is a single frame
+    pub fps: Option<u32>,
+    /// Video codec of mp4 output, the other outputs pick their own
+    pub codec: String,
+    pub preset: Option<String>,
+    pub crf: Option<u32>,
+    /// Frame size for every format instead of the size of its cards
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub transition: TransitionSpec,
+    pub audio: Option<AudioSpec>,
+    pub intro: Option<ExtraCardSpec>,
+    pub outro: Option<ExtraCardSpec>,
+}
+
+impl Default for VideoSpec {
+    fn default() -> VideoSpec {
+        VideoSpec {
+            card_seconds: 5.0,
+            fps: None,
+            codec: "libx264".to_string(),
+            preset: Some("ultrafast".to_string()),
+            crf: None,
+            width: None,
+            height: None,
+            transition: TransitionSpec::default(),
+            audio: None,
+            intro: None,
+            outro: None,
+        }
+    }
+}
+
+impl VideoSpec {
+    /// Read the video settings of a template mode. Defaults if the mode has no `video.json`.
+    pub fn load(input_dir: &Path, mode: &str) -> Result<VideoSpec> {
+        let path = input_dir.join(mode).join("video.json");
+        if !path.exists() {
+            return Ok(VideoSpec::default());
+        }
+        let data = std::fs::read_to_string(&path)?;
+        let mut spec: VideoSpec =
+            serde_json::from_str(&data).map_err(|e| format!("Invalid {}: {}", path.display(), e))?;
+
+        let invalid = |reason: &str| -> Result<VideoSpec> { Err(format!("Invalid {}: {}", path.display(), reason).into()) };
+        let extra_seconds = [&spec.intro, &spec.outro].into_iter().flatten().map(|card| card.seconds);
+        let shortest = extra_seconds.fold(spec.card_seconds, f64::min);
+        if shortest <= 0.0 {
+            return invalid("card durations must be positive");
+        }
+        if spec.transition.kind != TransitionKind::None
+            && (spec.transition.seconds <= 0.0 || spec.transition.seconds >= shortest)
+        {
+            return invalid("transition seconds must be positive and shorter than every card");
+        }
+        if let Some(audio) = spec.audio.as_mut() {
+            audio.file = input_dir.join(mode).join(&audio.file);
+            if !audio.file.exists() {
+                return invalid(&format!("no audio file {}", audio.file.display()));
+            }
+        }
+        Ok(spec)
+    }
+
+    /// Whether a post video of `seconds` fits the transition, which must be shorter than every segment
+    pub fn check_post_video(&self, seconds: u32) -> std::result::Result<(), String> {
+        if self.transition.kind != TransitionKind::None && self.transition.seconds >= seconds as f64 {
+            return Err(format!(
+                "Post videos must be longer than the {}s transition of this mode",
+                self.transition.seconds
+            ));
+        }
+        Ok(())
+    }
+
+    /// Encoder with these settings for a video of `format`; intro and outro images are added once rendered
+    pub fn encoder(&self, format: VideoFormat, output: OutputFormat) -> Encoder {
+        let (width, height) = format.frame_size();
+        let mut encoder = Encoder::new()
+            .card_seconds(self.card_seconds)
+            .codec(&self.codec)
+            .output(output)
+            .resolution(self.width.unwrap_or(width), self.height.unwrap_or(height));
+        if let Some(fps) = self.fps {
+            encoder = encoder.fps(fps);
+        }
+        if let Some(preset) = &self.preset {
+            encoder = encoder.preset(preset);
+        }
+        if let Some(crf) = self.crf {
+            encoder = encoder.crf(crf);
+        }
+        if self.transition.kind != TransitionKind::None {
+            encoder = encoder.transition(self.transition.kind, self.transition.seconds);
+        }
+        if let Some(audio) = &self.audio {
+            encoder = encoder.audio(&audio.file, audio.volume, audio.fade_out_seconds);
+        }
+        encoder
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Audio {
+    file: PathBuf,
+    volume: f64,
+    fade_out_seconds: f64,
+}
+
+/// A post's own video played over its card
+#[derive(Clone, Debug)]
+struct Clip {
+    video: PathBuf,
+    seconds: f64,
+    /// (x, y, width, height) of the card image the video is fitted into
+    slot: (u32, u32, u32, u32),
+}
+
+/// An image shown for a while, optionally with a video on top
+#[derive(Clone, Debug)]
+struct Segment {
+    image: PathBuf,
+    seconds: f64,
+    clip: Option<Clip>,
+}
+
+/// ffmpeg invocation for a slideshow of cards
+#[derive(Clone, Debug)]
+pub struct Encoder {
+    ffmpeg: PathBuf,
+    card_seconds: f64,
+    fps: Option<u32>,
+    codec: String,
+    output: OutputFormat,
+    preset: Option<String>,
+    crf: Option<u32>,
+    resolution: Option<(u32, u32)>,
+    transition: Option<(TransitionKind, f64)>,
+    audio: Option<Audio>,
+    intro: Option<Segment>,
+    outro: Option<Segment>,
+    post_videos: Vec<(PathBuf, Clip)>,
+}
+
+impl Encoder {
+    pub fn new() -> Encoder {
+        Encoder {
+            ffmpeg: PathBuf::from("ffmpeg"),
+            card_seconds: 5.0,
+            fps: None,
+            codec: "libx264".to_string(),
+            output: OutputFormat::Mp4,
+            preset: None,
+            crf: None,
+            resolution: None,
+            transition: None,
+            audio: None,
+            intro: None,
+            outro: None,
+            post_videos: Vec::new(),
+        }
+    }
+
+    pub fn card_seconds(mut self, seconds: f64) -> Encoder {
+        self.card_seconds = seconds;
+        self
+    }
+
+    pub fn fps(mut self, fps: u32) -> Encoder {
+        self.fps = Some(fps);
+        self
+    }
+
+    pub fn codec(mut self, codec: &str) -> Encoder {
+        self.codec = codec.to_string();
+        self
+    }
+
+    pub fn output(mut self, output: OutputFormat) -> Encoder {
+        self.output = output;
+        self
+    }
+
+    pub fn preset(mut self, preset: &str) -> Encoder {
+        self.preset = Some(preset.to_string());
+        self
+    }
+
+    pub fn crf(mut self, crf: u32) -> Encoder {
+        self.crf = Some(crf);
+        self
+    }
+
+    /// Scale every card to fit `width`x`height`, padding the rest
+    pub fn resolution(mut self, width: u32, height: u32) -> Encoder {
+        self.resolution = Some((width, height));
+        self
+    }
+
+    /// Blend consecutive cards for `seconds`; `TransitionKind::None` keeps hard cuts
+    pub fn transition(mut self, kind: TransitionKind, seconds: f64) -> Encoder {
+        self.transition = (kind != TransitionKind::None).then_some((kind, seconds));
+        self
+    }
+
+    /// Loop `file` under the video and fade it out over the last `fade_out_seconds`
+    pub fn audio(mut self, file: &Path, volume: f64, fade_out_seconds: f64) -> Encoder {
+        self.audio = Some(Audio {
+            file: file.to_path_buf(),
+            volume,
+            fade_out_seconds,
+        });
+        self
+    }
+
+    pub fn intro(mut self, image: &Path, seconds: f64) -> Encoder {
+        self.intro = Some(Segment {
+            image: image.to_path_buf(),
+            seconds,
+            clip: None,
+        });
+        self
+    }
+
+    pub fn outro(mut self, image: &Path, seconds: f64) -> Encoder {
+        self.outro = Some(Segment {
+            image: image.to_path_buf(),
+            seconds,
+            clip: None,
+        });
+        self
+    }
+
+    /// Show the first `seconds` of `video` on `card`, fitted into `slot` (x, y, width, height) of the card image.
+    /// The card stays for `seconds` instead of `card_seconds`, a shorter video freezes on its last frame.
+    pub fn post_video(mut self, card: &Path, video: &Path, seconds: f64, slot: (u32, u32, u32, u32)) -> Encoder {
+        let clip = Clip {
+            video: video.to_path_buf(),
+            seconds,
+            slot,
+        };
+        self.post_videos.push((card.to_path_buf(), clip));
+        self
+    }
+
+    fn segments(&self, cards: &[PathBuf]) -> Vec<Segment> {
+        let cards = cards.iter().map(|card| {
+            let clip = self
+                .post_videos
+                .iter()
+                .find(|(image, _)| image == card)
+                .map(|(_, clip)| clip.clone());
+            Segment {
+                image: card.clone(),
+                seconds: clip.as_ref().map_or(self.card_seconds, |clip| clip.seconds),
+                clip,
+            }
+        });
+        self.intro
+            .iter()
+            .cloned()
+            .chain(cards)
+            .chain(self.outro.iter().cloned())
+            .collect()
+    }
+
+    /// Whether every segment is an input of its own, needed to blend or animate segments
+    fn composed(&self) -> bool {
+        self.transition.is_some() || !self.post_videos.is_empty()
+    }
+
+    /// Frame size every image is fitted to, if the images must agree on one
+    fn frame_size(&self) -> Option<(u32, u32)> {
+        let mixed = self.composed() || self.intro.is_some() || self.outro.is_some();
+        self.resolution.or(mixed.then_some(COMPOSE_SIZE))
+    }
+
+    fn fps_or_default(&self) -> Option<u32> {
+        self.fps.or(self.composed().then_some(COMPOSE_FPS))
+    }
+
+    /// Length of the video, transitions overlap neighbouring segments
+    fn duration(&self, segments: &[Segment]) -> f64 {
+        let total: f64 = segments.iter().map(|segment| segment.seconds).sum();
+        let overlap = self.transition.map_or(0.0, |(_, seconds)| seconds);
+        total - overlap * segments.len().saturating_sub(1) as f64
+    }
+
+    /// Concat demuxer list showing each segment for its duration
+    fn segment_list(&self, segments: &[Segment]) -> String {
+        let mut list = String::new();
+        for segment in segments {
+            list += &format!("file '{}'\nduration {}\n", escape_path(&segment.image), segment.seconds);
+        }
+        // The concat demuxer ignores the duration of the last entry unless the file is repeated
+        if let Some(last) = segments.last() {
+            list += &format!("file '{}'\n", escape_path(&last.image));
+        }
+        list
+    }
+
+    fn scale_filter(&self) -> String {
+        match self.frame_size() {
+            Some((width, height)) => format!(
+                "scale={w}:{h}:force_original_aspect_ratio=decrease,pad={w}:{h}:(ow-iw)/2:(oh-ih)/2,setsar=1",
+                w = width,
+                h = height
+            ),
+            // yuv420p needs even dimensions
+            None => "pad=ceil(iw/2)*2:ceil(ih/2)*2".to_string(),
+        }
+    }
+
+    /// Inputs and filter graph: one concat input for plain hard cuts, otherwise a looped input per segment
+    /// (and one per post video) joined by `concat` or `xfade`
+    fn args(&self, segments: &[Segment], segment_list: &Path, output: &Path) -> Vec<String> {
+        let mut args: Vec<String> = vec!["-y".into(), "-hide_banner".into(), "-loglevel".into(), "error".into()];
+        let mut graph = Vec::new();
+        let fps = self.fps_or_default();
+
+        let (video_inputs, video_label) = if !self.composed() {
+            args.extend(["-f", "concat", "-safe", "0", "-i"].map(String::from));
+            args.push(segment_list.to_string_lossy().into_owned());
+            graph.push(format!("[0:v]{}[v]", self.scale_filter()));
+            (1, "v".to_string())
+        } else {
+            let fps = fps.unwrap_or(COMPOSE_FPS);
+            let mut inputs = 0;
+            for (i, segment) in segments.iter().enumerate() {
+                args.extend(["-loop".to_string(), "1".to_string(), "-t".to_string(), segment.seconds.to_string()]);
+                args.extend(["-framerate".to_string(), fps.to_string(), "-i".to_string()]);
+                args.push(segment.image.to_string_lossy().into_owned());
+                let image = inputs;
+                inputs += 1;
+
+                let card = match &segment.clip {
+                    None => format!("[{}:v]", image),
+                    Some(clip) => {
+                        args.extend(["-t".to_string(), clip.seconds.to_string(), "-i".to_string()]);
+                        args.push(clip.video.to_string_lossy().into_owned());
+                        let video = inputs;
+                        inputs += 1;
+                        let (x, y, width, height) = clip.slot;
+                        // Centered in the slot; the card image keeps the segment going after the video ends
+                        graph.push(format!(
+                            "[{video}:v]scale={width}:{height}:force_original_aspect_ratio=decrease,setsar=1[c{i}];\
+                             [{image}:v][c{i}]overlay=x={x}+({width}-overlay_w)/2:y={y}+({height}-overlay_h)/2:eof_action=repeat[o{i}]"
+                        ));
+                        format!("[o{}]", i)
+                    }
+                };
+                graph.push(format!("{}{},fps={},format=yuv420p[s{}]", card, self.scale_filter(), fps, i));
+            }
+
+            let label = match self.transition {
+                None => {
+                    let labels: String = (0..segments.len()).map(|i| format!("[s{}]", i)).collect();
+                    graph.push(format!("{}concat=n={}:v=1:a=0[v]", labels, segments.len()));
+                    "v".to_string()
+                }
+                Some((kind, seconds)) => {
+                    let mut label = "s0".to_string();
+                    let mut offset = 0.0;
+                    // Each transition starts `seconds` before the previous segment ends
+                    for (i, previous) in segments.iter().enumerate().take(segments.len().saturating_sub(1)) {
+                        offset += previous.seconds - seconds;
+                        let next = format!("x{}", i + 1);
+                        graph.push(format!(
+                            "[{}][s{}]xfade=transition={}:duration={}:offset={}[{}]",
+                            label,
+                            i + 1,
+                            kind.xfade_name().unwrap_or("fade"),
+                            seconds,
+                            offset,
+                            next
+                        ));
+                        label = next;
+                    }
+                    label
+                }
+            };
+            (inputs, label)
+        };
+
+        let duration = self.duration(segments);
+        let audio = self.audio.as_ref().filter(|_| self.output.has_audio());
+        if let Some(audio) = audio {
+            args.extend(["-stream_loop", "-1", "-i"].map(String::from));
+            args.push(audio.file.to_string_lossy().into_owned());
+            graph.push(format!(
+                "[{}:a]volume={},afade=t=out:st={}:d={}[a]",
+                video_inputs,
+                audio.volume,
+                (duration - audio.fade_out_seconds).max(0.0),
+                audio.fade_out_seconds
+            ));
+        }
+
+        let video_label = match self.output {
+            // A palette computed from the whole video keeps the colors of the cards
+            OutputFormat::Gif => {
+                graph.push(format!(
+                    "[{}]split[p0][p1];[p0]palettegen=stats_mode=diff[pal];[p1][pal]paletteuse=dither=sierra2_4a[g]",
+                    video_label
+                ));
+                "g".to_string()
+            }
+            _ => video_label,
+        };
+
+        args.extend(["-filter_complex".to_string(), graph.join(";")]);
+        args.extend(["-map".to_string(), format!("[{}]", video_label)]);
+        if audio.is_some() {
+            let codec = match self.output {
+                OutputFormat::Webm => "libopus",
+                _ => "aac",
+            };
+            args.extend(["-map", "[a]", "-c:a", codec, "-b:a", "192k"].map(String::from));
+        }
+        match fps {
+            Some(fps) => args.extend(["-r".to_string(), fps.to_string()]),
+            None => args.extend(["-vsync", "vfr"].map(String::from)),
+        }
+        args.extend(self.codec_args());
+        args.push("-t".to_string());
+        args.push(duration.to_string());
+        args.push(output.to_string_lossy().into_owned());
+        args
+    }
+
+    fn codec_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        match self.output {
+            OutputFormat::Mp4 => {
+                args.extend(["-c:v".to_string(), self.codec.clone()]);
+                if let Some(preset) = &self.preset {
+                    args.extend(["-preset".to_string(), preset.clone()]);
+                }
+                if let Some(crf) = self.crf {
+                    args.extend(["-crf".to_string(), crf.to_string()]);
+                }
+                args.extend(["-pix_fmt", "yuv420p"].map(String::from));
+            }
+            OutputFormat::Webm => {
+                // Constant quality mode of VP9 needs a zero bitrate
+                args.extend(["-c:v", "libvpx-vp9", "-b:v", "0", "-row-mt", "1"].map(String::from));
+                args.extend(["-crf".to_string(), self.crf.unwrap_or(32).to_string()]);
+                args.extend(["-deadline", "realtime", "-cpu-used", "8", "-pix_fmt", "yuv420p"].map(String::from));
+            }
+            OutputFormat::Gif => args.extend(["-loop", "0"].map(String::from)),
+            OutputFormat::Webp => args.extend(["-c:v", "libwebp", "-lossless", "0", "-quality", "80", "-loop", "0"].map(String::from)),
+        }
+        args
+    }
+
+    /// Encode `cards` in order into `output`. The error carries the tail of ffmpeg's stderr.
+    pub async fn encode(&self, cards: &[PathBuf], output: &Path) -> Result<()> {
+        if cards.is_empty() {
+            return Err("No cards to encode".into());
+        }
+        let segments = self.segments(cards);
+        let list_path = output.with_extension("txt");
+        std::fs::File::create(&list_path)?.write_all(self.segment_list(&segments).as_bytes())?;
+
+        let args = self.args(&segments, &list_path, output);
+        log::debug!("Running {} {}", self.ffmpeg.display(), args.join(" "));
+        let result = tokio::process::Command::new(&self.ffmpeg)
+            .args(&args)
+            .stdin(Stdio::null())
+            .output()
+            .await;
+        let _ = std::fs::remove_file(&list_path);
+
+        let output_status = result.map_err(|e| format!("Can't run {}: {}", self.ffmpeg.display(), e))?;
+        if !output_status.status.success() {
+            return Err(format!(
+                "ffmpeg failed ({}): {}",
+                output_status.status,
+                stderr_tail(&output_status.stderr)
+            )
+            .into());
+        }
+        if !output.exists() {
+            return Err("ffmpeg did not write the video".into());
+        }
+        Ok(())
+    }
+}
+
+/// `card_XX.png` files of a render directory in card order
+pub fn find_cards(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut cards = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let index = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_prefix("card_")?.strip_suffix(".png")?.parse::<usize>().ok());
+        if let Some(index) = index {
+            cards.push((index, path));
+        }
+    }
+    cards.sort();
+    Ok(cards.into_iter().map(|(_, path)| path).collect())
+}
+
+pub fn stderr_tail(stderr: &[u8]) -> String {
+    let stderr = String::from_utf8_lossy(stderr);
+    let lines: Vec<&str> = stderr.lines().filter(|line| !line.trim().is_empty()).collect();
+    lines[lines.len().saturating_sub(STDERR_TAIL_LINES)..].join("\n")
+}
+
+/// Quote a path for a concat demuxer list
+fn escape_path(path: &Path) -> String {
+    path_util::to_slash(path)
+        .map(|path| path.to_string_lossy().into_owned())
+        .unwrap_or_else(|_| path.to_string_lossy().into_owned())
+        .replace('\'', r"'\''")
+}
+
+/// Run a mode's own `make_video.sh` with `shell` in the directory with the cards, it has to write
+/// `digest.mp4`
+pub async fn run_script(shell: &str, script: &Path, dir: &Path) -> Result<()> {
+    let script = path_util::to_slash(script)?;
+    log::debug!("Running {}: {} at {}", shell, script.display(), dir.display());
+    let output = tokio::process::Command::new(shell)
+        .current_dir(dir)
+        .arg(&script)
+        .stdin(Stdio::null())
+        .output()
+        .await
+        .map_err(|e| format!("Can't run {}: {}", script.display(), e))?;
+
+    log::debug!("Status: {}", output.status);
+    log::debug!("Stdout: {}", String::from_utf8_lossy(&output.stdout));
+    log::debug!("Stderr: {}", String::from_utf8_lossy(&output.stderr));
+    if !output.status.success() {
+        return Err(format!(
+            "{} failed ({}): {}",
+            script.display(),
+            output.status,
+            stderr_tail(&output.stderr)
+        )
+        .into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new() -> TempDir {
+            let dir = std::env::temp_dir().join(format!("tgdigest-encoder-{}", uuid::Uuid::new_v4().as_simple()));
+            std::fs::create_dir_all(&dir).unwrap();
+            TempDir(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn cards_are_sorted_by_number() {
+        let dir = TempDir::new();
+        for name in ["card_10.png", "card_02.png", "card_100.png", "card_01.png", "digest.mp4", "card_x.png"] {
+            std::fs::write(dir.0.join(name), b"").unwrap();
+        }
+        let cards: Vec<String> = find_cards(&dir.0)
+            .unwrap()
+            .iter()
+            .map(|card| card.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(cards, ["card_01.png", "card_02.png", "card_10.png", "card_100.png"]);
+    }
+
+    #[test]
+    fn card_list_repeats_the_last_card() {
+        let encoder = Encoder::new().card_seconds(2.5);
+        let segments = encoder.segments(&[PathBuf::from("/out/card_00.png"), PathBuf::from("/out/it's.png")]);
+        let list = encoder.segment_list(&segments);
+        assert_eq!(
+            list,
+            "file '/out/card_00.png'\nduration 2.5\nfile '/out/it'\\''s.png'\nduration 2.5\nfile '/out/it'\\''s.png'\n"
+        );
+    }
+
+    #[test]
+    fn args_follow_the_builder() {
+        let args = Encoder::new()
+            .codec("libx265")
+            .crf(28)
+            .fps(30)
+            .resolution(1080, 1920);
+        let segments = args.segments(&[PathBuf::from("card_00.png"), PathBuf::from("card_01.png")]);
+        let args = args.args(&segments, Path::new("cards.txt"), Path::new("digest.mp4"));
+        let args = args.join(" ");
+        assert!(args.contains("-f concat -safe 0 -i cards.txt"));
+        assert!(args.contains("[0:v]scale=1080:1920:force_original_aspect_ratio=decrease,pad=1080:1920"));
+        assert!(args.contains("-map [v] -r 30 -c:v libx265 -crf 28 -pix_fmt yuv420p -t 10"));
+        assert!(!args.contains("-preset"));
+        assert!(args.ends_with("digest.mp4"));
+
+        let encoder = Encoder::new();
+        let segments = encoder.segments(&[PathBuf::from("card_00.png")]);
+        let args = encoder.args(&segments, Path::new("cards.txt"), Path::new("digest.mp4")).join(" ");
+        assert!(args.contains("[0:v]pad=ceil(iw/2)*2:ceil(ih/2)*2[v]"));
+        assert!(args.contains("-vsync vfr"));
+    }
+
+    #[test]
+    fn composed_video_overlaps_transitions() {
+        let encoder = Encoder::new()
+            .card_seconds(4.0)
+            .transition(TransitionKind::Crossfade, 0.5)
+            .audio(Path::new("music.mp3"), 0.5, 2.0)
+            .intro(Path::new("intro.png"), 3.0)
+            .outro(Path::new("outro.png"), 3.0);
+        let segments = encoder.segments(&[PathBuf::from("card_00.png"), PathBuf::from("card_01.png")]);
+        assert_eq!(segments.len(), 4);
+        assert_eq!(encoder.duration(&segments), 12.5);
+
+        let args = encoder.args(&segments, Path::new("cards.txt"), Path::new("digest.mp4")).join(" ");
+        assert!(args.starts_with("-y -hide_banner -loglevel error -loop 1 -t 3 -framerate 30 -i intro.png"));
+        assert!(args.contains("-loop 1 -t 4 -framerate 30 -i card_01.png -loop 1 -t 3 -framerate 30 -i outro.png"));
+        assert!(args.contains("scale=1080:1920"));
+        assert!(args.contains("[s0][s1]xfade=transition=fade:duration=0.5:offset=2.5[x1]"));
+        assert!(args.contains("[x1][s2]xfade=transition=fade:duration=0.5:offset=6[x2]"));
+        assert!(args.contains("[x2][s3]xfade=transition=fade:duration=0.5:offset=9.5[x3]"));
+        assert!(args.contains("-stream_loop -1 -i music.mp3"));
+        assert!(args.contains("[4:a]volume=0.5,afade=t=out:st=10.5:d=2[a]"));
+        assert!(args.contains("-map [x3] -map [a] -c:a aac"));
+        assert!(args.contains("-t 12.5 digest.mp4"));
+
+        let slide = Encoder::new().transition(TransitionKind::Slide, 1.0);
+        let segments = slide.segments(&[PathBuf::from("card_00.png"), PathBuf::from("card_01.png")]);
+        let args = slide.args(&segments, Path::new("cards.txt"), Path::new("digest.mp4")).join(" ");
+        assert!(args.contains("xfade=transition=slideleft:duration=1:offset=4[x1]"));
+    }
+
+    #[test]
+    fn post_videos_play_on_their_cards() {
+        let encoder = Encoder::new()
+            .card_seconds(4.0)
+            .resolution(1080, 1920)
+            .post_video(Path::new("card_01.png"), Path::new("post_7.mp4"), 8.0, (0, 200, 1080, 1600));
+        let cards = [PathBuf::from("card_00.png"), PathBuf::from("card_01.png"), PathBuf::from("card_02.png")];
+        let segments = encoder.segments(&cards);
+        assert_eq!(encoder.duration(&segments), 16.0);
+
+        let args = encoder.args(&segments, Path::new("cards.txt"), Path::new("digest.mp4")).join(" ");
+        assert!(!args.contains("-f concat"), "a video card needs inputs of its own");
+        assert!(args.contains("-loop 1 -t 8 -framerate 30 -i card_01.png -t 8 -i post_7.mp4 -loop 1 -t 4"));
+        assert!(args.contains("[2:v]scale=1080:1600:force_original_aspect_ratio=decrease,setsar=1[c1]"));
+        assert!(args.contains("[1:v][c1]overlay=x=0+(1080-overlay_w)/2:y=200+(1600-overlay_h)/2:eof_action=repeat[o1]"));
+        assert!(args.contains("[o1]scale=1080:1920"));
+        assert!(args.contains("[s0][s1][s2]concat=n=3:v=1:a=0[v]"));
+        assert!(args.contains("-map [v] -r 30"));
+    }
+
+    #[test]
+    fn outputs_pick_their_codecs() {
+        let cards = [PathBuf::from("card_00.png"), PathBuf::from("card_01.png")];
+        let args = |output: OutputFormat| {
+            let encoder = Encoder::new()
+                .output(output)
+                .preset("ultrafast")
+                .audio(Path::new("music.mp3"), 1.0, 2.0);
+            let segments = encoder.segments(&cards);
+            encoder.args(&segments, Path::new("cards.txt"), Path::new("digest")).join(" ")
+        };
+
+        let mp4 = args(OutputFormat::Mp4);
+        assert!(mp4.contains("-c:a aac"));
+        assert!(mp4.contains("-c:v libx264 -preset ultrafast -pix_fmt yuv420p"));
+
+        let webm = args(OutputFormat::Webm);
+        assert!(webm.contains("-c:a libopus"));
+        assert!(webm.contains("-c:v libvpx-vp9 -b:v 0"));
+        assert!(!webm.contains("-preset"));
+
+        let gif = args(OutputFormat::Gif);
+        assert!(gif.contains("[v]split[p0][p1];[p0]palettegen"));
+        assert!(gif.contains("-map [g] -vsync vfr -loop 0"));
+        assert!(!gif.contains("music.mp3"), "gif has no sound");
+
+        let webp = args(OutputFormat::Webp);
+        assert!(webp.contains("-c:v libwebp"));
+        assert!(!webp.contains("-map [a]"));
+    }
+
+    #[test]
+    fn formats_size_cards_and_frames() {
+        assert_eq!(VideoFormat::parse("landscape"), Some(VideoFormat::Landscape));
+        assert_eq!(VideoFormat::parse("portrait"), None);
+        assert_eq!(OutputFormat::parse("webm"), Some(OutputFormat::Webm));
+        assert_eq!(VideoFormat::Story.frame_size(), (1080, 1920));
+        assert_eq!(VideoFormat::Square.frame_size(), (1080, 1080));
+        assert_eq!(VideoFormat::Landscape.viewport().width, 960);
+
+        let spec = VideoSpec::default();
+        let segments = [Segment {
+            image: PathBuf::from("card_00.png"),
+            seconds: 5.0,
+            clip: None,
+        }];
+        let args = spec
+            .encoder(VideoFormat::Landscape, OutputFormat::Mp4)
+            .args(&segments, Path::new("cards.txt"), Path::new("digest.mp4"))
+            .join(" ");
+        assert!(args.contains("scale=1920:1080"));
+
+        let spec = VideoSpec {
+            width: Some(720),
+            height: Some(1280),
+            ..VideoSpec::default()
+        };
+        assert_eq!(spec.encoder(VideoFormat::Story, OutputFormat::Mp4).frame_size(), Some((720, 1280)));
+    }
+
+    #[test]
+    fn video_spec_defaults_and_overrides() {
+        let dir = TempDir::new();
+        let spec = VideoSpec::load(&dir.0, "example").unwrap();
+        assert_eq!(spec.card_seconds, 5.0);
+        assert_eq!(spec.preset.as_deref(), Some("ultrafast"));
+
+        std::fs::create_dir_all(dir.0.join("example")).unwrap();
+        std::fs::write(dir.0.join("example/video.json"), r#"{"card_seconds": 3, "crf": 20}"#).unwrap();
+        let spec = VideoSpec::load(&dir.0, "example").unwrap();
+        assert_eq!(spec.card_seconds, 3.0);
+        assert_eq!(spec.crf, Some(20));
+        assert_eq!(spec.codec, "libx264");
+
+        std::fs::write(dir.0.join("example/video.json"), r#"{"card_seconds": 0}"#).unwrap();
+        assert!(VideoSpec::load(&dir.0, "example").is_err());
+
+        let composed = r#"{"transition": {"type": "slide", "seconds": 1}, "intro": {"seconds": 2}, "audio": {"file": "music.mp3"}}"#;
+        std::fs::write(dir.0.join("example/video.json"), composed).unwrap();
+        assert!(VideoSpec::load(&dir.0, "example").is_err(), "audio file is missing");
+        std::fs::write(dir.0.join("example/music.mp3"), b"").unwrap();
+        let spec = VideoSpec::load(&dir.0, "example").unwrap();
+        assert_eq!(spec.transition.kind, TransitionKind::Slide);
+        assert_eq!(spec.audio.unwrap().file, dir.0.join("example/music.mp3"));
+        assert_eq!(spec.intro.unwrap().seconds, 2.0);
+
+        let too_long = r#"{"transition": {"type": "crossfade", "seconds": 3}, "intro": {"seconds": 2}}"#;
+        std::fs::write(dir.0.join("example/video.json"), too_long).unwrap();
+        assert!(VideoSpec::load(&dir.0, "example").is_err());
+
+        let fade = r#"{"transition": {"type": "crossfade", "seconds": 1.5}}"#;
+        std::fs::write(dir.0.join("example/video.json"), fade).unwrap();
+        let spec = VideoSpec::load(&dir.0, "example").unwrap();
+        assert!(spec.check_post_video(1).is_err());
+        assert!(spec.check_post_video(2).is_ok());
+        assert!(VideoSpec::default().check_post_video(1).is_ok());
+    }
+}
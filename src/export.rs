@@ -0,0 +1,512 @@
+//! Static copy of a channel's digests for hosting without the server.
+//!
+//! Digests of every week, month and year of a range are rendered with the
+//! mode's static digest template into `<year>.html`, `<year>-<month>.html`
+//! and `<year>-<month>-w<week>.html`. The `/view/` iframes of a page become
+//! `posts/<id>.html`, and media, thumbnails, the channel avatar and local
+//! files are downloaded into `assets/` with their URLs made relative.
+//! `index.html` lists the digests and `sitemap.xml` has all pages.
+
+use crate::locale::Locales;
+use crate::cli::Commands;
+use crate::task::Task;
+use crate::util::Result;
+use crate::{App, FetchProgress, ViewQuery};
+
+use chrono::{DateTime, Datelike, Days, Months, NaiveDate, Utc};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Length of an exported digest
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, clap::ValueEnum)]
+pub enum Period {
+    Week,
+    Month,
+    Year,
+}
+
+impl Period {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Period::Week => "week",
+            Period::Month => "month",
+            Period::Year => "year",
+        }
+    }
+}
+
+pub struct ExportOptions {
+    pub mode: String,
+    pub channel: String,
+    pub from: NaiveDate,
+    /// Last day of the range, included
+    pub to: NaiveDate,
+    pub periods: Vec<Period>,
+    pub top_count: usize,
+    pub lang: String,
+    pub dir: PathBuf,
+    pub max_media_size: i64,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct ExportSummary {
+    pub digests: usize,
+    pub posts: usize,
+    pub assets: usize,
+    /// Links left pointing to the server because their file could not be downloaded
+    pub missing: usize,
+}
+
+/// A digest page of the site
+#[derive(Clone, Debug, serde::Serialize)]
+struct DigestPage {
+    period: &'static str,
+    label: String,
+    file: String,
+    from_date: i64,
+    to_date: i64,
+}
+
+/// A server URL on a page of the exported channel
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum Link {
+    View { id: i32, query: String },
+    Media(i32),
+    Thumb(i32),
+    Userpic,
+    LocalMedia(String),
+    Image(i64),
+}
+
+const LINK_PREFIXES: [&str; 6] = ["/view/", "/media/", "/thumb/", "/userpic/", "/localmedia/", "/img/"];
+
+/// A link found in a page: its byte range, target and whether it included the public base url
+struct FoundLink {
+    start: usize,
+    end: usize,
+    link: Link,
+    absolute: bool,
+}
+
+impl Link {
+    fn parse(prefix: &str, url: &str, channel: &str) -> Option<Link> {
+        let (path, query) = url.split_once('?').unwrap_or((url, ""));
+        let mut segments = path.split('/');
+        let mut channel_id = || -> Option<i32> {
+            if segments.next()? != channel {
+                return None;
+            }
+            segments.next()?.parse().ok()
+        };
+        match prefix {
+            "/view/" => channel_id().map(|id| Link::View { id, query: query.replace("&amp;", "&") }),
+            "/media/" => channel_id().map(Link::Media),
+            "/thumb/" => channel_id().map(Link::Thumb),
+            "/userpic/" => (path == channel).then_some(Link::Userpic),
+            "/localmedia/" => (!path.is_empty() && !path.contains('/') && !path.starts_with('.'))
+                .then(|| Link::LocalMedia(path.to_string())),
+            "/img/" => path.parse().ok().map(Link::Image),
+            _ => None,
+        }
+    }
+}
+
+/// Links of `html` to server routes of `channel`, root-relative or prefixed by `base_url`,
+/// as written or html-escaped by tera
+fn find_links(html: &str, base_url: &str, channel: &str) -> Vec<FoundLink> {
+    let mut found = Vec::new();
+    for prefix in LINK_PREFIXES {
+        for escape in [false, true] {
+            let escaped = |text: &str| if escape { text.replace('/', "&#x2F;") } else { text.to_string() };
+            let (written_prefix, written_base) = (escaped(prefix), escaped(base_url));
+            for (at, _) in html.match_indices(&written_prefix) {
+                let before = &html[..at];
+                let (start, absolute) = if before.ends_with(&written_base) {
+                    (at - written_base.len(), true)
+                } else if before.ends_with(['"', '\'', '(']) {
+                    (at, false)
+                } else {
+                    continue;
+                };
+                let rest = &html[at + written_prefix.len()..];
+                let len = rest
+                    .find(|c: char| matches!(c, '"' | '\'' | ')' | '<' | '>' | '\\') || c.is_whitespace())
+                    .unwrap_or(rest.len());
+                if let Some(link) = Link::parse(prefix, &rest[..len].replace("&#x2F;", "/"), channel) {
+                    found.push(FoundLink {
+                        start,
+                        end: at + written_prefix.len() + len,
+                        link,
+                        absolute,
+                    });
+                }
+            }
+        }
+    }
+    found.sort_by_key(|link| link.start);
+    found
+}
+
+/// Path of `target` from a page at `page`, both relative to the site root
+fn relative_path(page: &str, target: &str) -> String {
+    "../".repeat(page.matches('/').count()) + target
+}
+
+/// File of a post page, with the view options in its name
+fn post_page(id: i32, query: &str) -> String {
+    let options: String = query
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    if options.is_empty() {
+        format!("posts/{}.html", id)
+    } else {
+        format!("posts/{}-{}.html", id, options)
+    }
+}
+
+fn view_query(query: &str) -> ViewQuery {
+    let mut view = ViewQuery::default();
+    for (key, value) in query.split('&').filter_map(|pair| pair.split_once('=')) {
+        let flag = value.parse().ok();
+        match key {
+            "views" => view.views = flag,
+            "forwards" => view.forwards = flag,
+            "reactions" => view.reactions = flag,
+            "comments" => view.comments = flag,
+            "dark" => view.dark = flag,
+            "iframe" => view.iframe = flag,
+            "px_limit" => view.px_limit = value.parse().ok(),
+            _ => {}
+        }
+    }
+    view
+}
+
+fn digest_page(
+    period: Period,
+    label: String,
+    file: &str,
+    start: Option<DateTime<Utc>>,
+    end: impl FnOnce(DateTime<Utc>) -> Option<DateTime<Utc>>,
+) -> Option<DigestPage> {
+    let start = start?;
+    Some(DigestPage {
+        period: period.as_str(),
+        label,
+        file: format!("{}.html", file),
+        from_date: start.timestamp(),
+        to_date: end(start)?.timestamp(),
+    })
+}
+
+/// Started digests of `periods` overlapping `from..to`, dated as `/digest` dates them
+fn digest_pages(periods: &[Period], from: i64, to: i64, locales: &Locales, lang: &str) -> Vec<DigestPage> {
+    let year = |date: i64| DateTime::<Utc>::from_timestamp(date, 0).map_or(2014, |date| date.year());
+    let mut pages = Vec::new();
+    for y in year(from).max(2014)..=year(to - 1) {
+        let start = crate::get_date_from_year(y).ok();
+        pages.extend(digest_page(Period::Year, y.to_string(), &y.to_string(), start, |start| {
+            start.checked_add_months(Months::new(12))
+        }));
+        for m in 1..=12 {
+            let month = format!("{}-{:02}", y, m);
+            let start = crate::get_date_from_month(y, m).ok();
+            pages.extend(digest_page(Period::Month, month.clone(), &month, start, |start| {
+                start.checked_add_months(Months::new(1))
+            }));
+            for w in 1..=5 {
+                let label = locales.t_args(lang, "export.week", &[("month", &month), ("week", &w.to_string())]);
+                let start = crate::get_date_from_week(y, m, w).ok();
+                pages.extend(digest_page(Period::Week, label, &format!("{}-w{}", month, w), start, |start| {
+                    start.checked_add_days(Days::new(7))
+                }));
+            }
+        }
+    }
+
+    let now = Utc::now().timestamp();
+    pages.retain(|page| {
+        periods.iter().any(|period| period.as_str() == page.period)
+            && page.from_date < to
+            && page.to_date > from
+            && page.from_date <= now
+    });
+    pages
+}
+
+struct Exporter<'a> {
+    app: &'a Arc<App>,
+    options: &'a ExportOptions,
+    base_url: String,
+    /// Site path of every link handled so far, `None` if its file could not be made
+    targets: HashMap<Link, Option<String>>,
+    summary: ExportSummary,
+}
+
+impl Exporter<'_> {
+    fn path(&self, file: &str) -> PathBuf {
+        self.options.dir.join(file)
+    }
+
+    /// Site path of the file behind `link`, downloading or rendering it on first use
+    async fn target(&mut self, link: &Link) -> Option<String> {
+        if let Some(target) = self.targets.get(link) {
+            return target.clone();
+        }
+        if let Link::View { id, query } = link {
+            // A post linking to itself finds its own page
+            self.targets.insert(link.clone(), Some(post_page(*id, query)));
+        }
+        let target = match self.make_target(link).await {
+            Ok(target) => {
+                if target.starts_with("assets/") {
+                    self.summary.assets += 1;
+                }
+                Some(target)
+            }
+            Err(e) => {
+                log::warn!("Export keeps the server link for {:?}: {}", link, e);
+                self.summary.missing += 1;
+                None
+            }
+        };
+        self.targets.insert(link.clone(), target.clone());
+        target
+    }
+
+    async fn make_target(&mut self, link: &Link) -> std::result::Result<String, String> {
+        let app = self.app;
+        let channel = self.options.channel.as_str();
+        match link {
+            Link::View { id, query } => {
+                // Post pages link media of their own, their futures nest
+                Box::pin(self.post(*id, query)).await.map_err(|e| e.to_string())?;
+                self.summary.posts += 1;
+                Ok(post_page(*id, query))
+            }
+            Link::Media(id) => self.media(*id).await,
+            Link::Thumb(id) => {
+                let bytes = crate::thumb_bytes(app, channel, *id).await.map_err(|e| e.1)?;
+                self.write_asset(&format!("assets/thumbs/{}.jpg", id), &bytes)
+            }
+            Link::Userpic => {
+                let mut chunks = app.tg.channel_photo(channel).await.map_err(|e| e.to_string())?;
+                let mut bytes = Vec::new();
+                while let Some(chunk) = chunks.next().await.map_err(|e| e.to_string())? {
+                    bytes.extend_from_slice(&chunk);
+                }
+                self.write_asset("assets/userpic.jpg", &bytes)
+            }
+            Link::LocalMedia(file) => self.copy_asset(&app.ctx.output_dir.join(file), &format!("assets/{}", file)),
+            Link::Image(id) => self.copy_asset(
+                &app.ctx.output_dir.join(format!("{}.jpg", id)),
+                &format!("assets/img/{}.jpg", id),
+            ),
+        }
+    }
+
+    /// Media of a post from the media cache, or downloaded unless larger than `max_media_size`
+    async fn media(&self, id: i32) -> std::result::Result<String, String> {
+        let app = self.app;
+        let channel = self.options.channel.as_str();
+        if let Some((path, mime, _)) = app.cache.get_cached_media(channel, id).map_err(|e| e.to_string())? {
+            let ext = crate::post_data::mime_ext(&mime);
+            return self.copy_asset(&path, &format!("assets/media/{}.{}", id, ext));
+        }
+        if !app.connection.is_online() {
+            return Err("Telegram is offline".to_string());
+        }
+
+        let (media, mut chunks) = app.tg.open_media(channel, id).await.map_err(|e| e.to_string())?;
+        if media.size.is_some_and(|size| size > self.options.max_media_size) {
+            return Ok(crate::source::post_url(channel, id));
+        }
+        let mut data = Vec::new();
+        while let Some(chunk) = chunks.next().await.map_err(|e| e.to_string())? {
+            data.extend_from_slice(&chunk);
+        }
+        if media.size.is_some_and(|size| size != data.len() as i64) {
+            return Err("Incomplete download".to_string());
+        }
+        let _ = app.cache.store_cached_media(channel, id, media.media_id, &media.mime, &data);
+        self.write_asset(&format!("assets/media/{}.{}", id, crate::post_data::mime_ext(&media.mime)), &data)
+    }
+
+    fn write_asset(&self, file: &str, data: &[u8]) -> std::result::Result<String, String> {
+        let path = self.path(file);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        std::fs::write(&path, data).map_err(|e| e.to_string())?;
+        Ok(file.to_string())
+    }
+
+    fn copy_asset(&self, from: &Path, file: &str) -> std::result::Result<String, String> {
+        let data = std::fs::read(from).map_err(|e| format!("{}: {}", from.display(), e))?;
+        self.write_asset(file, &data)
+    }
+
+    /// Replace the server links of a written page with files of the site
+    async fn localize(&mut self, page: &str) -> Result<()> {
+        let path = self.path(page);
+        let html = std::fs::read_to_string(&path)?;
+        let links = find_links(&html, &self.base_url, &self.options.channel);
+
+        let mut localized = String::with_capacity(html.len());
+        let mut copied = 0;
+        for found in links {
+            if found.start < copied {
+                continue;
+            }
+            let Some(target) = self.target(&found.link).await else {
+                continue;
+            };
+            localized.push_str(&html[copied..found.start]);
+            if target.starts_with("https://") {
+                localized.push_str(&target);
+            } else if found.absolute {
+                localized.push_str(&format!("{}/{}", self.base_url, target));
+            } else {
+                localized.push_str(&relative_path(page, &target));
+            }
+            copied = found.end;
+        }
+        localized.push_str(&html[copied..]);
+        std::fs::write(&path, localized)?;
+        Ok(())
+    }
+
+    fn task(&self, page: &DigestPage) -> Task {
+        Task {
+            command: Commands::Digest {},
+            mode: self.options.mode.clone(),
+            channel_name: self.options.channel.clone(),
+            top_count: self.options.top_count,
+            editor_choice_post_id: 0,
+            from_date: page.from_date,
+            to_date: page.to_date,
+            lang: self.options.lang.clone(),
+            ..Task::default()
+        }
+    }
+
+    /// Render the digest of `page`, `false` if the channel has no posts in its period
+    async fn digest(&mut self, page: &DigestPage) -> Result<bool> {
+        let app = self.app;
+        let task = self.task(page);
+        if app.connection.is_online() {
+            let fetch_target = crate::compute_fetch_target(true, crate::cache::DEFAULT_FETCH_CAP);
+            let progress = Arc::new(FetchProgress::new(fetch_target.unwrap_or_default()));
+            crate::background_fetch(app, &task, fetch_target, false, &progress)
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+        if app.cache.count_cached_posts(&task.channel_name, task.from_date, task.to_date)? == 0 {
+            return Ok(false);
+        }
+
+        let context = crate::digest_context(app, &task).await.map_err(|e| e.1)?;
+        let template = format!("{}/digest_template.html", task.mode);
+        app.html_renderer.render_to_file(&template, &context, &self.path(&page.file))?;
+        self.localize(&page.file).await?;
+        Ok(true)
+    }
+
+    async fn post(&mut self, id: i32, query: &str) -> Result<()> {
+        let app = self.app;
+        let post = app
+            .tg
+            .get_post_data(&self.options.channel, id)
+            .await
+            .map_err(|e| e.to_string())?;
+        let context = crate::view_context(&self.options.channel, &post, &view_query(query));
+        let page = post_page(id, query);
+        app.html_renderer.render_to_file("view_template.html", &context, &self.path(&page))?;
+        self.localize(&page).await
+    }
+
+    async fn index(&mut self, pages: &[DigestPage]) -> Result<()> {
+        let app = self.app;
+        let channel_title = crate::channel_title(app, &self.options.channel).await;
+        let mut context = tera::Context::new();
+        context.insert("channel_name", &self.options.channel);
+        context.insert("channel_title", &channel_title);
+        context.insert("lang", &self.options.lang);
+        context.insert("base_url", &self.base_url);
+        context.insert("site_name", &app.ctx.public_site_name());
+        for period in [Period::Year, Period::Month, Period::Week] {
+            let mut listed: Vec<&DigestPage> = pages.iter().filter(|page| page.period == period.as_str()).collect();
+            listed.reverse();
+            context.insert(format!("{}s", period.as_str()), &listed);
+        }
+        crate::insert_page_meta(
+            &mut context,
+            &crate::channel_page_meta(&app.locales, &self.options.lang, &self.base_url, &self.options.channel, &channel_title),
+        );
+
+        let mode_template = format!("{}/index_template.html", self.options.mode);
+        let template = if app.html_renderer.has_template(&mode_template) {
+            mode_template.as_str()
+        } else {
+            "index_template.html"
+        };
+        app.html_renderer.render_to_file(template, &context, &self.path("index.html"))?;
+        self.localize("index.html").await
+    }
+
+    fn sitemap(&self, pages: &[DigestPage]) -> Result<()> {
+        let mut sitemap = String::from(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n",
+        );
+        let files = std::iter::once("index.html").chain(pages.iter().map(|page| page.file.as_str()));
+        for file in files {
+            sitemap.push_str(&format!("  <url><loc>{}/{}</loc></url>\n", self.base_url, file));
+        }
+        sitemap.push_str("</urlset>\n");
+        std::fs::write(self.path("sitemap.xml"), sitemap)?;
+        Ok(())
+    }
+}
+
+/// Render the digests of `options` into a static site in `options.dir`
+pub async fn export(app: &Arc<App>, options: &ExportOptions) -> Result<ExportSummary> {
+    let template = app.ctx.input_dir.join(&options.mode).join("digest_template.html");
+    let source = std::fs::read_to_string(&template).map_err(|e| format!("{}: {}", template.display(), e))?;
+    if source.contains("data_url") {
+        return Err(format!("The digest template of {} loads its data with JS, export needs a static one", options.mode).into());
+    }
+    if !app.locales.has(&options.lang) {
+        return Err(format!("Unknown lang {}", options.lang).into());
+    }
+    std::fs::create_dir_all(&options.dir)?;
+
+    let from = options.from.and_hms_opt(0, 0, 0).unwrap().and_utc().timestamp();
+    let to = (options.to + Days::new(1)).and_hms_opt(0, 0, 0).unwrap().and_utc().timestamp();
+    let mut pages = digest_pages(&options.periods, from, to, &app.locales, &options.lang);
+    // Longer periods first, they fetch the posts of the shorter ones
+    pages.sort_by_key(|page| std::cmp::Reverse(page.to_date - page.from_date));
+
+    let mut exporter = Exporter {
+        app,
+        options,
+        base_url: app.ctx.public_base_url(),
+        targets: HashMap::new(),
+        summary: ExportSummary::default(),
+    };
+
+    let mut exported = Vec::new();
+    for page in pages {
+        log::info!("Exporting {} digest {} of t.me/{}", page.period, page.label, options.channel);
+        if exporter.digest(&page).await? {
+            exporter.summary.digests += 1;
+            exported.push(page);
+        }
+    }
+
+    exported.sort_by_key(|page| page.from_date);
+    exporter.index(&exported).await?;
+    exporter.sitemap(&exported)?;
+    Ok(exporter.summary)
+}
@@ -0,0 +1,312 @@
+//! Scoring expressions used by `blocks.json`, e.g. `reactions*3 + forwards*5`.
+//!
+//! Supported: numbers, `+ - * /`, parentheses, unary minus and the variables
+//! `views`, `forwards`, `replies`, `reactions` (missing counters are 0) and
+//! `age` (hours since the post was published).
+
+use crate::post::Post;
+use crate::util::Result;
+
+#[derive(Clone, Copy, Debug)]
+enum Var {
+    Views,
+    Forwards,
+    Replies,
+    Reactions,
+    Age,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+#[derive(Clone, Debug)]
+enum Expr {
+    Num(f64),
+    Var(Var),
+    Neg(Box<Expr>),
+    Bin(Op, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Num(f64),
+    Ident(String),
+    Op(char),
+    Open,
+    Close,
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '0'..='9' | '.' => {
+                let mut number = String::new();
+                while let Some(&c) = chars.peek().filter(|c| c.is_ascii_digit() || **c == '.') {
+                    number.push(c);
+                    chars.next();
+                }
+                let value = number
+                    .parse()
+                    .map_err(|_| format!("Invalid number '{}' in '{}'", number, source))?;
+                tokens.push(Token::Num(value));
+            }
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                let mut ident = String::new();
+                while let Some(&c) = chars.peek().filter(|c| c.is_ascii_alphanumeric() || **c == '_') {
+                    ident.push(c);
+                    chars.next();
+                }
+                tokens.push(Token::Ident(ident));
+            }
+            '+' | '-' | '*' | '/' => {
+                tokens.push(Token::Op(c));
+                chars.next();
+            }
+            '(' => {
+                tokens.push(Token::Open);
+                chars.next();
+            }
+            ')' => {
+                tokens.push(Token::Close);
+                chars.next();
+            }
+            _ => return Err(format!("Unexpected '{}' in '{}'", c, source).into()),
+        }
+    }
+    Ok(tokens)
+}
+
+/// Recursive descent over `expr := term (('+'|'-') term)*`,
+/// `term := unary (('*'|'/') unary)*`, `unary := '-' unary | atom`.
+struct Parser<'a> {
+    source: &'a str,
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn expr(&mut self) -> Result<Expr> {
+        let mut lhs = self.term()?;
+        while let Some(Token::Op(c @ ('+' | '-'))) = self.peek().cloned() {
+            self.next();
+            let op = if c == '+' { Op::Add } else { Op::Sub };
+            lhs = Expr::Bin(op, Box::new(lhs), Box::new(self.term()?));
+        }
+        Ok(lhs)
+    }
+
+    fn term(&mut self) -> Result<Expr> {
+        let mut lhs = self.unary()?;
+        while let Some(Token::Op(c @ ('*' | '/'))) = self.peek().cloned() {
+            self.next();
+            let op = if c == '*' { Op::Mul } else { Op::Div };
+            lhs = Expr::Bin(op, Box::new(lhs), Box::new(self.unary()?));
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expr> {
+        if self.peek() == Some(&Token::Op('-')) {
+            self.next();
+            return Ok(Expr::Neg(Box::new(self.unary()?)));
+        }
+        self.atom()
+    }
+
+    fn atom(&mut self) -> Result<Expr> {
+        match self.next() {
+            Some(Token::Num(value)) => Ok(Expr::Num(value)),
+            Some(Token::Ident(name)) => {
+                let var = match name.as_str() {
+                    "views" => Var::Views,
+                    "forwards" => Var::Forwards,
+                    "replies" => Var::Replies,
+                    "reactions" => Var::Reactions,
+                    "age" => Var::Age,
+                    _ => return Err(format!("Unknown variable '{}' in '{}'", name, self.source).into()),
+                };
+                Ok(Expr::Var(var))
+            }
+            Some(Token::Open) => {
+                let expr = self.expr()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(expr),
+                    _ => Err(format!("Missing ')' in '{}'", self.source).into()),
+                }
+            }
+            _ => Err(format!("Unexpected end of expression '{}'", self.source).into()),
+        }
+    }
+}
+
+/// A parsed scoring expression.
+#[derive(Clone, Debug)]
+pub struct Formula {
+    expr: Expr,
+}
+
+impl Formula {
+    pub fn parse(source: &str) -> Result<Formula> {
+        let mut parser = Parser {
+            source,
+            tokens: tokenize(source)?,
+            pos: 0,
+        };
+        let expr = parser.expr()?;
+        if parser.pos != parser.tokens.len() {
+            return Err(format!("Unexpected trailing input in '{}'", source).into());
+        }
+        Ok(Formula { expr })
+    }
+
+    /// Score a post. `now` is the UTC timestamp `age` is measured against.
+    pub fn eval(&self, post: &Post, now: i64) -> f64 {
+        Self::eval_expr(&self.expr, post, now)
+    }
+
+    fn eval_expr(expr: &Expr, post: &Post, now: i64) -> f64 {
+        match expr {
+            Expr::Num(value) => *value,
+            Expr::Var(var) => match var {
+                Var::Views => post.views.unwrap_or(0) as f64,
+                Var::Forwards => post.forwards.unwrap_or(0) as f64,
+                Var::Replies => post.replies.unwrap_or(0) as f64,
+                Var::Reactions => post.reactions.unwrap_or(0) as f64,
+                Var::Age => (now - post.date).max(0) as f64 / 3600.0,
+            },
+            Expr::Neg(inner) => -Self::eval_expr(inner, post, now),
+            Expr::Bin(op, lhs, rhs) => {
+                let (lhs, rhs) = (Self::eval_expr(lhs, post, now), Self::eval_expr(rhs, post, now));
+                match op {
+                    Op::Add => lhs + rhs,
+                    Op::Sub => lhs - rhs,
+                    Op::Mul => lhs * rhs,
+                    Op::Div => lhs / rhs,
+                }
+            }
+        }
+    }
+
+    /// The `top_count` best posts by this formula with their rounded scores.
+    /// Non-finite scores (e.g. division by zero) rank last.
+    pub fn top<'a>(&self, posts: &'a [Post], top_count: usize, now: i64) -> Vec<(&'a Post, i32)> {
+        let mut scored: Vec<(f64, &'a Post)> = posts
+            .iter()
+            .map(|p| {
+                let score = self.eval(p, now);
+                (if score.is_finite() { score } else { f64::NEG_INFINITY }, p)
+            })
+            .collect();
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+        scored
+            .into_iter()
+            .take(top_count)
+            .filter(|(score, _)| score.is_finite())
+            .map(|(score, p)| (p, score.round() as i32))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: i64 = 3600;
+
+    fn post(views: i32, forwards: i32, replies: i32, reactions: Option<i32>, date: i64) -> Post {
+        Post {
+            date,
+            id: 1,
+            views: Some(views),
+            forwards: Some(forwards),
+            replies: Some(replies),
+            reactions,
+            message: None,
+            image: None,
+            grouped_id: None,
+            velocity: None,
+            rates: None,
+            reaction_counts: Vec::new(),
+            selected_reaction: None,
+            top_comment: None,
+        }
+    }
+
+    fn eval(source: &str) -> f64 {
+        Formula::parse(source).unwrap().eval(&post(100, 5, 3, None, 0), 10 * HOUR)
+    }
+
+    fn error(source: &str) -> String {
+        Formula::parse(source).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn follows_precedence_and_parentheses() {
+        assert_eq!(eval("1 + 2 * 3"), 7.0);
+        assert_eq!(eval("(1 + 2) * 3"), 9.0);
+        assert_eq!(eval("10 - 4 - 3"), 3.0);
+        assert_eq!(eval("12 / 3 / 2"), 2.0);
+        assert_eq!(eval("2 * (3 + (4 - 1)) / 4"), 3.0);
+    }
+
+    #[test]
+    fn negates_with_unary_minus() {
+        assert_eq!(eval("-2 * 3"), -6.0);
+        assert_eq!(eval("--views"), 100.0);
+        assert_eq!(eval("forwards - -replies"), 8.0);
+        assert_eq!(eval("-(views + 0.5)"), -100.5);
+    }
+
+    #[test]
+    fn reads_counters_and_age() {
+        // Missing counters are 0, age is in hours
+        assert_eq!(eval("views + forwards*10 + replies*100 + reactions"), 450.0);
+        assert_eq!(eval("views / (age + 10)"), 5.0);
+    }
+
+    #[test]
+    fn rejects_malformed_expressions() {
+        assert!(error("views + likes").contains("Unknown variable 'likes'"));
+        assert!(error("views 3").contains("trailing input"));
+        assert!(error("(views + 1))").contains("trailing input"));
+        assert!(error("(views + 1").contains("Missing ')'"));
+        assert!(error("views *").contains("Unexpected end"));
+        assert!(error("views % 2").contains("Unexpected '%'"));
+        assert!(error("1.2.3").contains("Invalid number"));
+    }
+
+    #[test]
+    fn division_by_zero_ranks_last() {
+        assert!(eval("views / 0").is_infinite());
+        assert!(eval("reactions / reactions").is_nan());
+
+        let formula = Formula::parse("views / reactions").unwrap();
+        let posts = [
+            post(100, 0, 0, Some(0), 0),
+            post(100, 0, 0, Some(4), 0),
+            post(100, 0, 0, Some(2), 0),
+        ];
+        let top: Vec<i32> = formula.top(&posts, 3, 0).into_iter().map(|(_, score)| score).collect();
+        assert_eq!(top, vec![50, 25]);
+    }
+}
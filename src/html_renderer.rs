@@ -1,10 +1,12 @@
 use crate::context::AppContext;
+use crate::locale::{self, Locales};
 use crate::util::*;
 
 use std::collections::HashMap;
 use std::fs::File;
 use std::io::Write as _;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use tera::Tera;
 
 fn format_number(
@@ -36,12 +38,13 @@ pub struct HtmlRenderer {
 }
 
 impl HtmlRenderer {
-    pub fn new(ctx: &AppContext) -> Result<HtmlRenderer> {
+    pub fn new(ctx: &AppContext, locales: Arc<Locales>) -> Result<HtmlRenderer> {
         let mut engine =
             Tera::new(format!("{}/**/*_template.html", ctx.input_dir.to_str().unwrap()).as_str())?;
         engine.autoescape_on(vec!["html"]);
 
         engine.register_filter("format_number", format_number);
+        engine.register_function("t", locale::tera_function(locales));
 
         log::info!("Loaded templates:");
         for template in engine.get_template_names() {
@@ -60,18 +63,21 @@ impl HtmlRenderer {
             .map_err(Into::into)
     }
 
-    pub fn render_to_file(&self, template_name: &str, context: &tera::Context) -> Result<PathBuf> {
+    /// Render into `file`, relative to the output dir unless absolute
+    pub fn render_to_file(&self, template_name: &str, context: &tera::Context, file: &Path) -> Result<PathBuf> {
         let rendered = self.render(template_name, context)?;
 
-        let output_name = template_name
-            .replace("_template", "")
-            .replace("/", "_")
-            .replace("\\", "_");
-
-        let output_path = self.output_dir.join(output_name);
+        let output_path = self.output_dir.join(file);
+        if let Some(parent) = output_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
 
-        let mut file = File::create(&output_path)?; // Use the cloned output_path
+        let mut file = File::create(&output_path)?;
         file.write_all(rendered.as_bytes())?;
         Ok(output_path)
     }
+
+    pub fn has_template(&self, template_name: &str) -> bool {
+        self.engine.get_template_names().any(|name| name == template_name)
+    }
 }
@@ -0,0 +1,136 @@
+//! Asynchronous video jobs.
+//!
+//! `POST /jobs/video` starts the same pipeline as `/video/...` in a spawned task
+//! and returns at once, so the client polls `GET /jobs/<id>` instead of holding
+//! a request open through fetch, card screenshots and encoding. A job id is the
+//! hash of its request, so repeating a request joins the job already running.
+
+use crate::card_renderer::CardProgress;
+
+use rocket::http::Status;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::sync::atomic::Ordering;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Finished jobs are forgotten after this many seconds
+pub const JOB_TTL: u64 = 3600;
+
+#[derive(Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Stage {
+    Queued,
+    Fetching,
+    Rendering,
+    Encoding,
+    Done,
+    Failed,
+}
+
+struct JobState {
+    stage: Stage,
+    /// Key of the background fetch in `App::fetch_progress` while fetching
+    fetch_task_id: Option<String>,
+    file: Option<PathBuf>,
+    error: Option<(Status, String)>,
+    finished_at: Option<u64>,
+}
+
+pub struct VideoJob {
+    pub id: String,
+    pub created_at: u64,
+    pub cards: CardProgress,
+    state: Mutex<JobState>,
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+impl VideoJob {
+    pub fn new(id: String) -> VideoJob {
+        VideoJob {
+            id,
+            created_at: now_secs(),
+            cards: CardProgress::default(),
+            state: Mutex::new(JobState {
+                stage: Stage::Queued,
+                fetch_task_id: None,
+                file: None,
+                error: None,
+                finished_at: None,
+            }),
+        }
+    }
+
+    pub fn stage(&self) -> Stage {
+        self.state.lock().unwrap().stage
+    }
+
+    pub fn set_stage(&self, stage: Stage) {
+        self.state.lock().unwrap().stage = stage;
+    }
+
+    pub fn set_fetching(&self, fetch_task_id: &str) {
+        let mut state = self.state.lock().unwrap();
+        state.stage = Stage::Fetching;
+        state.fetch_task_id = Some(fetch_task_id.to_string());
+    }
+
+    pub fn fetch_task_id(&self) -> Option<String> {
+        self.state.lock().unwrap().fetch_task_id.clone()
+    }
+
+    pub fn finish(&self, result: std::result::Result<PathBuf, (Status, String)>) {
+        let mut state = self.state.lock().unwrap();
+        match result {
+            Ok(file) => {
+                state.stage = Stage::Done;
+                state.file = Some(file);
+            }
+            Err(error) => {
+                state.stage = Stage::Failed;
+                state.error = Some(error);
+            }
+        }
+        state.finished_at = Some(now_secs());
+    }
+
+    pub fn file(&self) -> Option<PathBuf> {
+        self.state.lock().unwrap().file.clone()
+    }
+
+    pub fn error(&self) -> Option<(Status, String)> {
+        self.state.lock().unwrap().error.clone()
+    }
+
+    /// Finished longer than `JOB_TTL` ago, or finished with a file that is gone
+    pub fn is_expired(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        match state.finished_at {
+            Some(finished_at) => {
+                now_secs().saturating_sub(finished_at) > JOB_TTL
+                    || state.file.as_ref().is_some_and(|file| !file.exists())
+            }
+            None => false,
+        }
+    }
+
+    /// Status without the fetch counters, which live in `App::fetch_progress`
+    pub fn to_json(&self) -> serde_json::Value {
+        let state = self.state.lock().unwrap();
+        let mut json = serde_json::json!({
+            "id": self.id,
+            "status": state.stage,
+            "elapsed": state.finished_at.unwrap_or_else(now_secs).saturating_sub(self.created_at),
+        });
+        if matches!(state.stage, Stage::Rendering | Stage::Encoding | Stage::Done) {
+            json["cards_done"] = self.cards.done.load(Ordering::Relaxed).into();
+            json["cards_total"] = self.cards.total.load(Ordering::Relaxed).into();
+        }
+        if let Some((_, error)) = &state.error {
+            json["error"] = error.clone().into();
+        }
+        json
+    }
+}
@@ -0,0 +1,208 @@
+//! User-facing strings.
+//!
+//! Catalogs are flat JSON objects `{"key": "text with {arg}"}` stored as
+//! `<input_dir>/locales/<lang>.json`. The Russian catalog is built in, so a
+//! missing file or key falls back to it and then to the key itself.
+
+use crate::util::Result;
+
+use std::collections::HashMap;
+use std::path::Path;
+
+pub const DEFAULT_LANG: &str = "ru";
+
+const BUILTIN_CATALOG: &str = include_str!("../data/locales/ru.json");
+
+type Catalog = HashMap<String, String>;
+
+pub struct Locales {
+    default_lang: String,
+    catalogs: HashMap<String, Catalog>,
+}
+
+impl Locales {
+    pub fn new(input_dir: &Path, default_lang: &str) -> Result<Locales> {
+        let mut catalogs = HashMap::new();
+        catalogs.insert(DEFAULT_LANG.to_string(), serde_json::from_str(BUILTIN_CATALOG)?);
+
+        let dir = input_dir.join("locales");
+        if dir.is_dir() {
+            for entry in std::fs::read_dir(&dir)? {
+                let path = entry?.path();
+                if path.extension().is_none_or(|ext| ext != "json") {
+                    continue;
+                }
+                let Some(lang) = path.file_stem().and_then(|s| s.to_str()) else {
+                    continue;
+                };
+                let catalog: Catalog = serde_json::from_str(&std::fs::read_to_string(&path)?)
+                    .map_err(|e| format!("Invalid {}: {}", path.display(), e))?;
+                catalogs
+                    .entry(lang.to_lowercase())
+                    .or_insert_with(Catalog::new)
+                    .extend(catalog);
+            }
+        }
+
+        if !catalogs.contains_key(default_lang) {
+            return Err(format!("No locale catalog for default_lang '{}'", default_lang).into());
+        }
+
+        log::info!("Loaded locales: {:?}", catalogs.keys().collect::<Vec<_>>());
+        Ok(Locales {
+            default_lang: default_lang.to_string(),
+            catalogs,
+        })
+    }
+
+    pub fn default_lang(&self) -> &str {
+        &self.default_lang
+    }
+
+    pub fn has(&self, lang: &str) -> bool {
+        self.catalogs.contains_key(lang)
+    }
+
+    /// Catalog for a language tag in any case, the tag itself or its primary subtag (`uk-UA` -> `uk`)
+    pub fn find(&self, tag: &str) -> Option<String> {
+        let tag = tag.trim().to_lowercase();
+        let primary = tag.split('-').next().unwrap_or_default().to_string();
+        [tag, primary].into_iter().find(|lang| self.has(lang))
+    }
+
+    /// Best match from an `Accept-Language` header, otherwise the default.
+    pub fn negotiate(&self, accept_language: Option<&str>) -> String {
+        let mut ranges: Vec<(f32, String)> = accept_language
+            .unwrap_or_default()
+            .split(',')
+            .filter_map(|range| {
+                let mut parts = range.split(';');
+                let tag = parts.next()?.trim().to_lowercase();
+                let q = parts
+                    .find_map(|p| p.trim().strip_prefix("q="))
+                    .map_or(Some(1.0), |q| q.parse().ok())?;
+                (!tag.is_empty() && q > 0.0).then_some((q, tag))
+            })
+            .collect();
+        ranges.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+        ranges
+            .into_iter()
+            .find_map(|(_, tag)| self.find(&tag))
+            .unwrap_or_else(|| self.default_lang.clone())
+    }
+
+    /// Translate `key`, substituting `{name}` placeholders from `args`.
+    pub fn t_args(&self, lang: &str, key: &str, args: &[(&str, &str)]) -> String {
+        let text = [lang, self.default_lang.as_str(), DEFAULT_LANG]
+            .iter()
+            .find_map(|lang| self.catalogs.get(*lang)?.get(key))
+            .map(String::as_str)
+            .unwrap_or(key);
+        args.iter().fold(text.to_string(), |text, (name, value)| {
+            text.replace(&format!("{{{}}}", name), value)
+        })
+    }
+
+    pub fn t(&self, lang: &str, key: &str) -> String {
+        self.t_args(lang, key, &[])
+    }
+}
+
+/// Tera function `t(key="...", lang=lang, name=value...)`: translate a key,
+/// extra arguments fill `{name}` placeholders.
+pub fn tera_function(
+    locales: std::sync::Arc<Locales>,
+) -> impl Fn(&HashMap<String, tera::Value>) -> tera::Result<tera::Value> + Send + Sync {
+    move |args| {
+        let Some(key) = args.get("key").and_then(tera::Value::as_str) else {
+            return Err(tera::Error::msg("Function `t` requires a string `key` argument"));
+        };
+        let lang = args
+            .get("lang")
+            .and_then(tera::Value::as_str)
+            .unwrap_or(locales.default_lang());
+        let values: Vec<(&str, String)> = args
+            .iter()
+            .filter(|(name, _)| *name != "key" && *name != "lang")
+            .map(|(name, value)| {
+                let value = match value {
+                    tera::Value::String(s) => s.clone(),
+                    other => other.to_string(),
+                };
+                (name.as_str(), value)
+            })
+            .collect();
+        let values: Vec<(&str, &str)> = values.iter().map(|(n, v)| (*n, v.as_str())).collect();
+        Ok(tera::Value::String(locales.t_args(lang, key, &values)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Catalogs `ru`, `en` and `uk`, with `en` as the default
+    fn locales() -> Locales {
+        let catalog = |entries: &[(&str, &str)]| {
+            entries.iter().map(|(key, text)| (key.to_string(), text.to_string())).collect::<Catalog>()
+        };
+        let catalogs = HashMap::from([
+            (DEFAULT_LANG.to_string(), catalog(&[("greeting", "Привет"), ("builtin", "Встроенный")])),
+            ("en".to_string(), catalog(&[("greeting", "Hello"), ("farewell", "Bye, {name}!")])),
+            ("uk".to_string(), catalog(&[("greeting", "Привіт")])),
+        ]);
+        Locales {
+            default_lang: "en".to_string(),
+            catalogs,
+        }
+    }
+
+    #[test]
+    fn negotiate_follows_q_values() {
+        let locales = locales();
+        assert_eq!(locales.negotiate(Some("en;q=0.5, uk;q=0.8")), "uk");
+        assert_eq!(locales.negotiate(Some("fr, uk;q=0.1, en;q=0.2")), "en");
+        assert_eq!(locales.negotiate(Some("uk;q=0, ru;q=0.3")), "ru");
+        // A range with a broken q-value is skipped
+        assert_eq!(locales.negotiate(Some("uk;q=high, ru;q=0.3")), "ru");
+    }
+
+    #[test]
+    fn negotiate_falls_back_to_the_primary_tag() {
+        let locales = locales();
+        assert_eq!(locales.negotiate(Some("uk-UA,en;q=0.9")), "uk");
+        assert_eq!(locales.negotiate(Some("EN-us")), "en");
+        assert_eq!(locales.negotiate(Some("de-AT, fr")), "en");
+        assert_eq!(locales.negotiate(Some("")), "en");
+        assert_eq!(locales.negotiate(None), "en");
+    }
+
+    #[test]
+    fn find_ignores_case_and_region() {
+        let locales = locales();
+        assert_eq!(locales.find("EN").as_deref(), Some("en"));
+        assert_eq!(locales.find("uk-UA").as_deref(), Some("uk"));
+        assert_eq!(locales.find("de-AT"), None);
+    }
+
+    #[test]
+    fn missing_keys_fall_back_to_the_default_lang() {
+        let locales = locales();
+        assert_eq!(locales.t("uk", "greeting"), "Привіт");
+        assert_eq!(locales.t("uk", "farewell"), "Bye, {name}!");
+        assert_eq!(locales.t("de", "greeting"), "Hello");
+        // Then to the built-in catalog and to the key itself
+        assert_eq!(locales.t("uk", "builtin"), "Встроенный");
+        assert_eq!(locales.t("uk", "missing"), "missing");
+    }
+
+    #[test]
+    fn t_args_fills_placeholders() {
+        let locales = locales();
+        assert_eq!(locales.t_args("uk", "farewell", &[("name", "Bob")]), "Bye, Bob!");
+        // Unknown arguments are ignored, unfilled placeholders are kept
+        assert_eq!(locales.t_args("en", "farewell", &[("user", "Bob")]), "Bye, {name}!");
+        assert_eq!(locales.t_args("en", "{name}", &[("name", "Bob")]), "Bob");
+    }
+}
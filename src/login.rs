@@ -0,0 +1,210 @@
+//! Signing in to Telegram from the admin page.
+//!
+//! The server starts with the session it has instead of asking on stdin. While
+//! that session is not signed in, `ConnectionState` keeps the Telegram routes at
+//! `503` and an admin walks through phone, code and password here. Logging out
+//! signs the session out and starts over with a fresh one.
+//!
+//! This is the first account of `AppContext::accounts`, the others sign in
+//! with the `login` command.
+
+use crate::connection::ConnectionState;
+use crate::context::AppContext;
+use crate::source::pool::PooledSource;
+use crate::tg::TelegramAPI;
+
+use grammers_client::SignInError;
+use grammers_client::types::{LoginToken, PasswordToken};
+use rocket::http::Status;
+use std::sync::Arc;
+
+/// What the login waits for
+enum Step {
+    Phone,
+    Code { phone: String, token: LoginToken },
+    Password { hint: Option<String>, token: PasswordToken },
+}
+
+/// Reported by `GET /admin/telegram` and every login step
+#[derive(Debug, serde::Serialize)]
+pub struct LoginStatus {
+    /// `signed_in`, or the input the login waits for: `phone`, `code` or `password`
+    pub state: &'static str,
+    /// The number a code was sent to, all but the last digits hidden
+    pub phone: Option<String>,
+    /// Hint of the two-step verification password
+    pub hint: Option<String>,
+    /// Name of the signed in account, once known
+    pub user: Option<String>,
+}
+
+pub struct Login {
+    /// Held through each step, so steps run one at a time
+    step: tokio::sync::Mutex<Step>,
+    user: std::sync::Mutex<Option<String>>,
+    /// Takes calls on the account once it signs in
+    pool: Arc<PooledSource>,
+}
+
+fn mask_phone(phone: &str) -> String {
+    let digits: Vec<char> = phone.chars().filter(|c| c.is_ascii_digit()).collect();
+    let shown = digits.len().saturating_sub(4);
+    digits
+        .iter()
+        .enumerate()
+        .map(|(i, digit)| if i < shown { '*' } else { *digit })
+        .collect()
+}
+
+fn client() -> Result<grammers_client::client::Client, (Status, String)> {
+    TelegramAPI::try_client().ok_or((Status::ServiceUnavailable, "Not connected to Telegram".to_string()))
+}
+
+impl Login {
+    pub fn new(pool: Arc<PooledSource>) -> Login {
+        Login {
+            step: tokio::sync::Mutex::new(Step::Phone),
+            user: std::sync::Mutex::new(None),
+            pool,
+        }
+    }
+
+    pub async fn status(&self, connection: &ConnectionState) -> LoginStatus {
+        let step = self.step.lock().await;
+        self.status_of(&step, connection)
+    }
+
+    /// Whether the first account takes calls. The others may serve while it is signed out.
+    fn is_signed_in(&self, connection: &ConnectionState) -> bool {
+        connection.is_authorized() && self.pool.is_enabled(0)
+    }
+
+    fn status_of(&self, step: &Step, connection: &ConnectionState) -> LoginStatus {
+        let (state, phone, hint) = match step {
+            Step::Phone if self.is_signed_in(connection) => ("signed_in", None, None),
+            Step::Phone => ("phone", None, None),
+            Step::Code { phone, .. } => ("code", Some(mask_phone(phone)), None),
+            Step::Password { hint, .. } => ("password", None, hint.clone()),
+        };
+        LoginStatus {
+            state,
+            phone,
+            hint,
+            user: self.user.lock().unwrap().clone(),
+        }
+    }
+
+    /// Send a login code to `phone`, restarting a login in progress
+    pub async fn request_code(&self, connection: &ConnectionState, phone: &str) -> Result<LoginStatus, (Status, String)> {
+        let mut step = self.step.lock().await;
+        if self.is_signed_in(connection) {
+            return Err((Status::Conflict, "Already signed in, log out first".to_string()));
+        }
+        let phone = phone.trim().to_string();
+        let token = client()?
+            .request_login_code(&phone)
+            .await
+            .map_err(|e| (Status::BadRequest, format!("Can't send a code to {}: {}", mask_phone(&phone), e)))?;
+        log::info!("Sent a login code to {}", mask_phone(&phone));
+        *step = Step::Code { phone, token };
+        Ok(self.status_of(&step, connection))
+    }
+
+    /// Sign in with the code sent by `request_code`. An account with two-step verification asks
+    /// for its password next.
+    pub async fn submit_code(
+        &self,
+        ctx: &AppContext,
+        connection: &ConnectionState,
+        code: &str,
+    ) -> Result<LoginStatus, (Status, String)> {
+        let mut step = self.step.lock().await;
+        let Step::Code { phone, token } = std::mem::replace(&mut *step, Step::Phone) else {
+            return Err((Status::Conflict, "No code was requested".to_string()));
+        };
+        let client = client()?;
+        match client.sign_in(&token, code.trim()).await {
+            Ok(user) => self.signed_in(ctx, connection, &client, user.full_name())?,
+            Err(SignInError::PasswordRequired(token)) => {
+                let hint = token.hint().map(str::to_string);
+                *step = Step::Password { hint, token };
+            }
+            Err(SignInError::InvalidCode) => {
+                *step = Step::Code { phone, token };
+                return Err((Status::BadRequest, "Invalid code".to_string()));
+            }
+            Err(SignInError::SignUpRequired { .. }) => {
+                return Err((Status::BadRequest, format!("{} has no Telegram account", mask_phone(&phone))));
+            }
+            Err(e) => return Err((Status::BadRequest, format!("Can't sign in: {}", e))),
+        }
+        Ok(self.status_of(&step, connection))
+    }
+
+    /// Finish a login with the two-step verification password. A wrong password ends the login,
+    /// as its token is spent.
+    pub async fn submit_password(
+        &self,
+        ctx: &AppContext,
+        connection: &ConnectionState,
+        password: &str,
+    ) -> Result<LoginStatus, (Status, String)> {
+        let mut step = self.step.lock().await;
+        let Step::Password { token, .. } = std::mem::replace(&mut *step, Step::Phone) else {
+            return Err((Status::Conflict, "No password was asked for".to_string()));
+        };
+        let client = client()?;
+        match client.check_password(token, password).await {
+            Ok(user) => self.signed_in(ctx, connection, &client, user.full_name())?,
+            Err(SignInError::InvalidPassword) => {
+                return Err((Status::BadRequest, "Invalid password, request a new code".to_string()));
+            }
+            Err(e) => return Err((Status::BadRequest, format!("Can't sign in: {}", e))),
+        }
+        Ok(self.status_of(&step, connection))
+    }
+
+    fn signed_in(
+        &self,
+        ctx: &AppContext,
+        connection: &ConnectionState,
+        client: &grammers_client::client::Client,
+        user: String,
+    ) -> Result<(), (Status, String)> {
+        client.session().save_to_file(&ctx.tg_session).map_err(|e| {
+            (Status::InternalServerError, format!("Signed in, but can't save the session: {}", e))
+        })?;
+        log::info!("Signed in to Telegram as {}", user);
+        *self.user.lock().unwrap() = Some(user);
+        self.pool.enable(0);
+        connection.set_authorized(true);
+        Ok(())
+    }
+
+    /// Sign out, delete the session file and connect with a fresh session to sign in again
+    pub async fn logout(&self, ctx: &AppContext, connection: &ConnectionState) -> Result<LoginStatus, (Status, String)> {
+        let mut step = self.step.lock().await;
+        let client = client()?;
+        if self.is_signed_in(connection)
+            && let Err(e) = client.sign_out().await
+        {
+            log::warn!("Telegram sign out failed, the session is deleted anyway: {}", e);
+        }
+        self.pool.disable(0, "Logged out");
+        if self.pool.enabled_accounts().is_empty() {
+            connection.set_authorized(false);
+        }
+        *step = Step::Phone;
+        *self.user.lock().unwrap() = None;
+        if let Err(e) = std::fs::remove_file(&ctx.tg_session)
+            && e.kind() != std::io::ErrorKind::NotFound
+        {
+            return Err((Status::InternalServerError, format!("Can't delete the session: {}", e)));
+        }
+        TelegramAPI::start_account(ctx, 0)
+            .await
+            .map_err(|e| (Status::ServiceUnavailable, e.to_string()))?;
+        log::info!("Logged out of Telegram");
+        Ok(self.status_of(&step, connection))
+    }
+}
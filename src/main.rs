@@ -2,21 +2,38 @@ mod action;
 mod cache;
 mod card_renderer;
 mod cli;
+mod connection;
 mod context;
+mod encoder;
+mod export;
+mod formula;
 mod html_renderer;
+mod jobs;
+mod locale;
+mod login;
 mod path_util;
 mod post;
 mod post_data;
+mod scheduler;
+mod source;
 mod task;
+#[cfg(test)]
+mod tests;
 mod tg;
 mod util;
 mod workers;
 
-use crate::cache::PostCache;
-use crate::card_renderer::CardRenderer;
+use crate::action::Rank;
+use crate::cache::{ArtifactKind, ArtifactUsage, PostCache};
+use crate::card_renderer::{CardRenderer, RendererSettings};
 use crate::cli::*;
+use crate::connection::ConnectionState;
+use crate::encoder::{OutputFormat, VideoFormat};
 use crate::html_renderer::HtmlRenderer;
+use crate::jobs::{Stage, VideoJob};
+use crate::locale::Locales;
 use crate::post::TopPost;
+use crate::source::{CHUNK_SIZE, TelegramSource};
 use crate::task::*;
 use crate::util::*;
 
@@ -28,12 +45,12 @@ use rocket::http::Header;
 use rocket::response::{content, Response};
 use rocket::response::content::RawHtml;
 use rocket::response::status;
+use rocket::response::stream::{Event, EventStream};
 use rustc_hash::FxHasher;
 use simple_logger::SimpleLogger;
 use std::collections::HashMap;
 use std::hash::Hasher;
 use std::path::PathBuf;
-use std::process::Command;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
@@ -49,18 +66,67 @@ pub struct FetchProgress {
     pub done: AtomicBool,
     pub cancelled: AtomicBool,
     pub last_poll: AtomicU64,
+    /// Open `/data/.../events` streams; the watchdog leaves the fetch alone while there are any
+    pub subscribers: AtomicUsize,
     pub error: std::sync::Mutex<Option<String>>,
     pub notify: tokio::sync::Notify,
 }
 
+impl FetchProgress {
+    fn new(limit: usize) -> FetchProgress {
+        FetchProgress {
+            fetched: AtomicUsize::new(0),
+            limit: AtomicUsize::new(limit),
+            done: AtomicBool::new(false),
+            cancelled: AtomicBool::new(false),
+            last_poll: AtomicU64::new(now_secs()),
+            subscribers: AtomicUsize::new(0),
+            error: std::sync::Mutex::new(None),
+            notify: tokio::sync::Notify::new(),
+        }
+    }
+}
+
+/// Held by an event stream for as long as its client stays connected
+struct FetchSubscription(Arc<FetchProgress>);
+
+impl FetchSubscription {
+    fn new(progress: Arc<FetchProgress>) -> FetchSubscription {
+        progress.subscribers.fetch_add(1, Ordering::Relaxed);
+        FetchSubscription(progress)
+    }
+}
+
+impl Drop for FetchSubscription {
+    fn drop(&mut self) {
+        // Pollers of the same fetch get the usual grace period from now on
+        self.0.last_poll.store(now_secs(), Ordering::Relaxed);
+        self.0.subscribers.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
 struct App {
     args: Args,
     ctx: context::AppContext,
     cache: PostCache,
     html_renderer: HtmlRenderer,
-    card_renderer: CardRenderer,
+    /// `None` when the app runs without a browser, e.g. in tests
+    card_renderer: Option<CardRenderer>,
+    locales: Arc<Locales>,
+    tg: Arc<dyn TelegramSource>,
+    /// The same source as `tg`, for its accounts
+    pool: Arc<source::pool::PooledSource>,
+    connection: ConnectionState,
+    login: login::Login,
     fetch_progress: std::sync::Mutex<HashMap<String, Arc<FetchProgress>>>,
-    tg_semaphore: Arc<tokio::sync::Semaphore>,
+    video_jobs: std::sync::Mutex<HashMap<String, Arc<VideoJob>>>,
+    /// One lock per video content hash, so the same video is never rendered twice at once
+    video_renders: std::sync::Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>,
+    /// One lock per media file or thumbnail, so concurrent cache misses download it once
+    media_downloads: std::sync::Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>,
+    /// When each old username was last asked of Telegram, see `alias_holds`
+    alias_checks: std::sync::Mutex<HashMap<String, i64>>,
+    block_specs: workers::block::BlockSpecCache,
 }
 
 struct VideoRenderTimings {
@@ -73,6 +139,33 @@ struct CachedVideoCandidate {
     file: PathBuf,
 }
 
+/// Longest post video spliced into a video card
+const MAX_POST_VIDEO_SECONDS: u32 = 60;
+
+/// Largest post video downloaded for a video card
+const MAX_POST_VIDEO_SIZE: i64 = 50 * 1024 * 1024;
+
+/// Shape and container of a requested video
+#[derive(Copy, Clone, Default)]
+struct VideoOptions {
+    format: VideoFormat,
+    output: OutputFormat,
+    /// Seconds of a post's own video played on its card
+    post_video: Option<u32>,
+}
+
+impl VideoOptions {
+    /// Distinguishes videos of the same digest in the cache
+    fn key(&self) -> String {
+        format!(
+            "{}:{}:{}",
+            self.format.as_str(),
+            self.output.as_str(),
+            self.post_video.unwrap_or(0)
+        )
+    }
+}
+
 impl App {
     async fn new() -> Result<App> {
         let args = Args::parse_args();
@@ -84,12 +177,63 @@ impl App {
             }
         };
 
+        // Only the server and the commands rendering cards run a browser. The commands serve the
+        // render pages on a port of their own, so they work next to a running server.
+        let render_port = match &args.command {
+            None | Some(CliCommand::Serve) => Some(
+                std::env::var("ROCKET_PORT")
+                    .ok()
+                    .and_then(|port| port.parse().ok())
+                    .unwrap_or(8000),
+            ),
+            Some(CliCommand::Cards { .. } | CliCommand::Video { .. }) => Some(free_port()?),
+            Some(_) => None,
+        };
+        let card_renderer = match render_port {
+            Some(port) => Some(
+                CardRenderer::new(RendererSettings {
+                    pool_size: ctx.renderer_pool_size,
+                    page_uses: ctx.renderer_page_uses,
+                    render_timeout: Duration::from_secs(ctx.render_timeout_secs),
+                    ready_timeout: Duration::from_secs(ctx.render_ready_timeout_secs),
+                    port,
+                })
+                .await?,
+            ),
+            None => None,
+        };
+        App::with_context(args, ctx, card_renderer)
+    }
+
+    fn with_context(args: Args, ctx: context::AppContext, card_renderer: Option<CardRenderer>) -> Result<App> {
         let db_path = ctx.tg_session.with_file_name("cache.db");
         let cache = PostCache::new(&db_path, &ctx.output_dir, ctx.cache_limit_mb)?;
         log::info!("Opened cache DB at {}, media cache in {}", db_path.display(), ctx.output_dir.display());
 
-        let html_renderer: HtmlRenderer = HtmlRenderer::new(&ctx)?;
-        let card_renderer: CardRenderer = CardRenderer::new().await?;
+        let locales = Arc::new(Locales::new(&ctx.input_dir, &ctx.default_lang)?);
+        let html_renderer: HtmlRenderer = HtmlRenderer::new(&ctx, locales.clone())?;
+
+        let accounts: Vec<(String, Arc<dyn TelegramSource>)> = match &ctx.fixture_dir {
+            // Every account answers from the same fixtures
+            Some(dir) => {
+                let fixtures: Arc<dyn TelegramSource> = Arc::new(source::fixture::FixtureSource::new(dir)?);
+                (0..ctx.accounts().len())
+                    .map(|account| (format!("fixtures{}", account), fixtures.clone()))
+                    .collect()
+            }
+            None => ctx
+                .accounts()
+                .iter()
+                .enumerate()
+                .map(|(account, credentials)| {
+                    let name = credentials.tg_session.file_stem().unwrap_or_default().to_string_lossy().to_string();
+                    let source: Arc<dyn TelegramSource> = Arc::new(source::grammers::GrammersSource::new(account));
+                    (name, source)
+                })
+                .collect(),
+        };
+        let pool = Arc::new(source::pool::PooledSource::new(accounts));
+        let tg: Arc<dyn TelegramSource> = pool.clone();
 
         Ok(App {
             args,
@@ -97,8 +241,17 @@ impl App {
             cache,
             html_renderer,
             card_renderer,
+            locales,
+            tg,
+            login: login::Login::new(pool.clone()),
+            pool,
+            connection: ConnectionState::new(),
             fetch_progress: std::sync::Mutex::new(HashMap::new()),
-            tg_semaphore: Arc::new(tokio::sync::Semaphore::new(2)),
+            video_jobs: std::sync::Mutex::new(HashMap::new()),
+            video_renders: std::sync::Mutex::new(HashMap::new()),
+            media_downloads: std::sync::Mutex::new(HashMap::new()),
+            alias_checks: std::sync::Mutex::new(HashMap::new()),
+            block_specs: workers::block::BlockSpecCache::default(),
         })
     }
 }
@@ -126,20 +279,21 @@ struct PageMeta {
     image_alt: String,
 }
 
-fn channel_page_meta(base_url: &str, channel_name: &str, channel_title: &str) -> PageMeta {
+fn channel_page_meta(locales: &Locales, lang: &str, base_url: &str, channel_name: &str, channel_title: &str) -> PageMeta {
+    let title = locales.t_args(lang, "meta.channel_title", &[("channel", channel_title)]);
     PageMeta {
-        title: format!("Лучшие посты телеграм-канала {}", channel_title),
-        description: format!("Лучшие посты телеграм-канала {}", channel_title),
+        title: title.clone(),
+        description: title,
         url: format!("{}/digest/main/{}", base_url.trim_end_matches('/'), channel_name),
         image: format!("{}/userpic/{}", base_url.trim_end_matches('/'), channel_name),
-        image_alt: format!("Аватар канала {}", channel_title),
+        image_alt: locales.t_args(lang, "meta.channel_image_alt", &[("channel", channel_title)]),
     }
 }
 
-fn service_page_meta(base_url: &str) -> PageMeta {
+fn service_page_meta(locales: &Locales, lang: &str, base_url: &str) -> PageMeta {
     PageMeta {
-        title: "TGDigest — статистика и видео-дайджесты для телеграм-каналов".to_string(),
-        description: "Смотри лучшие посты и собирай видео-дайджесты для сторис.".to_string(),
+        title: locales.t(lang, "meta.service_title"),
+        description: locales.t(lang, "meta.service_description"),
         url: base_url.trim_end_matches('/').to_string(),
         image: format!("{}/logo.png", base_url.trim_end_matches('/')),
         image_alt: "TGDigest".to_string(),
@@ -161,28 +315,302 @@ fn get_cached_top_posts(app: &App, task: &Task, fetch_target: Option<usize>, for
     let is_loading = !fetch_plan.is_empty();
     posts.sort_by_key(|p| p.id);
     posts.dedup_by_key(|p| p.id);
-    Ok((TopPost::get_top(task.top_count, &mut posts), is_loading))
+
+    let velocities = app.cache.get_velocities(
+        &task.channel_name, task.from_date, task.to_date, task.velocity_window * 3600,
+    )?;
+    let reactions = match &task.reaction {
+        Some(reaction) => app.cache.get_reaction_counts(
+            &task.channel_name, task.from_date, task.to_date, reaction,
+        )?,
+        None => HashMap::new(),
+    };
+    let mut top_comments = app.cache.get_top_comments(&task.channel_name, task.from_date, task.to_date)?;
+    for post in posts.iter_mut() {
+        post.velocity = velocities.get(&post.id).copied();
+        post.rates = post.engagement_rates(app.ctx.min_rate_views);
+        post.selected_reaction = reactions.get(&post.id).copied();
+        post.top_comment = top_comments.remove(&post.id);
+    }
+
+    Ok((TopPost::get_top(task.top_count, &mut posts, task.rank), is_loading))
 }
 
-fn cached_video_candidate(
+/// Cards page of `task` with cards sized for `options.format`
+fn render_video_html(
     app: &App,
     task: &Task,
     post_top: TopPost,
-) -> std::result::Result<CachedVideoCandidate, status::Custom<String>> {
-    let render_context = workers::cards::create_context(post_top, task.clone())
+    options: VideoOptions,
+) -> std::result::Result<String, status::Custom<String>> {
+    let mut render_context = workers::cards::create_context(post_top, task.clone(), load_block_specs(app, &task.mode)?, &app.locales)
         .map_err(|e| http_status(Status::BadRequest, e.to_string().as_ref()))?;
-    let rendered_html = app
-        .html_renderer
+    insert_card_size(&mut render_context, options.format);
+    app.html_renderer
         .render(
             format!("{}/render_template.html", task.mode).as_str(),
             &render_context,
         )
-        .map_err(|e| http_status(Status::InternalServerError, e.to_string().as_ref()))?;
-    let task_id = hash(format!("video:{}:{}", task.mode, rendered_html));
-    let file = app.ctx.output_dir.join(format!("{}.mp4", task_id));
+        .map_err(|e| http_status(Status::InternalServerError, e.to_string().as_ref()))
+}
+
+fn insert_card_size(context: &mut tera::Context, format: VideoFormat) {
+    let (card_width, card_height) = format.card_size();
+    context.insert("format", format.as_str());
+    context.insert("card_width", &card_width);
+    context.insert("card_height", &card_height);
+}
+
+/// Videos are cached by their content, so the same digest in another format or container is another video
+fn video_task_id(task: &Task, options: VideoOptions, rendered_html: &str) -> String {
+    hash(format!("video:{}:{}:{}", task.mode, options.key(), rendered_html))
+}
+
+fn video_file(app: &App, task_id: &str, options: VideoOptions) -> PathBuf {
+    app.ctx
+        .output_dir
+        .join(format!("{}.{}", task_id, options.output.as_str()))
+}
+
+fn cached_video_candidate(
+    app: &App,
+    task: &Task,
+    post_top: TopPost,
+    options: VideoOptions,
+) -> std::result::Result<CachedVideoCandidate, status::Custom<String>> {
+    let rendered_html = render_video_html(app, task, post_top, options)?;
+    let task_id = video_task_id(task, options, &rendered_html);
+    let file = video_file(app, &task_id, options);
     Ok(CachedVideoCandidate { task_id, file })
 }
 
+/// Explicit `lang` if it has a catalog (matched like an `Accept-Language` tag), otherwise
+/// negotiated from `Accept-Language`.
+fn get_lang(
+    app: &App,
+    lang: Option<&str>,
+    accept_language: &AcceptLanguage,
+) -> std::result::Result<String, status::Custom<String>> {
+    match lang {
+        Some(lang) => match app.locales.find(lang) {
+            Some(lang) => Ok(lang),
+            None => http_status_err(Status::BadRequest, &format!("Unknown lang '{}'", lang)),
+        },
+        None => Ok(app.locales.negotiate(accept_language.0.as_deref())),
+    }
+}
+
+/// Request guard to extract Accept-Language header.
+struct AcceptLanguage(Option<String>);
+
+#[rocket::async_trait]
+impl<'r> rocket::request::FromRequest<'r> for AcceptLanguage {
+    type Error = ();
+    async fn from_request(
+        req: &'r rocket::Request<'_>,
+    ) -> rocket::request::Outcome<Self, Self::Error> {
+        let accept_language = req.headers().get_one("Accept-Language").map(str::to_string);
+        rocket::request::Outcome::Success(AcceptLanguage(accept_language))
+    }
+}
+
+/// 503 while Telegram is unreachable or signed out, `Retry-After` is added by the fairing in `rocket()`.
+fn ensure_online(app: &App) -> std::result::Result<(), status::Custom<String>> {
+    if app.connection.is_online() {
+        return Ok(());
+    }
+    if !app.connection.is_authorized() {
+        return http_status_err(Status::ServiceUnavailable, "Telegram is signed out, an admin signs in at /admin");
+    }
+    http_status_err(
+        Status::ServiceUnavailable,
+        &format!("Telegram is unavailable, retry in {}s", app.connection.retry_after()),
+    )
+}
+
+/// Adds `degraded` and `retry_after` to a `/data` response served from cache while Telegram is unreachable.
+fn insert_connection_state(app: &App, json: &mut serde_json::Value) {
+    if !app.connection.is_online() {
+        json["degraded"] = serde_json::json!(true);
+        if app.connection.is_authorized() {
+            json["retry_after"] = serde_json::json!(app.connection.retry_after());
+        } else {
+            json["signed_out"] = serde_json::json!(true);
+        }
+    }
+}
+
+/// Digest data of the cached posts, as `/data` returns it.
+async fn digest_json(app: &App, task: &Task) -> std::result::Result<serde_json::Value, status::Custom<String>> {
+    let (post_top, _) = get_cached_top_posts(app, task, None, false)
+        .map_err(|e| http_status(Status::InternalServerError, e.to_string().as_ref()))?;
+
+    let channel_title = channel_title(app, &task.channel_name).await;
+
+    let data = workers::digest::create_digest_data(
+        post_top,
+        task.clone(),
+        load_block_specs(app, &task.mode)?,
+        &app.locales,
+        &channel_title,
+        &app.ctx.public_base_url(),
+        &app.ctx.public_site_name(),
+    )
+        .map_err(|e| http_status(Status::InternalServerError, e.to_string().as_ref()))?;
+
+    Ok(data.to_json())
+}
+
+/// Context of a static digest template from the cached posts of `task`
+async fn digest_context(app: &App, task: &Task) -> std::result::Result<tera::Context, status::Custom<String>> {
+    let (post_top, _) = get_cached_top_posts(app, task, None, false)
+        .map_err(|e| http_status(Status::InternalServerError, e.to_string().as_ref()))?;
+
+    let channel_title = channel_title(app, &task.channel_name).await;
+    let base_url = app.ctx.public_base_url();
+
+    let data = workers::digest::create_digest_data(
+        post_top,
+        task.clone(),
+        load_block_specs(app, &task.mode)?,
+        &app.locales,
+        &channel_title,
+        &base_url,
+        &app.ctx.public_site_name(),
+    )
+        .map_err(|e| http_status(Status::InternalServerError, e.to_string().as_ref()))?;
+    let mut context = data.to_context();
+    insert_page_meta(
+        &mut context,
+        &channel_page_meta(&app.locales, &task.lang, &base_url, &task.channel_name, &channel_title),
+    );
+    Ok(context)
+}
+
+/// Channel title, the one it had when last resolved while Telegram is away, or the channel name
+/// if it can't be resolved.
+async fn channel_title(app: &App, channel_name: &str) -> String {
+    let known = || {
+        app.cache
+            .channel(channel_name)
+            .ok()
+            .flatten()
+            .map(|channel| channel.title)
+            .unwrap_or_else(|| channel_name.to_string())
+    };
+    if !app.connection.is_online() {
+        return known();
+    }
+    match app.tg.resolve_channel(channel_name).await {
+        Ok(channel) => {
+            if let Err(e) = app.cache.record_channel(channel_name, &channel) {
+                log::warn!("Can't record channel {}: {}", channel_name, e);
+            }
+            channel.title
+        }
+        Err(_) => known(),
+    }
+}
+
+/// Look up the id of a channel named by username or invite link once, so its cached posts are
+/// keyed by the id
+async fn identify_channel(app: &App, channel_name: &str) -> Result<()> {
+    if app.cache.is_identified(channel_name)? {
+        return Ok(());
+    }
+    let channel = app.tg.resolve_channel(channel_name).await?;
+    app.cache.record_channel(channel_name, &channel)
+}
+
+/// Routes with a channel in the path, by prefix, and the index of the channel segment
+const CHANNEL_ROUTES: [(&str, usize); 9] = [
+    ("digest", 2),
+    ("data", 2),
+    ("video", 2),
+    ("post", 1),
+    ("history", 1),
+    ("view", 1),
+    ("thumb", 1),
+    ("media", 1),
+    ("userpic", 1),
+];
+
+/// How long an old username is trusted before Telegram is asked whether another channel took it
+const ALIAS_RECHECK_SECS: i64 = 3600;
+
+/// Where a request naming a channel by a username it no longer has moves to, see `moved`
+struct ChannelMoved(Option<String>);
+
+/// Whether the old username `name` still leads to its channel. Resolving it records the channel
+/// that took the username since, which ends the alias.
+async fn alias_holds(app: &App, name: &str) -> bool {
+    let now = chrono::Utc::now().timestamp();
+    {
+        let mut checks = app.alias_checks.lock().unwrap();
+        if !app.connection.is_online() || checks.get(name).is_some_and(|checked| now - checked < ALIAS_RECHECK_SECS) {
+            return true;
+        }
+        checks.insert(name.to_string(), now);
+    }
+    // Nobody answering to the name keeps the alias
+    let Ok(channel) = app.tg.resolve_channel(name).await else {
+        return true;
+    };
+    if let Err(e) = app.cache.record_channel(name, &channel) {
+        log::warn!("Can't record channel {}: {}", name, e);
+    }
+    app.cache.renamed_channel(name).ok().flatten().is_some()
+}
+
+/// The request URI with the old username of a renamed channel replaced by the current one
+async fn renamed_channel_uri(app: &App, uri: &rocket::http::uri::Origin<'_>) -> Option<String> {
+    let mut segments: Vec<String> = uri.path().segments().map(str::to_string).collect();
+    let index = CHANNEL_ROUTES
+        .iter()
+        .find(|(prefix, _)| segments.first().is_some_and(|first| first == prefix))
+        .map(|(_, index)| *index)?;
+    let channel = segments.get(index)?;
+    app.cache.renamed_channel(channel).ok().flatten()?;
+    if !alias_holds(app, channel).await {
+        return None;
+    }
+    let current = app.cache.renamed_channel(channel).ok().flatten()?;
+    log::debug!("{} is now {}", channel, current);
+    segments[index] = current;
+    let mut moved = format!("/{}", segments.join("/"));
+    if let Some(query) = uri.query() {
+        moved.push('?');
+        moved.push_str(query.as_str());
+    }
+    Some(moved)
+}
+
+/// Old URLs of a renamed channel land here from the fairing in `rocket()`. The redirect is
+/// temporary, another channel may take the old username.
+#[get("/moved")]
+fn moved(to: &ChannelMoved) -> Option<rocket::response::Redirect> {
+    to.0.clone().map(rocket::response::Redirect::temporary)
+}
+
+#[rocket::async_trait]
+impl<'r> rocket::request::FromRequest<'r> for &'r ChannelMoved {
+    type Error = ();
+
+    async fn from_request(req: &'r rocket::Request<'_>) -> rocket::request::Outcome<Self, ()> {
+        rocket::request::Outcome::Success(req.local_cache(|| ChannelMoved(None)))
+    }
+}
+
+/// Blocks declared in `<input_dir>/<mode>/blocks.json`, if any.
+fn load_block_specs(
+    app: &App,
+    mode: &str,
+) -> std::result::Result<Option<Vec<workers::block::BlockSpec>>, status::Custom<String>> {
+    app.block_specs
+        .get(&app.ctx.input_dir, mode)
+        .map_err(|e| http_status(Status::InternalServerError, e.to_string().as_ref()))
+}
+
 /// Compute how many additional posts to pull into the cache.
 ///
 /// - Without `force_limit`: `min(top_count, 1000)` — capped at the default.
@@ -195,6 +623,69 @@ fn compute_fetch_target(force_limit: bool, top_count: usize) -> Option<usize> {
     }
 }
 
+fn get_rank(
+    rank: Option<&str>,
+    velocity_window: Option<i64>,
+) -> std::result::Result<(Rank, i64), status::Custom<String>> {
+    let rank = match rank {
+        Some(rank) => Rank::parse(rank)
+            .ok_or_else(|| http_status(Status::BadRequest, "Provided rank is not allowed"))?,
+        None => Rank::default(),
+    };
+    let velocity_window = velocity_window.unwrap_or(task::DEFAULT_VELOCITY_WINDOW);
+    if velocity_window <= 0 || velocity_window > task::MAX_VELOCITY_WINDOW {
+        return http_status_err(Status::BadRequest, "Provided velocity window is not allowed");
+    }
+    Ok((rank, velocity_window))
+}
+
+fn get_video_options(
+    format: Option<&str>,
+    output: Option<&str>,
+    post_video: Option<u32>,
+) -> std::result::Result<VideoOptions, status::Custom<String>> {
+    let format = match format {
+        Some(format) => VideoFormat::parse(format)
+            .ok_or_else(|| http_status(Status::BadRequest, "Provided video format is not allowed"))?,
+        None => VideoFormat::default(),
+    };
+    let output = match output {
+        Some(output) => OutputFormat::parse(output)
+            .ok_or_else(|| http_status(Status::BadRequest, "Provided video output is not allowed"))?,
+        None => OutputFormat::default(),
+    };
+    if post_video.is_some_and(|seconds| seconds == 0 || seconds > MAX_POST_VIDEO_SECONDS) {
+        return http_status_err(Status::BadRequest, "Provided post video length is not allowed");
+    }
+    Ok(VideoOptions {
+        format,
+        output,
+        post_video,
+    })
+}
+
+/// Checks the mode's `video.json` against the requested options, before any card is rendered
+fn check_video_spec(app: &App, mode: &str, options: VideoOptions) -> std::result::Result<(), status::Custom<String>> {
+    let video_spec = encoder::VideoSpec::load(&app.ctx.input_dir, mode)
+        .map_err(|e| http_status(Status::InternalServerError, e.to_string().as_ref()))?;
+    if let Some(seconds) = options.post_video {
+        video_spec.check_post_video(seconds).map_err(|e| http_status(Status::BadRequest, &e))?;
+    }
+    Ok(())
+}
+
+/// Query string suffix forwarding the ranking options to `/data/`.
+fn ranking_query(task: &Task) -> String {
+    let mut query = match task.rank {
+        Rank::Total => String::new(),
+        Rank::Velocity => format!("&rank={}&velocity_window={}", task.rank.as_str(), task.velocity_window),
+    };
+    if let Some(reaction) = &task.reaction {
+        query.push_str(&format!("&reaction={}", rocket::http::RawStr::new(reaction).percent_encode()));
+    }
+    query
+}
+
 fn now_secs() -> u64 {
     SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
 }
@@ -203,15 +694,7 @@ fn start_background_fetch(app: &Arc<App>, task: &Task, force: bool, fetch_target
     let task_id = format!("{}:{}:{}:{}:{:?}", task.channel_name, task.from_date, task.to_date, force, fetch_target);
 
     let estimated_limit = fetch_target.unwrap_or(cache::ALWAYS_REFRESH_HEAD);
-    let progress = Arc::new(FetchProgress {
-        fetched: AtomicUsize::new(0),
-        limit: AtomicUsize::new(estimated_limit),
-        done: AtomicBool::new(false),
-        cancelled: AtomicBool::new(false),
-        last_poll: AtomicU64::new(now_secs()),
-        error: std::sync::Mutex::new(None),
-        notify: tokio::sync::Notify::new(),
-    });
+    let progress = Arc::new(FetchProgress::new(estimated_limit));
 
     {
         let mut map = app.fetch_progress.lock().unwrap();
@@ -240,11 +723,12 @@ fn start_background_fetch(app: &Arc<App>, task: &Task, force: bool, fetch_target
         progress.notify.notify_waiters();
     });
 
-    // Watchdog: cancel fetch if client stops polling for 10s
+    // Watchdog: cancel fetch if client stops polling for 10s and no event stream is connected
     tokio::spawn(async move {
         loop {
             tokio::time::sleep(std::time::Duration::from_secs(1)).await;
             if progress_clone.done.load(Ordering::Relaxed) { break; }
+            if progress_clone.subscribers.load(Ordering::Relaxed) > 0 { continue; }
             let elapsed = now_secs() - progress_clone.last_poll.load(Ordering::Relaxed);
             if elapsed > 10 {
                 log::info!("Client stopped polling, cancelling fetch");
@@ -269,7 +753,7 @@ async fn wait_background_fetch(app: &Arc<App>, task_id: &str) {
         };
 
         progress.last_poll.store(now_secs(), Ordering::Relaxed);
-        if progress.done.load(Ordering::Relaxed) {
+        if progress.done.load(Ordering::Relaxed) || !app.connection.is_online() {
             break;
         }
 
@@ -287,8 +771,7 @@ async fn background_fetch(
     force: bool,
     progress: &Arc<FetchProgress>,
 ) -> std::result::Result<(), Box<dyn std::error::Error>> {
-    let client = tg::TelegramAPI::client();
-
+    identify_channel(app, &task.channel_name).await?;
     let (_, fetch_plan) = app.cache.get_posts_and_fetch_plan(
         &task.channel_name, task.from_date, task.to_date, fetch_target, force,
     )?;
@@ -324,14 +807,28 @@ async fn background_fetch(
                 ..task.clone()
             };
 
-            let fetched = tokio::time::timeout(
-                std::time::Duration::from_secs(120),
-                workers::tg::fetch_posts(
-                    &client, &sub_task, batch_limit,
-                    Some(&progress.fetched), Some(&progress.cancelled),
-                ),
-            ).await
-                .map_err(|_| -> Box<dyn std::error::Error> { "Telegram fetch timed out".into() })??;
+            // Pages wait for their turn and time out on their own, see `TelegramSource::fetch_page`
+            let fetched = app.tg.fetch_posts(
+                &sub_task, batch_limit,
+                Some(&progress.fetched), Some(&progress.cancelled),
+            ).await.map_err(|e| e.to_string());
+            let fetched = match fetched {
+                Ok(fetched) => fetched,
+                Err(e) => {
+                    if app.connection.confirm_online().await {
+                        return Err(e.into());
+                    }
+                    // Connection lost — resume this batch once the supervisor reconnects
+                    log::info!("Fetch for {} paused until Telegram is back: {}", task.channel_name, e);
+                    while !app.connection.is_online() && !progress.cancelled.load(Ordering::Relaxed) {
+                        let _ = tokio::time::timeout(
+                            std::time::Duration::from_secs(1),
+                            app.connection.wait_online(),
+                        ).await;
+                    }
+                    continue;
+                }
+            };
 
             let fetched_count = fetched.len();
             let oldest_fetched_date = fetched.iter().map(|p| p.date).min();
@@ -339,6 +836,8 @@ async fn background_fetch(
             let _ = app.cache.store_posts(&task.channel_name, &fetched);
             let _ = app.cache.touch_posts_in_range(&task.channel_name, *from, current_to);
             let _ = app.cache.update_fetch_bounds(&task.channel_name, *from, current_to);
+            // Event streams send a fresh snapshot after every stored batch
+            progress.notify.notify_waiters();
 
             remaining = remaining.saturating_sub(fetched_count);
 
@@ -355,6 +854,39 @@ async fn background_fetch(
         }
     }
 
+    if app.ctx.comment_posts > 0 && !progress.cancelled.load(Ordering::Relaxed) {
+        fetch_top_threads(app, task).await?;
+    }
+
+    Ok(())
+}
+
+/// Discussion threads are fetched again once their cache is older
+const COMMENTS_TTL: i64 = 3600;
+
+/// Fetch the replies to a post and cache them
+async fn fetch_comments(app: &App, channel: &str, id: i32) -> Result<Vec<post_data::CommentData>> {
+    let comments = app.tg.get_comments(channel, id, app.ctx.comment_limit).await?;
+    app.cache.store_comments(channel, id, &comments)?;
+    Ok(comments)
+}
+
+/// Load the stale threads of the `comment_posts` most commented posts of the task's range, for
+/// the top comment block
+async fn fetch_top_threads(app: &App, task: &Task) -> Result<()> {
+    let (mut posts, _) = app.cache.get_posts_and_fetch_plan(
+        &task.channel_name, task.from_date, task.to_date, None, false,
+    )?;
+    posts.retain(|p| p.replies.is_some_and(|replies| replies > 0));
+    posts.sort_by_key(|p| std::cmp::Reverse(p.replies));
+    for post in posts.iter().take(app.ctx.comment_posts) {
+        if app.cache.get_comments(&task.channel_name, post.id, Some(COMMENTS_TTL))?.is_some() {
+            continue;
+        }
+        if let Err(e) = fetch_comments(app, &task.channel_name, post.id).await {
+            log::warn!("Can't fetch comments of {}/{}: {}", task.channel_name, post.id, e);
+        }
+    }
     Ok(())
 }
 
@@ -420,7 +952,7 @@ async fn file(app: &rocket::State<Arc<App>>, file: &str) -> Option<NamedFile> {
     }
 }
 
-#[get("/?<mode>&<channel>&<top_count>&<editor_choice>&<from_date>&<to_date>&<force>&<force_limit>")]
+#[get("/?<mode>&<channel>&<top_count>&<editor_choice>&<from_date>&<to_date>&<force>&<force_limit>&<rank>&<velocity_window>&<reaction>&<lang>")]
 async fn index(
     mode: Option<&str>,
     channel: Option<&str>,
@@ -430,6 +962,11 @@ async fn index(
     to_date: Option<i64>,
     force: Option<bool>,
     force_limit: Option<bool>,
+    rank: Option<&str>,
+    velocity_window: Option<i64>,
+    reaction: Option<&str>,
+    lang: Option<&str>,
+    accept_language: AcceptLanguage,
     app: &rocket::State<Arc<App>>,
 ) -> std::result::Result<RawHtml<String>, status::Custom<String>> {
     let mode = mode.unwrap_or("main");
@@ -445,10 +982,16 @@ async fn index(
             to_date,
             force,
             force_limit,
+            rank,
+            velocity_window,
+            reaction,
+            lang,
+            accept_language,
             app,
         ).await;
     }
 
+    let (rank, velocity_window) = get_rank(rank, velocity_window)?;
     let defaults = Task::default();
     let task = Task {
         command: Commands::Digest {},
@@ -458,6 +1001,10 @@ async fn index(
         editor_choice_post_id: editor_choice.unwrap_or(defaults.editor_choice_post_id),
         from_date: from_date.unwrap_or(defaults.from_date),
         to_date: to_date.unwrap_or(defaults.to_date),
+        rank,
+        velocity_window,
+        reaction: reaction.map(str::to_string),
+        lang: get_lang(app, lang, &accept_language)?,
         ..defaults
     };
 
@@ -472,11 +1019,10 @@ async fn index(
     );
     if force.unwrap_or(false) { data_url.push_str("&force=true"); }
     if force_limit.unwrap_or(false) { data_url.push_str("&force_limit=true"); }
+    data_url.push_str(&ranking_query(&task));
+    data_url.push_str(&format!("&lang={}", task.lang));
 
-    let client = tg::TelegramAPI::client();
-    let channel_title = workers::tg::get_channel_title(&client, &task.channel_name)
-        .await
-        .unwrap_or_else(|_| task.channel_name.clone());
+    let channel_title = channel_title(app, &task.channel_name).await;
 
     let base_url = app.ctx.public_base_url();
     let site_name = app.ctx.public_site_name();
@@ -484,16 +1030,17 @@ async fn index(
     context.insert("channel_name", &task.channel_name);
     context.insert("channel_title", &channel_title);
     context.insert("data_url", &data_url);
+    context.insert("lang", &task.lang);
     context.insert("base_url", &base_url);
     context.insert("site_name", &site_name);
-    insert_page_meta(&mut context, &service_page_meta(&base_url));
+    insert_page_meta(&mut context, &service_page_meta(&app.locales, &task.lang, &base_url));
 
     let digest = app.html_renderer.render("main/digest_template.html", &context)
         .map_err(|e| http_status(Status::InternalServerError, e.to_string().as_ref()))?;
     Ok(content::RawHtml(digest))
 }
 
-#[get("/digest/<mode>/<channel>/<year>/<month>/<week>?<top_count>&<editor_choice>&<force>")]
+#[get("/digest/<mode>/<channel>/<year>/<month>/<week>?<top_count>&<editor_choice>&<force>&<rank>&<velocity_window>&<reaction>&<lang>")]
 async fn digest_by_week(
     mode: &str,
     channel: &str,
@@ -503,6 +1050,11 @@ async fn digest_by_week(
     top_count: Option<usize>,
     editor_choice: Option<i32>,
     force: Option<bool>,
+    rank: Option<&str>,
+    velocity_window: Option<i64>,
+    reaction: Option<&str>,
+    lang: Option<&str>,
+    accept_language: AcceptLanguage,
     app: &rocket::State<Arc<App>>,
 ) -> std::result::Result<RawHtml<String>, status::Custom<String>> {
     let from_date = get_date_from_week(year, month, week)?;
@@ -517,12 +1069,17 @@ async fn digest_by_week(
         Some(to_date.timestamp()),
         force,
         None,
+        rank,
+        velocity_window,
+        reaction,
+        lang,
+        accept_language,
         app,
     )
     .await
 }
 
-#[get("/digest/<mode>/<channel>/<year>/<month>?<top_count>&<editor_choice>&<force>")]
+#[get("/digest/<mode>/<channel>/<year>/<month>?<top_count>&<editor_choice>&<force>&<rank>&<velocity_window>&<reaction>&<lang>")]
 async fn digest_by_month(
     mode: &str,
     channel: &str,
@@ -531,6 +1088,11 @@ async fn digest_by_month(
     top_count: Option<usize>,
     editor_choice: Option<i32>,
     force: Option<bool>,
+    rank: Option<&str>,
+    velocity_window: Option<i64>,
+    reaction: Option<&str>,
+    lang: Option<&str>,
+    accept_language: AcceptLanguage,
     app: &rocket::State<Arc<App>>,
 ) -> std::result::Result<RawHtml<String>, status::Custom<String>> {
     let from_date = get_date_from_month(year, month)?;
@@ -546,12 +1108,17 @@ async fn digest_by_month(
         Some(to_date.timestamp()),
         force,
         None,
+        rank,
+        velocity_window,
+        reaction,
+        lang,
+        accept_language,
         app,
     )
     .await
 }
 
-#[get("/digest/<mode>/<channel>/<year>?<top_count>&<editor_choice>&<force>")]
+#[get("/digest/<mode>/<channel>/<year>?<top_count>&<editor_choice>&<force>&<rank>&<velocity_window>&<reaction>&<lang>")]
 async fn digest_by_year(
     mode: &str,
     channel: &str,
@@ -559,6 +1126,11 @@ async fn digest_by_year(
     top_count: Option<usize>,
     editor_choice: Option<i32>,
     force: Option<bool>,
+    rank: Option<&str>,
+    velocity_window: Option<i64>,
+    reaction: Option<&str>,
+    lang: Option<&str>,
+    accept_language: AcceptLanguage,
     app: &rocket::State<Arc<App>>,
 ) -> std::result::Result<RawHtml<String>, status::Custom<String>> {
     let from_date = get_date_from_year(year)?;
@@ -573,12 +1145,17 @@ async fn digest_by_year(
         Some(to_date.timestamp()),
         force,
         None,
+        rank,
+        velocity_window,
+        reaction,
+        lang,
+        accept_language,
         app,
     )
     .await
 }
 
-#[get("/digest/<mode>/<channel>?<top_count>&<editor_choice>&<from_date>&<to_date>&<force>&<force_limit>")]
+#[get("/digest/<mode>/<channel>?<top_count>&<editor_choice>&<from_date>&<to_date>&<force>&<force_limit>&<rank>&<velocity_window>&<reaction>&<lang>")]
 async fn digest(
     mode: &str,
     channel: &str,
@@ -588,8 +1165,14 @@ async fn digest(
     to_date: Option<i64>,
     force: Option<bool>,
     force_limit: Option<bool>,
+    rank: Option<&str>,
+    velocity_window: Option<i64>,
+    reaction: Option<&str>,
+    lang: Option<&str>,
+    accept_language: AcceptLanguage,
     app: &rocket::State<Arc<App>>,
 ) -> std::result::Result<RawHtml<String>, status::Custom<String>> {
+    let (rank, velocity_window) = get_rank(rank, velocity_window)?;
     let defaults = Task::default();
     let task = Task {
         command: Commands::Digest {},
@@ -599,6 +1182,10 @@ async fn digest(
         editor_choice_post_id: editor_choice.unwrap_or(defaults.editor_choice_post_id),
         from_date: from_date.unwrap_or(defaults.from_date),
         to_date: to_date.unwrap_or(defaults.to_date),
+        rank,
+        velocity_window,
+        reaction: reaction.map(str::to_string),
+        lang: get_lang(app, lang, &accept_language)?,
         ..defaults
     };
     log::debug!("Working on task: {}", task.to_string().unwrap());
@@ -629,21 +1216,21 @@ async fn digest(
         );
         if force { data_url.push_str("&force=true"); }
         if force_limit { data_url.push_str("&force_limit=true"); }
+        data_url.push_str(&ranking_query(&task));
+        data_url.push_str(&format!("&lang={}", task.lang));
 
-        let client = tg::TelegramAPI::client();
-        let channel_title = workers::tg::get_channel_title(&client, &task.channel_name)
-            .await
-            .unwrap_or_else(|_| task.channel_name.clone());
+        let channel_title = channel_title(app, &task.channel_name).await;
 
         let mut context = tera::Context::new();
         context.insert("channel_name", &task.channel_name);
         context.insert("channel_title", &channel_title);
         context.insert("data_url", &data_url);
+        context.insert("lang", &task.lang);
         context.insert("base_url", &base_url);
         context.insert("site_name", &site_name);
         insert_page_meta(
             &mut context,
-            &channel_page_meta(&base_url, &task.channel_name, &channel_title),
+            &channel_page_meta(&app.locales, &task.lang, &base_url, &task.channel_name, &channel_title),
         );
 
         let digest = app.html_renderer.render(&template_name, &context)
@@ -654,10 +1241,10 @@ async fn digest(
         let (_, is_stale) = get_cached_top_posts(&app, &task, fetch_target, force)
             .map_err(|e| http_status(Status::InternalServerError, e.to_string().as_ref()))?;
 
-        if is_stale || force {
+        if (is_stale || force) && app.connection.is_online() {
             let task_id = start_background_fetch(app.inner(), &task, force, fetch_target);
-            // Wait for fetch completion, keeping watchdog alive
-            loop {
+            // Wait for fetch completion, keeping watchdog alive; render from cache if Telegram goes away
+            while app.connection.is_online() {
                 {
                     let map = app.fetch_progress.lock().unwrap();
                     if let Some(p) = map.get(&task_id) {
@@ -671,28 +1258,7 @@ async fn digest(
             }
         }
 
-        let (post_top, _) = get_cached_top_posts(&app, &task, None, false)
-            .map_err(|e| http_status(Status::InternalServerError, e.to_string().as_ref()))?;
-
-        let client = tg::TelegramAPI::client();
-        let channel_title = workers::tg::get_channel_title(&client, &task.channel_name)
-            .await
-            .unwrap_or_else(|_| task.channel_name.clone());
-
-        let data = workers::digest::create_digest_data(
-            post_top,
-            task.clone(),
-            &channel_title,
-            &base_url,
-            &site_name,
-        )
-            .map_err(|e| http_status(Status::InternalServerError, e.to_string().as_ref()))?;
-        let mut context = data.to_context();
-        insert_page_meta(
-            &mut context,
-            &channel_page_meta(&base_url, &task.channel_name, &channel_title),
-        );
-
+        let context = digest_context(app, &task).await?;
         let digest = app.html_renderer.render(&template_name, &context)
             .map_err(|e| http_status(Status::InternalServerError, e.to_string().as_ref()))?;
         log::trace!("Digest html rendered (static): length={}", digest.len());
@@ -700,7 +1266,7 @@ async fn digest(
     }
 }
 
-#[get("/data/<mode>/<channel>?<top_count>&<editor_choice>&<from_date>&<to_date>&<force>&<force_limit>&<task_id>")]
+#[get("/data/<mode>/<channel>?<top_count>&<editor_choice>&<from_date>&<to_date>&<force>&<force_limit>&<rank>&<velocity_window>&<reaction>&<lang>&<task_id>")]
 async fn data_endpoint(
     mode: &str,
     channel: &str,
@@ -710,9 +1276,15 @@ async fn data_endpoint(
     to_date: Option<i64>,
     force: Option<bool>,
     force_limit: Option<bool>,
+    rank: Option<&str>,
+    velocity_window: Option<i64>,
+    reaction: Option<&str>,
+    lang: Option<&str>,
     task_id: Option<String>,
+    accept_language: AcceptLanguage,
     app: &rocket::State<Arc<App>>,
 ) -> std::result::Result<Json<serde_json::Value>, status::Custom<String>> {
+    let (rank, velocity_window) = get_rank(rank, velocity_window)?;
     let defaults = Task::default();
     let task = Task {
         command: Commands::Digest {},
@@ -722,6 +1294,10 @@ async fn data_endpoint(
         editor_choice_post_id: editor_choice.unwrap_or(defaults.editor_choice_post_id),
         from_date: from_date.unwrap_or(defaults.from_date),
         to_date: to_date.unwrap_or(defaults.to_date),
+        rank,
+        velocity_window,
+        reaction: reaction.map(str::to_string),
+        lang: get_lang(app, lang, &accept_language)?,
         ..defaults
     };
 
@@ -729,9 +1305,6 @@ async fn data_endpoint(
         return http_status_err(Status::BadRequest, "Provided date is not allowed");
     }
 
-    let base_url = app.ctx.public_base_url();
-    let site_name = app.ctx.public_site_name();
-
     // 1. If task_id provided, check its progress
     if let Some(ref tid) = task_id {
         // Extract progress state under the lock, then drop it before any .await
@@ -750,28 +1323,12 @@ async fn data_endpoint(
         if let Some((done, fetched, limit, error)) = progress_state {
             if !done {
                 // Return progress + current cached data so the frontend can render incrementally
-                let (post_top, _) = get_cached_top_posts(&app, &task, None, false)
-                    .map_err(|e| http_status(Status::InternalServerError, e.to_string().as_ref()))?;
-
-                let client = tg::TelegramAPI::client();
-                let channel_title = workers::tg::get_channel_title(&client, &task.channel_name)
-                    .await
-                    .unwrap_or_else(|_| task.channel_name.clone());
-
-                let data = workers::digest::create_digest_data(
-                    post_top,
-                    task.clone(),
-                    &channel_title,
-                    &base_url,
-                    &site_name,
-                )
-                    .map_err(|e| http_status(Status::InternalServerError, e.to_string().as_ref()))?;
-
-                let mut json = data.to_json();
+                let mut json = digest_json(app, &task).await?;
                 json["status"] = serde_json::json!("loading");
                 json["task_id"] = serde_json::json!(tid);
                 json["fetched"] = serde_json::json!(fetched);
                 json["limit"] = serde_json::json!(limit);
+                insert_connection_state(app, &mut json);
 
                 return Ok(Json(json));
             }
@@ -790,31 +1347,15 @@ async fn data_endpoint(
     let (_, is_stale) = get_cached_top_posts(&app, &task, fetch_target, force)
         .map_err(|e| http_status(Status::InternalServerError, e.to_string().as_ref()))?;
 
-    let need_fetch = is_stale || force;
+    // While degraded the cache is all there is
+    let need_fetch = (is_stale || force) && app.connection.is_online();
 
     if need_fetch && task_id.is_none() {
         let tid = start_background_fetch(app.inner(), &task, force, fetch_target);
         let estimated_limit = fetch_target.unwrap_or(cache::ALWAYS_REFRESH_HEAD);
 
         // Return initial cached data along with loading status
-        let (post_top, _) = get_cached_top_posts(&app, &task, None, false)
-            .map_err(|e| http_status(Status::InternalServerError, e.to_string().as_ref()))?;
-
-        let client = tg::TelegramAPI::client();
-        let channel_title = workers::tg::get_channel_title(&client, &task.channel_name)
-            .await
-            .unwrap_or_else(|_| task.channel_name.clone());
-
-        let data = workers::digest::create_digest_data(
-            post_top,
-            task.clone(),
-            &channel_title,
-            &base_url,
-            &site_name,
-        )
-            .map_err(|e| http_status(Status::InternalServerError, e.to_string().as_ref()))?;
-
-        let mut json = data.to_json();
+        let mut json = digest_json(app, &task).await?;
         json["status"] = serde_json::json!("loading");
         json["task_id"] = serde_json::json!(tid);
         json["fetched"] = serde_json::json!(0);
@@ -824,28 +1365,138 @@ async fn data_endpoint(
     }
 
     // 3. Return data
-    let (post_top, _) = get_cached_top_posts(&app, &task, None, false)
-        .map_err(|e| http_status(Status::InternalServerError, e.to_string().as_ref()))?;
+    let mut json = digest_json(app, &task).await?;
+    insert_connection_state(app, &mut json);
+    Ok(Json(json))
+}
 
-    let client = tg::TelegramAPI::client();
-    let channel_title = workers::tg::get_channel_title(&client, &task.channel_name)
-        .await
-        .unwrap_or_else(|_| task.channel_name.clone());
+#[get("/data/<mode>/<channel>/events?<top_count>&<editor_choice>&<from_date>&<to_date>&<force>&<force_limit>&<rank>&<velocity_window>&<reaction>&<lang>")]
+async fn data_events(
+    mode: &str,
+    channel: &str,
+    top_count: Option<usize>,
+    editor_choice: Option<i32>,
+    from_date: Option<i64>,
+    to_date: Option<i64>,
+    force: Option<bool>,
+    force_limit: Option<bool>,
+    rank: Option<&str>,
+    velocity_window: Option<i64>,
+    reaction: Option<&str>,
+    lang: Option<&str>,
+    accept_language: AcceptLanguage,
+    app: &rocket::State<Arc<App>>,
+) -> std::result::Result<EventStream![], status::Custom<String>> {
+    let (rank, velocity_window) = get_rank(rank, velocity_window)?;
+    let defaults = Task::default();
+    let task = Task {
+        command: Commands::Digest {},
+        mode: mode.to_string(),
+        channel_name: channel.to_string(),
+        top_count: top_count.unwrap_or(defaults.top_count),
+        editor_choice_post_id: editor_choice.unwrap_or(defaults.editor_choice_post_id),
+        from_date: from_date.unwrap_or(defaults.from_date),
+        to_date: to_date.unwrap_or(defaults.to_date),
+        rank,
+        velocity_window,
+        reaction: reaction.map(str::to_string),
+        lang: get_lang(app, lang, &accept_language)?,
+        ..defaults
+    };
 
-    let data = workers::digest::create_digest_data(
-        post_top,
-        task,
-        &channel_title,
-        &base_url,
-        &site_name,
-    )
+    if task.from_date < 0 || task.to_date < 0 {
+        return http_status_err(Status::BadRequest, "Provided date is not allowed");
+    }
+
+    let force = force.unwrap_or(false);
+    let fetch_target = compute_fetch_target(force_limit.unwrap_or(false), task.top_count);
+
+    let (_, is_stale) = get_cached_top_posts(app, &task, fetch_target, force)
         .map_err(|e| http_status(Status::InternalServerError, e.to_string().as_ref()))?;
 
-    Ok(Json(data.to_json()))
+    // Errors in the templates or block specs are reported before the stream starts
+    let mut json = digest_json(app, &task).await?;
+
+    let app = app.inner().clone();
+    let subscription = if (is_stale || force) && app.connection.is_online() {
+        let tid = start_background_fetch(&app, &task, force, fetch_target);
+        let progress = app.fetch_progress.lock().unwrap().get(&tid).cloned();
+        progress.map(FetchSubscription::new)
+    } else {
+        None
+    };
+
+    Ok(EventStream! {
+        let Some(subscription) = subscription else {
+            insert_connection_state(&app, &mut json);
+            yield Event::json(&json).event("data");
+            return;
+        };
+        let progress = &subscription.0;
+
+        let mut reported = progress.fetched.load(Ordering::Relaxed);
+        json["status"] = serde_json::json!("loading");
+        json["fetched"] = serde_json::json!(reported);
+        json["limit"] = serde_json::json!(progress.limit.load(Ordering::Relaxed));
+        insert_connection_state(&app, &mut json);
+        yield Event::json(&json).event("data");
+
+        loop {
+            let notified = progress.notify.notified();
+            if progress.done.load(Ordering::Relaxed) {
+                break;
+            }
+            let stored = tokio::select! {
+                _ = notified => true,
+                _ = tokio::time::sleep(std::time::Duration::from_secs(1)) => false,
+            };
+            if progress.done.load(Ordering::Relaxed) {
+                break;
+            }
+
+            let fetched = progress.fetched.load(Ordering::Relaxed);
+            let limit = progress.limit.load(Ordering::Relaxed);
+            if stored {
+                // A batch reached the cache, send the digest as it is now
+                let mut json = match digest_json(&app, &task).await {
+                    Ok(json) => json,
+                    Err(e) => {
+                        yield Event::json(&serde_json::json!({"status": "error", "error": e.1})).event("error");
+                        return;
+                    }
+                };
+                json["status"] = serde_json::json!("loading");
+                json["fetched"] = serde_json::json!(fetched);
+                json["limit"] = serde_json::json!(limit);
+                insert_connection_state(&app, &mut json);
+                yield Event::json(&json).event("data");
+            } else if fetched != reported {
+                yield Event::json(&serde_json::json!({"fetched": fetched, "limit": limit})).event("progress");
+            }
+            reported = fetched;
+        }
+
+        let error = progress.error.lock().unwrap().clone();
+        if let Some(error) = error {
+            yield Event::json(&serde_json::json!({"status": "error", "error": error})).event("error");
+            return;
+        }
+        match digest_json(&app, &task).await {
+            Ok(mut json) => {
+                insert_connection_state(&app, &mut json);
+                yield Event::json(&json).event("data");
+            }
+            Err(e) => {
+                yield Event::json(&serde_json::json!({"status": "error", "error": e.1})).event("error");
+            }
+        }
+    }
+    // Writing the heartbeat is what notices a disconnected client
+    .heartbeat(std::time::Duration::from_secs(5)))
 }
 
 #[get(
-    "/video/<mode>/<channel>/<year>/<month>/<week>?<replies>&<reactions>&<forwards>&<views>&<top_count>&<editor_choice>&<force>"
+    "/video/<mode>/<channel>/<year>/<month>/<week>?<replies>&<reactions>&<forwards>&<views>&<top_count>&<editor_choice>&<force>&<lang>&<format>&<output>&<post_video>"
 )]
 async fn video_by_week(
     mode: &str,
@@ -860,6 +1511,11 @@ async fn video_by_week(
     top_count: Option<usize>,
     editor_choice: Option<i32>,
     force: Option<bool>,
+    lang: Option<&str>,
+    format: Option<&str>,
+    output: Option<&str>,
+    post_video: Option<u32>,
+    accept_language: AcceptLanguage,
     app: &rocket::State<Arc<App>>,
 ) -> std::result::Result<NamedFile, status::Custom<String>> {
     let from_date = get_date_from_week(year, month, week)?;
@@ -877,13 +1533,18 @@ async fn video_by_week(
         Some(from_date.timestamp()),
         Some(to_date.timestamp()),
         force,
+        lang,
+        format,
+        output,
+        post_video,
+        accept_language,
         app,
     )
     .await
 }
 
 #[get(
-    "/video/<mode>/<channel>/<year>/<month>?<replies>&<reactions>&<forwards>&<views>&<top_count>&<editor_choice>&<force>"
+    "/video/<mode>/<channel>/<year>/<month>?<replies>&<reactions>&<forwards>&<views>&<top_count>&<editor_choice>&<force>&<lang>&<format>&<output>&<post_video>"
 )]
 async fn video_by_month(
     mode: &str,
@@ -897,6 +1558,11 @@ async fn video_by_month(
     top_count: Option<usize>,
     editor_choice: Option<i32>,
     force: Option<bool>,
+    lang: Option<&str>,
+    format: Option<&str>,
+    output: Option<&str>,
+    post_video: Option<u32>,
+    accept_language: AcceptLanguage,
     app: &rocket::State<Arc<App>>,
 ) -> std::result::Result<NamedFile, status::Custom<String>> {
     let from_date = get_date_from_month(year, month)?;
@@ -915,13 +1581,18 @@ async fn video_by_month(
         Some(from_date.timestamp()),
         Some(to_date.timestamp()),
         force,
+        lang,
+        format,
+        output,
+        post_video,
+        accept_language,
         app,
     )
     .await
 }
 
 #[get(
-    "/video/<mode>/<channel>/<year>?<replies>&<reactions>&<forwards>&<views>&<top_count>&<editor_choice>&<force>"
+    "/video/<mode>/<channel>/<year>?<replies>&<reactions>&<forwards>&<views>&<top_count>&<editor_choice>&<force>&<lang>&<format>&<output>&<post_video>"
 )]
 async fn video_by_year(
     mode: &str,
@@ -934,6 +1605,11 @@ async fn video_by_year(
     top_count: Option<usize>,
     editor_choice: Option<i32>,
     force: Option<bool>,
+    lang: Option<&str>,
+    format: Option<&str>,
+    output: Option<&str>,
+    post_video: Option<u32>,
+    accept_language: AcceptLanguage,
     app: &rocket::State<Arc<App>>,
 ) -> std::result::Result<NamedFile, status::Custom<String>> {
     let from_date = get_date_from_year(year)?;
@@ -951,15 +1627,79 @@ async fn video_by_year(
         Some(from_date.timestamp()),
         Some(to_date.timestamp()),
         force,
+        lang,
+        format,
+        output,
+        post_video,
+        accept_language,
         app,
     )
     .await
 }
 
-#[get(
-    "/video/<mode>/<channel>?<replies>&<reactions>&<forwards>&<views>&<top_count>&<editor_choice>&<from_date>&<to_date>&<force>"
-)]
-async fn video(
+/// Parameters of `/video/...`, also the JSON body of `POST /jobs/video`
+#[derive(serde::Deserialize)]
+struct VideoParams {
+    mode: String,
+    channel: String,
+    replies: Option<usize>,
+    reactions: Option<usize>,
+    forwards: Option<usize>,
+    views: Option<usize>,
+    top_count: Option<usize>,
+    editor_choice: Option<i32>,
+    from_date: Option<i64>,
+    to_date: Option<i64>,
+    force: Option<bool>,
+    lang: Option<String>,
+    /// `story`, `square` or `landscape`
+    format: Option<String>,
+    /// `mp4`, `webm`, `gif` or `webp`
+    output: Option<String>,
+    /// Seconds of a post's own video played on its card
+    post_video: Option<u32>,
+}
+
+impl VideoParams {
+    fn task(&self, app: &App, accept_language: &AcceptLanguage) -> std::result::Result<Task, status::Custom<String>> {
+        let task = Task::default();
+        let task = Task {
+            command: Commands::Cards {
+                replies: self.replies,
+                reactions: self.reactions,
+                forwards: self.forwards,
+                views: self.views,
+            },
+            mode: self.mode.clone(),
+            channel_name: self.channel.clone(),
+            top_count: self.top_count.unwrap_or(task.top_count),
+            editor_choice_post_id: self.editor_choice.unwrap_or(task.editor_choice_post_id),
+            from_date: self.from_date.unwrap_or(task.from_date),
+            to_date: self.to_date.unwrap_or(task.to_date),
+            rank: task.rank,
+            velocity_window: task.velocity_window,
+            reaction: None,
+            lang: get_lang(app, self.lang.as_deref(), accept_language)?,
+            task_id: "0".to_string(),
+        };
+
+        if task.from_date < 0 || task.to_date < 0 {
+            return http_status_err(Status::BadRequest, "Provided date is not allowed");
+        }
+        Ok(task)
+    }
+
+    fn options(&self, app: &App) -> std::result::Result<VideoOptions, status::Custom<String>> {
+        let options = get_video_options(self.format.as_deref(), self.output.as_deref(), self.post_video)?;
+        check_video_spec(app, &self.mode, options)?;
+        Ok(options)
+    }
+}
+
+#[get(
+    "/video/<mode>/<channel>?<replies>&<reactions>&<forwards>&<views>&<top_count>&<editor_choice>&<from_date>&<to_date>&<force>&<lang>&<format>&<output>&<post_video>"
+)]
+async fn video(
     mode: &str,
     channel: &str,
     replies: Option<usize>,
@@ -971,32 +1711,120 @@ async fn video(
     from_date: Option<i64>,
     to_date: Option<i64>,
     force: Option<bool>,
+    lang: Option<&str>,
+    format: Option<&str>,
+    output: Option<&str>,
+    post_video: Option<u32>,
+    accept_language: AcceptLanguage,
     app: &rocket::State<Arc<App>>,
 ) -> std::result::Result<NamedFile, status::Custom<String>> {
-    let force = force.unwrap_or(false);
-
-    let task = Task::default();
-    let mut task = Task {
-        command: Commands::Cards {
-            replies,
-            reactions,
-            forwards,
-            views,
-        },
+    let params = VideoParams {
         mode: mode.to_string(),
-        channel_name: channel.to_string(),
-        top_count: top_count.unwrap_or(task.top_count),
-        editor_choice_post_id: editor_choice.unwrap_or(task.editor_choice_post_id),
-        from_date: from_date.unwrap_or(task.from_date),
-        to_date: to_date.unwrap_or(task.to_date),
-        task_id: "0".to_string(),
+        channel: channel.to_string(),
+        replies,
+        reactions,
+        forwards,
+        views,
+        top_count,
+        editor_choice,
+        from_date,
+        to_date,
+        force,
+        lang: lang.map(str::to_string),
+        format: format.map(str::to_string),
+        output: output.map(str::to_string),
+        post_video,
     };
+    let task = params.task(app, &accept_language)?;
+    let options = params.options(app)?;
 
-    log::debug!("Working on task: {}", task.to_string().unwrap());
+    let file = make_video(app.inner(), task, options, force.unwrap_or(false), None).await?;
+    match NamedFile::open(file).await {
+        Ok(file) => Ok(file),
+        Err(e) => http_status_err(Status::InternalServerError, &e.to_string()),
+    }
+}
 
-    if task.from_date < 0 || task.to_date < 0 {
-        return http_status_err(Status::BadRequest, "Provided date is not allowed");
+/// Seconds between sweeps of the output directory
+const OUTPUT_SWEEP_INTERVAL: u64 = 3600;
+
+fn touch_output(app: &App, path: &std::path::Path) {
+    if let Err(e) = app.cache.touch_artifact(path) {
+        log::warn!("Failed to touch {}: {}", path.display(), e);
+    }
+}
+
+/// Track a new file of the output directory and make room for it
+fn register_output(app: &App, path: &std::path::Path, kind: ArtifactKind) {
+    if let Err(e) = app.cache.register_artifact(path, kind) {
+        log::warn!("Failed to register {}: {}", path.display(), e);
+        return;
+    }
+    evict_outputs(app, Some(path));
+}
+
+/// Apply the output quota and max age of the config, sparing `keep`
+fn evict_outputs(app: &App, keep: Option<&std::path::Path>) -> ArtifactUsage {
+    let limit_bytes = app.ctx.output_limit_mb as i64 * 1024 * 1024;
+    let max_age = (app.ctx.output_max_age_days > 0).then(|| app.ctx.output_max_age_days as i64 * 86400);
+    app.cache.evict_artifacts(limit_bytes, max_age, keep).unwrap_or_else(|e| {
+        log::warn!("Output eviction failed: {}", e);
+        ArtifactUsage::default()
+    })
+}
+
+/// Card folders of renders that did not finish, named by the task hash
+fn remove_render_dirs(app: &App) {
+    let Ok(entries) = std::fs::read_dir(&app.ctx.output_dir) else {
+        return;
+    };
+    for path in entries.filter_map(|entry| entry.ok().map(|entry| entry.path())) {
+        let is_task_dir = path.is_dir()
+            && path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.len() == 20 && name.bytes().all(|b| b.is_ascii_digit()));
+        if is_task_dir {
+            match std::fs::remove_dir_all(&path) {
+                Ok(_) => log::info!("Removed leftover render folder {}", path.display()),
+                Err(e) => log::warn!("Failed to remove {}: {}", path.display(), e),
+            }
+        }
+    }
+}
+
+/// Adopt files written before the output registry, drop leftover render folders, then keep the
+/// output directory within its quota and max age.
+async fn sweep_outputs(app: Arc<App>) {
+    remove_render_dirs(&app);
+    match app.cache.adopt_artifacts(&app.ctx.output_dir) {
+        Ok(0) => {}
+        Ok(adopted) => log::info!("Tracking {} existing output files", adopted),
+        Err(e) => log::warn!("Failed to adopt output files: {}", e),
     }
+    loop {
+        evict_outputs(&app, None);
+        tokio::time::sleep(Duration::from_secs(OUTPUT_SWEEP_INTERVAL)).await;
+    }
+}
+
+fn video_render_lock(app: &App, task_id: &str) -> Arc<tokio::sync::Mutex<()>> {
+    let mut map = app.video_renders.lock().unwrap();
+    // Forget locks nobody holds or waits for
+    map.retain(|_, lock| Arc::strong_count(lock) > 1);
+    map.entry(task_id.to_string()).or_default().clone()
+}
+
+/// Fetch, render and encode the video of `task`, or return the existing video with the same content.
+/// A video job passes itself as `job` to report its stage.
+async fn make_video(
+    app: &Arc<App>,
+    mut task: Task,
+    options: VideoOptions,
+    force: bool,
+    job: Option<&VideoJob>,
+) -> std::result::Result<PathBuf, status::Custom<String>> {
+    log::debug!("Working on task: {}", task.to_string().unwrap());
 
     let request_started_at = Instant::now();
     let tg_task = task.clone();
@@ -1005,8 +1833,8 @@ async fn video(
     // waiting for the freshness refresh. This keeps repeated /video hits cheap.
     if !force {
         let early_video_file = {
-            match get_cached_top_posts(&app, &tg_task, None, false) {
-                Ok((post_top, _)) => match cached_video_candidate(app.inner(), &task, post_top) {
+            match get_cached_top_posts(app, &tg_task, None, false) {
+                Ok((post_top, _)) => match cached_video_candidate(app, &task, post_top, options) {
                     Ok(candidate) if candidate.file.exists() => Some(candidate),
                     Ok(_) => None,
                     Err(e) => {
@@ -1022,46 +1850,37 @@ async fn video(
         };
 
         if let Some(candidate) = early_video_file {
-            match NamedFile::open(candidate.file).await {
-                Ok(file) => {
-                    log::debug!(
-                        "Used video cache before fetch: task={} request={:.2}s",
-                        candidate.task_id,
-                        request_started_at.elapsed().as_secs_f64(),
-                    );
-                    return Ok(file);
-                }
-                Err(e) => {
-                    log::error!("Failed to open cached video: {}", e);
-                }
-            }
+            touch_output(app, &candidate.file);
+            log::debug!(
+                "Used video cache before fetch: task={} request={:.2}s",
+                candidate.task_id,
+                request_started_at.elapsed().as_secs_f64(),
+            );
+            return Ok(candidate.file);
         }
     }
 
     // No ready video for the current cached posts. Refresh the post cache and wait, then render
     // from the refreshed top posts. Passing None keeps the normal video policy: head/edge refresh
     // without progressive backfill.
-    let fetch_task_id = start_background_fetch(app.inner(), &tg_task, force, None);
-    wait_background_fetch(app.inner(), &fetch_task_id).await;
+    if app.connection.is_online() {
+        let fetch_task_id = start_background_fetch(app, &tg_task, force, None);
+        if let Some(job) = job {
+            job.set_fetching(&fetch_task_id);
+        }
+        wait_background_fetch(app, &fetch_task_id).await;
+    }
 
-    let (post_top, _) = get_cached_top_posts(&app, &tg_task, None, false)
+    let (post_top, _) = get_cached_top_posts(app, &tg_task, None, false)
         .map_err(|e| http_status(Status::InternalServerError, e.to_string().as_ref()))?;
 
-    let render_context = workers::cards::create_context(post_top, task.clone())
-        .map_err(|e| http_status(Status::BadRequest, e.to_string().as_ref()))?;
     let request_elapsed = request_started_at.elapsed();
 
     let html_started_at = Instant::now();
-    let rendered_html = app
-        .html_renderer
-        .render(
-            format!("{}/render_template.html", task.mode).as_str(),
-            &render_context,
-        )
-        .map_err(|e| http_status(Status::InternalServerError, e.to_string().as_ref()))?;
+    let rendered_html = render_video_html(app, &task, post_top, options)?;
     let html_elapsed = html_started_at.elapsed();
 
-    task.task_id = hash(format!("video:{}:{}", task.mode, rendered_html));
+    task.task_id = video_task_id(&task, options, &rendered_html);
 
     log::debug!(
         "Render file rendered to html for task {}: lenght={}",
@@ -1069,19 +1888,19 @@ async fn video(
         rendered_html.len()
     );
 
+    // A request for a video that is being rendered waits for it and takes the result
+    let render_lock = video_render_lock(app, &task.task_id);
+    let _render_guard = render_lock.lock().await;
+
     // Return cached video if the selected posts rendered to the same video content.
-    let file = app.ctx.output_dir.join(format!("{}.mp4", task.task_id));
+    let file = video_file(app, &task.task_id, options);
     if file.exists() && !force {
         log::trace!("Used cache: {}", file.to_str().unwrap_or("unknown"));
-        match NamedFile::open(file).await {
-            Ok(file) => return Ok(file),
-            Err(e) => {
-                log::error!("Failed to open file: {}", e);
-            }
-        }
+        touch_output(app, &file);
+        return Ok(file);
     }
 
-    let (file, render_timings) = render_video(&task, &rendered_html, app.inner()).await?;
+    let (file, render_timings) = render_video(&task, &rendered_html, options, app, job).await?;
     log::debug!(
         "Video task {} timings: request={:.2}s images={:.2}s ffmpeg={:.2}s total={:.2}s",
         task.task_id,
@@ -1091,66 +1910,342 @@ async fn video(
         (request_elapsed + html_elapsed + render_timings.images + render_timings.ffmpeg).as_secs_f64(),
     );
 
+    Ok(file)
+}
+
+fn video_job_json(app: &App, job: &VideoJob) -> serde_json::Value {
+    let mut json = job.to_json();
+    if job.stage() == Stage::Fetching
+        && let Some(fetch_task_id) = job.fetch_task_id()
+        && let Some(progress) = app.fetch_progress.lock().unwrap().get(&fetch_task_id)
+    {
+        json["fetched"] = progress.fetched.load(Ordering::Relaxed).into();
+        json["fetch_limit"] = progress.limit.load(Ordering::Relaxed).into();
+    }
+    json["status_url"] = format!("/jobs/{}", job.id).into();
+    if job.stage() == Stage::Done {
+        json["result_url"] = format!("/jobs/{}/result", job.id).into();
+    }
+    json
+}
+
+#[post("/jobs/video", data = "<params>")]
+async fn create_video_job(
+    params: Json<VideoParams>,
+    accept_language: AcceptLanguage,
+    app: &rocket::State<Arc<App>>,
+) -> std::result::Result<status::Accepted<Json<serde_json::Value>>, status::Custom<String>> {
+    let force = params.force.unwrap_or(false);
+    let task = params.task(app, &accept_language)?;
+    let options = params.options(app)?;
+    let task_json = task
+        .to_string()
+        .map_err(|e| http_status(Status::InternalServerError, e.to_string().as_ref()))?;
+    let job_id = hash(format!("job:{}:{}:{}", task_json, options.key(), force));
+
+    let (job, started) = {
+        let mut jobs = app.video_jobs.lock().unwrap();
+        jobs.retain(|_, job| !job.is_expired());
+        // Join the same request unless it failed, or a forced one already finished
+        let existing = jobs.get(&job_id).filter(|job| match job.stage() {
+            Stage::Failed => false,
+            Stage::Done => !force,
+            _ => true,
+        });
+        match existing {
+            Some(job) => (job.clone(), false),
+            None => {
+                let job = Arc::new(VideoJob::new(job_id.clone()));
+                jobs.insert(job_id, job.clone());
+                (job, true)
+            }
+        }
+    };
+
+    if started {
+        log::info!("Video job {} started for {}", job.id, task.channel_name);
+        let app = app.inner().clone();
+        let job = job.clone();
+        tokio::spawn(async move {
+            let run = {
+                let app = app.clone();
+                let job = job.clone();
+                tokio::spawn(async move { make_video(&app, task, options, force, Some(&job)).await })
+            };
+            // A panic must not leave the job running forever
+            let result = match run.await {
+                Ok(result) => result,
+                Err(e) => http_status_err(Status::InternalServerError, &format!("Video job crashed: {}", e)),
+            };
+            match &result {
+                Ok(file) => log::info!("Video job {} done: {}", job.id, file.display()),
+                Err(e) => log::error!("Video job {} failed: {}", job.id, e.1),
+            }
+            job.finish(result.map_err(|e| (e.0, e.1)));
+        });
+    }
+
+    Ok(status::Accepted(Json(video_job_json(app, &job))))
+}
+
+fn get_video_job(app: &App, id: &str) -> std::result::Result<Arc<VideoJob>, status::Custom<String>> {
+    let jobs = app.video_jobs.lock().unwrap();
+    match jobs.get(id) {
+        Some(job) if !job.is_expired() => Ok(job.clone()),
+        _ => http_status_err(Status::NotFound, "Unknown job"),
+    }
+}
+
+#[get("/jobs/<id>")]
+async fn video_job_status(
+    id: &str,
+    app: &rocket::State<Arc<App>>,
+) -> std::result::Result<Json<serde_json::Value>, status::Custom<String>> {
+    let job = get_video_job(app, id)?;
+    Ok(Json(video_job_json(app, &job)))
+}
+
+#[get("/jobs/<id>/result")]
+async fn video_job_result(
+    id: &str,
+    app: &rocket::State<Arc<App>>,
+) -> std::result::Result<NamedFile, status::Custom<String>> {
+    let job = get_video_job(app, id)?;
+    if let Some((status, error)) = job.error() {
+        return Err(status::Custom(status, error));
+    }
+    let Some(file) = job.file() else {
+        return http_status_err(Status::Conflict, "Job is not finished");
+    };
+    touch_output(app, &file);
     match NamedFile::open(file).await {
         Ok(file) => Ok(file),
         Err(e) => http_status_err(Status::InternalServerError, &e.to_string()),
     }
 }
 
+/// Template context shared by the intro and outro cards of a video
+async fn extra_card_context(app: &App, task: &Task) -> tera::Context {
+    let channel_title = channel_title(app, &task.channel_name).await;
+    // The render page has this server's origin, so `/localmedia` reaches the downloaded avatar
+    let avatar_url = if app.connection.is_online() {
+        match workers::tg::download_pic(app.tg.as_ref(), task, &app.ctx).await {
+            Ok(path) => {
+                register_output(app, &path, ArtifactKind::Avatar);
+                path.file_name()
+                    .map(|name| format!("/localmedia/{}", name.to_string_lossy()))
+            }
+            Err(e) => {
+                log::warn!("No avatar for the video of {}: {}", task.channel_name, e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    let mut context = tera::Context::new();
+    context.insert("channel_name", &task.channel_name);
+    context.insert("channel_title", &channel_title);
+    context.insert("avatar_url", &avatar_url);
+    context.insert("lang", &task.lang);
+    context.insert("from_date", &task.from_date);
+    context.insert("to_date", &task.to_date);
+    context
+}
+
+/// Render an intro or outro template of the mode to a single image in `dir`
+async fn render_extra_card(
+    app: &App,
+    task: &Task,
+    format: VideoFormat,
+    spec: &encoder::ExtraCardSpec,
+    default_template: &str,
+    context: &tera::Context,
+    dir: &std::path::Path,
+) -> std::result::Result<PathBuf, status::Custom<String>> {
+    let Some(card_renderer) = app.card_renderer.as_ref() else {
+        return http_status_err(Status::ServiceUnavailable, "Card renderer is not running");
+    };
+    let mut context = context.clone();
+    insert_card_size(&mut context, format);
+    let channel_title = context
+        .get("channel_title")
+        .and_then(|title| title.as_str())
+        .unwrap_or(&task.channel_name)
+        .to_string();
+    let call_to_action = spec.call_to_action.as_deref().unwrap_or("video.call_to_action");
+    context.insert(
+        "call_to_action",
+        &app.locales.t_args(&task.lang, call_to_action, &[("channel", &channel_title)]),
+    );
+
+    let template = spec.template.as_deref().unwrap_or(default_template);
+    let html = app
+        .html_renderer
+        .render(&format!("{}/{}", task.mode, template), &context)
+        .map_err(|e| http_status(Status::InternalServerError, e.to_string().as_ref()))?;
+
+    tokio::fs::create_dir_all(dir)
+        .await
+        .map_err(|e| http_status(Status::InternalServerError, e.to_string().as_ref()))?;
+    let cards = card_renderer
+        .render_html(dir, &html, format.viewport(), None)
+        .await
+        .map_err(|e| http_status(Status::InternalServerError, e.to_string().as_ref()))?;
+    match cards.into_iter().next() {
+        Some(card) => Ok(card.image),
+        None => http_status_err(Status::InternalServerError, &format!("{} renders no card", template)),
+    }
+}
+
+fn media_download_lock(app: &App, key: String) -> Arc<tokio::sync::Mutex<()>> {
+    let mut map = app.media_downloads.lock().unwrap();
+    // Forget locks nobody holds or waits for
+    map.retain(|_, lock| Arc::strong_count(lock) > 1);
+    map.entry(key).or_default().clone()
+}
+
+/// Video of a post from the media cache, downloaded on a miss. `None` if the post has no video,
+/// it is too large or it is not cached while offline.
+async fn post_video_file(
+    app: &App,
+    channel: &str,
+    id: i32,
+    dir: &std::path::Path,
+) -> std::result::Result<Option<PathBuf>, String> {
+    let lock = media_download_lock(app, format!("media:{}:{}", channel.to_lowercase(), id));
+    // Checked again after waiting, another download may have cached the file
+    let _download = lock.lock().await;
+    if let Some((path, mime, _)) = app.cache.get_cached_media(channel, id).map_err(|e| e.to_string())? {
+        return Ok(mime.starts_with("video/").then_some(path));
+    }
+    if !app.connection.is_online() {
+        return Ok(None);
+    }
+
+    let (media, mut chunks) = match app.tg.open_media(channel, id).await.map_err(|e| e.to_string()) {
+        Ok(media) => media,
+        Err(e) => {
+            log::debug!("No media in post {}/{}: {}", channel, id, e);
+            return Ok(None);
+        }
+    };
+    if !media.mime.starts_with("video/") {
+        return Ok(None);
+    }
+    if media.size.is_some_and(|size| size > MAX_POST_VIDEO_SIZE) {
+        log::info!("Video of post {}/{} is too large for its card", channel, id);
+        return Ok(None);
+    }
+
+    let mut data = Vec::new();
+    while let Some(chunk) = chunks.next().await.map_err(|e| e.to_string())? {
+        data.extend_from_slice(&chunk);
+    }
+    if media.size.is_some_and(|size| size != data.len() as i64) {
+        return Err("Incomplete download".to_string());
+    }
+
+    // Small videos stay in the media cache for `/media` and the next digest
+    let _ = app.cache.store_cached_media(channel, id, media.media_id, &media.mime, &data);
+    if let Some((path, _, _)) = app.cache.get_cached_media(channel, id).ok().flatten() {
+        return Ok(Some(path));
+    }
+    let path = dir.join(format!("post_{}.{}", id, post_data::mime_ext(&media.mime)));
+    tokio::fs::write(&path, &data).await.map_err(|e| e.to_string())?;
+    Ok(Some(path))
+}
+
 async fn render_video(
     task: &Task,
     rendered_html: &str,
+    options: VideoOptions,
     app: &Arc<App>,
+    job: Option<&VideoJob>,
 ) -> std::result::Result<(PathBuf, VideoRenderTimings), status::Custom<String>> {
+    // A mode's own make_video.sh replaces the built-in encoder
+    let video_maker = app.ctx.input_dir.join(&task.mode).join("make_video.sh");
+    if video_maker.exists() && (options.output != OutputFormat::Mp4 || options.post_video.is_some()) {
+        return http_status_err(Status::BadRequest, "The make_video.sh of this mode only makes mp4 of card images");
+    }
+    let video_spec = encoder::VideoSpec::load(&app.ctx.input_dir, &task.mode)
+        .map_err(|e| http_status(Status::InternalServerError, e.to_string().as_ref()))?;
     let output_dir = app.ctx.output_dir.join(&task.task_id);
     tokio::fs::create_dir_all(&output_dir)
         .await
         .map_err(|e| http_status(Status::InternalServerError, e.to_string().as_ref()))?;
 
     let images_started_at = Instant::now();
-    app.card_renderer
-        .render_html(&output_dir, rendered_html)
+    let Some(card_renderer) = app.card_renderer.as_ref() else {
+        return http_status_err(Status::ServiceUnavailable, "Card renderer is not running");
+    };
+    if let Some(job) = job {
+        job.set_stage(Stage::Rendering);
+    }
+    let cards = card_renderer
+        .render_html(&output_dir, rendered_html, options.format.viewport(), job.map(|job| &job.cards))
         .await
         .map_err(|e| http_status(Status::InternalServerError, e.to_string().as_ref()))?;
+
+    let mut encoder = video_spec.encoder(options.format, options.output);
+    if let Some(seconds) = options.post_video {
+        for card in &cards {
+            let Some(post_id) = card.post_id else {
+                continue;
+            };
+            match post_video_file(app, &task.channel_name, post_id, &output_dir).await {
+                Ok(Some(video)) => encoder = encoder.post_video(&card.image, &video, seconds as f64, card.media_slot),
+                Ok(None) => {}
+                Err(e) => log::warn!("No video of post {}/{} for its card: {}", task.channel_name, post_id, e),
+            }
+        }
+    }
+    if !video_maker.exists() && (video_spec.intro.is_some() || video_spec.outro.is_some()) {
+        let context = extra_card_context(app, task).await;
+        if let Some(intro) = &video_spec.intro {
+            let image = render_extra_card(app, task, options.format, intro, "intro_template.html", &context, &output_dir.join("intro")).await?;
+            encoder = encoder.intro(&image, intro.seconds);
+        }
+        if let Some(outro) = &video_spec.outro {
+            let image = render_extra_card(app, task, options.format, outro, "outro_template.html", &context, &output_dir.join("outro")).await?;
+            encoder = encoder.outro(&image, outro.seconds);
+        }
+    }
     let images_elapsed = images_started_at.elapsed();
 
-    let video_maker = app
-        .ctx
-        .input_dir
-        .join(format!("{}/make_video.sh", task.mode));
-    let video_maker = path_util::to_slash(&video_maker).expect("Can't fix path to make_video.sh");
-    log::debug!(
-        "Running bash: {} at {}",
-        video_maker.to_str().unwrap_or("unknown"),
-        output_dir.to_str().unwrap_or("unknown")
-    );
-    let mut command = if cfg!(windows) {
-        Command::new("C:/Program Files/Git/usr/bin/bash.exe")
+    if let Some(job) = job {
+        job.set_stage(Stage::Encoding);
+    }
+    let ffmpeg_started_at = Instant::now();
+    let file = output_dir.join(format!("digest.{}", options.output.as_str()));
+    let encoded = if video_maker.exists() {
+        encoder::run_script(&app.ctx.shell, &video_maker, &output_dir).await.map_err(|e| e.to_string())
     } else {
-        Command::new("bash")
+        match encoder::find_cards(&output_dir).map_err(|e| e.to_string()) {
+            Ok(cards) => encoder.encode(&cards, &file).await.map_err(|e| e.to_string()),
+            Err(e) => Err(e),
+        }
     };
-    let ffmpeg_started_at = Instant::now();
-    let output = command
-        .current_dir(output_dir.to_str().unwrap())
-        .arg(video_maker)
-        .output()
-        .expect("Failed to execute script");
+    encoded.map_err(|e| http_status(Status::InternalServerError, &format!("Failed to make a video: {}", e)))?;
     let ffmpeg_elapsed = ffmpeg_started_at.elapsed();
 
-    // Print the output of the script
-    log::debug!("Status: {}", output.status);
-    log::debug!("Stdout: {}", String::from_utf8_lossy(&output.stdout));
-    log::debug!("Stderr: {}", String::from_utf8_lossy(&output.stderr));
-
-    let file = output_dir.join("digest.mp4");
-    if !output.status.success() || !file.exists() {
-        return http_status_err(Status::InternalServerError, "Failed to make a video");
+    if !file.exists() {
+        return http_status_err(
+            Status::InternalServerError,
+            &format!("Failed to make a video: no {}", file.file_name().unwrap_or_default().to_string_lossy()),
+        );
     }
 
-    let new_file = app.ctx.output_dir.join(format!("{}.mp4", task.task_id));
+    let new_file = video_file(app, &task.task_id, options);
     tokio::fs::rename(file, &new_file)
         .await
         .map_err(|_| http_status(Status::InternalServerError, "Failed to move final file"))?;
+    register_output(app, &new_file, ArtifactKind::Video);
+    // The cards, post videos and intro/outro of the render are not needed anymore
+    if let Err(e) = tokio::fs::remove_dir_all(&output_dir).await {
+        log::warn!("Failed to remove {}: {}", output_dir.display(), e);
+    }
 
     Ok((
         new_file,
@@ -1165,7 +2260,7 @@ async fn render_video(
 async fn post_json(
     channel: &str,
     id: i32,
-    _app: &rocket::State<Arc<App>>,
+    app: &rocket::State<Arc<App>>,
 ) -> std::result::Result<rocket::serde::json::Json<post_data::PostData>, status::Custom<String>> {
     let task = Task {
         command: Commands::Post {},
@@ -1174,17 +2269,227 @@ async fn post_json(
         ..Task::default()
     };
     log::debug!("Working on task: {}", task.to_string().unwrap());
+    ensure_online(app)?;
 
-    let tg_task = task.clone();
-    let client = tg::TelegramAPI::client();
-
-    let post = workers::tg::get_post_data(client, tg_task)
+    let post = app.tg.get_post_data(&task.channel_name, task.editor_choice_post_id)
         .await
         .map_err(|e| http_status(Status::NotFound, e.to_string().as_ref()))?;
 
     Ok(rocket::serde::json::Json(post))
 }
 
+#[get("/stats/telegram")]
+async fn telegram_stats(app: &rocket::State<Arc<App>>) -> Json<serde_json::Value> {
+    let mut stats = app.pool.stats();
+    stats["online"] = serde_json::json!(app.connection.is_online());
+    stats["signed_in"] = serde_json::json!(app.connection.is_authorized());
+    Json(stats)
+}
+
+#[get("/stats/output")]
+async fn output_stats(
+    app: &rocket::State<Arc<App>>,
+) -> std::result::Result<Json<serde_json::Value>, status::Custom<String>> {
+    let usage = output_usage(app).map_err(|e| http_status(Status::InternalServerError, e.to_string().as_ref()))?;
+    Ok(Json(usage))
+}
+
+/// Tracked output files against their quota, as `/stats/output` and `cache stats` report them
+fn output_usage(app: &App) -> Result<serde_json::Value> {
+    let usage = app.cache.artifact_usage()?;
+    Ok(serde_json::json!({
+        "files": usage.values().map(|kind| kind.files).sum::<i64>(),
+        "bytes": usage.values().map(|kind| kind.bytes).sum::<i64>(),
+        "limit_bytes": app.ctx.output_limit_mb * 1024 * 1024,
+        "max_age_days": app.ctx.output_max_age_days,
+        "kinds": usage,
+    }))
+}
+
+#[get("/stats/renderer")]
+async fn renderer_stats(app: &rocket::State<Arc<App>>) -> Json<serde_json::Value> {
+    let Some(card_renderer) = app.card_renderer.as_ref() else {
+        return Json(serde_json::json!({ "status": "disabled" }));
+    };
+    Json(serde_json::json!(card_renderer.health().await))
+}
+
+/// Requests with `Authorization: Bearer <admin_token>`. Without an `admin_token` the admin routes
+/// don't exist.
+struct Admin;
+
+/// Compares in constant time, so timing does not give the token away
+fn token_matches(given: &str, token: &str) -> bool {
+    given.len() == token.len() && given.bytes().zip(token.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+#[rocket::async_trait]
+impl<'r> rocket::request::FromRequest<'r> for Admin {
+    type Error = ();
+    async fn from_request(
+        req: &'r rocket::Request<'_>,
+    ) -> rocket::request::Outcome<Self, Self::Error> {
+        let token = req
+            .rocket()
+            .state::<Arc<App>>()
+            .and_then(|app| app.ctx.admin_token.clone())
+            .filter(|token| !token.is_empty());
+        let Some(token) = token else {
+            return rocket::request::Outcome::Error((Status::NotFound, ()));
+        };
+        let given = req.headers().get_one("Authorization").and_then(|value| value.strip_prefix("Bearer "));
+        if given.is_some_and(|given| token_matches(given, &token)) {
+            rocket::request::Outcome::Success(Admin)
+        } else {
+            rocket::request::Outcome::Error((Status::Unauthorized, ()))
+        }
+    }
+}
+
+/// The page asks for the admin token itself and sends it with every call of the login API
+#[get("/admin")]
+async fn admin_page(app: &rocket::State<Arc<App>>) -> Option<RawHtml<&'static str>> {
+    app.ctx.admin_token.as_ref().filter(|token| !token.is_empty())?;
+    Some(RawHtml(include_str!("../data/admin.html")))
+}
+
+#[derive(serde::Deserialize)]
+struct LoginInput {
+    phone: Option<String>,
+    code: Option<String>,
+    password: Option<String>,
+}
+
+fn login_response(
+    result: std::result::Result<login::LoginStatus, (Status, String)>,
+) -> std::result::Result<Json<login::LoginStatus>, status::Custom<String>> {
+    result.map(Json).map_err(|(status, message)| http_status(status, &message))
+}
+
+fn login_field(value: &Option<String>, name: &str) -> std::result::Result<String, status::Custom<String>> {
+    match value.as_deref().filter(|value| !value.trim().is_empty()) {
+        Some(value) => Ok(value.to_string()),
+        None => http_status_err(Status::BadRequest, &format!("Missing {}", name)),
+    }
+}
+
+#[get("/admin/telegram")]
+async fn login_status(_admin: Admin, app: &rocket::State<Arc<App>>) -> Json<login::LoginStatus> {
+    Json(app.login.status(&app.connection).await)
+}
+
+#[post("/admin/telegram/phone", data = "<input>")]
+async fn login_phone(
+    _admin: Admin,
+    input: Json<LoginInput>,
+    app: &rocket::State<Arc<App>>,
+) -> std::result::Result<Json<login::LoginStatus>, status::Custom<String>> {
+    let phone = login_field(&input.phone, "phone")?;
+    login_response(app.login.request_code(&app.connection, &phone).await)
+}
+
+#[post("/admin/telegram/code", data = "<input>")]
+async fn login_code(
+    _admin: Admin,
+    input: Json<LoginInput>,
+    app: &rocket::State<Arc<App>>,
+) -> std::result::Result<Json<login::LoginStatus>, status::Custom<String>> {
+    let code = login_field(&input.code, "code")?;
+    login_response(app.login.submit_code(&app.ctx, &app.connection, &code).await)
+}
+
+#[post("/admin/telegram/password", data = "<input>")]
+async fn login_password(
+    _admin: Admin,
+    input: Json<LoginInput>,
+    app: &rocket::State<Arc<App>>,
+) -> std::result::Result<Json<login::LoginStatus>, status::Custom<String>> {
+    let password = login_field(&input.password, "password")?;
+    login_response(app.login.submit_password(&app.ctx, &app.connection, &password).await)
+}
+
+#[post("/admin/telegram/logout")]
+async fn logout(
+    _admin: Admin,
+    app: &rocket::State<Arc<App>>,
+) -> std::result::Result<Json<login::LoginStatus>, status::Custom<String>> {
+    login_response(app.login.logout(&app.ctx, &app.connection).await)
+}
+
+#[get("/post/<channel>/<id>/comments?<force>")]
+async fn post_comments(
+    channel: &str,
+    id: i32,
+    force: Option<bool>,
+    app: &rocket::State<Arc<App>>,
+) -> std::result::Result<Json<serde_json::Value>, status::Custom<String>> {
+    let max_age = (!force.unwrap_or(false)).then_some(COMMENTS_TTL);
+    let cached = app
+        .cache
+        .get_comments(channel, id, max_age)
+        .map_err(|e| http_status(Status::InternalServerError, e.to_string().as_ref()))?;
+
+    let comments = match cached {
+        Some(comments) => comments,
+        // Stale replies beat none while Telegram is away
+        None if !app.connection.is_online() => {
+            let stale = app
+                .cache
+                .get_comments(channel, id, None)
+                .map_err(|e| http_status(Status::InternalServerError, e.to_string().as_ref()))?;
+            match stale {
+                Some(comments) => comments,
+                None => {
+                    ensure_online(app)?;
+                    Vec::new()
+                }
+            }
+        }
+        None => fetch_comments(app, channel, id)
+            .await
+            .map_err(|e| http_status(Status::NotFound, e.to_string().as_ref()))?,
+    };
+
+    let mut json = serde_json::json!({
+        "channel_name": channel,
+        "id": id,
+        "comments": comments,
+    });
+    insert_connection_state(app, &mut json);
+    Ok(Json(json))
+}
+
+#[get("/history/<channel>/<id>?<hours>")]
+async fn post_history(
+    channel: &str,
+    id: i32,
+    hours: Option<i64>,
+    app: &rocket::State<Arc<App>>,
+) -> std::result::Result<Json<serde_json::Value>, status::Custom<String>> {
+    let max_age = match hours {
+        Some(hours) if hours <= 0 => return http_status_err(Status::BadRequest, "Provided hours must be positive"),
+        Some(hours) => Some(
+            hours
+                .checked_mul(3600)
+                .ok_or_else(|| http_status(Status::BadRequest, "Provided hours are too many"))?,
+        ),
+        None => None,
+    };
+
+    let (date, snapshots) = app
+        .cache
+        .get_post_history(channel, id, max_age)
+        .map_err(|e| http_status(Status::InternalServerError, e.to_string().as_ref()))?
+        .ok_or_else(|| http_status(Status::NotFound, &format!("Post {}/{} is not cached", channel, id)))?;
+
+    Ok(Json(serde_json::json!({
+        "channel_name": channel,
+        "id": id,
+        "date": date,
+        "snapshots": snapshots,
+    })))
+}
+
 #[get("/view/<channel>/<id>?<views>&<forwards>&<reactions>&<comments>&<px_limit>&<dark>&<iframe>")]
 async fn view_post(
     channel: &str,
@@ -1204,17 +2509,36 @@ async fn view_post(
         editor_choice_post_id: id,
         ..Task::default()
     };
+    ensure_online(app)?;
 
-    let _permit = app.tg_semaphore.clone().acquire_owned().await
-        .map_err(|_| http_status(Status::ServiceUnavailable, "Server is shutting down"))?;
-
-    let client = tg::TelegramAPI::client();
-    let post = workers::tg::get_post_data(client, task)
+    let post = app.tg.get_post_data(&task.channel_name, task.editor_choice_post_id)
         .await
         .map_err(|e| http_status(Status::NotFound, e.to_string().as_ref()))?;
 
-    drop(_permit);
+    let query = ViewQuery { views, forwards, reactions, comments, px_limit, dark, iframe };
+    let ctx = view_context(channel, &post, &query);
+    let html = app
+        .html_renderer
+        .render("view_template.html", &ctx)
+        .map_err(|e| http_status(Status::InternalServerError, &e.to_string()))?;
+
+    Ok(RawHtml(html))
+}
+
+/// Display options of `/view`, all shown when unset
+#[derive(Default)]
+struct ViewQuery {
+    views: Option<bool>,
+    forwards: Option<bool>,
+    reactions: Option<bool>,
+    comments: Option<bool>,
+    px_limit: Option<u32>,
+    dark: Option<bool>,
+    iframe: Option<bool>,
+}
 
+/// Context of `view_template.html` for `post`
+fn view_context(channel: &str, post: &post_data::PostData, query: &ViewQuery) -> tera::Context {
     // Render entities into HTML text
     let rendered_text = render_entities(&post.text, &post.entities);
 
@@ -1236,13 +2560,13 @@ async fn view_post(
     ctx.insert("post_date", &DateTime::<Utc>::from_timestamp(post.date, 0)
         .map(|dt| dt.format("%d/%m/%Y %H:%M").to_string())
         .unwrap_or_default());
-    ctx.insert("dark", &dark.unwrap_or(false));
-    ctx.insert("iframe", &iframe.unwrap_or(false));
+    ctx.insert("dark", &query.dark.unwrap_or(false));
+    ctx.insert("iframe", &query.iframe.unwrap_or(false));
 
-    let show_views = views.unwrap_or(true);
-    let show_forwards = forwards.unwrap_or(true);
-    let show_reactions = reactions.unwrap_or(true);
-    let show_comments = comments.unwrap_or(true);
+    let show_views = query.views.unwrap_or(true);
+    let show_forwards = query.forwards.unwrap_or(true);
+    let show_reactions = query.reactions.unwrap_or(true);
+    let show_comments = query.comments.unwrap_or(true);
     let show_stats = show_views || show_forwards || show_reactions || show_comments;
 
     ctx.insert("show_stats", &show_stats);
@@ -1254,14 +2578,9 @@ async fn view_post(
     ctx.insert("forwards", &post.forwards);
     ctx.insert("reactions", &post.reactions);
     ctx.insert("comments", &post.replies);
-    ctx.insert("px_limit", &px_limit);
+    ctx.insert("px_limit", &query.px_limit);
 
-    let html = app
-        .html_renderer
-        .render("view_template.html", &ctx)
-        .map_err(|e| http_status(Status::InternalServerError, &e.to_string()))?;
-
-    Ok(RawHtml(html))
+    ctx
 }
 
 /// Convert post text + TL entities into HTML.
@@ -1368,6 +2687,7 @@ async fn post_image(
 ) -> std::result::Result<NamedFile, status::Custom<String>> {
     let file = app.ctx.output_dir.join(format!("{}.jpg", id));
     log::debug!("Trying to open file: {}", file.to_str().unwrap());
+    touch_output(app, &file);
     NamedFile::open(file)
         .await
         .map_err(|e| http_status(Status::NotFound, e.to_string().as_ref()))
@@ -1409,7 +2729,6 @@ impl<'r> rocket::request::FromRequest<'r> for RangeHeader {
     }
 }
 
-const CHUNK_SIZE: i32 = 512 * 1024; // Must match grammers MAX_CHUNK_SIZE
 
 /// Streaming media response that supports Range requests.
 struct MediaStream {
@@ -1452,36 +2771,31 @@ async fn thumb_proxy(
     id: i32,
     app: &rocket::State<Arc<App>>,
 ) -> std::result::Result<(ContentType, Vec<u8>), status::Custom<String>> {
-    // Check disk cache first (stored as thumb_{channel}_{id}.jpg in media dir)
-    let thumb_path = app.cache.media_dir().join(format!("thumb_{}_{}.jpg", channel, id));
-    if thumb_path.exists() {
-        let data = std::fs::read(&thumb_path)
-            .map_err(|e| http_status(Status::InternalServerError, &e.to_string()))?;
-        return Ok((ContentType::JPEG, data));
-    }
-
-    let permit = app.tg_semaphore.clone().acquire_owned().await
-        .map_err(|_| http_status(Status::ServiceUnavailable, "Server is shutting down"))?;
+    Ok((ContentType::JPEG, thumb_bytes(app, channel, id).await?))
+}
 
-    // Re-check after acquiring permit
+/// Thumbnail of a post's video or document, from the disk cache or downloaded and cached
+async fn thumb_bytes(app: &App, channel: &str, id: i32) -> std::result::Result<Vec<u8>, status::Custom<String>> {
+    // Check disk cache first (stored as thumb_{channel}_{id}.jpg in media dir, keyed like the cache)
+    let key = app.cache.channel_key(channel).map_err(|e| http_status(Status::InternalServerError, &e.to_string()))?;
+    let thumb_path = app.cache.media_dir().join(format!("thumb_{}_{}.jpg", key, id));
+    let lock = media_download_lock(app, format!("thumb:{}:{}", key, id));
+    // Checked again after waiting, another download may have cached the thumbnail
+    let _download = lock.lock().await;
     if thumb_path.exists() {
-        drop(permit);
-        let data = std::fs::read(&thumb_path)
-            .map_err(|e| http_status(Status::InternalServerError, &e.to_string()))?;
-        return Ok((ContentType::JPEG, data));
+        return std::fs::read(&thumb_path)
+            .map_err(|e| http_status(Status::InternalServerError, &e.to_string()));
     }
+    ensure_online(app)?;
 
-    let client = tg::TelegramAPI::client();
-    let (bytes, _mime) = workers::tg::download_thumb(&client, channel, id)
+    let (bytes, _mime) = app.tg.download_thumb(channel, id)
         .await
         .map_err(|e| http_status(Status::NotFound, &e.to_string()))?;
 
-    drop(permit);
-
     // Cache to disk
     let _ = std::fs::write(&thumb_path, &bytes);
 
-    Ok((ContentType::JPEG, bytes))
+    Ok(bytes)
 }
 
 #[get("/media/<channel>/<id>")]
@@ -1498,36 +2812,34 @@ async fn media_proxy(
         return serve_file_media(path, &mime, file_size, range).await;
     }
 
-    // Cache miss — fetch from Telegram (limited concurrency)
-    let permit = app.tg_semaphore.clone().acquire_owned().await
-        .map_err(|_| http_status(Status::ServiceUnavailable, "Server is shutting down"))?;
-
-    // Re-check cache — another request may have populated it while we waited
-    let cached = app.cache.get_cached_media(channel, id).ok().flatten();
-    if let Some((path, mime, file_size)) = cached {
-        drop(permit);
-        log::debug!("Serving cached media (after wait): {}/{}", channel, id);
+    // Cache miss — fetch from Telegram, every chunk waits for its turn in the scheduler.
+    // Concurrent misses wait for the first download and are served from its cache.
+    let lock = media_download_lock(app, format!("media:{}:{}", channel.to_lowercase(), id));
+    let _download = lock.lock().await;
+    if let Some((path, mime, file_size)) = app.cache.get_cached_media(channel, id).ok().flatten() {
         return serve_file_media(path, &mime, file_size, range).await;
     }
-
-    let client = tg::TelegramAPI::client();
-    let (downloadable, mime, total_size, media_id) = workers::tg::resolve_media(&client, channel, id)
+    ensure_online(app)?;
+    let (media, mut chunks) = app.tg.open_media(channel, id)
         .await
         .map_err(|e| http_status(Status::NotFound, &e.to_string()))?;
+    let source::MediaInfo { mime, size: total_size, media_id } = media;
 
     // If the file is small enough to cache, download fully, cache, then serve from disk
     let should_cache = total_size.map_or(true, |s| s <= 10 * 1024 * 1024); // cache photos (unknown size) and small files
     if should_cache {
+        // A failed download is not cached, nor served cut short
         let mut all_bytes = Vec::new();
-        let mut download_iter = client.iter_download(&downloadable);
-        while let Ok(Some(chunk)) = download_iter.next().await {
+        while let Some(chunk) = chunks.next().await.map_err(|e| {
+            http_status(Status::InternalServerError, &format!("Can't download media {}/{}: {}", channel, id, e))
+        })? {
             all_bytes.extend_from_slice(&chunk);
-            // Safety: abort if somehow exceeds 10MB (e.g. photo larger than expected)
-            if all_bytes.len() > 10 * 1024 * 1024 {
-                break;
-            }
+        }
+        if total_size.is_some_and(|size| size != all_bytes.len() as i64) {
+            return http_status_err(Status::InternalServerError, "Incomplete download");
         }
 
+        // A photo larger than expected is served without caching
         if all_bytes.len() <= 10 * 1024 * 1024 {
             let _ = app.cache.store_cached_media(channel, id, media_id, &mime, &all_bytes);
             let cached = app.cache.get_cached_media(channel, id).ok().flatten();
@@ -1573,36 +2885,39 @@ async fn media_proxy(
         total_size.unwrap_or(i64::MAX)
     };
 
-    let mut download_iter = client.iter_download(&downloadable);
     if skip_chunks_count > 0 {
-        download_iter = download_iter.skip_chunks(skip_chunks_count);
+        chunks.skip_chunks(skip_chunks_count);
     }
 
     let (duplex_write, duplex_read) = rocket::tokio::io::duplex(CHUNK_SIZE as usize * 2);
 
+    let channel_name = channel.to_string();
     rocket::tokio::spawn(async move {
         use rocket::tokio::io::AsyncWriteExt;
-        let _permit = permit; // hold permit until streaming completes
         let mut writer = duplex_write;
         let mut remaining = bytes_to_send;
         let mut first = true;
 
         while remaining > 0 {
-            match download_iter.next().await {
-                Ok(Some(chunk)) => {
-                    let mut data = &chunk[..];
-                    if first && skip_remainder > 0 {
-                        data = &data[skip_remainder..];
-                    }
-                    first = false;
-                    let to_write = data.len().min(remaining as usize);
-                    if writer.write_all(&data[..to_write]).await.is_err() {
-                        break;
-                    }
-                    remaining -= to_write as i64;
+            // The headers are sent, a failed download can only cut the stream short
+            let chunk = match chunks.next().await.map_err(|e| e.to_string()) {
+                Ok(Some(chunk)) => chunk,
+                Ok(None) => break,
+                Err(e) => {
+                    log::warn!("Media stream {}/{} stopped: {}", channel_name, id, e);
+                    break;
                 }
-                _ => break,
+            };
+            let mut data = &chunk[..];
+            if first && skip_remainder > 0 {
+                data = &data[skip_remainder..];
             }
+            first = false;
+            let to_write = data.len().min(remaining as usize);
+            if writer.write_all(&data[..to_write]).await.is_err() {
+                break;
+            }
+            remaining -= to_write as i64;
         }
     });
 
@@ -1662,38 +2977,33 @@ async fn serve_file_media(
 #[get("/userpic/<channel>")]
 async fn userpic_proxy(
     channel: &str,
-    _app: &rocket::State<Arc<App>>,
+    app: &rocket::State<Arc<App>>,
 ) -> std::result::Result<MediaStream, status::Custom<String>> {
-    let client = tg::TelegramAPI::client();
-    let chat = client
-        .resolve_username(channel)
+    ensure_online(app)?;
+    let mut chunks = app.tg.channel_photo(channel)
         .await
-        .map_err(|e| http_status(Status::InternalServerError, &e.to_string()))?
-        .ok_or_else(|| http_status(Status::NotFound, &format!("Channel {} not found", channel)))?;
-
-    let photo = chat
-        .photo_downloadable(true)
-        .ok_or_else(|| http_status(Status::NotFound, "Channel has no photo"))?;
-
-    let mut download_iter = client.iter_download(&photo);
+        .map_err(|e| http_status(Status::NotFound, &e.to_string()))?;
 
-    let (duplex_write, duplex_read) = rocket::tokio::io::duplex(CHUNK_SIZE as usize * 2);
+    // Userpics are small, downloading one whole turns a failed download into an error status
+    let mut photo = Vec::new();
+    while let Some(chunk) = chunks.next().await.map_err(|e| {
+        http_status(Status::InternalServerError, &format!("Can't download the userpic of {}: {}", channel, e))
+    })? {
+        photo.extend_from_slice(&chunk);
+    }
 
+    let (mut duplex_write, duplex_read) = rocket::tokio::io::duplex(CHUNK_SIZE as usize * 2);
+    let size = photo.len() as i64;
     rocket::tokio::spawn(async move {
         use rocket::tokio::io::AsyncWriteExt;
-        let mut writer = duplex_write;
-        while let Ok(Some(chunk)) = download_iter.next().await {
-            if writer.write_all(&chunk).await.is_err() {
-                break;
-            }
-        }
+        let _ = duplex_write.write_all(&photo).await;
     });
 
     Ok(MediaStream {
         content_type: ContentType::JPEG,
-        total_size: None,
+        total_size: Some(size),
         range_start: 0,
-        range_end: 0,
+        range_end: size - 1,
         is_range: false,
         reader: duplex_read,
     })
@@ -1710,58 +3020,323 @@ async fn localmedia_file(
     }
     let file = app.ctx.output_dir.join(filename);
     log::debug!("Serving local media file: {}", file.display());
+    touch_output(app, &file);
     NamedFile::open(file)
         .await
         .map_err(|e| http_status(Status::NotFound, e.to_string().as_ref()))
 }
 
-#[rocket::main]
-async fn main() {
-    // #[cfg(debug_assertions)]
-    let log_level = log::LevelFilter::Debug;
-    // #[cfg(not(debug_assertions))]
-    // let log_level = log::LevelFilter::Info;
-    SimpleLogger::new().with_level(log_level).init().unwrap();
-    log::debug!("Debug mode");
-
-    let app = match App::new().await {
-        Ok(app) => {
-            log::info!(
-                "Loaded app with config from {}",
-                app.args.config.to_str().unwrap()
-            );
-            app
+/// Task of the digest picked on the command line, dated as the `/digest` routes date it, and the
+/// name of its period for file names
+fn command_task(
+    app: &App,
+    args: &DigestArgs,
+    command: Commands,
+) -> std::result::Result<(Task, String), status::Custom<String>> {
+    let (rank, velocity_window) = get_rank(args.rank.as_deref(), args.velocity_window)?;
+    let defaults = Task::default();
+    let (from_date, to_date, period) = match (args.year, args.month, args.week) {
+        (Some(year), None, _) => {
+            let from_date = get_date_from_year(year)?;
+            (from_date, from_date.checked_add_months(Months::new(12)), year.to_string())
+        }
+        (Some(year), Some(month), None) => {
+            let from_date = get_date_from_month(year, month)?;
+            (from_date, from_date.checked_add_months(Months::new(1)), format!("{}-{:02}", year, month))
+        }
+        (Some(year), Some(month), Some(week)) => {
+            let from_date = get_date_from_week(year, month, week)?;
+            (from_date, from_date.checked_add_days(Days::new(7)), format!("{}-{:02}-w{}", year, month, week))
+        }
+        (None, ..) => {
+            let from_date = match args.from {
+                Some(from) => from.and_hms_opt(0, 0, 0).unwrap().and_utc(),
+                None => DateTime::from_timestamp(defaults.from_date, 0).unwrap(),
+            };
+            let to_date = match (args.from, args.to) {
+                (_, Some(to)) => (to + Days::new(1)).and_hms_opt(0, 0, 0).unwrap().and_utc(),
+                (Some(_), None) => (Utc::now().date_naive() + Days::new(1)).and_hms_opt(0, 0, 0).unwrap().and_utc(),
+                (None, None) => DateTime::from_timestamp(defaults.to_date, 0).unwrap(),
+            };
+            let last_day = (to_date - Days::new(1)).date_naive();
+            (from_date, Some(to_date), format!("{}_{}", from_date.date_naive(), last_day))
         }
-        Err(e) => panic!("Error: {}", e),
     };
-    let mut app = Arc::new(app);
-
-    match tg::TelegramAPI::create(&app.ctx).await {
-        Ok(_) => {
-            log::info!("Connected to Telegram");
-            rocket::tokio::task::spawn(async {
-                let tg = tg::TelegramAPI::client();
-                let tg_ping_timeout = std::time::Duration::from_secs(60);
-                let tg_error_exit_code = -1;
-                loop {
-                    rocket::tokio::time::sleep(tg_ping_timeout).await;
-                    // Ping Telegram to keep connection alive
-                    match tg.get_me().await {
-                        Ok(_) => {
-                            log::debug!("Telegram ping successful");
-                        }
-                        Err(e) => {
-                            log::error!("Telegram ping failed: {}", e);
-                            // TODO Graceful shutdown on errors
-                            std::process::exit(tg_error_exit_code);
-                        }
-                    }
+    let Some(to_date) = to_date else {
+        return http_status_err(Status::BadRequest, "Provided date is not allowed");
+    };
+    if from_date >= to_date {
+        return http_status_err(Status::BadRequest, "The range ends before it starts");
+    }
+
+    let task = Task {
+        command,
+        mode: args.mode.clone(),
+        channel_name: args.channel.clone(),
+        top_count: args.top_count.unwrap_or(defaults.top_count),
+        editor_choice_post_id: args.editor_choice.unwrap_or(defaults.editor_choice_post_id),
+        from_date: from_date.timestamp(),
+        to_date: to_date.timestamp(),
+        rank,
+        velocity_window,
+        reaction: args.reaction.clone(),
+        lang: get_lang(app, args.lang.as_deref(), &AcceptLanguage(None))?,
+        ..defaults
+    };
+    Ok((task, period))
+}
+
+impl CardArgs {
+    fn command(&self) -> Commands {
+        Commands::Cards {
+            replies: self.replies,
+            reactions: self.reactions,
+            forwards: self.forwards,
+            views: self.views,
+        }
+    }
+}
+
+/// Fetch what the cache lacks for `task`, as the static `/digest` does before rendering
+async fn fetch_for_command(app: &App, task: &Task, force: bool) -> Result<()> {
+    if !app.connection.is_online() {
+        return Ok(());
+    }
+    let fetch_target = compute_fetch_target(false, task.top_count);
+    let progress = Arc::new(FetchProgress::new(fetch_target.unwrap_or_default()));
+    background_fetch(app, task, fetch_target, force, &progress)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// A port nothing listens on, for the server of a command rendering cards
+fn free_port() -> Result<u16> {
+    Ok(std::net::TcpListener::bind("127.0.0.1:0")?.local_addr()?.port())
+}
+
+/// Serve the app on the card renderer's port while `work` runs. Card pages load their posts from
+/// `/view`, so rendering cards needs the routes even without the server.
+async fn with_render_server<T>(app: &Arc<App>, work: impl Future<Output = Result<T>>) -> Result<T> {
+    let port = app.card_renderer.as_ref().ok_or("Card renderer is not running")?.port();
+    let liftoff = Arc::new(tokio::sync::Notify::new());
+    let lifted = liftoff.clone();
+    let server = rocket(app.clone())
+        .configure(rocket::Config::figment().merge(("address", "127.0.0.1")).merge(("port", port)))
+        .attach(rocket::fairing::AdHoc::on_liftoff("Render server", move |_| {
+            let lifted = lifted.clone();
+            Box::pin(async move { lifted.notify_one() })
+        }))
+        .ignite()
+        .await
+        .map_err(|e| format!("Can't start the render server: {}", e))?;
+    let shutdown = server.shutdown();
+    let mut server = tokio::spawn(server.launch());
+    tokio::select! {
+        _ = liftoff.notified() => {}
+        launched = &mut server => {
+            let e = match launched {
+                Ok(Ok(_)) => "it stopped".to_string(),
+                Ok(Err(e)) => e.to_string(),
+                Err(e) => e.to_string(),
+            };
+            return Err(format!("Can't start the render server on port {}: {}", port, e).into());
+        }
+    }
+
+    let result = work.await.map_err(|e| e.to_string());
+    shutdown.notify();
+    if let Ok(Err(e)) = server.await {
+        log::warn!("Render server: {}", e);
+    }
+    Ok(result?)
+}
+
+/// Static digest templates only, an async one loads its data from the server in the browser
+fn static_digest_template(app: &App, mode: &str, command: &str) -> Result<String> {
+    let template = format!("{}/digest_template.html", mode);
+    let source = std::fs::read_to_string(app.ctx.input_dir.join(&template))?;
+    if source.contains("data_url") {
+        return Err(format!(
+            "The digest template of mode {} loads its data from the server, {} needs a static one",
+            mode, command
+        )
+        .into());
+    }
+    Ok(template)
+}
+
+/// Run a CLI command instead of the server
+async fn run_command(app: &Arc<App>, command: &CliCommand) -> Result<()> {
+    match command {
+        CliCommand::Serve => unreachable!("main runs the server"),
+        CliCommand::Digest { digest, out } => {
+            let (task, period) = command_task(app, digest, Commands::Digest {}).map_err(|e| e.1)?;
+            let template = static_digest_template(app, &task.mode, "the digest command")?;
+            fetch_for_command(app, &task, digest.force).await?;
+
+            let context = digest_context(app, &task).await.map_err(|e| e.1)?;
+            let file = match out {
+                Some(out) => std::path::absolute(out)?,
+                None => PathBuf::from("digest").join(&task.mode).join(&task.channel_name).join(format!("{}.html", period)),
+            };
+            let file = app.html_renderer.render_to_file(&template, &context, &file)?;
+            log::info!("Digest of t.me/{} saved to {}", task.channel_name, file.display());
+            println!("{}", file.display());
+            Ok(())
+        }
+        CliCommand::Cards { digest, cards, out } => {
+            let (task, period) = command_task(app, digest, cards.command()).map_err(|e| e.1)?;
+            let options = get_video_options(cards.format.as_deref(), None, None).map_err(|e| e.1)?;
+            fetch_for_command(app, &task, digest.force).await?;
+
+            let (post_top, _) = get_cached_top_posts(app, &task, None, false).map_err(|e| e.to_string())?;
+            let html = render_video_html(app, &task, post_top, options).map_err(|e| e.1)?;
+            let dir = match out {
+                Some(out) => std::path::absolute(out)?,
+                None => app.ctx.output_dir.join("cards").join(&task.mode).join(&task.channel_name).join(&period),
+            };
+            tokio::fs::create_dir_all(&dir).await?;
+            let card_renderer = app.card_renderer.as_ref().ok_or("Card renderer is not running")?;
+            let rendered = with_render_server(app, async {
+                card_renderer
+                    .render_html(&dir, &html, options.format.viewport(), None)
+                    .await
+                    .map_err(|e| e.to_string().into())
+            })
+            .await?;
+            log::info!("{} cards of t.me/{} saved to {}", rendered.len(), task.channel_name, dir.display());
+            for card in &rendered {
+                println!("{}", card.image.display());
+            }
+            Ok(())
+        }
+        CliCommand::Video { digest, cards, output, post_video, out } => {
+            let (task, _) = command_task(app, digest, cards.command()).map_err(|e| e.1)?;
+            let options = get_video_options(cards.format.as_deref(), output.as_deref(), *post_video).map_err(|e| e.1)?;
+            check_video_spec(app, &task.mode, options).map_err(|e| e.1)?;
+            let channel = task.channel_name.clone();
+            let file = with_render_server(app, async {
+                make_video(app, task, options, digest.force, None).await.map_err(|e| e.1.into())
+            })
+            .await?;
+            let file = match out {
+                Some(out) => {
+                    tokio::fs::copy(&file, out).await?;
+                    out.clone()
                 }
+                None => file,
+            };
+            log::info!("Video of t.me/{} saved to {}", channel, file.display());
+            println!("{}", file.display());
+            Ok(())
+        }
+        CliCommand::Post { channel, id, out } => {
+            let post = app.tg.get_post_data(channel, *id).await.map_err(|e| e.to_string())?;
+            let file = match out {
+                Some(out) => std::path::absolute(out)?,
+                None => app.ctx.output_dir.join("post").join(channel).join(format!("{}.json", id)),
+            };
+            if let Some(parent) = file.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            tokio::fs::write(&file, serde_json::to_string_pretty(&post)?).await?;
+            log::info!("Post t.me/{}/{} saved to {}", channel, id, file.display());
+            println!("{}", file.display());
+            Ok(())
+        }
+        CliCommand::Login => {
+            if app.ctx.fixture_dir.is_some() {
+                return Err("Fixtures replace the Telegram connection, there is nothing to sign in to".into());
+            }
+            // main signed in every account before running the command
+            for (account, credentials) in app.ctx.accounts().iter().enumerate() {
+                let client = tg::TelegramAPI::account_client(account)?;
+                let me = client.get_me().await.map_err(|e| e.to_string())?;
+                log::info!(
+                    "Signed in as {}, the session is saved to {}",
+                    me.full_name(),
+                    credentials.tg_session.display()
+                );
+            }
+            Ok(())
+        }
+        CliCommand::Cache { command: CacheCommand::Stats } => {
+            let media = app.cache.media_usage()?;
+            let stats = serde_json::json!({
+                "posts": app.cache.channel_post_counts()?,
+                "media": {
+                    "files": media.files,
+                    "bytes": media.bytes,
+                    "limit_bytes": app.ctx.cache_limit_mb * 1024 * 1024,
+                },
+                "output": output_usage(app)?,
             });
+            println!("{}", serde_json::to_string_pretty(&stats)?);
+            Ok(())
         }
-        Err(e) => panic!("Error: {}", e),
-    };
+        CliCommand::Cache { command: CacheCommand::Prune } => {
+            remove_render_dirs(app);
+            let adopted = app.cache.adopt_artifacts(&app.ctx.output_dir)?;
+            let outputs = evict_outputs(app, None);
+            let media = app.cache.evict_media()?;
+            log::info!(
+                "Tracking {} more output files, removed {} output files ({} bytes) and {} cached media ({} bytes)",
+                adopted,
+                outputs.files,
+                outputs.bytes,
+                media.files,
+                media.bytes
+            );
+            Ok(())
+        }
+        CliCommand::Export { mode, channel, from, to, periods, top_count, lang, out, max_media_mb } => {
+            let options = export::ExportOptions {
+                mode: mode.clone(),
+                channel: channel.clone(),
+                from: *from,
+                to: to.unwrap_or_else(|| Utc::now().date_naive()),
+                periods: periods.clone(),
+                top_count: *top_count,
+                lang: lang.clone().unwrap_or_else(|| app.locales.default_lang().to_string()),
+                dir: out.clone().unwrap_or_else(|| app.ctx.output_dir.join("site").join(channel)),
+                max_media_size: *max_media_mb as i64 * 1024 * 1024,
+            };
+            let summary = export::export(app, &options).await?;
+            log::info!(
+                "Exported {} digests, {} posts and {} assets of t.me/{} to {} ({} links left to the server)",
+                summary.digests,
+                summary.posts,
+                summary.assets,
+                channel,
+                options.dir.display(),
+                summary.missing
+            );
+            Ok(())
+        }
+    }
+}
+
+/// Take the accounts that are not signed in out of the pool. The server is signed out only when
+/// none is, the others take the calls of a signed out account meanwhile.
+fn apply_sign_in(app: &App, signed_in: &[bool]) {
+    for (account, signed_in) in signed_in.iter().enumerate() {
+        if !signed_in {
+            app.pool.disable(account, "Not signed in");
+        }
+    }
+    if !app.args.serve() {
+        return;
+    }
+    if app.pool.enabled_accounts().is_empty() {
+        app.connection.set_authorized(false);
+        log::warn!("Sign in to Telegram at /admin or with the login command");
+    } else if !app.pool.is_enabled(0) {
+        log::warn!("The first Telegram account is not signed in, sign it in at /admin");
+    }
+}
 
+fn rocket(app: Arc<App>) -> rocket::Rocket<rocket::Build> {
     rocket::build()
         .mount(
             "/",
@@ -1769,6 +3344,7 @@ async fn main() {
                 file,
                 index,
                 data_endpoint,
+                data_events,
                 digest_by_week,
                 digest_by_month,
                 digest_by_year,
@@ -1777,24 +3353,147 @@ async fn main() {
                 video_by_month,
                 video_by_year,
                 video,
+                create_video_job,
+                video_job_status,
+                video_job_result,
                 post_json,
+                post_comments,
+                post_history,
+                telegram_stats,
+                output_stats,
+                renderer_stats,
+                admin_page,
+                login_status,
+                login_phone,
+                login_code,
+                login_password,
+                logout,
                 view_post,
                 post_image,
                 media_proxy,
                 thumb_proxy,
                 userpic_proxy,
-                localmedia_file
+                localmedia_file,
+                moved
             ],
         )
-        .manage(app.clone())
-        .launch()
-        .await
-        .unwrap();
+        .attach(rocket::fairing::AdHoc::on_request("Channel aliases", |req, _| {
+            Box::pin(async move {
+                if req.method() != rocket::http::Method::Get {
+                    return;
+                }
+                let Some(app) = req.rocket().state::<Arc<App>>() else {
+                    return;
+                };
+                if let Some(to) = renamed_channel_uri(app, req.uri()).await {
+                    req.local_cache(|| ChannelMoved(Some(to)));
+                    req.set_uri(rocket::http::uri::Origin::parse("/moved").unwrap());
+                }
+            })
+        }))
+        .attach(rocket::fairing::AdHoc::on_response("Retry-After", |req, res| {
+            Box::pin(async move {
+                if res.status() != Status::ServiceUnavailable {
+                    return;
+                }
+                // Signing in has no time to retry after
+                if let Some(app) = req.rocket().state::<Arc<App>>()
+                    && app.connection.is_authorized()
+                    && !app.connection.is_online()
+                {
+                    res.set_header(Header::new("Retry-After", app.connection.retry_after().to_string()));
+                }
+            })
+        }))
+        .manage(app)
+}
+
+#[rocket::main]
+async fn main() {
+    // #[cfg(debug_assertions)]
+    let log_level = log::LevelFilter::Debug;
+    // #[cfg(not(debug_assertions))]
+    // let log_level = log::LevelFilter::Info;
+    SimpleLogger::new().with_level(log_level).init().unwrap();
+    log::debug!("Debug mode");
+
+    let app = match App::new().await {
+        Ok(app) => {
+            log::info!(
+                "Loaded app with config from {}",
+                app.args.config.to_str().unwrap()
+            );
+            app
+        }
+        Err(e) => panic!("Error: {}", e),
+    };
+    let app = Arc::new(app);
+
+    // Fixtures replace the Telegram connection entirely, the cache commands don't need it
+    let needs_telegram = !matches!(app.args.command, Some(CliCommand::Cache { .. }));
+    let supervisor = if app.ctx.fixture_dir.is_none() && needs_telegram {
+        // Only `login` asks to sign in. The server starts signed out instead and an admin signs in
+        // at /admin, other commands run unattended.
+        let connected = if app.args.serve() {
+            tg::TelegramAPI::start(&app.ctx).await.map_err(|e| e.to_string())
+        } else if matches!(app.args.command, Some(CliCommand::Login)) {
+            tg::TelegramAPI::create(&app.ctx)
+                .await
+                .map(|_| vec![true; app.ctx.accounts().len()])
+                .map_err(|e| e.to_string())
+        } else {
+            tg::TelegramAPI::connect(&app.ctx).await.map_err(|e| e.to_string())
+        };
+        match connected {
+            Ok(signed_in) => {
+                log::info!("Connected to Telegram");
+                apply_sign_in(&app, &signed_in);
+            }
+            Err(e) if app.args.serve() => panic!("Error: {}", e),
+            Err(e) => {
+                log::error!("{}", e);
+                std::process::exit(1);
+            }
+        };
+        let app = app.clone();
+        Some(rocket::tokio::task::spawn(async move {
+            connection::supervise(&app.ctx, &app.connection, &app.pool).await
+        }))
+    } else {
+        None
+    };
+
+    if let Some(command) = &app.args.command
+        && !app.args.serve()
+    {
+        let result = run_command(&app, command).await.map_err(|e| e.to_string());
+        if let Some(supervisor) = supervisor {
+            supervisor.abort();
+            let _ = supervisor.await;
+        }
+        if let Err(e) = result {
+            log::error!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let sweeper = rocket::tokio::spawn(sweep_outputs(app.clone()));
+
+    rocket(app.clone()).launch().await.unwrap();
 
     log::info!("Rocket server stopped");
-    let app = Arc::get_mut(&mut app).unwrap();
-    match app.card_renderer.close().await {
-        Ok(_) => log::info!("Browser closed"),
-        Err(e) => log::error!("{}", e),
+    sweeper.abort();
+    let _ = sweeper.await;
+    if let Some(supervisor) = supervisor {
+        supervisor.abort();
+        let _ = supervisor.await;
+    }
+    // Video jobs still running hold the app, the browser is closed under them
+    if let Some(card_renderer) = app.card_renderer.as_ref() {
+        match card_renderer.close().await {
+            Ok(_) => log::info!("Browser closed"),
+            Err(e) => log::error!("{}", e),
+        }
     }
 }
@@ -1,4 +1,5 @@
-use crate::action::ActionType;
+use crate::action::{ActionType, Rank};
+use crate::post_data::{self, CommentData, ReactionData};
 use crate::util::Result;
 
 use chrono::{DateTime, Utc};
@@ -16,6 +17,59 @@ pub struct Post {
     pub image: Option<i64>,
     #[serde(skip_serializing)]
     pub grouped_id: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub velocity: Option<Velocity>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rates: Option<Rates>,
+    /// Per-reaction breakdown as fetched from Telegram. Not loaded back from
+    /// the cache, see `cache::PostCache::get_reaction_counts`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub reaction_counts: Vec<ReactionData>,
+    /// Count of the reaction requested with `Task::reaction`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub selected_reaction: Option<i32>,
+    /// Most reacted cached comment, see `cache::PostCache::get_top_comments`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_comment: Option<CommentData>,
+}
+
+/// Counters per 1000 views (‰), see `Post::engagement_rates`. Kept unrounded for ranking,
+/// `Post::count` rounds them for display.
+#[derive(Clone, Copy, Default, serde::Serialize)]
+pub struct Rates {
+    pub reactions: f64,
+    pub forwards: f64,
+    pub replies: f64,
+    /// Combined engagement rate: (reactions + forwards + replies) / views
+    pub engagement: f64,
+}
+
+impl Rates {
+    fn get(&self, index: ActionType) -> Option<f64> {
+        match index {
+            ActionType::ReactionRate => Some(self.reactions),
+            ActionType::ForwardRate => Some(self.forwards),
+            ActionType::ReplyRate => Some(self.replies),
+            ActionType::EngagementRate => Some(self.engagement),
+            _ => None,
+        }
+    }
+}
+
+/// Counters gained per hour since the post was published.
+#[derive(Clone, Copy, Default, serde::Serialize)]
+pub struct Velocity {
+    pub views: i32,
+    pub forwards: i32,
+    pub replies: i32,
+    pub reactions: i32,
+}
+
+impl Velocity {
+    /// Interactions (replies + reactions + forwards) gained per hour.
+    pub fn engagement(&self) -> i32 {
+        self.replies + self.reactions + self.forwards
+    }
 }
 
 impl Post {
@@ -36,56 +90,138 @@ impl Post {
                 break;
             }
 
-            let grouped_id = message.grouped_id();
-            posts.push(Post {
-                date,
-                id: message.id(),
-                views: message.view_count(),
-                forwards: message.forward_count(),
-                replies: message.reply_count(),
-                reactions: message.reaction_count(),
-                message: Some(message.msg.message),
-                image: None,
-                grouped_id,
-            });
+            posts.push(Post::from_message(message));
         }
 
         Result::Ok(posts)
     }
 
+    /// Counters and text of a channel message, with its per-reaction breakdown
+    pub fn from_message(message: grammers_client::types::Message) -> Post {
+        let reaction_counts = message
+            .msg
+            .reactions
+            .as_ref()
+            .map(post_data::extract_reactions)
+            .unwrap_or_default();
+        Post {
+            date: message.date().timestamp(),
+            id: message.id(),
+            views: message.view_count(),
+            forwards: message.forward_count(),
+            replies: message.reply_count(),
+            reactions: message.reaction_count(),
+            grouped_id: message.grouped_id(),
+            message: Some(message.msg.message),
+            image: None,
+            velocity: None,
+            rates: None,
+            reaction_counts,
+            selected_reaction: None,
+            top_comment: None,
+        }
+    }
+
     pub fn count(&self, index: ActionType) -> Option<i32> {
         match index {
             ActionType::Replies => self.replies,
             ActionType::Reactions => self.reactions,
             ActionType::Forwards => self.forwards,
             ActionType::Views => self.views,
+            ActionType::Velocity => self.velocity.map(|v| v.engagement()),
+            ActionType::ReactionRate
+            | ActionType::ForwardRate
+            | ActionType::ReplyRate
+            | ActionType::EngagementRate => self.rates?.get(index).map(|rate| rate.round() as i32),
+            ActionType::Reaction => self.selected_reaction,
+            ActionType::TopComment => self.top_comment.as_ref().and_then(|c| c.reactions),
         }
     }
+
+    /// The value the post is ranked by: either the raw counter or its
+    /// per-hour growth, depending on `rank`. Derived metrics (velocity and
+    /// rates) are ranked the same way in both modes.
+    pub fn score(&self, index: ActionType, rank: Rank) -> Option<i32> {
+        if rank == Rank::Total {
+            return self.count(index);
+        }
+        match index {
+            ActionType::Replies => self.velocity.map(|v| v.replies),
+            ActionType::Reactions => self.velocity.map(|v| v.reactions),
+            ActionType::Forwards => self.velocity.map(|v| v.forwards),
+            ActionType::Views => self.velocity.map(|v| v.views),
+            _ => self.count(index),
+        }
+    }
+
+    /// `score` for ranking, with rates unrounded so low ones don't tie
+    fn rank_value(&self, index: ActionType, rank: Rank) -> Option<f64> {
+        match self.rates.and_then(|rates| rates.get(index)) {
+            Some(rate) => Some(rate),
+            None => self.score(index, rank).map(f64::from),
+        }
+    }
+
+    /// Reactions, forwards and replies per 1000 views. Posts with fewer than
+    /// `min_views` views get no rates, so a post with a single view and a
+    /// single reaction doesn't top the ranking.
+    pub fn engagement_rates(&self, min_views: i32) -> Option<Rates> {
+        let views = self.views.filter(|&v| v > 0 && v >= min_views)? as f64;
+        let per_mille = |count: Option<i32>| count.unwrap_or(0) as f64 * 1000.0 / views;
+        let engagement = self.reactions.unwrap_or(0) + self.forwards.unwrap_or(0) + self.replies.unwrap_or(0);
+        Some(Rates {
+            reactions: per_mille(self.reactions),
+            forwards: per_mille(self.forwards),
+            replies: per_mille(self.replies),
+            engagement: per_mille(Some(engagement)),
+        })
+    }
 }
 
 #[derive(serde::Serialize)]
 pub struct TopPost {
     pub top_count: usize,
+    pub rank: Rank,
     pub replies: Vec<Post>,
     pub reactions: Vec<Post>,
     pub forwards: Vec<Post>,
     pub views: Vec<Post>,
+    pub velocity: Vec<Post>,
+    pub reaction_rate: Vec<Post>,
+    pub forward_rate: Vec<Post>,
+    pub reply_rate: Vec<Post>,
+    pub engagement_rate: Vec<Post>,
+    pub reaction: Vec<Post>,
+    pub top_comment: Vec<Post>,
+    /// All candidate posts, ranked on demand by `blocks.json` formulas
+    pub posts: Vec<Post>,
 }
 
 impl TopPost {
-    fn get_top_by(top_count: usize, posts: &mut [Post], action: ActionType) -> Vec<Post> {
+    /// Posts with equal scores go newest first, posts without one last.
+    fn get_top_by(top_count: usize, posts: &mut [Post], action: ActionType, rank: Rank) -> Vec<Post> {
         let top_count = top_count.min(posts.len());
-        posts.partial_sort(top_count, |a, b| b.count(action).cmp(&a.count(action)));
+        let value = |post: &Post| post.rank_value(action, rank).unwrap_or(f64::NEG_INFINITY);
+        posts.partial_sort(top_count, |a, b| value(b).total_cmp(&value(a)).then(b.id.cmp(&a.id)));
         posts[0..top_count].to_vec()
     }
 
-    pub fn get_top(top_count: usize, posts: &mut [Post]) -> TopPost {
+    pub fn get_top(top_count: usize, posts: &mut [Post], rank: Rank) -> TopPost {
         TopPost {
             top_count,
-            replies: Self::get_top_by(top_count, posts, ActionType::Replies),
-            reactions: Self::get_top_by(top_count, posts, ActionType::Reactions),
-            forwards: Self::get_top_by(top_count, posts, ActionType::Forwards),
-            views: Self::get_top_by(top_count, posts, ActionType::Views),
+            rank,
+            replies: Self::get_top_by(top_count, posts, ActionType::Replies, rank),
+            reactions: Self::get_top_by(top_count, posts, ActionType::Reactions, rank),
+            forwards: Self::get_top_by(top_count, posts, ActionType::Forwards, rank),
+            views: Self::get_top_by(top_count, posts, ActionType::Views, rank),
+            velocity: Self::get_top_by(top_count, posts, ActionType::Velocity, rank),
+            reaction_rate: Self::get_top_by(top_count, posts, ActionType::ReactionRate, rank),
+            forward_rate: Self::get_top_by(top_count, posts, ActionType::ForwardRate, rank),
+            reply_rate: Self::get_top_by(top_count, posts, ActionType::ReplyRate, rank),
+            engagement_rate: Self::get_top_by(top_count, posts, ActionType::EngagementRate, rank),
+            reaction: Self::get_top_by(top_count, posts, ActionType::Reaction, rank),
+            top_comment: Self::get_top_by(top_count, posts, ActionType::TopComment, rank),
+            posts: posts.to_vec(),
         }
     }
 
@@ -95,6 +231,13 @@ impl TopPost {
             ActionType::Reactions => &self.reactions,
             ActionType::Forwards => &self.forwards,
             ActionType::Views => &self.views,
+            ActionType::Velocity => &self.velocity,
+            ActionType::ReactionRate => &self.reaction_rate,
+            ActionType::ForwardRate => &self.forward_rate,
+            ActionType::ReplyRate => &self.reply_rate,
+            ActionType::EngagementRate => &self.engagement_rate,
+            ActionType::Reaction => &self.reaction,
+            ActionType::TopComment => &self.top_comment,
         }
     }
 
@@ -104,12 +247,19 @@ impl TopPost {
             format!("Top {} by reactions:", self.top_count),
             format!("Top {} by forwards:", self.top_count),
             format!("Top {} by views:", self.top_count),
+            format!("Top {} by velocity:", self.top_count),
+            format!("Top {} by reactions per 1000 views:", self.top_count),
+            format!("Top {} by forwards per 1000 views:", self.top_count),
+            format!("Top {} by comments per 1000 views:", self.top_count),
+            format!("Top {} by engagement rate:", self.top_count),
+            format!("Top {} by selected reaction:", self.top_count),
+            format!("Top {} by top comment reactions:", self.top_count),
         ];
         for (index, header) in headers.iter().enumerate() {
             println!("{header}");
             let action = ActionType::from(index);
             for (pos, post) in self.index(action).iter().enumerate() {
-                match post.count(action) {
+                match post.score(action, self.rank) {
                     Some(count) => {
                         println!(
                             "\t{}. {}: {}\t({})",
@@ -129,3 +279,76 @@ impl TopPost {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn post(id: i32, views: i32, reactions: i32, forwards: i32, replies: i32) -> Post {
+        Post {
+            date: 0,
+            id,
+            views: Some(views),
+            forwards: Some(forwards),
+            replies: Some(replies),
+            reactions: Some(reactions),
+            message: None,
+            image: None,
+            grouped_id: None,
+            velocity: None,
+            rates: None,
+            reaction_counts: Vec::new(),
+            selected_reaction: None,
+            top_comment: None,
+        }
+    }
+
+    #[test]
+    fn rates_are_per_mille_of_views() {
+        let post = post(1, 2000, 7, 2, 1);
+        let rates = post.engagement_rates(0).unwrap();
+        assert_eq!(rates.reactions, 3.5);
+        assert_eq!(rates.forwards, 1.0);
+        assert_eq!(rates.replies, 0.5);
+        assert_eq!(rates.engagement, 5.0);
+        let post = Post { rates: Some(rates), ..post };
+        assert_eq!(post.count(ActionType::ReactionRate), Some(4));
+        assert_eq!(post.count(ActionType::ReplyRate), Some(1));
+    }
+
+    #[test]
+    fn rates_skip_posts_under_min_views() {
+        assert!(post(1, 99, 1, 0, 0).engagement_rates(100).is_none());
+        assert!(post(1, 100, 1, 0, 0).engagement_rates(100).is_some());
+        assert!(post(1, 0, 1, 0, 0).engagement_rates(0).is_none());
+        let mut no_views = post(1, 0, 1, 0, 0);
+        no_views.views = None;
+        assert!(no_views.engagement_rates(0).is_none());
+    }
+
+    #[test]
+    fn low_rates_rank_unrounded_and_ties_newest_first() {
+        // 1‰ twice, 0.2‰ and 0.1‰ that both show as 0, and no rates under the cutoff
+        let posts = [
+            post(1, 1000, 1, 0, 0),
+            post(2, 2000, 2, 0, 0),
+            post(3, 10000, 1, 0, 0),
+            post(4, 5000, 1, 0, 0),
+            post(5, 10, 5, 0, 0),
+        ];
+        let mut posts: Vec<Post> = posts
+            .into_iter()
+            .map(|mut p| {
+                p.rates = p.engagement_rates(50);
+                p
+            })
+            .collect();
+        let top = TopPost::get_top(5, &mut posts, Rank::Total);
+        let ranked: Vec<(i32, Option<i32>)> = top
+            .reaction_rate
+            .iter()
+            .map(|p| (p.id, p.count(ActionType::ReactionRate)))
+            .collect();
+        assert_eq!(ranked, vec![(2, Some(1)), (1, Some(1)), (4, Some(0)), (3, Some(0)), (5, None)]);
+    }
+}
@@ -1,8 +1,10 @@
+use crate::source::{ChannelRef, channel_key, post_url};
+
 use grammers_tl_types::enums;
 
 // ── JSON response structs ──────────────────────────────────────────────
 
-#[derive(serde::Serialize)]
+#[derive(serde::Serialize, serde::Deserialize)]
 pub struct PostData {
     // Identity
     pub id: i32,
@@ -33,6 +35,8 @@ pub struct PostData {
     pub forwards: Option<i32>,
     pub replies: Option<i32>,
     pub reactions: Option<i32>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub reaction_counts: Vec<ReactionData>,
 
     // Metadata
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -46,12 +50,26 @@ pub struct PostData {
     pub pinned: bool,
 
     // Album: all media items in the group (populated when grouped_id is set)
-    #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub album: Vec<AlbumItem>,
 }
 
+/// A reply in the discussion group linked to the channel, see `/post/<channel>/<id>/comments`
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct CommentData {
+    pub id: i32,
+    pub date: i64,
+    /// Name of the user or chat that wrote it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+    pub text: String,
+    pub reactions: Option<i32>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub reaction_counts: Vec<ReactionData>,
+}
+
 /// A single media item within an album (media group).
-#[derive(serde::Serialize)]
+#[derive(serde::Serialize, serde::Deserialize)]
 pub struct AlbumItem {
     pub msg_id: i32,
     pub url: String,
@@ -63,7 +81,7 @@ pub struct AlbumItem {
     pub document: Option<DocumentData>,
 }
 
-#[derive(serde::Serialize)]
+#[derive(serde::Serialize, serde::Deserialize)]
 pub struct Entity {
     #[serde(rename = "type")]
     pub entity_type: String,
@@ -79,7 +97,7 @@ pub struct Entity {
     pub document_id: Option<i64>,
 }
 
-#[derive(serde::Serialize)]
+#[derive(serde::Serialize, serde::Deserialize)]
 pub struct PhotoData {
     pub id: i64,
     pub url: String,
@@ -89,7 +107,7 @@ pub struct PhotoData {
     pub height: Option<i32>,
 }
 
-#[derive(serde::Serialize)]
+#[derive(serde::Serialize, serde::Deserialize)]
 pub struct VideoData {
     pub id: i64,
     pub url: String,
@@ -108,7 +126,7 @@ pub struct VideoData {
     pub supports_streaming: bool,
 }
 
-#[derive(serde::Serialize)]
+#[derive(serde::Serialize, serde::Deserialize)]
 pub struct DocumentData {
     pub id: i64,
     pub url: String,
@@ -121,7 +139,7 @@ pub struct DocumentData {
     pub audio: Option<AudioInfo>,
 }
 
-#[derive(serde::Serialize)]
+#[derive(serde::Serialize, serde::Deserialize)]
 pub struct AudioInfo {
     pub duration: i32,
     pub voice: bool,
@@ -131,7 +149,7 @@ pub struct AudioInfo {
     pub performer: Option<String>,
 }
 
-#[derive(serde::Serialize)]
+#[derive(serde::Serialize, serde::Deserialize)]
 pub struct ContactData {
     pub phone_number: String,
     pub first_name: String,
@@ -139,7 +157,7 @@ pub struct ContactData {
     pub user_id: i64,
 }
 
-#[derive(serde::Serialize)]
+#[derive(serde::Serialize, serde::Deserialize)]
 pub struct WebPageData {
     pub url: String,
     pub display_url: String,
@@ -159,7 +177,7 @@ pub struct WebPageData {
     pub duration: Option<i32>,
 }
 
-#[derive(serde::Serialize)]
+#[derive(serde::Serialize, serde::Deserialize)]
 pub struct ForwardData {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub from_name: Option<String>,
@@ -174,15 +192,76 @@ pub struct ForwardData {
     pub from_username: Option<String>,
 }
 
-#[derive(serde::Serialize)]
+#[derive(serde::Serialize, serde::Deserialize)]
 pub struct PeerData {
     #[serde(rename = "type")]
     pub peer_type: String,
     pub id: i64,
 }
 
+/// Count of a single reaction: a regular emoji, a custom emoji or paid stars.
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct ReactionData {
+    #[serde(rename = "type")]
+    pub reaction_type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub emoticon: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub document_id: Option<i64>,
+    pub count: i32,
+}
+
+impl ReactionData {
+    /// Stable key used in the cache and the `reaction` query parameter:
+    /// the emoji itself, `custom:<document_id>` or `paid`.
+    pub fn key(&self) -> String {
+        match (&self.emoticon, self.document_id) {
+            (Some(emoticon), _) => emoticon.clone(),
+            (None, Some(document_id)) => format!("custom:{}", document_id),
+            (None, None) => self.reaction_type.clone(),
+        }
+    }
+
+    /// Inverse of `key`
+    pub fn from_key(key: &str, count: i32) -> ReactionData {
+        let (reaction_type, emoticon, document_id) = match key.strip_prefix("custom:") {
+            Some(document_id) => ("custom_emoji", None, document_id.parse().ok()),
+            None if key == "paid" => ("paid", None, None),
+            None => ("emoji", Some(key.to_string()), None),
+        };
+        ReactionData {
+            reaction_type: reaction_type.to_string(),
+            emoticon,
+            document_id,
+            count,
+        }
+    }
+}
+
 // ── Conversion from grammers types ─────────────────────────────────────
 
+pub fn extract_reactions(reactions: &enums::MessageReactions) -> Vec<ReactionData> {
+    let enums::MessageReactions::Reactions(r) = reactions;
+    r.results
+        .iter()
+        .filter_map(|rc| {
+            let enums::ReactionCount::Count(rc) = rc;
+            let (reaction_type, emoticon, document_id) = match &rc.reaction {
+                enums::Reaction::Emoji(e) => ("emoji", Some(e.emoticon.clone()), None),
+                enums::Reaction::CustomEmoji(ce) => ("custom_emoji", None, Some(ce.document_id)),
+                enums::Reaction::Paid => ("paid", None, None),
+                enums::Reaction::Empty => return None,
+            };
+            Some(ReactionData {
+                reaction_type: reaction_type.to_string(),
+                emoticon,
+                document_id,
+                count: rc.count,
+            })
+        })
+        .collect()
+}
+
 pub fn extract_entities(entities: &[enums::MessageEntity]) -> Vec<Entity> {
     entities
         .iter()
@@ -465,7 +544,10 @@ pub fn from_message(msg: &grammers_client::types::Message, channel_name: &str) -
         id: msg.id(),
         date: msg.date().timestamp(),
         edit_date: raw.edit_date.map(|d| d as i64),
-        url: format!("https://t.me/{}/{}", channel_name, msg.id()),
+        url: match ChannelRef::parse(channel_name) {
+            ChannelRef::Username(_) => post_url(channel_name, msg.id()),
+            _ => post_url(&channel_key(msg.chat().id()), msg.id()),
+        },
         text: raw.message.clone(),
         entities,
         photo,
@@ -477,6 +559,11 @@ pub fn from_message(msg: &grammers_client::types::Message, channel_name: &str) -
         forwards: msg.forward_count(),
         replies: msg.reply_count(),
         reactions: msg.reaction_count(),
+        reaction_counts: raw
+            .reactions
+            .as_ref()
+            .map(extract_reactions)
+            .unwrap_or_default(),
         post_author: raw.post_author.clone(),
         forward_from,
         reply_to_msg_id,
@@ -487,6 +574,19 @@ pub fn from_message(msg: &grammers_client::types::Message, channel_name: &str) -
     }
 }
 
+/// Build a CommentData from a raw reply of a discussion thread.
+pub fn comment_from_message(msg: &grammers_tl_types::types::Message, author: Option<String>) -> CommentData {
+    let reaction_counts = msg.reactions.as_ref().map(extract_reactions).unwrap_or_default();
+    CommentData {
+        id: msg.id,
+        date: msg.date as i64,
+        author,
+        text: msg.message.clone(),
+        reactions: (!reaction_counts.is_empty()).then(|| reaction_counts.iter().map(|r| r.count).sum()),
+        reaction_counts,
+    }
+}
+
 /// Build an AlbumItem from one message in a media group.
 pub fn album_item_from_message(
     msg: &grammers_client::types::Message,
@@ -0,0 +1,429 @@
+//! Rate limiting for Telegram requests.
+//!
+//! Every call made through `source::scheduled::ScheduledSource` takes a token
+//! from the bucket of its method class and from a shared account bucket.
+//! Interactive callers (post views, media, titles) go first: background fetches
+//! don't take account tokens while an interactive caller waits for them.
+//!
+//! A `FLOOD_WAIT_X` error pauses the whole method class for X seconds, then
+//! the call is retried. Waits longer than `MAX_FLOOD_WAIT` fail the call.
+//!
+//! Each Telegram account has its own scheduler. In a pool of accounts every
+//! flood wait fails the call, so `source::pool::PooledSource` moves it to
+//! another account instead of sleeping.
+
+use crate::util::Result;
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio::time::Instant;
+
+/// Longest flood wait the scheduler sleeps through before giving up
+pub const MAX_FLOOD_WAIT: Duration = Duration::from_secs(15 * 60);
+
+/// How often a background caller re-checks while it yields to interactive ones
+const YIELD_POLL: Duration = Duration::from_millis(50);
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MethodClass {
+    /// Channel lookups by username
+    Resolve,
+    /// Message history pages
+    History,
+    /// Single messages by id
+    Messages,
+    /// File downloads
+    Media,
+}
+
+impl MethodClass {
+    const ALL: [MethodClass; 4] = [
+        MethodClass::Resolve,
+        MethodClass::History,
+        MethodClass::Messages,
+        MethodClass::Media,
+    ];
+
+    /// Burst size and sustained requests per second
+    fn limits(self) -> (f64, f64) {
+        match self {
+            MethodClass::Resolve => (20.0, 1.0),
+            MethodClass::History => (5.0, 0.5),
+            MethodClass::Messages => (20.0, 5.0),
+            MethodClass::Media => (10.0, 2.0),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    /// A user waits for the answer
+    Interactive,
+    /// Cache refresh and backfill
+    Background,
+}
+
+struct Bucket {
+    capacity: f64,
+    per_sec: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn new((capacity, per_sec): (f64, f64)) -> Bucket {
+        Bucket {
+            capacity,
+            per_sec,
+            tokens: capacity,
+            updated: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.per_sec).min(self.capacity);
+        self.updated = now;
+    }
+
+    /// Time until a whole token is available
+    fn wait(&self) -> Duration {
+        if self.tokens >= 1.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64((1.0 - self.tokens) / self.per_sec)
+        }
+    }
+}
+
+/// How long callers of one method class and priority waited for their turn
+#[derive(Default, serde::Serialize)]
+pub struct WaitStats {
+    pub requests: u64,
+    pub waited_ms: u64,
+    pub max_wait_ms: u64,
+    pub flood_waits: u64,
+    pub flood_wait_secs: u64,
+}
+
+struct State {
+    account: Bucket,
+    buckets: HashMap<MethodClass, Bucket>,
+    flood_until: HashMap<MethodClass, Instant>,
+    stats: HashMap<(MethodClass, Priority), WaitStats>,
+}
+
+pub struct Scheduler {
+    state: Mutex<State>,
+    /// Longer flood waits fail the call
+    max_flood_wait: Duration,
+    /// Interactive callers waiting for account tokens
+    interactive_waiting: AtomicUsize,
+    wake: Notify,
+}
+
+/// Keeps `interactive_waiting` right when a waiting call is dropped
+struct WaitingGuard<'a>(&'a Scheduler);
+
+impl Drop for WaitingGuard<'_> {
+    fn drop(&mut self) {
+        self.0.interactive_waiting.fetch_sub(1, Ordering::Relaxed);
+        self.0.wake.notify_waiters();
+    }
+}
+
+/// Seconds to wait from a `FLOOD_WAIT_X` error
+pub fn flood_wait(error: &(dyn std::error::Error + 'static)) -> Option<Duration> {
+    if let Some(grammers_client::InvocationError::Rpc(rpc)) =
+        error.downcast_ref::<grammers_client::InvocationError>()
+    {
+        if rpc.name == "FLOOD_WAIT" || rpc.name == "FLOOD_PREMIUM_WAIT" {
+            return Some(Duration::from_secs(rpc.value.unwrap_or(1) as u64));
+        }
+        return None;
+    }
+    let text = error.to_string();
+    let (_, rest) = text.split_once("FLOOD_WAIT_")?;
+    let secs: String = rest.chars().take_while(char::is_ascii_digit).collect();
+    secs.parse().ok().map(Duration::from_secs)
+}
+
+impl Scheduler {
+    pub fn new() -> Scheduler {
+        Scheduler::with_max_flood_wait(MAX_FLOOD_WAIT)
+    }
+
+    pub fn with_max_flood_wait(max_flood_wait: Duration) -> Scheduler {
+        Scheduler {
+            max_flood_wait,
+            state: Mutex::new(State {
+                account: Bucket::new((30.0, 10.0)),
+                buckets: MethodClass::ALL
+                    .iter()
+                    .map(|class| (*class, Bucket::new(class.limits())))
+                    .collect(),
+                flood_until: HashMap::new(),
+                stats: HashMap::new(),
+            }),
+            interactive_waiting: AtomicUsize::new(0),
+            wake: Notify::new(),
+        }
+    }
+
+    /// Wait for a token, returns how long it took. Fails instead of sleeping through a flood wait
+    /// longer than `MAX_FLOOD_WAIT`, also one that began while the caller was queued.
+    async fn acquire(&self, class: MethodClass, priority: Priority) -> Result<Duration> {
+        let started = Instant::now();
+        let mut waiting: Option<WaitingGuard> = None;
+        loop {
+            let wait = {
+                let mut state = self.state.lock().unwrap();
+                let now = Instant::now();
+                let flood = state
+                    .flood_until
+                    .get(&class)
+                    .map_or(Duration::ZERO, |until| until.saturating_duration_since(now));
+                if flood > MAX_FLOOD_WAIT {
+                    return Err(format!("Telegram {:?} requests wait out FLOOD_WAIT_{}", class, flood.as_secs()).into());
+                } else if !flood.is_zero() {
+                    flood
+                } else {
+                    let State { account, buckets, .. } = &mut *state;
+                    let bucket = buckets.get_mut(&class).unwrap();
+                    account.refill(now);
+                    bucket.refill(now);
+                    let yields = priority == Priority::Background
+                        && self.interactive_waiting.load(Ordering::Relaxed) > 0;
+                    let token_wait = account.wait().max(bucket.wait());
+                    if token_wait.is_zero() && !yields {
+                        account.tokens -= 1.0;
+                        bucket.tokens -= 1.0;
+                        break;
+                    }
+                    if priority == Priority::Interactive && waiting.is_none() {
+                        self.interactive_waiting.fetch_add(1, Ordering::Relaxed);
+                        waiting = Some(WaitingGuard(self));
+                    }
+                    if token_wait.is_zero() { YIELD_POLL } else { token_wait }
+                }
+            };
+            tokio::select! {
+                _ = tokio::time::sleep(wait) => {}
+                _ = self.wake.notified() => {}
+            }
+        }
+        drop(waiting);
+        Ok(started.elapsed())
+    }
+
+    /// Wait for a token without running a call, for calls that can't be retried like chunk reads
+    pub async fn take_turn(&self, class: MethodClass, priority: Priority) -> Result<()> {
+        let waited = self.acquire(class, priority).await?;
+        self.record(class, priority, waited);
+        Ok(())
+    }
+
+    fn record(&self, class: MethodClass, priority: Priority, waited: Duration) {
+        let mut state = self.state.lock().unwrap();
+        let stats = state.stats.entry((class, priority)).or_default();
+        let waited = waited.as_millis() as u64;
+        stats.requests += 1;
+        stats.waited_ms += waited;
+        stats.max_wait_ms = stats.max_wait_ms.max(waited);
+    }
+
+    fn flood(&self, class: MethodClass, priority: Priority, wait: Duration) {
+        let mut state = self.state.lock().unwrap();
+        let until = Instant::now() + wait;
+        let flood_until = state.flood_until.entry(class).or_insert(until);
+        *flood_until = (*flood_until).max(until);
+        let stats = state.stats.entry((class, priority)).or_default();
+        stats.flood_waits += 1;
+        stats.flood_wait_secs += wait.as_secs();
+        drop(state);
+        // Queued callers give up now if the wait is too long for them
+        self.wake.notify_waiters();
+    }
+
+    /// Run a Telegram call in its turn, retrying after flood waits.
+    pub async fn run<T, F, Fut>(&self, class: MethodClass, priority: Priority, mut call: F) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        loop {
+            let waited = self.acquire(class, priority).await?;
+            self.record(class, priority, waited);
+
+            let wait = match call().await {
+                Ok(value) => return Ok(value),
+                Err(e) => match flood_wait(e.as_ref()) {
+                    Some(wait) if wait <= self.max_flood_wait => wait,
+                    Some(wait) => {
+                        self.flood(class, priority, wait);
+                        return Err(e);
+                    }
+                    None => return Err(e),
+                },
+            };
+            log::warn!("Telegram flood wait {}s for {:?} requests", wait.as_secs(), class);
+            self.flood(class, priority, wait);
+        }
+    }
+
+    /// Time until a flood wait of `class` ends
+    pub fn flood_wait_left(&self, class: MethodClass) -> Duration {
+        let state = self.state.lock().unwrap();
+        state
+            .flood_until
+            .get(&class)
+            .map_or(Duration::ZERO, |until| until.saturating_duration_since(Instant::now()))
+    }
+
+    pub fn stats(&self) -> serde_json::Value {
+        let state = self.state.lock().unwrap();
+        let now = Instant::now();
+        let classes: serde_json::Map<String, serde_json::Value> = MethodClass::ALL
+            .iter()
+            .map(|class| {
+                let flood_wait_left = state
+                    .flood_until
+                    .get(class)
+                    .map_or(0, |until| until.saturating_duration_since(now).as_secs());
+                let callers: serde_json::Map<String, serde_json::Value> = [Priority::Interactive, Priority::Background]
+                    .iter()
+                    .filter_map(|priority| {
+                        let stats = state.stats.get(&(*class, *priority))?;
+                        Some((
+                            serde_json::to_value(priority).ok()?.as_str()?.to_string(),
+                            serde_json::to_value(stats).ok()?,
+                        ))
+                    })
+                    .collect();
+                let name = serde_json::to_value(class).unwrap().as_str().unwrap().to_string();
+                (
+                    name,
+                    serde_json::json!({
+                        "tokens": state.buckets[class].tokens.floor(),
+                        "flood_wait_left_secs": flood_wait_left,
+                        "callers": callers,
+                    }),
+                )
+            })
+            .collect();
+        serde_json::json!({
+            "account_tokens": state.account.tokens.floor(),
+            "interactive_waiting": self.interactive_waiting.load(Ordering::Relaxed),
+            "classes": classes,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_flood_wait_errors() {
+        let error: Box<dyn std::error::Error> = "rpc error 420: FLOOD_WAIT_35".into();
+        assert_eq!(flood_wait(error.as_ref()), Some(Duration::from_secs(35)));
+        let error: Box<dyn std::error::Error> = "Can't find channel t.me/x".into();
+        assert_eq!(flood_wait(error.as_ref()), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn flood_wait_pauses_class_and_retries() {
+        let scheduler = Scheduler::new();
+        let calls = AtomicUsize::new(0);
+        let started = tokio::time::Instant::now();
+        let result = scheduler
+            .run(MethodClass::History, Priority::Background, || async {
+                match calls.fetch_add(1, Ordering::Relaxed) {
+                    0 => Err("FLOOD_WAIT_30".into()),
+                    n => Ok(n),
+                }
+            })
+            .await
+            .unwrap();
+        assert_eq!(result, 1);
+        assert!(started.elapsed() >= Duration::from_secs(30));
+
+        let stats = scheduler.stats();
+        let background = &stats["classes"]["history"]["callers"]["background"];
+        assert_eq!(background["requests"], 2);
+        assert_eq!(background["flood_waits"], 1);
+        assert_eq!(background["flood_wait_secs"], 30);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn long_flood_wait_fails() {
+        let scheduler = Scheduler::new();
+        let result: Result<()> = scheduler
+            .run(MethodClass::Resolve, Priority::Interactive, || async {
+                Err("FLOOD_WAIT_86400".into())
+            })
+            .await;
+        assert!(result.is_err());
+        assert!(scheduler.stats()["classes"]["resolve"]["flood_wait_left_secs"].as_u64().unwrap() > 80000);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn long_flood_wait_fails_queued_turns() {
+        let scheduler = Scheduler::new();
+        let result: Result<()> = scheduler
+            .run(MethodClass::Media, Priority::Interactive, || async {
+                Err("FLOOD_WAIT_7200".into())
+            })
+            .await;
+        assert!(result.is_err());
+        let started = tokio::time::Instant::now();
+        let e = scheduler.take_turn(MethodClass::Media, Priority::Interactive).await.unwrap_err();
+        assert!(flood_wait(e.as_ref()).unwrap() > MAX_FLOOD_WAIT);
+        assert!(started.elapsed() < Duration::from_secs(1));
+        // Other classes still take turns
+        scheduler.take_turn(MethodClass::Messages, Priority::Interactive).await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn background_yields_to_interactive() {
+        let scheduler = std::sync::Arc::new(Scheduler::new());
+        // Drain the account bucket
+        scheduler.state.lock().unwrap().account.tokens = 0.0;
+
+        let order = std::sync::Arc::new(Mutex::new(Vec::new()));
+        let background = {
+            let (scheduler, order) = (scheduler.clone(), order.clone());
+            tokio::spawn(async move {
+                scheduler
+                    .run(MethodClass::History, Priority::Background, || async {
+                        order.lock().unwrap().push("background");
+                        Ok(())
+                    })
+                    .await
+                    .map_err(|e| e.to_string())
+            })
+        };
+        tokio::task::yield_now().await;
+        let interactive = {
+            let (scheduler, order) = (scheduler.clone(), order.clone());
+            tokio::spawn(async move {
+                scheduler
+                    .run(MethodClass::Messages, Priority::Interactive, || async {
+                        order.lock().unwrap().push("interactive");
+                        Ok(())
+                    })
+                    .await
+                    .map_err(|e| e.to_string())
+            })
+        };
+        background.await.unwrap().unwrap();
+        interactive.await.unwrap().unwrap();
+        assert_eq!(*order.lock().unwrap(), vec!["interactive", "background"]);
+    }
+}
@@ -0,0 +1,169 @@
+//! Where posts and media come from.
+//!
+//! Routes talk to a `TelegramSource` held by `App`: `grammers::GrammersSource`
+//! is the live client, `fixture::FixtureSource` replays a directory of JSON and
+//! media files so the server runs without a Telegram account. Each account is
+//! wrapped in `scheduled::ScheduledSource` to respect Telegram rate limits, and
+//! `pool::PooledSource` spreads the calls over the accounts.
+
+pub mod fixture;
+pub mod grammers;
+pub mod pool;
+pub mod scheduled;
+
+use crate::post::Post;
+use crate::post_data::{CommentData, PostData};
+use crate::task::Task;
+use crate::util::Result;
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// Download chunk size. Must match grammers MAX_CHUNK_SIZE.
+pub const CHUNK_SIZE: i32 = 512 * 1024;
+
+/// Posts per `fetch_page`, the most one history request returns
+pub const HISTORY_PAGE: usize = 100;
+
+pub struct ChannelInfo {
+    /// Telegram id, the same under every name of the channel. Fixtures may leave it out.
+    pub id: Option<i64>,
+    /// Current username, `None` for private channels
+    pub username: Option<String>,
+    pub title: String,
+    /// Id of the userpic, `None` without one
+    pub photo_id: Option<i64>,
+}
+
+/// How a request names a channel
+#[derive(Debug, PartialEq)]
+pub enum ChannelRef<'a> {
+    /// Public channel, t.me/<username>
+    Username(&'a str),
+    /// Invite link hash of a private channel the account joined, t.me/+<hash>
+    Invite(&'a str),
+    /// Channel id as the Bot API writes it, `-100<id>`, looked up in the joined channels
+    Id(i64),
+}
+
+impl ChannelRef<'_> {
+    /// Accepts the forms above, also as t.me links
+    pub fn parse(channel: &str) -> ChannelRef<'_> {
+        let channel = channel.trim();
+        let channel = ["https://", "http://"]
+            .iter()
+            .find_map(|scheme| channel.strip_prefix(scheme))
+            .unwrap_or(channel);
+        let channel = channel.strip_prefix("t.me/").unwrap_or(channel);
+        if let Some(hash) = channel.strip_prefix('+').or_else(|| channel.strip_prefix("joinchat/")) {
+            return ChannelRef::Invite(hash);
+        }
+        if let Some(id) = channel.strip_prefix("-100")
+            && let Ok(id) = id.parse()
+        {
+            return ChannelRef::Id(id);
+        }
+        ChannelRef::Username(channel.strip_prefix('@').unwrap_or(channel))
+    }
+}
+
+/// Link to a post. Posts of private channels open by id for members, an invite link is all
+/// there is without the id.
+pub fn post_url(channel: &str, msg_id: i32) -> String {
+    match ChannelRef::parse(channel) {
+        ChannelRef::Username(username) => format!("https://t.me/{}/{}", username, msg_id),
+        ChannelRef::Id(id) => format!("https://t.me/c/{}/{}", id, msg_id),
+        ChannelRef::Invite(hash) => format!("https://t.me/+{}", hash),
+    }
+}
+
+/// The `-100<id>` name of a channel, it keys the channel's rows in `cache::PostCache`
+pub fn channel_key(id: i64) -> String {
+    format!("-100{}", id)
+}
+
+pub struct MediaInfo {
+    pub mime: String,
+    /// `None` for photos, their size is unknown until downloaded
+    pub size: Option<i64>,
+    pub media_id: i64,
+}
+
+/// Media download in `CHUNK_SIZE` pieces.
+#[rocket::async_trait]
+pub trait Chunks: Send {
+    /// Skip whole chunks; call before the first `next`.
+    fn skip_chunks(&mut self, count: i32);
+
+    /// Next chunk, `None` at the end of the file. After an error the download is incomplete.
+    async fn next(&mut self) -> Result<Option<Vec<u8>>>;
+}
+
+#[rocket::async_trait]
+pub trait TelegramSource: Send + Sync {
+    /// Resolve a channel by any name `ChannelRef` accepts
+    async fn resolve_channel(&self, channel_name: &str) -> Result<ChannelInfo>;
+
+    /// One history request: posts older than `offset_id` (any for 0) and published no later
+    /// than `task.to_date`, newest first, at most `limit`. Fewer only at the start of the channel.
+    async fn fetch_page(&self, task: &Task, offset_id: i32, limit: usize) -> Result<Vec<Post>>;
+
+    /// Posts published in [task.from_date, task.to_date], newest first, at most `limit`.
+    /// Walks `fetch_page`, so a page that fails is retried from the last post fetched.
+    async fn fetch_posts(
+        &self,
+        task: &Task,
+        limit: usize,
+        progress: Option<&AtomicUsize>,
+        cancelled: Option<&AtomicBool>,
+    ) -> Result<Vec<Post>> {
+        let mut posts: Vec<Post> = Vec::new();
+        let mut offset_id = 0;
+        while posts.len() < limit {
+            if cancelled.is_some_and(|c| c.load(Ordering::Relaxed)) {
+                log::info!("Fetch cancelled for {}, returning {} posts", task.channel_name, posts.len());
+                break;
+            }
+            let page_limit = (limit - posts.len()).min(HISTORY_PAGE);
+            let page = self.fetch_page(task, offset_id, page_limit).await?;
+            let last_page = page.len() < page_limit || page.iter().any(|p| p.date < task.from_date);
+            let Some(last) = page.last() else {
+                break;
+            };
+            offset_id = last.id;
+            let page: Vec<Post> = page
+                .into_iter()
+                .filter(|p| p.date >= task.from_date && p.date <= task.to_date)
+                .collect();
+            if let Some(p) = progress {
+                p.fetch_add(page.len(), Ordering::Relaxed);
+            }
+            posts.extend(page);
+            if last_page {
+                break;
+            }
+        }
+        log::debug!(
+            "Fetched {} posts for {} from {} to {}",
+            posts.len(),
+            task.channel_name,
+            task.from_date,
+            task.to_date
+        );
+        Ok(posts)
+    }
+
+    /// Single post with full data for the JSON API, including its album
+    async fn get_post_data(&self, channel_name: &str, id: i32) -> Result<PostData>;
+
+    /// Replies to a post in the channel's discussion group, newest first, at most `limit`
+    async fn get_comments(&self, channel_name: &str, id: i32, limit: usize) -> Result<Vec<CommentData>>;
+
+    /// Photo or document attached to a post
+    async fn open_media(&self, channel_name: &str, id: i32) -> Result<(MediaInfo, Box<dyn Chunks>)>;
+
+    /// Thumbnail of a video/document as (bytes, mime_type)
+    async fn download_thumb(&self, channel_name: &str, id: i32) -> Result<(Vec<u8>, String)>;
+
+    /// Channel userpic
+    async fn channel_photo(&self, channel_name: &str) -> Result<Box<dyn Chunks>>;
+}
@@ -0,0 +1,259 @@
+use crate::post::Post;
+use crate::post_data::{CommentData, PostData};
+use crate::source::{CHUNK_SIZE, ChannelInfo, ChannelRef, Chunks, MediaInfo, TelegramSource};
+use crate::task::Task;
+use crate::util::Result;
+
+use std::path::{Path, PathBuf};
+
+/// Offline stand-in for Telegram, configured with `fixture_dir`.
+///
+/// Files are re-read on every call, so a fixture can change while the server runs:
+///
+/// ```text
+/// <fixture_dir>/<channel>/channel.json   {"title": "Channel", "id": 123, "invite": "AbC"}
+/// <fixture_dir>/<channel>/posts.json     [PostData, ...] as returned by /post/<channel>/<id>
+/// <fixture_dir>/<channel>/photo.jpg      userpic
+/// <fixture_dir>/<channel>/media/<id>.*   media of post <id>, mime type from the extension
+/// <fixture_dir>/<channel>/thumbs/<id>.jpg
+/// <fixture_dir>/<channel>/comments/<id>.json [CommentData, ...] replies to post <id>
+/// ```
+///
+/// `id` and `invite` are optional, they let the channel be named `-100<id>` or `+<invite>`.
+/// The directory name is the username of a channel without an invite, renaming the directory
+/// renames the channel.
+pub struct FixtureSource {
+    dir: PathBuf,
+}
+
+#[derive(serde::Deserialize)]
+struct ChannelFixture {
+    title: String,
+    #[serde(default)]
+    id: Option<i64>,
+    #[serde(default)]
+    invite: Option<String>,
+}
+
+struct FixtureChunks {
+    data: Vec<u8>,
+    offset: usize,
+}
+
+impl FixtureChunks {
+    fn boxed(data: Vec<u8>) -> Box<dyn Chunks> {
+        Box::new(FixtureChunks { data, offset: 0 })
+    }
+}
+
+#[rocket::async_trait]
+impl Chunks for FixtureChunks {
+    fn skip_chunks(&mut self, count: i32) {
+        self.offset = self.offset.saturating_add(count.max(0) as usize * CHUNK_SIZE as usize);
+    }
+
+    async fn next(&mut self) -> Result<Option<Vec<u8>>> {
+        if self.offset >= self.data.len() {
+            return Ok(None);
+        }
+        let end = (self.offset + CHUNK_SIZE as usize).min(self.data.len());
+        let chunk = self.data[self.offset..end].to_vec();
+        self.offset = end;
+        Ok(Some(chunk))
+    }
+}
+
+fn mime_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|e| e.to_str()).unwrap_or_default() {
+        "jpg" | "jpeg" => "image/jpeg",
+        "png" => "image/png",
+        "webp" => "image/webp",
+        "gif" => "image/gif",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        "mp3" => "audio/mpeg",
+        "ogg" => "audio/ogg",
+        _ => "application/octet-stream",
+    }
+}
+
+fn read_channel(dir: &Path) -> Result<ChannelFixture> {
+    let path = dir.join("channel.json");
+    let data = std::fs::read_to_string(&path)?;
+    Ok(serde_json::from_str(&data).map_err(|e| format!("Invalid {}: {}", path.display(), e))?)
+}
+
+impl FixtureSource {
+    pub fn new(dir: &Path) -> Result<FixtureSource> {
+        if !dir.is_dir() {
+            return Err(format!("Fixture directory {} does not exist", dir.display()).into());
+        }
+        log::info!("Serving Telegram data from fixtures in {}", dir.display());
+        Ok(FixtureSource { dir: dir.to_path_buf() })
+    }
+
+    fn channel_dir(&self, channel_name: &str) -> Result<PathBuf> {
+        let not_found = || format!("Can't find channel t.me/{}", channel_name).into();
+        let username = match ChannelRef::parse(channel_name) {
+            ChannelRef::Username(username) => username,
+            private => {
+                // Private channels are found by the id or invite in their channel.json
+                let dirs = std::fs::read_dir(&self.dir)?.filter_map(|entry| entry.ok().map(|e| e.path()));
+                for dir in dirs.filter(|dir| dir.is_dir()) {
+                    let Ok(channel) = read_channel(&dir) else {
+                        continue;
+                    };
+                    let found = match private {
+                        ChannelRef::Id(id) => channel.id == Some(id),
+                        ChannelRef::Invite(hash) => channel.invite.as_deref() == Some(hash),
+                        ChannelRef::Username(_) => false,
+                    };
+                    if found {
+                        return Ok(dir);
+                    }
+                }
+                return Err(not_found());
+            }
+        };
+        let dir = self.dir.join(username);
+        if username.contains(['/', '\\']) || username.starts_with('.') || !dir.is_dir() {
+            return Err(not_found());
+        }
+        Ok(dir)
+    }
+
+    fn channel(&self, channel_name: &str) -> Result<ChannelFixture> {
+        read_channel(&self.channel_dir(channel_name)?)
+    }
+
+    /// All posts of a channel, newest first
+    fn posts(&self, channel_name: &str) -> Result<Vec<PostData>> {
+        let path = self.channel_dir(channel_name)?.join("posts.json");
+        let data = std::fs::read_to_string(&path)?;
+        let mut posts: Vec<PostData> =
+            serde_json::from_str(&data).map_err(|e| format!("Invalid {}: {}", path.display(), e))?;
+        posts.sort_by_key(|p| std::cmp::Reverse(p.id));
+        Ok(posts)
+    }
+
+    fn post(&self, channel_name: &str, id: i32) -> Result<PostData> {
+        self.posts(channel_name)?
+            .into_iter()
+            .find(|p| p.id == id)
+            .ok_or_else(|| format!("Message {}/{} not found", channel_name, id).into())
+    }
+
+    fn media_file(&self, channel_name: &str, id: i32) -> Result<PathBuf> {
+        let dir = self.channel_dir(channel_name)?.join("media");
+        let id = id.to_string();
+        std::fs::read_dir(&dir)
+            .ok()
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .find(|path| path.file_stem().is_some_and(|stem| *stem == *id))
+            .ok_or_else(|| "No downloadable media in message".into())
+    }
+}
+
+#[rocket::async_trait]
+impl TelegramSource for FixtureSource {
+    async fn resolve_channel(&self, channel_name: &str) -> Result<ChannelInfo> {
+        let dir = self.channel_dir(channel_name)?;
+        let channel = read_channel(&dir)?;
+        let username = match channel.invite {
+            Some(_) => None,
+            None => dir.file_name().and_then(|name| name.to_str()).map(str::to_string),
+        };
+        Ok(ChannelInfo {
+            id: channel.id,
+            username,
+            title: channel.title,
+            photo_id: None,
+        })
+    }
+
+    async fn fetch_page(&self, task: &Task, offset_id: i32, limit: usize) -> Result<Vec<Post>> {
+        let mut posts = Vec::new();
+        let messages = self
+            .posts(&task.channel_name)?
+            .into_iter()
+            .filter(|p| p.date <= task.to_date && (offset_id == 0 || p.id < offset_id))
+            .take(limit);
+        for message in messages {
+            posts.push(Post {
+                date: message.date,
+                id: message.id,
+                views: message.views,
+                forwards: message.forwards,
+                replies: message.replies,
+                reactions: message.reactions,
+                message: Some(message.text),
+                image: None,
+                grouped_id: message.grouped_id,
+                velocity: None,
+                rates: None,
+                reaction_counts: message.reaction_counts,
+                selected_reaction: None,
+                top_comment: None,
+            });
+        }
+        Ok(posts)
+    }
+
+    async fn get_post_data(&self, channel_name: &str, id: i32) -> Result<PostData> {
+        let mut post = self.post(channel_name, id)?;
+        post.channel_title = Some(self.channel(channel_name)?.title);
+        Ok(post)
+    }
+
+    async fn get_comments(&self, channel_name: &str, id: i32, limit: usize) -> Result<Vec<CommentData>> {
+        self.post(channel_name, id)?;
+        let path = self.channel_dir(channel_name)?.join("comments").join(format!("{}.json", id));
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+        let data = std::fs::read_to_string(&path)?;
+        let mut comments: Vec<CommentData> =
+            serde_json::from_str(&data).map_err(|e| format!("Invalid {}: {}", path.display(), e))?;
+        comments.sort_by_key(|c| std::cmp::Reverse(c.id));
+        comments.truncate(limit);
+        Ok(comments)
+    }
+
+    async fn open_media(&self, channel_name: &str, id: i32) -> Result<(MediaInfo, Box<dyn Chunks>)> {
+        let post = self.post(channel_name, id)?;
+        let path = self.media_file(channel_name, id)?;
+        let data = std::fs::read(&path)?;
+        let mime = mime_type(&path).to_string();
+        let is_photo = mime.starts_with("image/");
+        let media_id = post
+            .photo
+            .as_ref()
+            .map(|p| p.id)
+            .or(post.video.as_ref().map(|v| v.id))
+            .or(post.document.as_ref().map(|d| d.id))
+            .unwrap_or(id as i64);
+        let info = MediaInfo {
+            mime,
+            size: (!is_photo).then_some(data.len() as i64),
+            media_id,
+        };
+        Ok((info, FixtureChunks::boxed(data)))
+    }
+
+    async fn download_thumb(&self, channel_name: &str, id: i32) -> Result<(Vec<u8>, String)> {
+        let path = self
+            .channel_dir(channel_name)?
+            .join("thumbs")
+            .join(format!("{}.jpg", id));
+        let bytes = std::fs::read(&path).map_err(|_| "Document has no thumbnails")?;
+        Ok((bytes, "image/jpeg".to_string()))
+    }
+
+    async fn channel_photo(&self, channel_name: &str) -> Result<Box<dyn Chunks>> {
+        let path = self.channel_dir(channel_name)?.join("photo.jpg");
+        let data = std::fs::read(&path).map_err(|_| format!("Channel {} has no photo", channel_name))?;
+        Ok(FixtureChunks::boxed(data))
+    }
+}
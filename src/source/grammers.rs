@@ -0,0 +1,111 @@
+use crate::post::Post;
+use crate::post_data::{CommentData, PostData};
+use crate::source::{ChannelInfo, Chunks, MediaInfo, TelegramSource};
+use crate::task::Task;
+use crate::tg::{AccountClient, TelegramAPI};
+use crate::util::Result;
+use crate::workers;
+
+use grammers_client::client::files::DownloadIter;
+use std::time::Duration;
+
+/// A history page that takes longer fails, the walk retries it
+const PAGE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Live Telegram through the grammers client of one account, see `TelegramAPI::create`.
+pub struct GrammersSource {
+    account: usize,
+}
+
+impl GrammersSource {
+    pub fn new(account: usize) -> GrammersSource {
+        GrammersSource { account }
+    }
+
+    fn client(&self) -> Result<AccountClient> {
+        TelegramAPI::account_client(self.account)
+    }
+}
+
+struct GrammersChunks {
+    iter: Option<DownloadIter>,
+}
+
+impl GrammersChunks {
+    fn boxed(iter: DownloadIter) -> Box<dyn Chunks> {
+        Box::new(GrammersChunks { iter: Some(iter) })
+    }
+}
+
+#[rocket::async_trait]
+impl Chunks for GrammersChunks {
+    fn skip_chunks(&mut self, count: i32) {
+        self.iter = self.iter.take().map(|iter| iter.skip_chunks(count));
+    }
+
+    async fn next(&mut self) -> Result<Option<Vec<u8>>> {
+        let Some(iter) = self.iter.as_mut() else {
+            return Ok(None);
+        };
+        Ok(iter.next().await?)
+    }
+}
+
+#[rocket::async_trait]
+impl TelegramSource for GrammersSource {
+    async fn resolve_channel(&self, channel_name: &str) -> Result<ChannelInfo> {
+        let client = self.client()?;
+        let channel = workers::tg::get_channel(&client, channel_name).await?;
+        Ok(ChannelInfo {
+            id: Some(channel.id()),
+            username: channel.username().map(str::to_string),
+            title: channel.name().to_string(),
+            photo_id: workers::tg::photo_id(&channel),
+        })
+    }
+
+    async fn fetch_page(&self, task: &Task, offset_id: i32, limit: usize) -> Result<Vec<Post>> {
+        let client = self.client()?;
+        tokio::time::timeout(PAGE_TIMEOUT, workers::tg::fetch_page(&client, task, offset_id, limit))
+            .await
+            .map_err(|_| "Telegram fetch timed out")?
+    }
+
+    async fn get_post_data(&self, channel_name: &str, id: i32) -> Result<PostData> {
+        let task = Task {
+            command: crate::cli::Commands::Post {},
+            channel_name: channel_name.to_string(),
+            editor_choice_post_id: id,
+            ..Task::default()
+        };
+        let client = self.client()?;
+        workers::tg::get_post_data(client, task).await
+    }
+
+    async fn get_comments(&self, channel_name: &str, id: i32, limit: usize) -> Result<Vec<CommentData>> {
+        let client = self.client()?;
+        workers::tg::get_comments(&client, channel_name, id, limit).await
+    }
+
+    async fn open_media(&self, channel_name: &str, id: i32) -> Result<(MediaInfo, Box<dyn Chunks>)> {
+        let client = self.client()?;
+        let (downloadable, mime, size, media_id) =
+            workers::tg::resolve_media(&client, channel_name, id).await?;
+        let info = MediaInfo { mime, size, media_id };
+        Ok((info, GrammersChunks::boxed(client.iter_download(&downloadable))))
+    }
+
+    async fn download_thumb(&self, channel_name: &str, id: i32) -> Result<(Vec<u8>, String)> {
+        let client = self.client()?;
+        workers::tg::download_thumb(&client, channel_name, id).await
+    }
+
+    async fn channel_photo(&self, channel_name: &str) -> Result<Box<dyn Chunks>> {
+        let client = self.client()?;
+        let channel = workers::tg::get_channel(&client, channel_name).await?;
+        let photo = channel
+            .photo_downloadable(true)
+            .ok_or_else(|| format!("Channel {} has no photo", channel_name))?;
+        Ok(GrammersChunks::boxed(client.iter_download(&photo)))
+    }
+}
@@ -0,0 +1,407 @@
+//! Telegram calls spread over several accounts.
+//!
+//! Each channel sticks to one account, so the client that resolved it also
+//! pages its history and downloads its media. A new channel goes to the
+//! account serving the fewest channels. While its account is flood-waited a
+//! call takes a detour through another one; an account that is banned or whose
+//! session is revoked leaves the pool and its channels move to the others.
+
+use crate::post::Post;
+use crate::post_data::{CommentData, PostData};
+use crate::scheduler::{MAX_FLOOD_WAIT, MethodClass, Scheduler, flood_wait};
+use crate::source::scheduled::ScheduledSource;
+use crate::source::{ChannelInfo, Chunks, MediaInfo, TelegramSource};
+use crate::task::Task;
+use crate::util::Result;
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Errors after which an account makes no calls until it signs in again
+const LOST_ACCOUNT_ERRORS: [&str; 8] = [
+    "AUTH_KEY_UNREGISTERED",
+    "AUTH_KEY_INVALID",
+    "AUTH_KEY_DUPLICATED",
+    "SESSION_REVOKED",
+    "SESSION_EXPIRED",
+    "USER_DEACTIVATED",
+    "USER_DEACTIVATED_BAN",
+    "PHONE_NUMBER_BANNED",
+];
+
+/// Whether an error means the account is banned or signed out
+pub fn account_lost(error: &(dyn std::error::Error + 'static)) -> bool {
+    if let Some(grammers_client::InvocationError::Rpc(rpc)) =
+        error.downcast_ref::<grammers_client::InvocationError>()
+    {
+        return LOST_ACCOUNT_ERRORS.contains(&rpc.name.as_str());
+    }
+    let text = error.to_string();
+    LOST_ACCOUNT_ERRORS.iter().any(|name| text.contains(name))
+}
+
+struct Member {
+    name: String,
+    source: ScheduledSource,
+    scheduler: Arc<Scheduler>,
+    /// Why the account left the pool, `None` while it takes calls
+    lost: Mutex<Option<String>>,
+}
+
+pub struct PooledSource {
+    members: Vec<Member>,
+    /// Account each channel sticks to
+    channels: Mutex<HashMap<String, usize>>,
+}
+
+impl PooledSource {
+    /// Accounts as (name, source), each with a scheduler of its own
+    pub fn new(accounts: Vec<(String, Arc<dyn TelegramSource>)>) -> PooledSource {
+        let pooled = accounts.len() > 1;
+        let members = accounts
+            .into_iter()
+            .map(|(name, source)| {
+                // A lone account sleeps through flood waits, in a pool another account takes the call
+                let scheduler = Arc::new(if pooled {
+                    Scheduler::with_max_flood_wait(Duration::ZERO)
+                } else {
+                    Scheduler::new()
+                });
+                Member {
+                    name,
+                    source: ScheduledSource::new(source, scheduler.clone()),
+                    scheduler,
+                    lost: Mutex::new(None),
+                }
+            })
+            .collect();
+        PooledSource {
+            members,
+            channels: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Take calls on an account again, after it signed in
+    pub fn enable(&self, account: usize) {
+        if self.members[account].lost.lock().unwrap().take().is_some() {
+            log::info!("Telegram account {} takes calls again", self.members[account].name);
+        }
+    }
+
+    /// Stop calls on an account, its channels move to the others
+    pub fn disable(&self, account: usize, reason: &str) {
+        let member = &self.members[account];
+        log::error!("Telegram account {} left the pool: {}", member.name, reason);
+        *member.lost.lock().unwrap() = Some(reason.to_string());
+    }
+
+    pub fn is_enabled(&self, account: usize) -> bool {
+        self.members[account].lost.lock().unwrap().is_none()
+    }
+
+    pub fn enabled_accounts(&self) -> Vec<usize> {
+        (0..self.members.len()).filter(|account| self.is_enabled(*account)).collect()
+    }
+
+    /// Account for a call on `channel`, other than the `tried` ones. The channel's own account
+    /// unless it is flood-waited, else the least busy account with the shortest flood wait.
+    fn pick(&self, channel: &str, class: MethodClass, tried: &[usize]) -> Option<usize> {
+        let mut channels = self.channels.lock().unwrap();
+        let own = channels.get(channel).copied().filter(|account| self.is_enabled(*account));
+        let candidates: Vec<usize> = self
+            .enabled_accounts()
+            .into_iter()
+            .filter(|account| !tried.contains(account))
+            .collect();
+        if let Some(account) = own
+            && candidates.contains(&account)
+            && self.members[account].scheduler.flood_wait_left(class).is_zero()
+        {
+            return Some(account);
+        }
+        let load = |account: usize| channels.values().filter(|other| **other == account).count();
+        let account = candidates
+            .iter()
+            .copied()
+            .min_by_key(|account| (self.members[*account].scheduler.flood_wait_left(class), load(*account)))?;
+        // A flood wait is a detour, the channel moves only when its account left the pool
+        if own.is_none() {
+            channels.insert(channel.to_string(), account);
+        }
+        Some(account)
+    }
+
+    /// Run `call` on the account of `channel`, moving to another account on a flood wait or a
+    /// lost account. Once every account was tried, the one with the shortest flood wait sleeps
+    /// through it.
+    async fn call<T, F, Fut>(&self, channel: &str, class: MethodClass, call: F) -> Result<T>
+    where
+        F: Fn(usize) -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut tried = Vec::new();
+        let mut last_round = false;
+        loop {
+            let Some(account) = self.pick(channel, class, &tried) else {
+                return Err(self.unavailable().into());
+            };
+            let member = &self.members[account];
+            let wait = member.scheduler.flood_wait_left(class);
+            if wait > MAX_FLOOD_WAIT {
+                return Err(format!("Every Telegram account waits, FLOOD_WAIT_{}", wait.as_secs()).into());
+            }
+            let error = match call(account).await {
+                Ok(value) => return Ok(value),
+                Err(e) => e,
+            };
+            if account_lost(error.as_ref()) {
+                self.disable(account, &error.to_string());
+            } else if last_round || flood_wait(error.as_ref()).is_none() {
+                return Err(error);
+            } else {
+                log::info!("Telegram account {} is flood-waited, trying another one", member.name);
+            }
+            tried.push(account);
+            if !last_round && self.enabled_accounts().iter().all(|account| tried.contains(account)) {
+                last_round = true;
+                tried.clear();
+            }
+        }
+    }
+
+    fn unavailable(&self) -> String {
+        let reasons: Vec<String> = self
+            .members
+            .iter()
+            .filter_map(|member| Some(format!("{}: {}", member.name, member.lost.lock().unwrap().as_ref()?)))
+            .collect();
+        format!("No Telegram account takes calls ({})", reasons.join(", "))
+    }
+
+    /// Scheduler stats of the first account, with every account under `accounts`
+    pub fn stats(&self) -> serde_json::Value {
+        let channels = self.channels.lock().unwrap();
+        let accounts: Vec<serde_json::Value> = self
+            .members
+            .iter()
+            .enumerate()
+            .map(|(account, member)| {
+                serde_json::json!({
+                    "name": member.name,
+                    "lost": *member.lost.lock().unwrap(),
+                    "channels": channels.values().filter(|other| **other == account).count(),
+                    "scheduler": member.scheduler.stats(),
+                })
+            })
+            .collect();
+        let mut stats = self.members[0].scheduler.stats();
+        stats["accounts"] = serde_json::json!(accounts);
+        stats
+    }
+}
+
+#[rocket::async_trait]
+impl TelegramSource for PooledSource {
+    async fn resolve_channel(&self, channel_name: &str) -> Result<ChannelInfo> {
+        self.call(channel_name, MethodClass::Resolve, |account| self.members[account].source.resolve_channel(channel_name))
+            .await
+    }
+
+    async fn fetch_page(&self, task: &Task, offset_id: i32, limit: usize) -> Result<Vec<Post>> {
+        self.call(&task.channel_name, MethodClass::History, |account| {
+            self.members[account].source.fetch_page(task, offset_id, limit)
+        })
+        .await
+    }
+
+    async fn get_post_data(&self, channel_name: &str, id: i32) -> Result<PostData> {
+        self.call(channel_name, MethodClass::Messages, |account| self.members[account].source.get_post_data(channel_name, id))
+            .await
+    }
+
+    async fn get_comments(&self, channel_name: &str, id: i32, limit: usize) -> Result<Vec<CommentData>> {
+        self.call(channel_name, MethodClass::Messages, |account| {
+            self.members[account].source.get_comments(channel_name, id, limit)
+        })
+        .await
+    }
+
+    async fn open_media(&self, channel_name: &str, id: i32) -> Result<(MediaInfo, Box<dyn Chunks>)> {
+        self.call(channel_name, MethodClass::Media, |account| self.members[account].source.open_media(channel_name, id))
+            .await
+    }
+
+    async fn download_thumb(&self, channel_name: &str, id: i32) -> Result<(Vec<u8>, String)> {
+        self.call(channel_name, MethodClass::Media, |account| self.members[account].source.download_thumb(channel_name, id))
+            .await
+    }
+
+    async fn channel_photo(&self, channel_name: &str) -> Result<Box<dyn Chunks>> {
+        self.call(channel_name, MethodClass::Media, |account| self.members[account].source.channel_photo(channel_name))
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    /// Answers `resolve_channel` with its account, after failing with the queued errors.
+    /// Pages through `CHANNEL_POSTS` posts, failing from the second page on with the same errors.
+    struct FakeSource {
+        account: usize,
+        errors: Mutex<Vec<&'static str>>,
+    }
+
+    const CHANNEL_POSTS: i32 = 250;
+
+    fn pool(errors: Vec<Vec<&'static str>>) -> PooledSource {
+        PooledSource::new(
+            errors
+                .into_iter()
+                .enumerate()
+                .map(|(account, errors)| {
+                    let source: Arc<dyn TelegramSource> = Arc::new(FakeSource {
+                        account,
+                        errors: Mutex::new(errors),
+                    });
+                    (format!("account{}", account), source)
+                })
+                .collect(),
+        )
+    }
+
+    async fn account_of(pool: &PooledSource, channel: &str) -> String {
+        pool.resolve_channel(channel).await.map(|info| info.title).unwrap_or_else(|e| e.to_string())
+    }
+
+    #[rocket::async_trait]
+    impl TelegramSource for FakeSource {
+        async fn resolve_channel(&self, _channel_name: &str) -> Result<ChannelInfo> {
+            let mut errors = self.errors.lock().unwrap();
+            if !errors.is_empty() {
+                return Err(errors.remove(0).into());
+            }
+            Ok(ChannelInfo {
+                id: None,
+                username: None,
+                title: self.account.to_string(),
+                photo_id: None,
+            })
+        }
+
+        async fn fetch_page(&self, _task: &Task, offset_id: i32, limit: usize) -> Result<Vec<Post>> {
+            let mut errors = self.errors.lock().unwrap();
+            if offset_id != 0 && !errors.is_empty() {
+                return Err(errors.remove(0).into());
+            }
+            let newest = if offset_id == 0 { CHANNEL_POSTS } else { offset_id - 1 };
+            let posts = (1..=newest)
+                .rev()
+                .take(limit)
+                .map(|id| Post {
+                    date: id as i64,
+                    id,
+                    views: None,
+                    forwards: None,
+                    replies: None,
+                    reactions: None,
+                    message: Some(self.account.to_string()),
+                    image: None,
+                    grouped_id: None,
+                    velocity: None,
+                    rates: None,
+                    reaction_counts: Vec::new(),
+                    selected_reaction: None,
+                    top_comment: None,
+                })
+                .collect();
+            Ok(posts)
+        }
+
+        async fn get_post_data(&self, _channel_name: &str, _id: i32) -> Result<PostData> {
+            Err("not supported by FakeSource".into())
+        }
+
+        async fn get_comments(&self, _channel_name: &str, _id: i32, _limit: usize) -> Result<Vec<CommentData>> {
+            Err("not supported by FakeSource".into())
+        }
+
+        async fn open_media(&self, _channel_name: &str, _id: i32) -> Result<(MediaInfo, Box<dyn Chunks>)> {
+            Err("not supported by FakeSource".into())
+        }
+
+        async fn download_thumb(&self, _channel_name: &str, _id: i32) -> Result<(Vec<u8>, String)> {
+            Err("not supported by FakeSource".into())
+        }
+
+        async fn channel_photo(&self, _channel_name: &str) -> Result<Box<dyn Chunks>> {
+            Err("not supported by FakeSource".into())
+        }
+    }
+
+    #[tokio::test]
+    async fn channels_stick_to_the_least_busy_account() {
+        let pool = pool(vec![vec![], vec![]]);
+        assert_eq!(account_of(&pool, "a").await, "0");
+        assert_eq!(account_of(&pool, "b").await, "1");
+        assert_eq!(account_of(&pool, "a").await, "0");
+        assert_eq!(account_of(&pool, "c").await, "0");
+        assert_eq!(account_of(&pool, "b").await, "1");
+        assert_eq!(pool.stats()["accounts"][0]["channels"], 2);
+    }
+
+    #[tokio::test]
+    async fn lost_account_leaves_the_pool() {
+        let pool = pool(vec![vec!["rpc error 401: AUTH_KEY_UNREGISTERED"], vec![]]);
+        assert_eq!(account_of(&pool, "a").await, "1");
+        assert!(!pool.is_enabled(0));
+        assert_eq!(pool.stats()["accounts"][1]["channels"], 1);
+        assert_eq!(account_of(&pool, "b").await, "1");
+
+        pool.disable(1, "Logged out");
+        assert!(account_of(&pool, "a").await.starts_with("No Telegram account takes calls"));
+        pool.enable(0);
+        assert_eq!(account_of(&pool, "a").await, "0");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn flood_wait_takes_a_detour() {
+        let pool = pool(vec![vec!["rpc error 420: FLOOD_WAIT_60"], vec!["rpc error 420: FLOOD_WAIT_30"]]);
+        // Both accounts are flood-waited, the one with the shorter wait sleeps through it
+        let started = tokio::time::Instant::now();
+        assert_eq!(account_of(&pool, "a").await, "1");
+        assert!(started.elapsed() >= Duration::from_secs(30));
+        let started = tokio::time::Instant::now();
+        assert_eq!(account_of(&pool, "b").await, "1");
+        assert!(started.elapsed() < Duration::from_secs(1));
+
+        tokio::time::advance(Duration::from_secs(60)).await;
+        assert_eq!(account_of(&pool, "a").await, "0");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn flood_wait_resumes_the_walk_after_the_last_page() {
+        let pool = pool(vec![vec!["rpc error 420: FLOOD_WAIT_60"], vec![]]);
+        let task = Task {
+            channel_name: "a".to_string(),
+            from_date: 0,
+            to_date: CHANNEL_POSTS as i64,
+            ..Task::default()
+        };
+        let progress = AtomicUsize::new(0);
+        let posts = pool.fetch_posts(&task, 1000, Some(&progress), None).await.unwrap();
+
+        // The first page came from account 0, the others took a detour through account 1
+        let ids: Vec<i32> = posts.iter().map(|post| post.id).collect();
+        assert_eq!(ids, (1..=CHANNEL_POSTS).rev().collect::<Vec<_>>());
+        assert_eq!(progress.load(std::sync::atomic::Ordering::Relaxed), CHANNEL_POSTS as usize);
+        assert!(posts[..100].iter().all(|post| post.message.as_deref() == Some("0")));
+        assert!(posts[100..].iter().all(|post| post.message.as_deref() == Some("1")));
+        let history = &pool.stats()["accounts"][0]["scheduler"]["classes"]["history"];
+        assert_eq!(history["callers"]["background"]["requests"], 2);
+    }
+}
@@ -0,0 +1,110 @@
+use crate::post::Post;
+use crate::post_data::{CommentData, PostData};
+use crate::scheduler::{MethodClass, Priority, Scheduler};
+use crate::source::{ChannelInfo, Chunks, MediaInfo, TelegramSource};
+use crate::task::Task;
+use crate::util::Result;
+
+use std::sync::Arc;
+
+/// Runs every call of another source through the `Scheduler`.
+/// Fetching history is background work, a token per page, everything else answers a user.
+/// Downloads take a media token for every chunk.
+pub struct ScheduledSource {
+    inner: Arc<dyn TelegramSource>,
+    scheduler: Arc<Scheduler>,
+}
+
+impl ScheduledSource {
+    pub fn new(inner: Arc<dyn TelegramSource>, scheduler: Arc<Scheduler>) -> ScheduledSource {
+        ScheduledSource { inner, scheduler }
+    }
+
+    fn chunks(&self, inner: Box<dyn Chunks>) -> Box<dyn Chunks> {
+        Box::new(ScheduledChunks {
+            inner,
+            scheduler: self.scheduler.clone(),
+        })
+    }
+}
+
+/// A download that takes a media token for every chunk it reads
+struct ScheduledChunks {
+    inner: Box<dyn Chunks>,
+    scheduler: Arc<Scheduler>,
+}
+
+#[rocket::async_trait]
+impl Chunks for ScheduledChunks {
+    fn skip_chunks(&mut self, count: i32) {
+        self.inner.skip_chunks(count);
+    }
+
+    async fn next(&mut self) -> Result<Option<Vec<u8>>> {
+        self.scheduler.take_turn(MethodClass::Media, Priority::Interactive).await?;
+        self.inner.next().await
+    }
+}
+
+#[rocket::async_trait]
+impl TelegramSource for ScheduledSource {
+    async fn resolve_channel(&self, channel_name: &str) -> Result<ChannelInfo> {
+        self.scheduler
+            .run(MethodClass::Resolve, Priority::Interactive, || {
+                self.inner.resolve_channel(channel_name)
+            })
+            .await
+    }
+
+    async fn fetch_page(&self, task: &Task, offset_id: i32, limit: usize) -> Result<Vec<Post>> {
+        self.scheduler
+            .run(MethodClass::History, Priority::Background, || {
+                self.inner.fetch_page(task, offset_id, limit)
+            })
+            .await
+    }
+
+    async fn get_post_data(&self, channel_name: &str, id: i32) -> Result<PostData> {
+        self.scheduler
+            .run(MethodClass::Messages, Priority::Interactive, || {
+                self.inner.get_post_data(channel_name, id)
+            })
+            .await
+    }
+
+    async fn get_comments(&self, channel_name: &str, id: i32, limit: usize) -> Result<Vec<CommentData>> {
+        self.scheduler
+            .run(MethodClass::Messages, Priority::Interactive, || {
+                self.inner.get_comments(channel_name, id, limit)
+            })
+            .await
+    }
+
+    async fn open_media(&self, channel_name: &str, id: i32) -> Result<(MediaInfo, Box<dyn Chunks>)> {
+        let (info, chunks) = self
+            .scheduler
+            .run(MethodClass::Media, Priority::Interactive, || {
+                self.inner.open_media(channel_name, id)
+            })
+            .await?;
+        Ok((info, self.chunks(chunks)))
+    }
+
+    async fn download_thumb(&self, channel_name: &str, id: i32) -> Result<(Vec<u8>, String)> {
+        self.scheduler
+            .run(MethodClass::Media, Priority::Interactive, || {
+                self.inner.download_thumb(channel_name, id)
+            })
+            .await
+    }
+
+    async fn channel_photo(&self, channel_name: &str) -> Result<Box<dyn Chunks>> {
+        let chunks = self
+            .scheduler
+            .run(MethodClass::Media, Priority::Interactive, || {
+                self.inner.channel_photo(channel_name)
+            })
+            .await?;
+        Ok(self.chunks(chunks))
+    }
+}
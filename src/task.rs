@@ -1,4 +1,6 @@
+use crate::action::Rank;
 use crate::cli::*;
+use crate::locale;
 use crate::util::Result;
 
 use chrono::{DateTime, Local, Timelike, Utc};
@@ -7,7 +9,7 @@ use chrono::{DateTime, Local, Timelike, Utc};
 pub struct Task {
     pub command: Commands,
 
-    /// t.me/<CHANNEL_NAME>
+    /// t.me/<CHANNEL_NAME>, a private channel's +<invite hash> or its -100<id> (see `source::ChannelRef`)
     pub channel_name: String,
 
     /// Count of posts in digest
@@ -25,10 +27,40 @@ pub struct Task {
     // UTC timestamp
     pub to_date: i64,
 
+    /// Rank posts by absolute counters or by their growth per hour
+    #[serde(default)]
+    pub rank: Rank,
+
+    /// Hours after publishing used to measure a post's velocity
+    #[serde(default = "default_velocity_window")]
+    pub velocity_window: i64,
+
+    /// Reaction to rank by in the "top by reaction" block, see `ReactionData::key`
+    #[serde(default)]
+    pub reaction: Option<String>,
+
+    /// Language of user-facing strings, see `locale::Locales`
+    #[serde(default = "default_lang")]
+    pub lang: String,
+
     // Unique task id
     pub task_id: String,
 }
 
+/// Measure velocity over the first day after publishing by default.
+pub const DEFAULT_VELOCITY_WINDOW: i64 = 24;
+
+/// Longest velocity window, a year of hours
+pub const MAX_VELOCITY_WINDOW: i64 = 24 * 365;
+
+fn default_velocity_window() -> i64 {
+    DEFAULT_VELOCITY_WINDOW
+}
+
+fn default_lang() -> String {
+    locale::DEFAULT_LANG.to_string()
+}
+
 impl Task {
     pub fn default() -> Self {
         let current_date = DateTime::<Utc>::from_timestamp(Local::now().timestamp(), 0)
@@ -48,6 +80,10 @@ impl Task {
             editor_choice_post_id: 0,
             from_date: week_ago.timestamp(),
             to_date: current_date.timestamp(),
+            rank: Rank::Total,
+            velocity_window: DEFAULT_VELOCITY_WINDOW,
+            reaction: None,
+            lang: default_lang(),
             task_id: uuid::Uuid::new_v4().as_simple().to_string(),
         }
     }
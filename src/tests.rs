@@ -0,0 +1,1029 @@
+//! Route tests against `source::fixture::FixtureSource` and a temporary cache DB.
+
+use crate::App;
+use crate::cli::Args;
+use crate::context::{AppContext, TelegramAccount};
+
+use rocket::http::{Header, Status};
+use rocket::local::asynchronous::Client;
+use serde_json::{Value, json};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+const CHANNEL: &str = "fixture";
+/// Private channel without a username, named by invite link or id
+const PRIVATE_ID: i64 = 1234567;
+const PRIVATE_INVITE: &str = "AbCdEf";
+const ADMIN_TOKEN: &str = "admin-secret";
+const HOUR: i64 = 3600;
+
+const ASYNC_TEMPLATE: &str = r#"<html><body data-title="{{ channel_title }}">
+<script>fetch("{{ data_url | safe }}")</script>
+</body></html>"#;
+
+const VIEW_TEMPLATE: &str = r#"<article data-title="{{ channel_title }}">{{ rendered_text | safe }}</article>
+{% if video %}<video src="{{ video.url }}" poster="{{ video.thumb_url }}"></video>{% endif %}"#;
+
+struct TestServer {
+    client: Client,
+    dir: PathBuf,
+    now: i64,
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+impl TestServer {
+    /// Query string covering every fixture post
+    fn range(&self) -> String {
+        format!("from_date={}&to_date={}", self.now - 2 * 24 * HOUR, self.now)
+    }
+
+    async fn get_json(&self, uri: &str) -> Value {
+        let response = self.client.get(uri.to_string()).dispatch().await;
+        assert_eq!(response.status(), Status::Ok, "GET {}", uri);
+        response.into_json().await.expect("JSON body")
+    }
+
+    /// Poll `/data` with the task id of the first response until it stops loading
+    async fn poll_data(&self, uri: &str) -> Value {
+        let first = self.get_json(uri).await;
+        assert_eq!(first["status"], "loading");
+        assert_eq!(first["fetched"], 0);
+        let task_id = first["task_id"].as_str().expect("task_id").to_string();
+
+        for _ in 0..50 {
+            let data = self.get_json(&format!("{}&task_id={}", uri, task_id)).await;
+            if data["status"] != "loading" {
+                return data;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+        panic!("{} is still loading", uri);
+    }
+
+    async fn get_html(&self, uri: &str) -> String {
+        let response = self.client.get(uri.to_string()).dispatch().await;
+        assert_eq!(response.status(), Status::Ok, "GET {}", uri);
+        response.into_string().await.expect("HTML body")
+    }
+}
+
+fn write(path: &Path, data: impl AsRef<[u8]>) {
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    std::fs::write(path, data).unwrap();
+}
+
+fn post(id: i32, date: i64, views: i32, forwards: i32, replies: i32, reactions: i32) -> Value {
+    json!({
+        "id": id,
+        "date": date,
+        "url": format!("https://t.me/{}/{}", CHANNEL, id),
+        "text": format!("Post {}", id),
+        "entities": [],
+        "views": views,
+        "forwards": forwards,
+        "replies": replies,
+        "reactions": reactions,
+        "pinned": false,
+    })
+}
+
+/// Media of post 3, large enough to span several chunks
+fn video_bytes() -> Vec<u8> {
+    (0..3 * crate::CHUNK_SIZE as usize / 2).map(|i| (i % 251) as u8).collect()
+}
+
+fn write_fixtures(dir: &Path, now: i64) {
+    let channel = dir.join(CHANNEL);
+    write(&channel.join("channel.json"), json!({"title": "Fixture Channel"}).to_string());
+
+    let mut entities_post = post(1, now - 30 * HOUR, 100, 1, 1, 1);
+    entities_post["text"] = json!("Hello bold world, see docs");
+    entities_post["entities"] = json!([
+        {"type": "bold", "offset": 6, "length": 4},
+        {"type": "text_url", "offset": 22, "length": 4, "url": "https://example.com/docs"},
+    ]);
+    let mut video_post = post(3, now - 10 * HOUR, 300, 2, 40, 3);
+    video_post["video"] = json!({
+        "id": 3003,
+        "url": format!("/media/{}/3", CHANNEL),
+        "thumb_url": format!("/thumb/{}/3", CHANNEL),
+        "mime_type": "video/mp4",
+        "size": video_bytes().len(),
+        "round_message": false,
+        "supports_streaming": true,
+    });
+    let mut viewed_post = post(2, now - 20 * HOUR, 5000, 3, 2, 2);
+    viewed_post["reaction_counts"] = json!([
+        {"type": "emoji", "emoticon": "👍", "count": 1},
+        {"type": "custom_emoji", "document_id": 42, "count": 1},
+    ]);
+    let mut shared_post = post(4, now - 5 * HOUR, 200, 50, 3, 4);
+    shared_post["reaction_counts"] = json!([{"type": "emoji", "emoticon": "🔥", "count": 4}]);
+    let mut reacted_post = post(5, now - HOUR, 150, 4, 4, 90);
+    reacted_post["reaction_counts"] = json!([
+        {"type": "emoji", "emoticon": "🔥", "count": 60},
+        {"type": "custom_emoji", "document_id": 42, "count": 20},
+        {"type": "paid", "count": 10},
+    ]);
+    let posts = json!([entities_post, viewed_post, video_post, shared_post, reacted_post]);
+    write(&channel.join("posts.json"), posts.to_string());
+
+    write(&channel.join("media/3.mp4"), video_bytes());
+    write(&channel.join("media/2.jpg"), b"fake jpeg");
+    write(&channel.join("thumbs/3.jpg"), b"fake thumb");
+    write(&channel.join("photo.jpg"), b"fake userpic");
+    let comments = json!([
+        {"id": 101, "date": now - 9 * HOUR, "author": "Alice", "text": "First!", "reactions": 2},
+        {
+            "id": 102,
+            "date": now - 8 * HOUR,
+            "author": "Bob",
+            "text": "Great post",
+            "reactions": 5,
+            "reaction_counts": [{"type": "emoji", "emoticon": "🔥", "count": 5}],
+        },
+    ]);
+    write(&channel.join("comments/3.json"), comments.to_string());
+
+    let private = dir.join("private");
+    let channel = json!({"title": "Private Channel", "id": PRIVATE_ID, "invite": PRIVATE_INVITE});
+    write(&private.join("channel.json"), channel.to_string());
+    let posts = json!([post(1, now - 10 * HOUR, 10, 1, 1, 1), post(2, now - 5 * HOUR, 20, 2, 2, 2)]);
+    write(&private.join("posts.json"), posts.to_string());
+}
+
+async fn test_server() -> TestServer {
+    test_server_with(|_| {}).await
+}
+
+/// A test server with its context changed by `configure` before the app starts
+async fn test_server_with(configure: impl FnOnce(&mut AppContext)) -> TestServer {
+    let dir = std::env::temp_dir().join(format!("tgdigest-test-{}", uuid::Uuid::new_v4().as_simple()));
+    let input_dir = dir.join("input");
+    let output_dir = dir.join("output");
+    let fixture_dir = dir.join("fixtures");
+    std::fs::create_dir_all(&output_dir).unwrap();
+
+    let data_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("data");
+    for lang in ["en", "uk"] {
+        let catalog = std::fs::read(data_dir.join(format!("locales/{}.json", lang))).unwrap();
+        write(&input_dir.join(format!("locales/{}.json", lang)), catalog);
+    }
+    let sync_template = std::fs::read(data_dir.join("example/digest_template.html")).unwrap();
+    write(&input_dir.join("example/digest_template.html"), sync_template);
+    let render_template = std::fs::read(data_dir.join("example/render_template.html")).unwrap();
+    write(&input_dir.join("example/render_template.html"), render_template);
+    write(&input_dir.join("async/digest_template.html"), ASYNC_TEMPLATE);
+    write(&input_dir.join("view_template.html"), VIEW_TEMPLATE);
+    let index_template = std::fs::read(data_dir.join("index_template.html")).unwrap();
+    write(&input_dir.join("index_template.html"), index_template);
+
+    let now = chrono::Utc::now().timestamp();
+    write_fixtures(&fixture_dir, now);
+
+    let mut ctx = AppContext {
+        input_dir,
+        output_dir,
+        tg_session: dir.join("session.dat"),
+        tg_id: 0,
+        tg_hash: String::new(),
+        proxy_url: None,
+        tg_accounts: Vec::new(),
+        public_base_url: Some("http://127.0.0.1:8000".to_string()),
+        cache_limit_mb: 16,
+        output_limit_mb: 64,
+        output_max_age_days: 30,
+        renderer_pool_size: 2,
+        renderer_page_uses: 50,
+        render_timeout_secs: 120,
+        render_ready_timeout_secs: 30,
+        min_rate_views: 0,
+        comment_posts: 0,
+        comment_limit: 1000,
+        default_lang: "en".to_string(),
+        shell: "bash".to_string(),
+        admin_token: Some(ADMIN_TOKEN.to_string()),
+        fixture_dir: Some(fixture_dir),
+    };
+    configure(&mut ctx);
+    let args = Args {
+        config: dir.join("config.json"),
+        command: None,
+    };
+    let app = App::with_context(args, ctx, None).expect("App with fixtures");
+    let client = Client::tracked(crate::rocket(Arc::new(app)))
+        .await
+        .expect("Rocket instance");
+    TestServer { client, dir, now }
+}
+
+fn card_ids(data: &Value, block: usize) -> Vec<i64> {
+    data["blocks"][block]["cards"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|card| card[0].as_i64().unwrap())
+        .collect()
+}
+
+#[rocket::async_test]
+async fn data_reports_progress_until_ready() {
+    let server = test_server().await;
+    let uri = format!("/data/example/{}?{}&lang=en", CHANNEL, server.range());
+
+    // An empty cache fetches the newest `top_count` posts
+    let data = server.poll_data(&uri).await;
+    assert_eq!(data["status"], "ready");
+    assert_eq!(data["channel_title"], "Fixture Channel");
+    assert_eq!(data["lang"], "en");
+    assert_eq!(data["blocks"][0]["header"], "By comments");
+    assert_eq!(card_ids(&data, 0), vec![3, 5, 4]);
+    assert_eq!(data["blocks"][3]["header"], "By views");
+    assert_eq!(card_ids(&data, 3), vec![3, 4, 5]);
+
+    // Each following request walks back from the oldest cached post
+    let data = server.poll_data(&uri).await;
+    assert_eq!(data["status"], "ready");
+    assert_eq!(card_ids(&data, 3), vec![2, 3, 4]);
+}
+
+#[rocket::async_test]
+async fn data_rejects_unknown_lang() {
+    let server = test_server().await;
+    let uri = format!("/data/example/{}?{}&lang=xx", CHANNEL, server.range());
+    let response = server.client.get(uri).dispatch().await;
+    assert_eq!(response.status(), Status::BadRequest);
+
+    // Tags match in any case and by their primary subtag, like in Accept-Language
+    for lang in ["EN", "uk-UA"] {
+        let uri = format!("/data/example/{}?{}&lang={}", CHANNEL, server.range(), lang);
+        let response = server.client.get(uri).dispatch().await;
+        assert_eq!(response.status(), Status::Ok, "lang={}", lang);
+    }
+}
+
+#[rocket::async_test]
+async fn digest_sync_template_waits_for_data() {
+    let server = test_server().await;
+    let html = server
+        .get_html(&format!("/digest/example/{}?{}&top_count=5&editor_choice=1", CHANNEL, server.range()))
+        .await;
+
+    assert!(html.contains("By views"));
+    assert!(html.contains(&format!("/view/{}/2?iframe=true", CHANNEL)));
+    assert!(html.contains("Editor&#x27;s choice"));
+    assert!(!html.contains("data_url"));
+}
+
+#[rocket::async_test]
+async fn digest_async_template_renders_shell() {
+    let server = test_server().await;
+    let request = server
+        .client
+        .get(format!("/digest/async/{}?{}", CHANNEL, server.range()))
+        .header(Header::new("Accept-Language", "uk;q=0.5, ru;q=0.9"));
+    let response = request.dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+    let html = response.into_string().await.unwrap();
+
+    assert!(html.contains(r#"data-title="Fixture Channel""#));
+    assert!(html.contains(&format!("/data/async/{}?{}", CHANNEL, server.range())));
+    assert!(html.contains("&lang=ru"));
+    // Nothing is fetched until the page asks /data
+    assert!(server.client.rocket().state::<Arc<App>>().unwrap().fetch_progress.lock().unwrap().is_empty());
+}
+
+#[rocket::async_test]
+async fn mode_blocks_rank_by_their_score() {
+    let server = test_server().await;
+    let blocks = json!([
+        {"key": "reactions", "header": "block.views", "icon": "🔥", "score": "reactions*10 - forwards"},
+        {"header": "Fresh", "icon": "⚡", "score": "views / (age + 2)"},
+    ]);
+    let blocks_file = server.dir.join("input/example/blocks.json");
+    write(&blocks_file, blocks.to_string());
+
+    let uri = format!("/data/example/{}?{}&lang=en", CHANNEL, server.range());
+    // The second request walks back to the two older posts
+    server.poll_data(&uri).await;
+    let data = server.poll_data(&uri).await;
+    let blocks = data["blocks"].as_array().unwrap();
+    assert_eq!(blocks.len(), 2);
+    assert_eq!(blocks[0]["header"], "By views");
+    assert_eq!(blocks[0]["cards"], json!([[5, 896], [3, 28], [2, 17]]));
+    assert_eq!(blocks[1]["header"], "Fresh");
+    assert_eq!(blocks[1]["cards"], json!([[2, 227], [5, 50], [4, 29]]));
+
+    write(&blocks_file, json!([{"header": "Broken", "icon": "⚡", "score": "views +"}]).to_string());
+    let response = server
+        .client
+        .get(format!("/data/example/{}?{}", CHANNEL, server.range()))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::InternalServerError);
+}
+
+#[rocket::async_test]
+async fn history_lists_snapshots_of_cached_posts() {
+    let server = test_server().await;
+    let uri = format!("/history/{}/3", CHANNEL);
+    let response = server.client.get(uri.clone()).dispatch().await;
+    assert_eq!(response.status(), Status::NotFound);
+
+    server.poll_data(&format!("/data/example/{}?{}", CHANNEL, server.range())).await;
+    let history = server.get_json(&uri).await;
+    assert_eq!(history["id"], 3);
+    assert_eq!(history["date"], server.now - 10 * HOUR);
+    let snapshots = history["snapshots"].as_array().unwrap();
+    assert_eq!(snapshots.len(), 1);
+    assert_eq!(snapshots[0]["views"], 300);
+    assert_eq!(snapshots[0]["replies"], 40);
+
+    // The snapshot was taken 10 hours after publishing
+    let history = server.get_json(&format!("{}?hours=1", uri)).await;
+    assert!(history["snapshots"].as_array().unwrap().is_empty());
+    for hours in ["0", "9223372036854775807"] {
+        let response = server.client.get(format!("{}?hours={}", uri, hours)).dispatch().await;
+        assert_eq!(response.status(), Status::BadRequest, "hours={}", hours);
+    }
+}
+
+#[rocket::async_test]
+async fn data_ranks_by_velocity() {
+    let server = test_server().await;
+    let uri = format!("/data/example/{}?{}&rank=velocity&velocity_window=48", CHANNEL, server.range());
+    // The second request walks back to the two older posts
+    server.poll_data(&uri).await;
+    let data = server.poll_data(&uri).await;
+    assert_eq!(data["blocks"][3]["header"], "By views per hour");
+    // 5000 views in 20 hours, 150 in one, 200 in five
+    assert_eq!(card_ids(&data, 3), vec![2, 5, 4]);
+
+    for window in ["0", "8761", "9223372036854775807"] {
+        let uri = format!("/data/example/{}?{}&rank=velocity&velocity_window={}", CHANNEL, server.range(), window);
+        let response = server.client.get(uri).dispatch().await;
+        assert_eq!(response.status(), Status::BadRequest, "velocity_window={}", window);
+    }
+}
+
+fn block_cards<'a>(data: &'a Value, header: &str) -> &'a Value {
+    let blocks = data["blocks"].as_array().unwrap();
+    let block = blocks.iter().find(|block| block["header"] == header);
+    &block.unwrap_or_else(|| panic!("no {} block", header))["cards"]
+}
+
+#[rocket::async_test]
+async fn data_ranks_rates_above_min_views() {
+    let server = test_server().await;
+    let uri = format!("/data/example/{}?{}", CHANNEL, server.range());
+    server.poll_data(&uri).await;
+    let data = server.poll_data(&uri).await;
+    // Posts 1 and 3 both get 10 reactions per 1000 views, the newer one goes first
+    assert_eq!(block_cards(&data, "Reactions per 1000 views"), &json!([[5, 600], [4, 20], [3, 10]]));
+
+    let server = test_server_with(|ctx| ctx.min_rate_views = 250).await;
+    let uri = format!("/data/example/{}?{}", CHANNEL, server.range());
+    server.poll_data(&uri).await;
+    let data = server.poll_data(&uri).await;
+    // Only posts 2 and 3 have 250 views
+    assert_eq!(block_cards(&data, "Reactions per 1000 views"), &json!([[3, 10], [2, 0]]));
+    assert_eq!(block_cards(&data, "Engagement per 1000 views"), &json!([[3, 150], [2, 1]]));
+}
+
+#[rocket::async_test]
+async fn data_ranks_by_a_single_reaction() {
+    let server = test_server().await;
+    let data_uri = |reaction: &str| {
+        let reaction = rocket::http::RawStr::new(reaction).percent_encode();
+        format!("/data/example/{}?{}&reaction={}", CHANNEL, server.range(), reaction)
+    };
+    server.poll_data(&data_uri("🔥")).await;
+    let data = server.poll_data(&data_uri("🔥")).await;
+    assert_eq!(data["reaction"], "🔥");
+    assert_eq!(block_cards(&data, "By reaction 🔥"), &json!([[5, 60], [4, 4]]));
+
+    let data = server.poll_data(&data_uri("custom:42")).await;
+    assert_eq!(block_cards(&data, "By reaction custom:42"), &json!([[5, 20], [2, 1]]));
+    let data = server.poll_data(&data_uri("paid")).await;
+    assert_eq!(block_cards(&data, "By reaction paid"), &json!([[5, 10]]));
+
+    // A reaction nobody left gets no block
+    let data = server.poll_data(&data_uri("😢")).await;
+    let blocks = data["blocks"].as_array().unwrap();
+    assert!(!blocks.iter().any(|block| block["header"] == "By reaction 😢"));
+}
+
+#[rocket::async_test]
+async fn post_returns_fixture_json() {
+    let server = test_server().await;
+    let post = server.get_json(&format!("/post/{}/3", CHANNEL)).await;
+    assert_eq!(post["id"], 3);
+    assert_eq!(post["channel_title"], "Fixture Channel");
+    assert_eq!(post["video"]["mime_type"], "video/mp4");
+    assert_eq!(post["replies"], 40);
+
+    let response = server.client.get(format!("/post/{}/404", CHANNEL)).dispatch().await;
+    assert_eq!(response.status(), Status::NotFound);
+
+    let stats = server.get_json("/stats/telegram").await;
+    assert_eq!(stats["online"], true);
+    assert_eq!(stats["classes"]["messages"]["callers"]["interactive"]["requests"], 2);
+    assert!(stats["classes"]["history"]["callers"].get("background").is_none());
+}
+
+#[rocket::async_test]
+async fn view_renders_entities() {
+    let server = test_server().await;
+    let html = server.get_html(&format!("/view/{}/1", CHANNEL)).await;
+    assert!(html.contains(r#"data-title="Fixture Channel""#));
+    assert!(html.contains("Hello <b>bold</b> world"));
+    assert!(html.contains(r#"<a href="https://example.com/docs" target="_blank" rel="noopener">docs</a>"#));
+}
+
+#[rocket::async_test]
+async fn media_supports_range_requests() {
+    let server = test_server().await;
+    let video = video_bytes();
+    let uri = format!("/media/{}/3", CHANNEL);
+
+    for _ in 0..2 {
+        // The first request downloads and caches the file, the second is served from disk
+        let response = server
+            .client
+            .get(uri.clone())
+            .header(Header::new("Range", "bytes=1000-1999"))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::PartialContent);
+        assert_eq!(response.content_type().unwrap().to_string(), "video/mp4");
+        assert_eq!(
+            response.headers().get_one("Content-Range").unwrap(),
+            format!("bytes 1000-1999/{}", video.len())
+        );
+        assert_eq!(response.into_bytes().await.unwrap(), &video[1000..2000]);
+    }
+    // Opening the file and each of its chunk reads took a media token, the end of the file too
+    let stats = server.get_json("/stats/telegram").await;
+    assert_eq!(stats["classes"]["media"]["callers"]["interactive"]["requests"], 4);
+
+    let response = server
+        .client
+        .get(uri.clone())
+        .header(Header::new("Range", format!("bytes={}-", video.len() - 10)))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::PartialContent);
+    assert_eq!(response.into_bytes().await.unwrap(), &video[video.len() - 10..]);
+
+    let response = server.client.get(uri).dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.headers().get_one("Accept-Ranges").unwrap(), "bytes");
+    assert_eq!(response.into_bytes().await.unwrap(), video);
+
+    let response = server.client.get(format!("/media/{}/5", CHANNEL)).dispatch().await;
+    assert_eq!(response.status(), Status::NotFound);
+}
+
+#[rocket::async_test]
+async fn concurrent_media_misses_download_once() {
+    let server = test_server().await;
+    let uri = format!("/media/{}/3", CHANNEL);
+    let (first, second) = tokio::join!(
+        server.client.get(uri.clone()).dispatch(),
+        server.client.get(uri.clone()).dispatch(),
+    );
+    assert_eq!(first.status(), Status::Ok);
+    assert_eq!(second.status(), Status::Ok);
+    assert_eq!(first.into_bytes().await.unwrap(), video_bytes());
+    assert_eq!(second.into_bytes().await.unwrap(), video_bytes());
+    let stats = server.get_json("/stats/telegram").await;
+    assert_eq!(stats["classes"]["media"]["callers"]["interactive"]["requests"], 4);
+}
+
+#[rocket::async_test]
+async fn userpic_is_downloaded_whole() {
+    let server = test_server().await;
+    let response = server.client.get(format!("/userpic/{}", CHANNEL)).dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.headers().get_one("Content-Length").unwrap(), "12");
+    assert_eq!(response.into_bytes().await.unwrap(), b"fake userpic");
+}
+
+#[rocket::async_test]
+async fn degraded_mode_serves_cache_or_503() {
+    let server = test_server().await;
+    let uri = format!("/data/example/{}?{}", CHANNEL, server.range());
+    server.poll_data(&uri).await;
+
+    let app = server.client.rocket().state::<Arc<App>>().unwrap();
+    app.connection.set_degraded(std::time::Duration::from_secs(30));
+
+    // Cached posts are still served, without starting another fetch
+    let data = server.get_json(&uri).await;
+    assert_eq!(data["status"], "ready");
+    assert_eq!(data["degraded"], true);
+    assert_eq!(card_ids(&data, 3), vec![3, 4, 5]);
+
+    for uri in [format!("/post/{}/3", CHANNEL), format!("/media/{}/3", CHANNEL)] {
+        let response = server.client.get(uri).dispatch().await;
+        assert_eq!(response.status(), Status::ServiceUnavailable);
+        let retry_after: u64 = response.headers().get_one("Retry-After").unwrap().parse().unwrap();
+        assert!((1..=30).contains(&retry_after));
+    }
+
+    app.connection.set_online();
+    server.get_json(&format!("/post/{}/3", CHANNEL)).await;
+    let data = server.poll_data(&uri).await;
+    assert!(data.get("degraded").is_none());
+    assert_eq!(card_ids(&data, 3), vec![2, 3, 4]);
+}
+
+#[rocket::async_test]
+async fn private_channel_is_cached_by_id() {
+    let server = test_server().await;
+    let data = server
+        .poll_data(&format!("/data/example/+{}?{}", PRIVATE_INVITE, server.range()))
+        .await;
+    assert_eq!(data["status"], "ready");
+    assert_eq!(data["channel_title"], "Private Channel");
+    assert_eq!(card_ids(&data, 3), vec![2, 1]);
+
+    let app = server.client.rocket().state::<Arc<App>>().unwrap();
+    let key = format!("-100{}", PRIVATE_ID);
+    assert_eq!(app.cache.channel_key(&format!("+{}", PRIVATE_INVITE)).unwrap(), key);
+
+    // The id names the same cached posts
+    app.connection.set_degraded(std::time::Duration::from_secs(30));
+    let data = server.get_json(&format!("/data/example/{}?{}", key, server.range())).await;
+    assert_eq!(data["status"], "ready");
+    assert_eq!(card_ids(&data, 3), vec![2, 1]);
+}
+
+#[rocket::async_test]
+async fn renamed_channel_redirects_old_urls() {
+    let server = test_server().await;
+    let fixtures = server.dir.join("fixtures");
+    let old = fixtures.join("oldname");
+    write(&old.join("channel.json"), json!({"title": "Renamed Channel", "id": 77}).to_string());
+    write(&old.join("posts.json"), json!([post(1, server.now - 10 * HOUR, 10, 1, 1, 1)]).to_string());
+
+    let data = server.poll_data(&format!("/data/example/oldname?{}", server.range())).await;
+    assert_eq!(data["status"], "ready");
+
+    std::fs::rename(&old, fixtures.join("newname")).unwrap();
+    let html = server.get_html(&format!("/digest/async/newname?{}", server.range())).await;
+    assert!(html.contains(r#"data-title="Renamed Channel""#));
+
+    let location = format!("/digest/async/newname?{}", server.range());
+    for old_name in ["oldname", "OldName"] {
+        let uri = format!("/digest/async/{}?{}", old_name, server.range());
+        let response = server.client.get(uri).dispatch().await;
+        assert_eq!(response.status(), Status::TemporaryRedirect);
+        assert_eq!(response.headers().get_one("Location"), Some(location.as_str()));
+    }
+
+    // The cached posts follow the channel
+    let app = server.client.rocket().state::<Arc<App>>().unwrap();
+    app.connection.set_degraded(std::time::Duration::from_secs(30));
+    let data = server.get_json(&format!("/data/example/newname?{}", server.range())).await;
+    assert_eq!(data["status"], "ready");
+    assert_eq!(card_ids(&data, 3), vec![1]);
+
+    // Another channel takes the old username, the next check of the alias ends it
+    app.connection.set_online();
+    write(&old.join("channel.json"), json!({"title": "Other Channel", "id": 88}).to_string());
+    write(&old.join("posts.json"), json!([post(2, server.now - 10 * HOUR, 10, 1, 1, 1)]).to_string());
+    app.alias_checks.lock().unwrap().clear();
+    let uri = format!("/digest/async/oldname?{}", server.range());
+    let response = server.client.get(uri).dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+    assert!(response.into_string().await.unwrap().contains(r#"data-title="Other Channel""#));
+    assert_eq!(app.cache.channel_key("OLDNAME").unwrap(), "-10088");
+    assert_eq!(app.cache.renamed_channel("oldname").unwrap(), None);
+}
+
+#[rocket::async_test]
+async fn comments_are_cached_and_get_a_block() {
+    let server = test_server().await;
+    let uri = format!("/data/example/{}?{}&lang=en", CHANNEL, server.range());
+    server.poll_data(&uri).await;
+
+    let data = server.get_json(&format!("/post/{}/3/comments", CHANNEL)).await;
+    let comments = data["comments"].as_array().unwrap();
+    assert_eq!(comments.len(), 2);
+    assert_eq!(comments[0]["author"], "Bob");
+    assert_eq!(comments[0]["reaction_counts"][0]["emoticon"], "🔥");
+
+    // The cached thread is served while Telegram is away and ranks the post's top comment
+    let app = server.client.rocket().state::<Arc<App>>().unwrap();
+    app.connection.set_degraded(std::time::Duration::from_secs(30));
+    let data = server.get_json(&format!("/post/{}/3/comments", CHANNEL)).await;
+    assert_eq!(data["degraded"], true);
+    assert_eq!(data["comments"].as_array().unwrap().len(), 2);
+    let response = server.client.get(format!("/post/{}/4/comments", CHANNEL)).dispatch().await;
+    assert_eq!(response.status(), Status::ServiceUnavailable);
+
+    let data = server.get_json(&uri).await;
+    let block = data["blocks"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|block| block["header"] == "Most discussed comment")
+        .expect("top comment block");
+    assert_eq!(block["cards"], json!([[3, 5]]));
+    assert_eq!(block["comments"][0]["text"], "Great post");
+}
+
+#[rocket::async_test]
+async fn signed_out_first_account_leaves_the_others_serving() {
+    let server = test_server_with(|ctx| {
+        ctx.tg_accounts.push(TelegramAccount {
+            tg_session: ctx.tg_session.with_file_name("second.session"),
+            tg_id: 0,
+            tg_hash: String::new(),
+            proxy_url: None,
+        });
+    })
+    .await;
+    let app = server.client.rocket().state::<Arc<App>>().unwrap();
+    crate::apply_sign_in(app, &[false, true]);
+    assert!(app.connection.is_authorized());
+    assert_eq!(app.pool.enabled_accounts(), vec![1]);
+
+    let data = server.get_json(&format!("/post/{}/3", CHANNEL)).await;
+    assert_eq!(data["id"], 3);
+    let uri = format!("/data/example/{}?{}", CHANNEL, server.range());
+    assert_eq!(server.poll_data(&uri).await["status"], "ready");
+
+    // The first account still signs in at /admin
+    let admin = Header::new("Authorization", format!("Bearer {}", ADMIN_TOKEN));
+    let response = server.client.get("/admin/telegram").header(admin).dispatch().await;
+    let status: Value = response.into_json().await.unwrap();
+    assert_eq!(status["state"], "phone");
+
+    app.pool.disable(1, "Logged out");
+    crate::apply_sign_in(app, &[false, false]);
+    assert!(!app.connection.is_authorized());
+}
+
+#[rocket::async_test]
+async fn signed_out_server_waits_for_admin_login() {
+    let server = test_server().await;
+    let admin = Header::new("Authorization", format!("Bearer {}", ADMIN_TOKEN));
+
+    let response = server.client.get("/admin/telegram").dispatch().await;
+    assert_eq!(response.status(), Status::Unauthorized);
+    let wrong = Header::new("Authorization", "Bearer admin-secreT");
+    let response = server.client.get("/admin/telegram").header(wrong).dispatch().await;
+    assert_eq!(response.status(), Status::Unauthorized);
+    let response = server.client.get("/admin").dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+
+    let response = server.client.get("/admin/telegram").header(admin.clone()).dispatch().await;
+    let status: Value = response.into_json().await.unwrap();
+    assert_eq!(status["state"], "signed_in");
+    let response = server
+        .client
+        .post("/admin/telegram/phone")
+        .header(admin.clone())
+        .json(&json!({ "phone": "+10000000000" }))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Conflict);
+
+    // Signed out: Telegram routes answer 503 without a time to retry, cached digests still work
+    let uri = format!("/data/example/{}?{}", CHANNEL, server.range());
+    server.poll_data(&uri).await;
+    let app = server.client.rocket().state::<Arc<App>>().unwrap();
+    app.connection.set_authorized(false);
+    let response = server.client.get(format!("/post/{}/3", CHANNEL)).dispatch().await;
+    assert_eq!(response.status(), Status::ServiceUnavailable);
+    assert!(response.headers().get_one("Retry-After").is_none());
+    assert!(response.into_string().await.unwrap().contains("/admin"));
+    let data = server.get_json(&uri).await;
+    assert_eq!(data["signed_out"], true);
+    assert!(data.get("retry_after").is_none());
+    assert_eq!(server.get_json("/stats/telegram").await["signed_in"], false);
+
+    let response = server.client.get("/admin/telegram").header(admin.clone()).dispatch().await;
+    let status: Value = response.into_json().await.unwrap();
+    assert_eq!(status["state"], "phone");
+    let response = server
+        .client
+        .post("/admin/telegram/code")
+        .header(admin.clone())
+        .json(&json!({ "code": "12345" }))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Conflict);
+    let response = server
+        .client
+        .post("/admin/telegram/phone")
+        .header(admin.clone())
+        .json(&json!({ "phone": " " }))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::BadRequest);
+    // Fixtures have no Telegram client to send a code with
+    let response = server
+        .client
+        .post("/admin/telegram/phone")
+        .header(admin)
+        .json(&json!({ "phone": "+10000000000" }))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::ServiceUnavailable);
+}
+
+#[rocket::async_test]
+async fn video_job_reports_stages() {
+    let server = test_server().await;
+    let body = json!({
+        "mode": "example",
+        "channel": CHANNEL,
+        "replies": 1,
+        "views": 1,
+        "from_date": server.now - 2 * 24 * HOUR,
+        "to_date": server.now,
+        "lang": "en",
+    });
+
+    let response = server.client.post("/jobs/video").json(&body).dispatch().await;
+    assert_eq!(response.status(), Status::Accepted);
+    let job: Value = response.into_json().await.unwrap();
+    let id = job["id"].as_str().unwrap().to_string();
+    assert_eq!(job["status_url"], format!("/jobs/{}", id));
+
+    // The same request joins the job instead of starting another one
+    let again: Value = server.client.post("/jobs/video").json(&body).dispatch().await.into_json().await.unwrap();
+    assert_eq!(again["id"], id);
+
+    // Without a browser the job fetches posts, then fails on the cards
+    let mut status = Value::Null;
+    for _ in 0..50 {
+        status = server.get_json(&format!("/jobs/{}", id)).await;
+        if status["status"] == "done" || status["status"] == "failed" {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    assert_eq!(status["status"], "failed");
+    assert!(status["error"].as_str().unwrap().contains("Card renderer is not running"));
+    assert!(status.get("result_url").is_none());
+
+    let response = server.client.get(format!("/jobs/{}/result", id)).dispatch().await;
+    assert_eq!(response.status(), Status::ServiceUnavailable);
+    assert_eq!(server.get_json("/stats/renderer").await["status"], "disabled");
+
+    for uri in ["/jobs/unknown", "/jobs/unknown/result"] {
+        let response = server.client.get(uri).dispatch().await;
+        assert_eq!(response.status(), Status::NotFound);
+    }
+}
+
+#[rocket::async_test]
+async fn video_format_and_output_are_validated() {
+    let server = test_server().await;
+    let body = json!({
+        "mode": "example",
+        "channel": CHANNEL,
+        "views": 1,
+        "from_date": server.now - 2 * 24 * HOUR,
+        "to_date": server.now,
+    });
+    let story: Value = server.client.post("/jobs/video").json(&body).dispatch().await.into_json().await.unwrap();
+
+    // Another shape or container of the same digest is another video
+    let mut square_gif = body.clone();
+    square_gif["format"] = "square".into();
+    square_gif["output"] = "gif".into();
+    let response = server.client.post("/jobs/video").json(&square_gif).dispatch().await;
+    assert_eq!(response.status(), Status::Accepted);
+    let square_gif: Value = response.into_json().await.unwrap();
+    assert_ne!(square_gif["id"], story["id"]);
+
+    let mut explicit = body.clone();
+    explicit["format"] = "story".into();
+    explicit["output"] = "mp4".into();
+    let explicit: Value = server.client.post("/jobs/video").json(&explicit).dispatch().await.into_json().await.unwrap();
+    assert_eq!(explicit["id"], story["id"]);
+
+    let mut circle = body.clone();
+    circle["format"] = "circle".into();
+    let response = server.client.post("/jobs/video").json(&circle).dispatch().await;
+    assert_eq!(response.status(), Status::BadRequest);
+
+    let mut post_video = body.clone();
+    post_video["post_video"] = 10.into();
+    let post_video: Value = server.client.post("/jobs/video").json(&post_video).dispatch().await.into_json().await.unwrap();
+    assert_ne!(post_video["id"], story["id"]);
+
+    let uri = format!("/video/example/{}?views=1&post_video=0", CHANNEL);
+    let response = server.client.get(uri).dispatch().await;
+    assert_eq!(response.status(), Status::BadRequest);
+
+    let uri = format!("/video/example/{}?views=1&output=avi", CHANNEL);
+    let response = server.client.get(uri).dispatch().await;
+    assert_eq!(response.status(), Status::BadRequest);
+    assert!(response.into_string().await.unwrap().contains("Provided video output is not allowed"));
+
+    // A post video must outlast the transition into and out of its card
+    let video_spec = json!({"transition": {"type": "crossfade", "seconds": 1.5}});
+    write(&server.dir.join("input/example/video.json"), video_spec.to_string());
+    let mut short_post_video = body.clone();
+    short_post_video["post_video"] = 1.into();
+    let response = server.client.post("/jobs/video").json(&short_post_video).dispatch().await;
+    assert_eq!(response.status(), Status::BadRequest);
+}
+
+#[rocket::async_test]
+async fn post_videos_are_downloaded_for_cards() {
+    let server = test_server().await;
+    let app = server.client.rocket().state::<Arc<App>>().unwrap();
+    let dir = server.dir.join("output");
+
+    let video = crate::post_video_file(app, CHANNEL, 3, &dir).await.unwrap().expect("post 3 has a video");
+    assert_eq!(std::fs::read(&video).unwrap(), video_bytes());
+    // The next card of the same post is served from the media cache
+    assert!(app.cache.get_cached_media(CHANNEL, 3).unwrap().is_some());
+    assert_eq!(crate::post_video_file(app, CHANNEL, 3, &dir).await.unwrap(), Some(video));
+
+    assert_eq!(crate::post_video_file(app, CHANNEL, 2, &dir).await.unwrap(), None, "photos stay cards");
+    assert_eq!(crate::post_video_file(app, CHANNEL, 5, &dir).await.unwrap(), None, "no media");
+}
+
+#[rocket::async_test]
+async fn outputs_are_tracked_until_removed() {
+    let server = test_server().await;
+    let app = server.client.rocket().state::<Arc<App>>().unwrap();
+    let output = server.dir.join("output");
+    let video = output.join("00000000000000000009.mp4");
+    write(&video, b"video");
+    write(&output.join("00000000000000000009/card_00.png"), b"card");
+
+    crate::remove_render_dirs(app);
+    assert!(!output.join("00000000000000000009").exists());
+    assert_eq!(app.cache.adopt_artifacts(&output).unwrap(), 1);
+
+    let stats = server.get_json("/stats/output").await;
+    assert_eq!(stats["files"], 1);
+    assert_eq!(stats["bytes"], 5);
+    assert_eq!(stats["kinds"]["video"]["files"], 1);
+    assert_eq!(stats["limit_bytes"], 64 * 1024 * 1024);
+
+    // A finished job whose video was evicted is forgotten, so posting it again renders anew
+    let job = Arc::new(crate::jobs::VideoJob::new("evicted".to_string()));
+    job.finish(Ok(video.clone()));
+    app.video_jobs.lock().unwrap().insert(job.id.clone(), job);
+    let response = server.client.get("/jobs/evicted/result").dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+    std::fs::remove_file(&video).unwrap();
+    let response = server.client.get("/jobs/evicted/result").dispatch().await;
+    assert_eq!(response.status(), Status::NotFound);
+}
+
+/// `(event, data)` pairs of a server-sent events body, without heartbeats
+fn parse_events(body: &str) -> Vec<(String, Value)> {
+    body.split("\n\n")
+        .filter_map(|chunk| {
+            let mut event = None;
+            let mut data = None;
+            for line in chunk.lines() {
+                if let Some(name) = line.strip_prefix("event:") {
+                    event = Some(name.trim().to_string());
+                } else if let Some(json) = line.strip_prefix("data:") {
+                    data = Some(serde_json::from_str(json.trim()).unwrap());
+                }
+            }
+            Some((event?, data?))
+        })
+        .collect()
+}
+
+#[rocket::async_test]
+async fn data_events_stream_until_ready() {
+    let server = test_server().await;
+    let uri = format!("/data/example/{}/events?{}&lang=en", CHANNEL, server.range());
+
+    let response = server.client.get(uri.clone()).dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.content_type(), Some(rocket::http::ContentType::EventStream));
+    let events = parse_events(&response.into_string().await.unwrap());
+
+    let (name, first) = events.first().unwrap();
+    assert_eq!(name, "data");
+    assert_eq!(first["status"], "loading");
+    assert!(first["fetched"].as_u64().unwrap() <= first["limit"].as_u64().unwrap());
+
+    let (name, last) = events.last().unwrap();
+    assert_eq!(name, "data");
+    assert_eq!(last["status"], "ready");
+    assert_eq!(last["channel_title"], "Fixture Channel");
+    assert_eq!(card_ids(last, 3), vec![3, 4, 5]);
+
+    // The stream ends with the fetch, so /data finds the posts cached
+    let data = server.poll_data(&format!("/data/example/{}?{}&lang=en", CHANNEL, server.range())).await;
+    assert_eq!(card_ids(&data, 3), vec![2, 3, 4]);
+}
+
+#[rocket::async_test]
+async fn export_writes_a_static_site() {
+    let server = test_server().await;
+    let app = server.client.rocket().state::<Arc<App>>().unwrap().clone();
+    let today = chrono::Utc::now().date_naive();
+    let mut options = crate::export::ExportOptions {
+        mode: "async".to_string(),
+        channel: CHANNEL.to_string(),
+        from: today - chrono::Days::new(2),
+        to: today,
+        periods: vec![crate::export::Period::Year],
+        top_count: 5,
+        lang: "en".to_string(),
+        dir: server.dir.join("site"),
+        max_media_size: 1024 * 1024,
+    };
+    let e = crate::export::export(&app, &options).await.unwrap_err();
+    assert!(e.to_string().contains("export needs a static one"));
+
+    options.mode = "example".to_string();
+    let summary = crate::export::export(&app, &options).await.unwrap();
+    assert_eq!(summary.digests, 1);
+    assert_eq!(summary.posts, 5);
+    assert_eq!(summary.missing, 0);
+
+    // Digest iframes open the exported posts, which play their media from the assets
+    let site = &options.dir;
+    let year = format!("{}.html", chrono::Datelike::year(&today));
+    let digest = std::fs::read_to_string(site.join(&year)).unwrap();
+    assert!(digest.contains(r#"src="posts/2-iframe_true.html""#));
+    assert!(!digest.contains("/view/"));
+    let post = std::fs::read_to_string(site.join("posts/3-iframe_true.html")).unwrap();
+    assert!(post.contains(r#"<video src="../assets/media/3.mp4" poster="../assets/thumbs/3.jpg">"#));
+    assert_eq!(std::fs::read(site.join("assets/media/3.mp4")).unwrap(), video_bytes());
+    assert_eq!(std::fs::read(site.join("assets/thumbs/3.jpg")).unwrap(), b"fake thumb");
+
+    let index = std::fs::read_to_string(site.join("index.html")).unwrap();
+    assert!(index.contains(&format!(r#"<a href="{}">"#, year)));
+    assert!(index.contains(r#"<img src="assets/userpic.jpg""#));
+    assert!(index.contains(r#"content="http://127.0.0.1:8000/assets/userpic.jpg""#));
+    assert_eq!(std::fs::read(site.join("assets/userpic.jpg")).unwrap(), b"fake userpic");
+
+    let sitemap = std::fs::read_to_string(site.join("sitemap.xml")).unwrap();
+    assert!(sitemap.contains("<loc>http://127.0.0.1:8000/index.html</loc>"));
+    assert!(sitemap.contains(&format!("<loc>http://127.0.0.1:8000/{}</loc>", year)));
+}
+
+fn cli_command(args: &[&str]) -> crate::cli::CliCommand {
+    let args = ["tgdigest", "--config", "config.json"].iter().chain(args);
+    <Args as clap::Parser>::try_parse_from(args).unwrap().command.unwrap()
+}
+
+#[test]
+fn cli_arguments_are_consistent() {
+    <Args as clap::CommandFactory>::command().debug_assert();
+}
+
+#[rocket::async_test]
+async fn cli_commands_write_to_the_output_dir() {
+    let server = test_server().await;
+    let app = server.client.rocket().state::<Arc<App>>().unwrap().clone();
+    let output_dir = &app.ctx.output_dir;
+    let today = chrono::Utc::now().date_naive();
+    let from = (today - chrono::Days::new(2)).to_string();
+
+    let digest = ["digest", "--channel", CHANNEL, "--from", &from, "--top-count", "5"];
+    let e = crate::run_command(&app, &cli_command(&[&digest[..], &["--mode", "async"]].concat()))
+        .await
+        .unwrap_err();
+    assert!(e.to_string().contains("needs a static one"));
+    crate::run_command(&app, &cli_command(&digest)).await.unwrap();
+    let file = output_dir.join(format!("digest/example/{}/{}_{}.html", CHANNEL, from, today));
+    let html = std::fs::read_to_string(file).unwrap();
+    assert!(html.contains(&format!(r#"src="/view/{}/2?iframe=true""#, CHANNEL)));
+
+    crate::run_command(&app, &cli_command(&["post", "--channel", CHANNEL, "--id", "3"]))
+        .await
+        .unwrap();
+    let post = std::fs::read(output_dir.join(format!("post/{}/3.json", CHANNEL))).unwrap();
+    assert_eq!(serde_json::from_slice::<Value>(&post).unwrap()["id"], 3);
+
+    // Prune drops folders of unfinished renders but keeps what the commands wrote
+    let leftover = output_dir.join("00000000000000000001");
+    std::fs::create_dir_all(&leftover).unwrap();
+    crate::run_command(&app, &cli_command(&["cache", "prune"])).await.unwrap();
+    assert!(!leftover.exists());
+    assert!(output_dir.join(format!("post/{}/3.json", CHANNEL)).exists());
+    crate::run_command(&app, &cli_command(&["cache", "stats"])).await.unwrap();
+
+    let e = crate::run_command(&app, &cli_command(&["digest", "--channel", CHANNEL, "--year", "2013"]))
+        .await
+        .unwrap_err();
+    assert!(e.to_string().ends_with("Telegram did not exist"));
+    let week_without_month = ["tgdigest", "-c", "config.json", "digest", "--channel", CHANNEL, "--week", "1"];
+    assert!(<Args as clap::Parser>::try_parse_from(week_without_month).is_err());
+}
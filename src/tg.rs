@@ -4,10 +4,40 @@ use crate::util::*;
 
 use grammers_client::{Client, Config, SignInError};
 use grammers_session::Session;
-use once_cell::sync::OnceCell;
+use once_cell::sync::Lazy;
 use std::io::{self, BufRead as _, Write as _};
+use std::sync::RwLock;
 
-static TG: OnceCell<grammers_client::client::Client> = OnceCell::new();
+/// Clients of `AppContext::accounts`, in the same order. The first account is the one the admin
+/// page signs in.
+static TG: Lazy<RwLock<Vec<Option<grammers_client::client::Client>>>> = Lazy::new(|| RwLock::new(Vec::new()));
+
+/// Client of one account. Resolved channels are cached per account, as access hashes are.
+#[derive(Clone)]
+pub struct AccountClient {
+    pub account: usize,
+    client: grammers_client::client::Client,
+}
+
+impl std::ops::Deref for AccountClient {
+    type Target = grammers_client::client::Client;
+
+    fn deref(&self) -> &Self::Target {
+        &self.client
+    }
+}
+
+fn set_client(account: usize, client: grammers_client::client::Client) {
+    let mut clients = TG.write().unwrap();
+    if clients.len() <= account {
+        clients.resize(account + 1, None);
+    }
+    clients[account] = Some(client);
+}
+
+fn get_client(account: usize) -> Option<grammers_client::client::Client> {
+    TG.read().unwrap().get(account).cloned().flatten()
+}
 
 fn prompt(message: &str) -> Result<String> {
     let stdout = io::stdout();
@@ -26,34 +56,56 @@ fn prompt(message: &str) -> Result<String> {
 pub struct TelegramAPI {}
 
 impl TelegramAPI {
-    async fn init_client(ctx: &context::AppContext) -> Result<grammers_client::client::Client> {
+    /// Connect with the saved session, signed in or not
+    async fn connect_client(account: &context::TelegramAccount) -> Result<grammers_client::client::Client> {
         log::info!("Connecting to Telegram...");
 
-        let api_id = ctx.tg_id;
-        let api_hash = ctx.tg_hash.clone();
-        let tg_session = ctx.tg_session.clone();
+        let api_id = account.tg_id;
+        let api_hash = account.tg_hash.clone();
+        let tg_session = account.tg_session.clone();
         let session = match Session::load_file_or_create(&tg_session) {
             Ok(session) => session,
-            Err(why) => panic!(
-                "Can't load session file {}: {why}",
-                path_util::to_slash(&tg_session).unwrap().to_str().unwrap()
-            ),
+            Err(why) => {
+                return Err(format!(
+                    "Can't load session file {}: {why}",
+                    path_util::to_slash(&tg_session)?.display()
+                )
+                .into());
+            }
         };
         let client = Client::connect(Config {
             session,
             api_id,
             api_hash,
             params: grammers_client::InitParams {
-                proxy_url: ctx.proxy_url.clone(),
+                proxy_url: account.proxy_url.clone(),
+                // Flood waits are handled by `scheduler::Scheduler`, which pauses the whole method class
+                flood_sleep_threshold: 0,
                 ..Default::default()
             },
         })
         .await
-        .expect("Can't connect to Telegram");
+        .map_err(|e| format!("Can't connect to Telegram: {}", e))?;
         log::info!("Connected!");
+        Ok(client)
+    }
 
-        if !client.is_authorized().await.expect("Authorization error") {
-            log::info!("Signing in...");
+    /// Connect with the saved session. Only an interactive start may sign in.
+    async fn init_client(
+        account: &context::TelegramAccount,
+        interactive: bool,
+    ) -> Result<grammers_client::client::Client> {
+        let client = Self::connect_client(account).await?;
+        let tg_session = account.tg_session.clone();
+        if !client.is_authorized().await? {
+            if !interactive {
+                return Err(format!(
+                    "Telegram session {} is not authorized, sign in with the `login` command",
+                    tg_session.display()
+                )
+                .into());
+            }
+            log::info!("Signing in to the account of {}...", tg_session.display());
             let phone = prompt("Enter your phone number (international format): ")?;
             let token = client.request_login_code(&phone).await?;
             let code = prompt("Enter the code you received: ")?;
@@ -85,23 +137,70 @@ impl TelegramAPI {
         Ok(client)
     }
 
+    /// Sign in every account that is not, asking on the console
     pub async fn create(ctx: &context::AppContext) -> Result<TelegramAPI> {
-        match TG.get() {
-            Some(_) => Ok(TelegramAPI {}),
-            None => {
-                let client = Self::init_client(ctx).await?;
-                TG.set(client).unwrap();
-                Ok(TelegramAPI {})
+        for (index, account) in ctx.accounts().iter().enumerate() {
+            if get_client(index).is_none() {
+                let client = Self::init_client(account, true).await?;
+                set_client(index, client);
             }
         }
+        Ok(TelegramAPI {})
+    }
+
+    /// Connect every account with its saved session without signing in, whether each one is
+    /// signed in. The server starts this way and signs in with `login`.
+    pub async fn start(ctx: &context::AppContext) -> Result<Vec<bool>> {
+        let mut signed_in = Vec::new();
+        for index in 0..ctx.accounts().len() {
+            signed_in.push(Self::start_account(ctx, index).await?);
+        }
+        Ok(signed_in)
+    }
+
+    /// Connect one account with its saved session without signing in, `false` if it is not signed in
+    pub async fn start_account(ctx: &context::AppContext, account: usize) -> Result<bool> {
+        let client = Self::connect_client(&ctx.accounts()[account]).await?;
+        let authorized = client.is_authorized().await?;
+        set_client(account, client);
+        Ok(authorized)
+    }
+
+    /// Connect every account with its saved session, for unattended commands. Accounts that are
+    /// not signed in are left out, fails if none is.
+    pub async fn connect(ctx: &context::AppContext) -> Result<Vec<bool>> {
+        let signed_in = Self::start(ctx).await?;
+        if !signed_in.contains(&true) {
+            return Err("Telegram session is not authorized, sign in with the `login` command".into());
+        }
+        for (account, _) in ctx.accounts().iter().zip(&signed_in).filter(|(_, signed_in)| !**signed_in) {
+            log::warn!(
+                "Telegram session {} is not authorized, sign in with the `login` command",
+                account.tg_session.display()
+            );
+        }
+        Ok(signed_in)
+    }
+
+    /// Replace the client of an account with a new connection, see `connection::supervise`.
+    /// Handles taken before keep using the old connection.
+    pub async fn reconnect(ctx: &context::AppContext, account: usize) -> Result<()> {
+        let client = Self::init_client(&ctx.accounts()[account], false).await?;
+        set_client(account, client);
+        Ok(())
+    }
+
+    /// The client of the first account if connected, fixtures never connect
+    pub fn try_client() -> Option<grammers_client::client::Client> {
+        get_client(0)
     }
 
-    pub fn client() -> grammers_client::client::Client {
-        // This handle can be `clone()`'d around and freely moved into other tasks, so you can invoke
-        // methods concurrently if you need to. While you do this, the single owned `client` is the
-        // one that communicates with the network.
-        let client = TG.get();
-        assert!(client.is_some());
-        client.unwrap().clone()
+    /// The client of an account, see `source::grammers::GrammersSource`.
+    // This handle can be `clone()`'d around and freely moved into other tasks, so you can invoke
+    // methods concurrently if you need to. While you do this, the single owned `client` is the
+    // one that communicates with the network.
+    pub fn account_client(account: usize) -> Result<AccountClient> {
+        let client = get_client(account).ok_or_else(|| format!("Telegram account {} is not connected", account))?;
+        Ok(AccountClient { account, client })
     }
 }
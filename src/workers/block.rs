@@ -1,6 +1,15 @@
+use crate::formula::Formula;
+use crate::locale::Locales;
+use crate::post::Post;
 use crate::util;
+use crate::util::Result;
 use crate::workers::card::Card;
 
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
 #[derive(Clone, serde::Serialize)]
 pub struct Block {
     pub header: String,
@@ -19,3 +28,112 @@ impl Default for Block {
         }
     }
 }
+
+/// Block as written in `<input_dir>/<mode>/blocks.json`
+#[derive(serde::Deserialize)]
+struct RawBlockSpec {
+    #[serde(default)]
+    key: Option<String>,
+    header: String,
+    #[serde(default)]
+    card_header: Option<String>,
+    icon: String,
+    #[serde(default)]
+    filter: String,
+    score: String,
+}
+
+/// Block declared in `<input_dir>/<mode>/blocks.json`, with its score formula parsed
+#[derive(Clone)]
+pub struct BlockSpec {
+    /// Name used by /video to pick a card from this block (`replies`, `reactions`, `forwards`, `views`)
+    pub key: Option<String>,
+    pub header: String,
+    /// Header of the video card, `header` if not set
+    pub card_header: Option<String>,
+    /// Emoji rendered as Noto icon
+    pub icon: String,
+    pub filter: String,
+    /// Scoring expression, see `formula`
+    pub score: String,
+    formula: Formula,
+}
+
+impl BlockSpec {
+    /// Read block specs for a template mode. `None` if the mode has no `blocks.json`.
+    pub fn load(input_dir: &Path, mode: &str) -> Result<Option<Vec<BlockSpec>>> {
+        let path = input_dir.join(mode).join("blocks.json");
+        if !path.exists() {
+            return Ok(None);
+        }
+        let data = std::fs::read_to_string(&path)?;
+        let raw: Vec<RawBlockSpec> = serde_json::from_str(&data)
+            .map_err(|e| format!("Invalid {}: {}", path.display(), e))?;
+        let specs = raw
+            .into_iter()
+            .map(|raw| {
+                let formula = Formula::parse(&raw.score).map_err(|e| format!("Invalid {}: {}", path.display(), e))?;
+                Ok(BlockSpec {
+                    key: raw.key,
+                    header: raw.header,
+                    card_header: raw.card_header,
+                    icon: raw.icon,
+                    filter: raw.filter,
+                    score: raw.score,
+                    formula,
+                })
+            })
+            .collect::<Result<Vec<BlockSpec>>>()?;
+        Ok(Some(specs))
+    }
+
+    /// Top posts by this block's formula as cards. Headers may be locale keys.
+    pub fn create_cards(&self, posts: &[Post], top_count: usize, locales: &Locales, lang: &str) -> Vec<Card> {
+        self.formula
+            .top(posts, top_count, chrono::Utc::now().timestamp())
+            .into_iter()
+            .map(|(post, score)| Card {
+                id: post.id,
+                count: Some(score),
+                header: locales.t(lang, self.card_header.as_ref().unwrap_or(&self.header)),
+                icon: util::icon_url(&self.icon),
+                filter: self.filter.clone(),
+                comment: None,
+            })
+            .collect()
+    }
+
+    pub fn create_block(&self, posts: &[Post], top_count: usize, locales: &Locales, lang: &str) -> Block {
+        let cards = self.create_cards(posts, top_count, locales, lang);
+        Block {
+            header: locales.t(lang, &self.header),
+            icon: util::icon_url(&self.icon),
+            filter: self.filter.clone(),
+            cards: (!cards.is_empty()).then_some(cards),
+        }
+    }
+}
+
+/// Block specs of each template mode, read again only when `blocks.json` changes
+#[derive(Default)]
+pub struct BlockSpecCache {
+    /// Mode -> modification time and size of `blocks.json` (`None` without the file) and its specs
+    modes: Mutex<HashMap<String, (Option<(SystemTime, u64)>, Option<Vec<BlockSpec>>)>>,
+}
+
+impl BlockSpecCache {
+    /// Same as `BlockSpec::load`. A broken file is not cached, it fails until fixed.
+    pub fn get(&self, input_dir: &Path, mode: &str) -> Result<Option<Vec<BlockSpec>>> {
+        let stamp = std::fs::metadata(input_dir.join(mode).join("blocks.json"))
+            .and_then(|meta| Ok((meta.modified()?, meta.len())))
+            .ok();
+        if let Some((cached, specs)) = self.modes.lock().unwrap().get(mode)
+            && *cached == stamp
+        {
+            return Ok(specs.clone());
+        }
+        let specs = BlockSpec::load(input_dir, mode)?;
+        self.modes.lock().unwrap().insert(mode.to_string(), (stamp, specs.clone()));
+        Ok(specs)
+    }
+}
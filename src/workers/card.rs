@@ -1,5 +1,6 @@
-use crate::action::ActionType;
+use crate::action::{ActionType, Rank};
 use crate::post::Post;
+use crate::post_data::CommentData;
 use crate::util;
 
 #[derive(Clone, serde::Serialize)]
@@ -9,6 +10,9 @@ pub struct Card {
     pub header: String,
     pub icon: String,
     pub filter: String,
+    /// The comment a `TopComment` card counts the reactions of
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<CommentData>,
 }
 
 impl Default for Card {
@@ -19,24 +23,28 @@ impl Default for Card {
             header: String::from("UNDEFINED"),
             icon: util::icon_url("⚠️"),
             filter: String::from(""),
+            comment: None,
         }
     }
 }
 
 impl Card {
-    pub fn create_card(post: Option<&Post>, action: ActionType) -> Card {
+    pub fn create_card(post: Option<&Post>, action: ActionType, rank: Rank) -> Card {
         post.map(|post| Card {
             id: post.id,
-            count: post.count(action),
+            count: post.score(action, rank),
+            comment: matches!(action, ActionType::TopComment)
+                .then(|| post.top_comment.clone())
+                .flatten(),
             ..Card::default()
         })
         .unwrap_or_default()
     }
 
-    pub fn create_cards(posts: &[Post], action: ActionType) -> Option<Vec<Card>> {
+    pub fn create_cards(posts: &[Post], action: ActionType, rank: Rank) -> Option<Vec<Card>> {
         let cards = posts
             .iter()
-            .map(|p| Card::create_card(Some(p), action))
+            .map(|p| Card::create_card(Some(p), action, rank))
             .filter(|c| c.count.is_some())
             .collect::<Vec<Card>>();
 
@@ -1,12 +1,19 @@
 use crate::Commands::Cards;
 use crate::action::ActionType;
+use crate::locale::Locales;
 use crate::post::Post;
 use crate::post::TopPost;
 use crate::task::Task;
 use crate::util::*;
+use crate::workers::block::BlockSpec;
 use crate::workers::card::Card;
 
-pub fn create_context(post_top: TopPost, task: Task) -> Result<RenderingContext> {
+pub fn create_context(
+    post_top: TopPost,
+    task: Task,
+    block_specs: Option<Vec<BlockSpec>>,
+    locales: &Locales,
+) -> Result<RenderingContext> {
     let card_post_index = match task.command {
         Cards {
             replies,
@@ -33,46 +40,86 @@ pub fn create_context(post_top: TopPost, task: Task) -> Result<RenderingContext>
         .into());
     }
 
+    let cards = match block_specs {
+        Some(specs) => spec_cards(&post_top, &specs, card_post_index, locales, &task.lang),
+        None => default_cards(&post_top, card_post_index, locales, &task.lang),
+    };
+    let cards: Vec<Card> = cards.into_iter().filter(|c| c.count.is_some()).collect();
+    if cards.is_empty() {
+        return Err(
+            "Set at least one index of replies/reactions/forwards/views/editor_choice".into(),
+        );
+    }
+
+    let mut context = RenderingContext::new();
+    context.insert("cards", &cards);
+    context.insert("editor_choice_id", &task.editor_choice_post_id);
+    context.insert("channel_name", &task.channel_name.as_str());
+    context.insert("lang", &task.lang);
+
+    Ok(context)
+}
+
+/// Cards from `blocks.json`: a block gets a card when its `key` names one of
+/// replies/reactions/forwards/views and that index is set
+fn spec_cards(
+    post_top: &TopPost,
+    specs: &[BlockSpec],
+    card_post_index: [Option<usize>; 4],
+    locales: &Locales,
+    lang: &str,
+) -> Vec<Card> {
+    specs
+        .iter()
+        .filter_map(|spec| {
+            let action = match spec.key.as_deref()? {
+                "replies" => ActionType::Replies,
+                "reactions" => ActionType::Reactions,
+                "forwards" => ActionType::Forwards,
+                "views" => ActionType::Views,
+                _ => return None,
+            };
+            let index = card_post_index[action as usize]?;
+            spec.create_cards(&post_top.posts, post_top.top_count, locales, lang)
+                .into_iter()
+                .nth(index - 1)
+        })
+        .collect()
+}
+
+fn default_cards(
+    post_top: &TopPost,
+    card_post_index: [Option<usize>; 4],
+    locales: &Locales,
+    lang: &str,
+) -> Vec<Card> {
     let get_post = |action: ActionType| {
-        card_post_index[action as usize].map(|index| &post_top.index(action)[index - 1])
+        card_post_index[action as usize].and_then(|index| post_top.index(action).get(index - 1))
     };
-    let cards = vec![
+    vec![
         Card {
-            header: String::from("Лучший по комментариям"),
+            header: locales.t(lang, "card.replies"),
             icon: icon_url("💬"),
-            ..Card::create_card(get_post(ActionType::Replies), ActionType::Replies)
+            ..Card::create_card(get_post(ActionType::Replies), ActionType::Replies, post_top.rank)
         },
         Card {
-            header: String::from("Лучший по реакциям"),
+            header: locales.t(lang, "card.reactions"),
             icon: icon_url("👏"),
-            ..Card::create_card(get_post(ActionType::Reactions), ActionType::Reactions)
+            ..Card::create_card(get_post(ActionType::Reactions), ActionType::Reactions, post_top.rank)
         },
         Card {
-            header: String::from("Лучший по репостам"),
+            header: locales.t(lang, "card.forwards"),
             icon: icon_url("🔁"),
             filter: String::from("filter-blue"),
-            ..Card::create_card(get_post(ActionType::Forwards), ActionType::Forwards)
+            ..Card::create_card(get_post(ActionType::Forwards), ActionType::Forwards, post_top.rank)
         },
         Card {
-            header: String::from("Лучший по просмотрам"),
+            header: locales.t(lang, "card.views"),
             icon: icon_url("👁️"),
             filter: String::from("filter-blue"),
-            ..Card::create_card(get_post(ActionType::Views), ActionType::Views)
+            ..Card::create_card(get_post(ActionType::Views), ActionType::Views, post_top.rank)
         },
-    ];
-    let cards: Vec<Card> = cards.into_iter().filter(|c| c.count.is_some()).collect();
-    if cards.is_empty() {
-        return Err(
-            "Set at least one index of replies/reactions/forwards/views/editor_choice".into(),
-        );
-    }
-
-    let mut context = RenderingContext::new();
-    context.insert("cards", &cards);
-    context.insert("editor_choice_id", &task.editor_choice_post_id);
-    context.insert("channel_name", &task.channel_name.as_str());
-
-    Ok(context)
+    ]
 }
 
 pub fn create_post_context(post: Post, task: Task) -> Result<RenderingContext> {
@@ -1,13 +1,17 @@
-use crate::action::ActionType;
+use crate::action::{ActionType, Rank};
+use crate::locale::Locales;
 use crate::post::TopPost;
 use crate::task::*;
 use crate::util::*;
-use crate::workers::block::Block;
+use crate::workers::block::{Block, BlockSpec};
 use crate::workers::card::Card;
 
 #[derive(serde::Serialize)]
 pub struct DigestData {
     pub blocks: Vec<Block>,
+    pub rank: Rank,
+    pub reaction: Option<String>,
+    pub lang: String,
     pub editor_choice_id: i32,
     pub channel_name: String,
     pub channel_title: String,
@@ -19,6 +23,9 @@ impl DigestData {
     pub fn to_context(&self) -> RenderingContext {
         let mut context = RenderingContext::new();
         context.insert("blocks", &self.blocks);
+        context.insert("rank", &self.rank);
+        context.insert("reaction", &self.reaction);
+        context.insert("lang", &self.lang);
         context.insert("editor_choice_id", &self.editor_choice_id);
         context.insert("channel_name", &self.channel_name);
         context.insert("channel_title", &self.channel_title);
@@ -27,20 +34,29 @@ impl DigestData {
         context
     }
 
-    /// Slim JSON for /data/ endpoint: blocks have only header and cards: [[id, count], ...]
+    /// Slim JSON for /data/ endpoint: blocks have only header and cards: [[id, count], ...],
+    /// the top comment block also has the comments of its cards
     pub fn to_json(&self) -> serde_json::Value {
         let blocks: Vec<serde_json::Value> = self.blocks.iter().map(|b| {
             let cards: Vec<[i32; 2]> = b.cards.as_ref().map(|cards| {
                 cards.iter().map(|c| [c.id, c.count.unwrap_or(0)]).collect()
             }).unwrap_or_default();
-            serde_json::json!({
+            let mut block = serde_json::json!({
                 "header": b.header,
                 "cards": cards,
-            })
+            });
+            let comments: Vec<_> = b.cards.iter().flatten().filter_map(|c| c.comment.as_ref()).collect();
+            if !comments.is_empty() {
+                block["comments"] = serde_json::json!(comments);
+            }
+            block
         }).collect();
         serde_json::json!({
             "status": "ready",
             "blocks": blocks,
+            "rank": self.rank,
+            "reaction": self.reaction,
+            "lang": self.lang,
             "editor_choice_id": self.editor_choice_id,
             "channel_name": self.channel_name,
             "channel_title": self.channel_title,
@@ -50,51 +66,126 @@ impl DigestData {
     }
 }
 
+/// Icon for a reaction key: the emoji itself, or a generic one for custom
+/// emoji and paid stars which have no Noto glyph.
+fn reaction_icon(reaction: &str) -> String {
+    match reaction {
+        "paid" => icon_url("⭐"),
+        r if r.starts_with("custom:") => icon_url("✨"),
+        r => icon_url(r),
+    }
+}
+
 pub fn create_digest_data(
     post_top: TopPost,
     task: Task,
+    block_specs: Option<Vec<BlockSpec>>,
+    locales: &Locales,
     channel_title: &str,
     base_url: &str,
     site_name: &str,
 ) -> Result<DigestData> {
     log::debug!("Creating digest data");
+    let blocks = match block_specs {
+        Some(specs) => specs
+            .iter()
+            .map(|spec| spec.create_block(&post_top.posts, post_top.top_count, locales, &task.lang))
+            .collect(),
+        None => default_blocks(&post_top, &task, locales),
+    }
+    .into_iter()
+    .filter(|b| b.cards.is_some())
+    .collect::<Vec<Block>>();
+
+    Ok(DigestData {
+        blocks,
+        rank: post_top.rank,
+        reaction: task.reaction.clone(),
+        lang: task.lang.clone(),
+        editor_choice_id: task.editor_choice_post_id,
+        channel_name: task.channel_name.clone(),
+        channel_title: channel_title.to_string(),
+        base_url: base_url.to_string(),
+        site_name: site_name.to_string(),
+    })
+}
+
+/// Built-in blocks used when the mode has no `blocks.json`
+fn default_blocks(post_top: &TopPost, task: &Task, locales: &Locales) -> Vec<Block> {
     let get_posts = |action: ActionType| post_top.index(action);
-    let blocks = vec![
+    let rank = post_top.rank;
+    let t = |key: &str| locales.t(&task.lang, key);
+    let header = |key: &str| match rank {
+        Rank::Total => t(key),
+        Rank::Velocity => locales.t_args(&task.lang, "block.per_hour", &[("header", &t(key))]),
+    };
+    let reaction = task.reaction.as_deref().unwrap_or_default();
+    vec![
         Block {
-            header: String::from("По комментариям"),
+            header: header("block.replies"),
             icon: icon_url("💬"),
-            cards: Card::create_cards(get_posts(ActionType::Replies), ActionType::Replies),
+            cards: Card::create_cards(get_posts(ActionType::Replies), ActionType::Replies, rank),
             ..Block::default()
         },
         Block {
-            header: String::from("По реакциям"),
+            header: header("block.reactions"),
             icon: icon_url("👏"),
-            cards: Card::create_cards(get_posts(ActionType::Reactions), ActionType::Reactions),
+            cards: Card::create_cards(get_posts(ActionType::Reactions), ActionType::Reactions, rank),
             ..Block::default()
         },
         Block {
-            header: String::from("По репостам"),
+            header: header("block.forwards"),
             icon: icon_url("🔁"),
             filter: String::from("filter-blue"),
-            cards: Card::create_cards(get_posts(ActionType::Forwards), ActionType::Forwards),
+            cards: Card::create_cards(get_posts(ActionType::Forwards), ActionType::Forwards, rank),
         },
         Block {
-            header: String::from("По просмотрам"),
+            header: header("block.views"),
             icon: icon_url("👁️"),
             filter: String::from("filter-blue"),
-            cards: Card::create_cards(get_posts(ActionType::Views), ActionType::Views),
+            cards: Card::create_cards(get_posts(ActionType::Views), ActionType::Views, rank),
+        },
+        Block {
+            header: t("block.velocity"),
+            icon: icon_url("🚀"),
+            cards: Card::create_cards(get_posts(ActionType::Velocity), ActionType::Velocity, rank),
+            ..Block::default()
+        },
+        Block {
+            header: t("block.reaction_rate"),
+            icon: icon_url("👏"),
+            cards: Card::create_cards(get_posts(ActionType::ReactionRate), ActionType::ReactionRate, rank),
+            ..Block::default()
+        },
+        Block {
+            header: t("block.forward_rate"),
+            icon: icon_url("🔁"),
+            filter: String::from("filter-blue"),
+            cards: Card::create_cards(get_posts(ActionType::ForwardRate), ActionType::ForwardRate, rank),
+        },
+        Block {
+            header: t("block.reply_rate"),
+            icon: icon_url("💬"),
+            cards: Card::create_cards(get_posts(ActionType::ReplyRate), ActionType::ReplyRate, rank),
+            ..Block::default()
+        },
+        Block {
+            header: t("block.engagement_rate"),
+            icon: icon_url("📈"),
+            cards: Card::create_cards(get_posts(ActionType::EngagementRate), ActionType::EngagementRate, rank),
+            ..Block::default()
+        },
+        Block {
+            header: locales.t_args(&task.lang, "block.reaction", &[("reaction", reaction)]),
+            icon: reaction_icon(reaction),
+            cards: Card::create_cards(get_posts(ActionType::Reaction), ActionType::Reaction, rank),
+            ..Block::default()
+        },
+        Block {
+            header: t("block.top_comment"),
+            icon: icon_url("🗨️"),
+            cards: Card::create_cards(get_posts(ActionType::TopComment), ActionType::TopComment, rank),
+            ..Block::default()
         },
     ]
-    .into_iter()
-    .filter(|b| b.cards.is_some())
-    .collect::<Vec<Block>>();
-
-    Ok(DigestData {
-        blocks,
-        editor_choice_id: task.editor_choice_post_id,
-        channel_name: task.channel_name.clone(),
-        channel_title: channel_title.to_string(),
-        base_url: base_url.to_string(),
-        site_name: site_name.to_string(),
-    })
 }
@@ -7,42 +7,104 @@ use std::sync::Mutex;
 use crate::context::AppContext;
 use crate::post::*;
 use crate::post_data::{self, PostData};
+use crate::source::grammers::GrammersSource;
+use crate::source::{ChannelRef, HISTORY_PAGE, TelegramSource};
 use crate::task::Task;
+use crate::tg::AccountClient;
 use crate::util::Result;
 
-static CHANNEL_CACHE: Lazy<Mutex<HashMap<String, grammers_client::types::chat::Chat>>> =
+/// Channel ids by the name they were asked for, ids are the same for every account
+static CHANNEL_IDS: Lazy<Mutex<HashMap<String, i64>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Resolved channels by account and id, each account sees its own access hash
+static CHANNEL_CACHE: Lazy<Mutex<HashMap<(usize, i64), grammers_client::types::chat::Chat>>> =
     Lazy::new(|| Mutex::new(HashMap::new()));
 
-async fn get_channel(
-    client: &grammers_client::Client,
+/// Resolve a channel by username, invite link or id, see `ChannelRef`
+pub async fn get_channel(
+    client: &AccountClient,
     channel_name: &str,
 ) -> Result<grammers_client::types::chat::Chat> {
-    if let Some(channel) = CHANNEL_CACHE.lock().unwrap().get(channel_name).cloned() {
+    let known_id = match ChannelRef::parse(channel_name) {
+        ChannelRef::Id(id) => Some(id),
+        _ => CHANNEL_IDS.lock().unwrap().get(channel_name).copied(),
+    };
+    if let Some(id) = known_id
+        && let Some(channel) = CHANNEL_CACHE.lock().unwrap().get(&(client.account, id)).cloned()
+    {
         return Ok(channel);
     }
 
-    match client.resolve_username(channel_name).await? {
-        Some(channel) => {
-            CHANNEL_CACHE
-                .lock()
-                .unwrap()
-                .insert(channel_name.to_string(), channel.clone());
-            Ok(channel)
+    let channel = match ChannelRef::parse(channel_name) {
+        ChannelRef::Username(username) => client
+            .resolve_username(username)
+            .await?
+            .ok_or_else(|| format!("Can't find channel t.me/{}", username))?,
+        ChannelRef::Invite(hash) => {
+            let id = invite_channel_id(client, hash).await?;
+            joined_channel(client, id)
+                .await?
+                .ok_or_else(|| format!("Can't find channel t.me/+{} among the joined chats", hash))?
         }
-        None => Err(format!("Can't find channel t.me/{}", channel_name).into()),
+        ChannelRef::Id(id) => joined_channel(client, id)
+            .await?
+            .ok_or_else(|| format!("Can't find channel {} among the joined chats", channel_name))?,
+    };
+    CHANNEL_IDS.lock().unwrap().insert(channel_name.to_string(), channel.id());
+    CHANNEL_CACHE
+        .lock()
+        .unwrap()
+        .insert((client.account, channel.id()), channel.clone());
+    Ok(channel)
+}
+
+/// Id of the channel userpic, it changes with the picture
+pub fn photo_id(chat: &grammers_client::types::chat::Chat) -> Option<i64> {
+    use grammers_tl_types::enums;
+
+    let grammers_client::types::chat::Chat::Channel(channel) = chat else {
+        return None;
+    };
+    match &channel.raw.photo {
+        enums::ChatPhoto::Photo(photo) => Some(photo.photo_id),
+        enums::ChatPhoto::Empty => None,
     }
 }
 
+/// Id of the channel of an invite link, the account must have joined it
+async fn invite_channel_id(client: &AccountClient, hash: &str) -> Result<i64> {
+    use grammers_tl_types::{enums, functions, types};
+
+    let invite = client
+        .invoke(&functions::messages::CheckChatInvite { hash: hash.to_string() })
+        .await?;
+    match invite {
+        enums::ChatInvite::Already(types::ChatInviteAlready {
+            chat: enums::Chat::Channel(channel),
+        }) => Ok(channel.id),
+        enums::ChatInvite::Already(_) => Err(format!("t.me/+{} is not a channel", hash).into()),
+        _ => Err(format!("Join t.me/+{} with the account first", hash).into()),
+    }
+}
+
+/// A channel the account joined, looked up in its dialogs
+async fn joined_channel(client: &AccountClient, id: i64) -> Result<Option<grammers_client::types::chat::Chat>> {
+    let mut dialogs = client.iter_dialogs();
+    while let Some(dialog) = dialogs.next().await? {
+        if dialog.chat().id() == id {
+            return Ok(Some(dialog.chat().clone()));
+        }
+    }
+    Ok(None)
+}
+
+/// Save the channel avatar as `<output_dir>/<channel>.png`, served by `/localmedia`
 pub async fn download_pic(
-    client: grammers_client::Client,
-    task: Task,
+    tg: &dyn TelegramSource,
+    task: &Task,
     ctx: &AppContext,
 ) -> Result<std::path::PathBuf> {
-    let channel = get_channel(&client, task.channel_name.as_str()).await?;
-    let photo = channel.photo_downloadable(true);
-    let Some(photo) = photo else {
-        return Err(format!("Can't find photo for t.me/{}", task.channel_name).into());
-    };
+    let mut chunks = tg.channel_photo(task.channel_name.as_str()).await?;
 
     let photo_out: std::path::PathBuf = ctx.output_dir.join(format!("{}.png", task.channel_name));
     log::trace!(
@@ -50,77 +112,45 @@ pub async fn download_pic(
         task.channel_name,
         photo_out.display()
     );
-    client.download_media(&photo, photo_out.clone()).await?;
+    let mut photo = Vec::new();
+    while let Some(chunk) = chunks.next().await? {
+        photo.extend(chunk);
+    }
+    if photo.is_empty() {
+        return Err(format!("Can't download photo for t.me/{}", task.channel_name).into());
+    }
+    tokio::fs::write(&photo_out, photo).await?;
     Ok(photo_out)
 }
 
 pub const DEFAULT_FETCH_LIMIT: usize = 1000;
 
-pub async fn fetch_posts(
-    client: &grammers_client::Client,
-    task: &Task,
-    limit: usize,
-    progress: Option<&std::sync::atomic::AtomicUsize>,
-    cancelled: Option<&std::sync::atomic::AtomicBool>,
-) -> Result<Vec<Post>> {
+/// One page of history, see `TelegramSource::fetch_page`. `limit` is at most `HISTORY_PAGE`, so
+/// the walk makes a single request.
+pub async fn fetch_page(client: &AccountClient, task: &Task, offset_id: i32, limit: usize) -> Result<Vec<Post>> {
     let channel = get_channel(client, task.channel_name.as_str()).await?;
     let mut messages = client
         .iter_messages(channel)
+        .offset_id(offset_id)
         .max_date(task.to_date as i32)
-        .limit(limit);
+        .limit(limit.min(HISTORY_PAGE));
     let mut posts: Vec<Post> = Vec::new();
     while let Some(message) = messages.next().await? {
-        if cancelled.is_some_and(|c| c.load(std::sync::atomic::Ordering::Relaxed)) {
-            log::info!("Fetch cancelled for {}, returning {} posts", task.channel_name, posts.len());
-            break;
-        }
-        let date = message.date().timestamp();
-        if date > task.to_date {
-            continue;
-        }
-        if date < task.from_date {
-            break;
-        }
-        let grouped_id = message.grouped_id();
-        let post = Post {
-            date,
-            id: message.id(),
-            views: message.view_count(),
-            forwards: message.forward_count(),
-            replies: message.reply_count(),
-            reactions: message.reaction_count(),
-            message: Some(message.msg.message),
-            image: None,
-            grouped_id,
-        };
-        posts.push(post);
-        if let Some(p) = progress {
-            p.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-        }
+        posts.push(Post::from_message(message));
     }
-    log::debug!(
-        "Fetched {} posts for https://t.me/{} from {} to {}",
-        posts.len(),
-        task.channel_name,
-        task.from_date,
-        task.to_date
-    );
     Ok(posts)
 }
 
-pub async fn get_channel_title(client: &grammers_client::Client, channel_name: &str) -> Result<String> {
-    let channel = get_channel(client, channel_name).await?;
-    Ok(channel.name().to_string())
-}
-
-pub async fn get_top_posts(client: grammers_client::Client, task: Task) -> Result<TopPost> {
-    let mut posts = fetch_posts(&client, &task, DEFAULT_FETCH_LIMIT, None, None).await?;
-    let post_top = TopPost::get_top(task.top_count, &mut posts);
+pub async fn get_top_posts(client: AccountClient, task: Task) -> Result<TopPost> {
+    let mut posts = GrammersSource::new(client.account)
+        .fetch_posts(&task, DEFAULT_FETCH_LIMIT, None, None)
+        .await?;
+    let post_top = TopPost::get_top(task.top_count, &mut posts, task.rank);
     Ok(post_top)
 }
 
 pub async fn get_post(
-    client: grammers_client::Client,
+    client: AccountClient,
     task: Task,
     ctx: &AppContext,
 ) -> Result<Post> {
@@ -150,6 +180,13 @@ pub async fn get_post(
         None => None,
     };
 
+    let reaction_counts = message
+        .msg
+        .reactions
+        .as_ref()
+        .map(post_data::extract_reactions)
+        .unwrap_or_default();
+
     Ok(Post {
         date: message.date().timestamp(),
         id: message.id(),
@@ -160,6 +197,11 @@ pub async fn get_post(
         message: Some(message.msg.message),
         image: photo_id,
         grouped_id: None,
+        velocity: None,
+        rates: None,
+        reaction_counts,
+        selected_reaction: None,
+        top_comment: None,
     })
 }
 
@@ -167,7 +209,7 @@ pub async fn get_post(
 /// No media is downloaded — media URLs point to the /media/ streaming proxy.
 /// If the message belongs to a media group (album), fetches all group members.
 pub async fn get_post_data(
-    client: grammers_client::Client,
+    client: AccountClient,
     task: Task,
 ) -> Result<PostData> {
     let channel = get_channel(&client, &task.channel_name).await?;
@@ -231,11 +273,111 @@ pub async fn get_post_data(
     Ok(post)
 }
 
+/// Most replies `messages.getReplies` returns at once
+const REPLIES_PAGE: usize = 100;
+
+/// Up to `limit` replies to a post in the linked discussion group (`messages.getReplies`), newest
+/// first, paged `REPLIES_PAGE` at a time. A channel without comments has none.
+pub async fn get_comments(
+    client: &AccountClient,
+    channel_name: &str,
+    msg_id: i32,
+    limit: usize,
+) -> Result<Vec<post_data::CommentData>> {
+    let channel = get_channel(client, channel_name).await?;
+    let peer = channel.pack().to_input_peer();
+    let mut comments = Vec::new();
+    let mut offset_id = 0;
+    while comments.len() < limit {
+        let page = (limit - comments.len()).min(REPLIES_PAGE);
+        let (mut replies, last_id, fetched) = get_replies_page(client, &peer, msg_id, offset_id, page).await?;
+        comments.append(&mut replies);
+        match last_id {
+            Some(id) if fetched == page => offset_id = id,
+            _ => break,
+        }
+    }
+    Ok(comments)
+}
+
+/// One page of replies older than `offset_id` (0 for the newest), with the id of the oldest
+/// message and the count of messages it had, service ones included
+async fn get_replies_page(
+    client: &AccountClient,
+    peer: &grammers_tl_types::enums::InputPeer,
+    msg_id: i32,
+    offset_id: i32,
+    limit: usize,
+) -> Result<(Vec<post_data::CommentData>, Option<i32>, usize)> {
+    use grammers_tl_types::{enums, functions};
+
+    let replies = client
+        .invoke(&functions::messages::GetReplies {
+            peer: peer.clone(),
+            msg_id,
+            offset_id,
+            offset_date: 0,
+            add_offset: 0,
+            limit: limit as i32,
+            max_id: 0,
+            min_id: 0,
+            hash: 0,
+        })
+        .await?;
+    let (messages, users, chats) = match replies {
+        enums::messages::Messages::Messages(m) => (m.messages, m.users, m.chats),
+        enums::messages::Messages::Slice(m) => (m.messages, m.users, m.chats),
+        enums::messages::Messages::ChannelMessages(m) => (m.messages, m.users, m.chats),
+        enums::messages::Messages::NotModified(_) => return Ok((Vec::new(), None, 0)),
+    };
+    let last_id = messages
+        .iter()
+        .map(|message| match message {
+            enums::Message::Empty(message) => message.id,
+            enums::Message::Message(message) => message.id,
+            enums::Message::Service(message) => message.id,
+        })
+        .min();
+    let fetched = messages.len();
+
+    let author = |peer: &enums::Peer| -> Option<String> {
+        match peer {
+            enums::Peer::User(peer) => users.iter().find_map(|user| match user {
+                enums::User::User(user) if user.id == peer.user_id => {
+                    let name = [user.first_name.as_deref(), user.last_name.as_deref()]
+                        .into_iter()
+                        .flatten()
+                        .collect::<Vec<_>>()
+                        .join(" ");
+                    Some(name)
+                }
+                _ => None,
+            }),
+            enums::Peer::Channel(peer) => chats.iter().find_map(|chat| match chat {
+                enums::Chat::Channel(chat) if chat.id == peer.channel_id => Some(chat.title.clone()),
+                _ => None,
+            }),
+            enums::Peer::Chat(_) => None,
+        }
+    };
+    let comments = messages
+        .iter()
+        .filter_map(|message| match message {
+            enums::Message::Message(message) => Some(post_data::comment_from_message(
+                message,
+                message.from_id.as_ref().and_then(&author),
+            )),
+            _ => None,
+        })
+        .collect();
+    Ok((comments, last_id, fetched))
+}
+
 /// Resolve media metadata for a post without downloading anything.
 /// Returns (Downloadable, mime_type, file_size, media_id) for use with iter_download.
 /// file_size is None for photos (unknown without downloading).
 pub async fn resolve_media(
-    client: &grammers_client::Client,
+    client: &AccountClient,
     channel_name: &str,
     msg_id: i32,
 ) -> Result<(Downloadable, String, Option<i64>, i64)> {
@@ -288,7 +430,7 @@ pub async fn resolve_media(
 /// Download the thumbnail of a video/document.
 /// Returns (bytes, mime_type) — the thumb is small enough to download in one go.
 pub async fn download_thumb(
-    client: &grammers_client::Client,
+    client: &AccountClient,
     channel_name: &str,
     msg_id: i32,
 ) -> Result<(Vec<u8>, String)> {